@@ -0,0 +1,75 @@
+//! Benchmarks the dispatch loop of the virtual machine.
+//!
+//! The programs only use the public API that existed before instructions
+//! were encoded as bytes, so the two representations can be compared by
+//! running the benchmark on both versions with criterion baselines:
+//!
+//! ```text
+//! cargo bench --bench dispatch -- --save-baseline enum    # before
+//! cargo bench --bench dispatch -- --baseline enum         # after
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use monkey::{compile::Compiler, parse, vm::VirtualMachine};
+
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "fibonacci",
+        r#"
+        let fibonacci = fn(x) {
+            if (x < 3) {
+                return 1;
+            } else {
+                return fibonacci(x - 1) + fibonacci(x - 2);
+            }
+        };
+
+        fibonacci(25)
+        "#,
+    ),
+    (
+        "countdown",
+        r#"
+        let countdown = fn(n, sum) {
+            if (n == 0) { sum } else { countdown(n - 1, sum + n) }
+        };
+
+        countdown(100000, 0)
+        "#,
+    ),
+    (
+        "collections",
+        r#"
+        let build = fn(n, arr) {
+            if (n == 0) { arr } else { build(n - 1, push(arr, {"n": n, "s": "x"})) }
+        };
+        let sum = fn(arr, acc) {
+            if (len(arr) == 0) { acc } else { sum(rest(arr), acc + first(arr)["n"]) }
+        };
+
+        sum(build(300, []), 0)
+        "#,
+    ),
+];
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm dispatch");
+
+    for (name, input) in PROGRAMS {
+        let program = parse::parse(input).unwrap();
+        let mut compiler = Compiler::new();
+        let bytecode = compiler.compile(&program).unwrap();
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut vm = VirtualMachine::new();
+                vm.run(&bytecode).unwrap();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
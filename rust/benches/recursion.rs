@@ -1,27 +1,38 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use monkey::{evaluate::Evaluator, parse};
+use monkey::{compile::Compiler, evaluate::Evaluator, parse, vm::VirtualMachine};
+
+const FIBONACCI: &str = r#"
+    let fibonacci = fn(x) {
+        if (x < 3) {
+            return 1;
+        } else {
+            return fibonacci(x - 1) + fibonacci(x - 2);
+        }
+    };
+
+    fibonacci(30)
+"#;
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("fib 30", |b| {
         b.iter(|| {
-            let input = r#"
-                let fibonacci = fn(x) {
-                    if (x < 3) {
-                        return 1;
-                    } else {
-                        return fibonacci(x - 1) + fibonacci(x - 2);
-                    }
-                };
-
-                fibonacci(30)
-            "#;
-
-            let program = parse::parse(input).unwrap();
+            let program = parse::parse(FIBONACCI).unwrap();
 
             let mut evaluator = Evaluator::new();
             let _result = evaluator.evaluate(&program).unwrap();
         })
     });
+
+    c.bench_function("vm fib 30", |b| {
+        b.iter(|| {
+            let program = parse::parse(FIBONACCI).unwrap();
+
+            let mut compiler = Compiler::new();
+            let bytecode = compiler.compile(&program).unwrap();
+            let mut vm = VirtualMachine::new();
+            vm.run(&bytecode).unwrap();
+        })
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -1,9 +1,16 @@
+pub mod modify;
 mod operator;
+mod types;
 use std::rc::Rc;
 
+use num_bigint::BigInt;
+
+use crate::token::Position;
+
 pub use operator::*;
+pub use types::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
@@ -20,19 +27,92 @@ impl Program {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Statement {
-    Let { name: String, value: Expression },
+    /// Binding created with `let` or `const`. Constants
+    /// can't be rebound in the same scope.
+    Let {
+        name: String,
+        type_annotation: Option<Type>,
+        value: Expression,
+        constant: bool,
+        position: Position,
+    },
     Return(Expression),
+    /// Suspends the generator and returns the value from `next`.
+    Yield(Expression),
     Expression(Expression),
+    Import {
+        path: String,
+        name: String,
+    },
+    Export(Vec<String>),
+}
+
+// Positions are only used to report errors, so the same code at
+// different positions is equal, like `quote(1 + 2) == quote(1 + 2)`.
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Let {
+                    name,
+                    type_annotation,
+                    value,
+                    constant,
+                    ..
+                },
+                Self::Let {
+                    name: other_name,
+                    type_annotation: other_type_annotation,
+                    value: other_value,
+                    constant: other_constant,
+                    ..
+                },
+            ) => {
+                name == other_name
+                    && type_annotation == other_type_annotation
+                    && value == other_value
+                    && constant == other_constant
+            }
+            (Self::Return(expr), Self::Return(other)) => expr == other,
+            (Self::Yield(expr), Self::Yield(other)) => expr == other,
+            (Self::Expression(expr), Self::Expression(other)) => expr == other,
+            (
+                Self::Import { path, name },
+                Self::Import {
+                    path: other_path,
+                    name: other_name,
+                },
+            ) => path == other_path && name == other_name,
+            (Self::Export(names), Self::Export(other)) => names == other,
+            _ => false,
+        }
+    }
 }
 
 impl Statement {
     pub fn debug_str(&self) -> String {
         match self {
-            Self::Let { name, value } => format!("let {} = {}", name, value.debug_str()),
+            Self::Let {
+                name,
+                type_annotation,
+                value,
+                constant,
+                ..
+            } => {
+                let keyword = if *constant { "const" } else { "let" };
+                let annotation = match type_annotation {
+                    Some(annotation) => format!(": {}", annotation),
+                    None => String::new(),
+                };
+                format!("{} {}{} = {}", keyword, name, annotation, value.debug_str())
+            }
             Self::Return(expr) => format!("return {}", expr.debug_str()),
+            Self::Yield(expr) => format!("yield {}", expr.debug_str()),
             Self::Expression(expr) => expr.debug_str(),
+            Self::Import { path, name } => format!("import \"{}\" as {}", path, name),
+            Self::Export(names) => format!("export {}", names.join(", ")),
         }
     }
 }
@@ -43,6 +123,22 @@ pub struct BlockStatement {
 }
 
 impl BlockStatement {
+    /// Returns true if the block contains a `yield` statement, which makes
+    /// the function with this body a generator. Yield statements can only
+    /// appear directly in the body or in its `if` statements and blocks.
+    pub fn contains_yield(&self) -> bool {
+        self.statements.iter().any(|stmt| match stmt {
+            Statement::Yield(_) => true,
+            Statement::Expression(Expression::If {
+                consequence,
+                alternative,
+                ..
+            }) => consequence.contains_yield() || alternative.contains_yield(),
+            Statement::Expression(Expression::Block(block)) => block.contains_yield(),
+            _ => false,
+        })
+    }
+
     pub fn debug_str(&self) -> String {
         let mut res = String::new();
         for stmt in self.statements.iter() {
@@ -54,56 +150,223 @@ impl BlockStatement {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct HashLiteralPair {
     pub key: Expression,
     pub value: Expression,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Expression {
     Identifier(String),
     IntegerLiteral(i64),
+    /// Integer literal that doesn't fit into `i64`.
+    BigIntegerLiteral(BigInt),
     BooleanLiteral(bool),
     StringLiteral(String),
+    BytesLiteral(Vec<u8>),
     ArrayLiteral(Vec<Expression>),
     HashLiteral(Vec<HashLiteralPair>),
     PrefixOperator {
         operator: PrefixOperatorKind,
         right: Box<Expression>,
+        position: Position,
     },
     InfixOperator {
         operator: InfixOperatorKind,
         left: Box<Expression>,
         right: Box<Expression>,
+        position: Position,
     },
     If {
         condition: Box<Expression>,
         consequence: BlockStatement,
         alternative: BlockStatement,
+        position: Position,
     },
+    /// Block with its own scope. The value is the value of the last statement.
+    Block(BlockStatement),
+    /// Function literal. Parameters and the return value can be annotated
+    /// with types, which are only used by the checker.
     FunctionLiteral {
         name: Option<String>,
+        parameters: Vec<String>,
+        parameter_types: Vec<Option<Type>>,
+        return_type: Option<Type>,
+        body: BlockStatement,
+        position: Position,
+    },
+    /// Macro literal, which is removed from the program
+    /// during the macro expansion.
+    MacroLiteral {
         parameters: Vec<String>,
         body: BlockStatement,
     },
     FunctionCall {
         function: Box<Expression>,
         arguments: Vec<Expression>,
+        position: Position,
     },
     Index {
         left: Box<Expression>,
         index: Box<Expression>,
+        position: Position,
     },
+    /// Postfix `?` operator, which returns early from the enclosing
+    /// function if the expression evaluates to an error.
+    Try(Box<Expression>),
+}
+
+// Positions are ignored like in `Statement`.
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Identifier(name), Self::Identifier(other)) => name == other,
+            (Self::IntegerLiteral(value), Self::IntegerLiteral(other)) => value == other,
+            (Self::BigIntegerLiteral(value), Self::BigIntegerLiteral(other)) => value == other,
+            (Self::BooleanLiteral(value), Self::BooleanLiteral(other)) => value == other,
+            (Self::StringLiteral(value), Self::StringLiteral(other)) => value == other,
+            (Self::BytesLiteral(value), Self::BytesLiteral(other)) => value == other,
+            (Self::ArrayLiteral(elements), Self::ArrayLiteral(other)) => elements == other,
+            (Self::HashLiteral(pairs), Self::HashLiteral(other)) => pairs == other,
+            (
+                Self::PrefixOperator {
+                    operator, right, ..
+                },
+                Self::PrefixOperator {
+                    operator: other_operator,
+                    right: other_right,
+                    ..
+                },
+            ) => operator == other_operator && right == other_right,
+            (
+                Self::InfixOperator {
+                    operator,
+                    left,
+                    right,
+                    ..
+                },
+                Self::InfixOperator {
+                    operator: other_operator,
+                    left: other_left,
+                    right: other_right,
+                    ..
+                },
+            ) => operator == other_operator && left == other_left && right == other_right,
+            (
+                Self::If {
+                    condition,
+                    consequence,
+                    alternative,
+                    ..
+                },
+                Self::If {
+                    condition: other_condition,
+                    consequence: other_consequence,
+                    alternative: other_alternative,
+                    ..
+                },
+            ) => {
+                condition == other_condition
+                    && consequence == other_consequence
+                    && alternative == other_alternative
+            }
+            (Self::Block(block), Self::Block(other)) => block == other,
+            (
+                Self::FunctionLiteral {
+                    name,
+                    parameters,
+                    parameter_types,
+                    return_type,
+                    body,
+                    ..
+                },
+                Self::FunctionLiteral {
+                    name: other_name,
+                    parameters: other_parameters,
+                    parameter_types: other_parameter_types,
+                    return_type: other_return_type,
+                    body: other_body,
+                    ..
+                },
+            ) => {
+                name == other_name
+                    && parameters == other_parameters
+                    && parameter_types == other_parameter_types
+                    && return_type == other_return_type
+                    && body == other_body
+            }
+            (
+                Self::MacroLiteral { parameters, body },
+                Self::MacroLiteral {
+                    parameters: other_parameters,
+                    body: other_body,
+                },
+            ) => parameters == other_parameters && body == other_body,
+            (
+                Self::FunctionCall {
+                    function,
+                    arguments,
+                    ..
+                },
+                Self::FunctionCall {
+                    function: other_function,
+                    arguments: other_arguments,
+                    ..
+                },
+            ) => function == other_function && arguments == other_arguments,
+            (
+                Self::Index { left, index, .. },
+                Self::Index {
+                    left: other_left,
+                    index: other_index,
+                    ..
+                },
+            ) => left == other_left && index == other_index,
+            (Self::Try(expr), Self::Try(other)) => expr == other,
+            _ => false,
+        }
+    }
 }
 
 impl Expression {
+    /// Returns the arguments if the expression is a call
+    /// of the identifier with the given name.
+    pub fn call_arguments(&self, name: &str) -> Option<&[Expression]> {
+        match self {
+            Self::FunctionCall {
+                function,
+                arguments,
+                ..
+            } if matches!(function.as_ref(), Self::Identifier(ident) if ident == name) => {
+                Some(arguments)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the position of the expression, if it has one.
+    /// Identifiers and literals don't have a position.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Self::PrefixOperator { position, .. }
+            | Self::InfixOperator { position, .. }
+            | Self::If { position, .. }
+            | Self::FunctionLiteral { position, .. }
+            | Self::FunctionCall { position, .. }
+            | Self::Index { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+
     pub fn debug_str(&self) -> String {
         match self {
             Self::Identifier(name) => name.clone(),
             Self::IntegerLiteral(value) => value.to_string(),
+            Self::BigIntegerLiteral(value) => value.to_string(),
             Self::BooleanLiteral(value) => value.to_string(),
             Self::StringLiteral(value) => value.clone(),
+            Self::BytesLiteral(value) => bytes_debug_str(value),
             Self::ArrayLiteral(value) => {
                 format!(
                     "[{}]",
@@ -124,13 +387,16 @@ impl Expression {
                         .join(", ")
                 )
             }
-            Self::PrefixOperator { operator, right } => {
+            Self::PrefixOperator {
+                operator, right, ..
+            } => {
                 format!("({}{})", operator.debug_str(), right.debug_str())
             }
             Self::InfixOperator {
                 operator,
                 left,
                 right,
+                ..
             } => format!(
                 "({} {} {})",
                 left.debug_str(),
@@ -141,31 +407,54 @@ impl Expression {
                 condition,
                 consequence,
                 alternative,
+                ..
             } => format!(
                 "if ({}) {{{}}} else {{{}}}",
                 condition.debug_str(),
                 consequence.debug_str(),
                 alternative.debug_str()
             ),
+            Self::Block(block) => format!("{{{}}}", block.debug_str()),
             Self::FunctionLiteral {
                 name,
                 parameters,
+                parameter_types,
+                return_type,
                 body,
+                ..
             } => {
                 let name = match name {
                     Some(nm) => format!("<{}>", nm),
                     None => String::new(),
                 };
+                let parameters = parameters
+                    .iter()
+                    .zip(parameter_types)
+                    .map(|(parameter, annotation)| match annotation {
+                        Some(annotation) => format!("{}: {}", parameter, annotation),
+                        None => parameter.clone(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let return_type = match return_type {
+                    Some(annotation) => format!(" -> {}", annotation),
+                    None => String::new(),
+                };
                 format!(
-                    "fn{}({}) {{{}}}",
+                    "fn{}({}){} {{{}}}",
                     name,
-                    parameters.join(", "),
+                    parameters,
+                    return_type,
                     body.debug_str()
                 )
             }
+            Self::MacroLiteral { parameters, body } => {
+                format!("macro({}) {{{}}}", parameters.join(", "), body.debug_str())
+            }
             Self::FunctionCall {
                 function,
                 arguments,
+                ..
             } => {
                 let args = arguments
                     .iter()
@@ -174,7 +463,26 @@ impl Expression {
                     .join(", ");
                 format!("{}({})", function.debug_str(), args)
             }
-            Self::Index { left, index } => format!("({}[{}])", left.debug_str(), index.debug_str()),
+            Self::Index { left, index, .. } => {
+                format!("({}[{}])", left.debug_str(), index.debug_str())
+            }
+            Self::Try(expr) => format!("({}?)", expr.debug_str()),
+        }
+    }
+}
+
+/// Formats bytes as a bytes literal. Printable ASCII characters
+/// are kept and everything else is escaped as `\xHH`.
+pub fn bytes_debug_str(bytes: &[u8]) -> String {
+    let mut res = String::from("b\"");
+    for byte in bytes {
+        match byte {
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            b' '..=b'~' => res.push(*byte as char),
+            _ => res.push_str(&format!("\\x{:02x}", byte)),
         }
     }
+    res.push('"');
+    res
 }
@@ -0,0 +1,218 @@
+//! Functions for rewriting the AST.
+//!
+//! Every function walks the tree depth first and calls the modifier
+//! on every expression after its children have been modified.
+
+use std::rc::Rc;
+
+use super::*;
+
+pub fn modify_program<E, F>(program: Program, modifier: &mut F) -> Result<Program, E>
+where
+    F: FnMut(Expression) -> Result<Expression, E>,
+{
+    let statements = program
+        .statements
+        .into_iter()
+        .map(|stmt| modify_statement(stmt, modifier))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Program { statements })
+}
+
+pub fn modify_statement<E, F>(statement: Statement, modifier: &mut F) -> Result<Statement, E>
+where
+    F: FnMut(Expression) -> Result<Expression, E>,
+{
+    let stmt = match statement {
+        Statement::Let {
+            name,
+            type_annotation,
+            value,
+            constant,
+            position,
+        } => Statement::Let {
+            name,
+            type_annotation,
+            value: modify_expression(value, modifier)?,
+            constant,
+            position,
+        },
+        Statement::Return(expr) => Statement::Return(modify_expression(expr, modifier)?),
+        Statement::Yield(expr) => Statement::Yield(modify_expression(expr, modifier)?),
+        Statement::Expression(expr) => Statement::Expression(modify_expression(expr, modifier)?),
+        Statement::Import { .. } | Statement::Export(_) => statement,
+    };
+
+    Ok(stmt)
+}
+
+pub fn modify_block_statement<E, F>(
+    block: BlockStatement,
+    modifier: &mut F,
+) -> Result<BlockStatement, E>
+where
+    F: FnMut(Expression) -> Result<Expression, E>,
+{
+    let statements = Rc::unwrap_or_clone(block.statements)
+        .into_iter()
+        .map(|stmt| modify_statement(stmt, modifier))
+        .collect::<Result<_, _>>()?;
+
+    Ok(BlockStatement {
+        statements: Rc::new(statements),
+    })
+}
+
+pub fn modify_expression<E, F>(expression: Expression, modifier: &mut F) -> Result<Expression, E>
+where
+    F: FnMut(Expression) -> Result<Expression, E>,
+{
+    let modify_box = |expr: Box<Expression>, modifier: &mut F| -> Result<Box<Expression>, E> {
+        Ok(Box::new(modify_expression(*expr, modifier)?))
+    };
+
+    let expr = match expression {
+        Expression::Identifier(_)
+        | Expression::IntegerLiteral(_)
+        | Expression::BigIntegerLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::BytesLiteral(_) => expression,
+        Expression::ArrayLiteral(elements) => Expression::ArrayLiteral(
+            elements
+                .into_iter()
+                .map(|expr| modify_expression(expr, modifier))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expression::HashLiteral(pairs) => Expression::HashLiteral(
+            pairs
+                .into_iter()
+                .map(|pair| -> Result<HashLiteralPair, E> {
+                    Ok(HashLiteralPair {
+                        key: modify_expression(pair.key, modifier)?,
+                        value: modify_expression(pair.value, modifier)?,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        Expression::PrefixOperator {
+            operator,
+            right,
+            position,
+        } => Expression::PrefixOperator {
+            operator,
+            right: modify_box(right, modifier)?,
+            position,
+        },
+        Expression::InfixOperator {
+            operator,
+            left,
+            right,
+            position,
+        } => Expression::InfixOperator {
+            operator,
+            left: modify_box(left, modifier)?,
+            right: modify_box(right, modifier)?,
+            position,
+        },
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+            position,
+        } => Expression::If {
+            condition: modify_box(condition, modifier)?,
+            consequence: modify_block_statement(consequence, modifier)?,
+            alternative: modify_block_statement(alternative, modifier)?,
+            position,
+        },
+        Expression::Block(block) => Expression::Block(modify_block_statement(block, modifier)?),
+        Expression::FunctionLiteral {
+            name,
+            parameters,
+            parameter_types,
+            return_type,
+            body,
+            position,
+        } => Expression::FunctionLiteral {
+            name,
+            parameters,
+            parameter_types,
+            return_type,
+            body: modify_block_statement(body, modifier)?,
+            position,
+        },
+        Expression::MacroLiteral { parameters, body } => Expression::MacroLiteral {
+            parameters,
+            body: modify_block_statement(body, modifier)?,
+        },
+        Expression::FunctionCall {
+            function,
+            arguments,
+            position,
+        } => Expression::FunctionCall {
+            function: modify_box(function, modifier)?,
+            arguments: arguments
+                .into_iter()
+                .map(|expr| modify_expression(expr, modifier))
+                .collect::<Result<_, _>>()?,
+            position,
+        },
+        Expression::Index {
+            left,
+            index,
+            position,
+        } => Expression::Index {
+            left: modify_box(left, modifier)?,
+            index: modify_box(index, modifier)?,
+            position,
+        },
+        Expression::Try(expr) => Expression::Try(modify_box(expr, modifier)?),
+    };
+
+    modifier(expr)
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use crate::{
+        ast::{modify::modify_program, Expression},
+        parse::parse,
+    };
+
+    #[test]
+    fn test_modify() {
+        let tests = [
+            ("1", "2;"),
+            ("1 + 1", "(2 + 2);"),
+            ("-1", "(-2);"),
+            ("[1, 1][1]", "([2, 2][2]);"),
+            ("{1: 1}", "{2: 2};"),
+            ("if (1) { 1 } else { 1 }", "if (2) {2;} else {2;};"),
+            (
+                "let a = fn(b) { return 1; }",
+                "let a = fn<a>(b) {return 2;};",
+            ),
+            ("macro(b) { 1 }", "macro(b) {2;};"),
+            ("{ let a = 1; a }", "{let a = 2;a;};"),
+            ("f(1)?", "(f(2)?);"),
+        ];
+
+        let mut one_to_two = |expr| -> Result<Expression, Infallible> {
+            match expr {
+                Expression::IntegerLiteral(1) => Ok(Expression::IntegerLiteral(2)),
+                _ => Ok(expr),
+            }
+        };
+
+        for (input, expected) in tests {
+            let program = parse(input).unwrap();
+            let modified = modify_program(program, &mut one_to_two).unwrap();
+
+            assert_eq!(modified.debug_str(), expected);
+        }
+    }
+}
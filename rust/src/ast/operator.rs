@@ -4,6 +4,7 @@ use crate::{parse, token::Token};
 pub enum PrefixOperatorKind {
     Not,
     Negative,
+    BitNot,
 }
 
 impl PrefixOperatorKind {
@@ -11,6 +12,7 @@ impl PrefixOperatorKind {
         match self {
             PrefixOperatorKind::Not => "!",
             PrefixOperatorKind::Negative => "-",
+            PrefixOperatorKind::BitNot => "~",
         }
         .to_owned()
     }
@@ -23,6 +25,7 @@ impl TryFrom<&Option<Token>> for PrefixOperatorKind {
         match value {
             Some(Token::Bang) => Ok(Self::Not),
             Some(Token::Minus) => Ok(Self::Negative),
+            Some(Token::Tilde) => Ok(Self::BitNot),
             token => Err(parse::Error::unexpected_token(token)),
         }
     }
@@ -38,6 +41,11 @@ pub enum InfixOperatorKind {
     NotEqual,
     GreaterThan,
     LessThan,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 }
 
 impl InfixOperatorKind {
@@ -51,6 +59,11 @@ impl InfixOperatorKind {
             InfixOperatorKind::NotEqual => "!=",
             InfixOperatorKind::GreaterThan => ">",
             InfixOperatorKind::LessThan => "<",
+            InfixOperatorKind::BitAnd => "&",
+            InfixOperatorKind::BitOr => "|",
+            InfixOperatorKind::BitXor => "^",
+            InfixOperatorKind::ShiftLeft => "<<",
+            InfixOperatorKind::ShiftRight => ">>",
         }
         .to_owned()
     }
@@ -69,6 +82,11 @@ impl TryFrom<&Option<Token>> for InfixOperatorKind {
             Some(Token::NotEq) => Ok(Self::NotEqual),
             Some(Token::Gt) => Ok(Self::GreaterThan),
             Some(Token::Lt) => Ok(Self::LessThan),
+            Some(Token::Ampersand) => Ok(Self::BitAnd),
+            Some(Token::Pipe) => Ok(Self::BitOr),
+            Some(Token::Caret) => Ok(Self::BitXor),
+            Some(Token::ShiftLeft) => Ok(Self::ShiftLeft),
+            Some(Token::ShiftRight) => Ok(Self::ShiftRight),
             token => Err(parse::Error::unexpected_token(token)),
         }
     }
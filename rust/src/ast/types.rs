@@ -0,0 +1,50 @@
+use std::fmt::Display;
+
+/// Type of a value, written in annotations such as `fn(x: int) -> [int]`.
+/// The checker also uses `Any` for values, whose type it can't infer.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Type {
+    Int,
+    String,
+    Bool,
+    Bytes,
+    Any,
+    Array(Box<Type>),
+    Hash(Box<Type>, Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "int" => Some(Self::Int),
+            "string" => Some(Self::String),
+            "bool" => Some(Self::Bool),
+            "bytes" => Some(Self::Bytes),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => f.write_str("int"),
+            Type::String => f.write_str("string"),
+            Type::Bool => f.write_str("bool"),
+            Type::Bytes => f.write_str("bytes"),
+            Type::Any => f.write_str("any"),
+            Type::Array(element) => write!(f, "[{}]", element),
+            Type::Hash(key, value) => write!(f, "{{{}: {}}}", key, value),
+            Type::Function(parameters, ret) => {
+                let parameters = parameters
+                    .iter()
+                    .map(|parameter| parameter.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "fn({}) -> {}", parameters, ret)
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use thiserror::Error;
+
+use crate::{ast::Type, token::Position};
+
+/// Type error found by the checker. Every error
+/// has the position of the offending code.
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("{position}: expected {expected}, found {found}")]
+    Mismatch {
+        position: Position,
+        expected: Type,
+        found: Type,
+    },
+    #[error("{position}: unsupported operand types for {operator}: {left} and {right}")]
+    InvalidOperands {
+        position: Position,
+        operator: String,
+        left: Type,
+        right: Type,
+    },
+    #[error("{position}: unsupported operand type for {operator}: {operand}")]
+    InvalidOperand {
+        position: Position,
+        operator: String,
+        operand: Type,
+    },
+    #[error("{position}: wrong number of arguments: expected {expected}, got {got}")]
+    WrongNumberOfArguments {
+        position: Position,
+        expected: usize,
+        got: usize,
+    },
+    #[error("{position}: not a function: {found}")]
+    NotAFunction { position: Position, found: Type },
+    #[error("{position}: cannot index {left} with {index}")]
+    InvalidIndex {
+        position: Position,
+        left: Type,
+        index: Type,
+    },
+}
+
+impl Error {
+    pub fn position(&self) -> Position {
+        match self {
+            Error::Mismatch { position, .. }
+            | Error::InvalidOperands { position, .. }
+            | Error::InvalidOperand { position, .. }
+            | Error::WrongNumberOfArguments { position, .. }
+            | Error::NotAFunction { position, .. }
+            | Error::InvalidIndex { position, .. } => *position,
+        }
+    }
+}
@@ -0,0 +1,450 @@
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{self, Expression, InfixOperatorKind, PrefixOperatorKind, Statement, Type},
+    object::builtin::BuiltinFunction,
+    token::Position,
+};
+
+pub use error::Error;
+
+/// Checks the annotated types of the program and returns all errors found.
+/// Values without annotations have inferred types, or `any` if the type
+/// can't be inferred, so unannotated programs only report obvious errors.
+pub fn check(program: &ast::Program) -> Vec<Error> {
+    let mut checker = Checker::new();
+    checker.check(program);
+    checker.errors
+}
+
+struct FunctionContext {
+    return_type: Option<Type>,
+    position: Position,
+    returns: Vec<Type>,
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Type>>,
+    functions: Vec<FunctionContext>,
+    errors: Vec<Error>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            functions: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn check(&mut self, program: &ast::Program) {
+        for stmt in &program.statements {
+            self.check_statement(stmt);
+        }
+    }
+
+    fn check_block(&mut self, block: &ast::BlockStatement) -> Type {
+        self.scopes.push(HashMap::new());
+        let mut res = Type::Any;
+        for stmt in block.statements.iter() {
+            res = self.check_statement(stmt);
+        }
+        self.scopes.pop();
+
+        res
+    }
+
+    /// Checks the statement and returns the type of its value.
+    fn check_statement(&mut self, stmt: &Statement) -> Type {
+        match stmt {
+            Statement::Let {
+                name,
+                type_annotation,
+                value,
+                position,
+                ..
+            } => {
+                // Bind the function before checking its body to allow recursion.
+                if let Expression::FunctionLiteral {
+                    parameter_types,
+                    return_type,
+                    ..
+                } = value
+                {
+                    let signature = function_type(parameter_types, return_type.clone());
+                    self.bind(name, signature);
+                }
+
+                let found = self.infer(value);
+                match type_annotation {
+                    Some(expected) => {
+                        self.expect(expected, &found, *position);
+                        self.bind(name, expected.clone());
+                    }
+                    None => self.bind(name, found),
+                }
+                Type::Any
+            }
+            Statement::Return(value) => {
+                let found = self.infer(value);
+                if let Some(context) = self.functions.last_mut() {
+                    context.returns.push(found.clone());
+                    if let Some(expected) = context.return_type.clone() {
+                        let position = context.position;
+                        self.expect(&expected, &found, position);
+                    }
+                }
+                Type::Any
+            }
+            Statement::Yield(value) => {
+                self.infer(value);
+                Type::Any
+            }
+            Statement::Expression(expr) => self.infer(expr),
+            Statement::Import { name, .. } => {
+                self.bind(name, Type::Any);
+                Type::Any
+            }
+            Statement::Export(_) => Type::Any,
+        }
+    }
+
+    fn infer(&mut self, expr: &Expression) -> Type {
+        match expr {
+            Expression::Identifier(name) => self.resolve(name),
+            Expression::IntegerLiteral(_) | Expression::BigIntegerLiteral(_) => Type::Int,
+            Expression::BooleanLiteral(_) => Type::Bool,
+            Expression::StringLiteral(_) => Type::String,
+            Expression::BytesLiteral(_) => Type::Bytes,
+            Expression::ArrayLiteral(elements) => {
+                let elements = elements.iter().map(|el| self.infer(el)).collect::<Vec<_>>();
+                Type::Array(Box::new(join_all(elements)))
+            }
+            Expression::HashLiteral(pairs) => {
+                let mut keys = Vec::new();
+                let mut values = Vec::new();
+                for pair in pairs {
+                    keys.push(self.infer(&pair.key));
+                    values.push(self.infer(&pair.value));
+                }
+                Type::Hash(Box::new(join_all(keys)), Box::new(join_all(values)))
+            }
+            Expression::PrefixOperator {
+                operator,
+                right,
+                position,
+            } => {
+                let operand = self.infer(right);
+                match operator {
+                    PrefixOperatorKind::Not => Type::Bool,
+                    PrefixOperatorKind::Negative | PrefixOperatorKind::BitNot => {
+                        if !compatible(&Type::Int, &operand) {
+                            self.errors.push(Error::InvalidOperand {
+                                position: *position,
+                                operator: operator.debug_str(),
+                                operand,
+                            });
+                        }
+                        Type::Int
+                    }
+                }
+            }
+            Expression::InfixOperator {
+                operator,
+                left,
+                right,
+                position,
+            } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                self.infer_infix(*operator, left, right, *position)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.infer(condition);
+                let consequence = self.check_block(consequence);
+                let alternative = self.check_block(alternative);
+                join(consequence, alternative)
+            }
+            Expression::Block(block) => self.check_block(block),
+            Expression::FunctionLiteral {
+                name,
+                parameters,
+                parameter_types,
+                return_type,
+                body,
+                position,
+            } => {
+                let mut scope = HashMap::new();
+                if let Some(name) = name {
+                    scope.insert(
+                        name.clone(),
+                        function_type(parameter_types, return_type.clone()),
+                    );
+                }
+                for (parameter, annotation) in parameters.iter().zip(parameter_types) {
+                    scope.insert(parameter.clone(), annotation.clone().unwrap_or(Type::Any));
+                }
+
+                // Return values of generators are wrapped by `next`, so they
+                // aren't checked against the return type.
+                let generator = body.contains_yield();
+                self.scopes.push(scope);
+                self.functions.push(FunctionContext {
+                    return_type: return_type.clone().filter(|_| !generator),
+                    position: *position,
+                    returns: Vec::new(),
+                });
+                let value = self.check_block(body);
+                let context = self.functions.pop().unwrap();
+                self.scopes.pop();
+
+                // A body ending with a statement other than an expression
+                // returns null, which the annotations can't express.
+                let ends_with_value =
+                    matches!(body.statements.last(), Some(Statement::Expression(_)));
+                if let (Some(expected), true) = (&context.return_type, ends_with_value) {
+                    self.expect(expected, &value, *position);
+                }
+
+                let ret = match (return_type, generator) {
+                    (Some(ret), false) => ret.clone(),
+                    (None, false) if ends_with_value => {
+                        context.returns.into_iter().fold(value, join)
+                    }
+                    _ => Type::Any,
+                };
+                let parameters = parameter_types
+                    .iter()
+                    .map(|annotation| annotation.clone().unwrap_or(Type::Any))
+                    .collect();
+                Type::Function(parameters, Box::new(ret))
+            }
+            Expression::MacroLiteral { .. } => Type::Any,
+            Expression::FunctionCall {
+                function,
+                arguments,
+                position,
+            } => self.infer_call(function, arguments, *position),
+            Expression::Index {
+                left,
+                index,
+                position,
+            } => {
+                let left = self.infer(left);
+                let index = self.infer(index);
+                let element = match (&left, &index) {
+                    (Type::Any, _) => Some(Type::Any),
+                    (Type::Array(element), index) if compatible(&Type::Int, index) => {
+                        Some(element.as_ref().clone())
+                    }
+                    (Type::Bytes, index) if compatible(&Type::Int, index) => Some(Type::Int),
+                    (Type::Hash(key, value), index) if compatible(key, index) => {
+                        Some(value.as_ref().clone())
+                    }
+                    _ => None,
+                };
+                element.unwrap_or_else(|| {
+                    self.errors.push(Error::InvalidIndex {
+                        position: *position,
+                        left,
+                        index,
+                    });
+                    Type::Any
+                })
+            }
+            Expression::Try(expr) => self.infer(expr),
+        }
+    }
+
+    fn infer_infix(
+        &mut self,
+        operator: InfixOperatorKind,
+        left: Type,
+        right: Type,
+        position: Position,
+    ) -> Type {
+        let res = match operator {
+            InfixOperatorKind::Equal | InfixOperatorKind::NotEqual => Some(Type::Bool),
+            InfixOperatorKind::Add => match join(left.clone(), right.clone()) {
+                Type::Any if left == Type::Any || right == Type::Any => {
+                    let known = if left == Type::Any { &right } else { &left };
+                    match known {
+                        Type::Int | Type::String | Type::Bytes | Type::Any => Some(known.clone()),
+                        _ => None,
+                    }
+                }
+                ty @ (Type::Int | Type::String | Type::Bytes) => Some(ty),
+                _ => None,
+            },
+            InfixOperatorKind::GreaterThan | InfixOperatorKind::LessThan => {
+                (compatible(&Type::Int, &left) && compatible(&Type::Int, &right))
+                    .then_some(Type::Bool)
+            }
+            InfixOperatorKind::Subtract
+            | InfixOperatorKind::Multiply
+            | InfixOperatorKind::Divide
+            | InfixOperatorKind::BitAnd
+            | InfixOperatorKind::BitOr
+            | InfixOperatorKind::BitXor
+            | InfixOperatorKind::ShiftLeft
+            | InfixOperatorKind::ShiftRight => (compatible(&Type::Int, &left)
+                && compatible(&Type::Int, &right))
+            .then_some(Type::Int),
+        };
+
+        res.unwrap_or_else(|| {
+            self.errors.push(Error::InvalidOperands {
+                position,
+                operator: operator.debug_str(),
+                left,
+                right,
+            });
+            Type::Any
+        })
+    }
+
+    fn infer_call(
+        &mut self,
+        function: &Expression,
+        arguments: &[Expression],
+        position: Position,
+    ) -> Type {
+        // Quoted code isn't evaluated, so it isn't checked either.
+        if matches!(function, Expression::Identifier(name) if name == "quote") {
+            return Type::Any;
+        }
+
+        let callee = self.infer(function);
+        let arguments = arguments
+            .iter()
+            .map(|arg| self.infer(arg))
+            .collect::<Vec<_>>();
+
+        match callee {
+            Type::Function(parameters, ret) => {
+                if parameters.len() != arguments.len() {
+                    self.errors.push(Error::WrongNumberOfArguments {
+                        position,
+                        expected: parameters.len(),
+                        got: arguments.len(),
+                    });
+                } else {
+                    for (expected, found) in parameters.iter().zip(&arguments) {
+                        self.expect(expected, found, position);
+                    }
+                }
+                *ret
+            }
+            Type::Any => match function {
+                Expression::Identifier(name) if self.is_builtin(name) => {
+                    builtin_return_type(name, &arguments)
+                }
+                _ => Type::Any,
+            },
+            found => {
+                self.errors.push(Error::NotAFunction { position, found });
+                Type::Any
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, position: Position) {
+        if !compatible(expected, found) {
+            self.errors.push(Error::Mismatch {
+                position,
+                expected: expected.clone(),
+                found: found.clone(),
+            });
+        }
+    }
+
+    fn bind(&mut self, name: &str, ty: Type) {
+        self.scopes.last_mut().unwrap().insert(name.to_owned(), ty);
+    }
+
+    fn resolve(&self, name: &str) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .unwrap_or(Type::Any)
+    }
+
+    fn is_builtin(&self, name: &str) -> bool {
+        BuiltinFunction::from_ident(name).is_some()
+            && self.scopes.iter().all(|scope| !scope.contains_key(name))
+    }
+}
+
+fn function_type(parameter_types: &[Option<Type>], return_type: Option<Type>) -> Type {
+    let parameters = parameter_types
+        .iter()
+        .map(|annotation| annotation.clone().unwrap_or(Type::Any))
+        .collect();
+    Type::Function(parameters, Box::new(return_type.unwrap_or(Type::Any)))
+}
+
+fn builtin_return_type(name: &str, arguments: &[Type]) -> Type {
+    let first = arguments.first().cloned().unwrap_or(Type::Any);
+    match name {
+        "len" => Type::Int,
+        "is_error" | "is_done" => Type::Bool,
+        "string" | "hex" => Type::String,
+        "bytes" | "from_hex" => Type::Bytes,
+        "array" => Type::Array(Box::new(Type::Int)),
+        "first" | "last" => match first {
+            Type::Array(element) => *element,
+            _ => Type::Any,
+        },
+        "rest" | "slice" => first,
+        _ => Type::Any,
+    }
+}
+
+/// Returns true if a value of the `found` type can be used where the
+/// `expected` type is required. `any` is compatible with every type.
+fn compatible(expected: &Type, found: &Type) -> bool {
+    match (expected, found) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Array(expected), Type::Array(found)) => compatible(expected, found),
+        (Type::Hash(expected_key, expected_value), Type::Hash(found_key, found_value)) => {
+            compatible(expected_key, found_key) && compatible(expected_value, found_value)
+        }
+        (
+            Type::Function(expected_parameters, expected_ret),
+            Type::Function(found_parameters, found_ret),
+        ) => {
+            expected_parameters.len() == found_parameters.len()
+                && expected_parameters
+                    .iter()
+                    .zip(found_parameters)
+                    .all(|(expected, found)| compatible(found, expected))
+                && compatible(expected_ret, found_ret)
+        }
+        (expected, found) => expected == found,
+    }
+}
+
+/// Returns the common type of two values, or `any` if they differ.
+fn join(a: Type, b: Type) -> Type {
+    if a == b {
+        a
+    } else {
+        Type::Any
+    }
+}
+
+fn join_all(types: Vec<Type>) -> Type {
+    types.into_iter().reduce(join).unwrap_or(Type::Any)
+}
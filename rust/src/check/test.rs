@@ -0,0 +1,190 @@
+use crate::{ast::Type, check::check, parse, token::Position};
+
+use super::Error;
+
+fn position(line: usize, column: usize) -> Position {
+    Position { line, column }
+}
+
+fn array(element: Type) -> Type {
+    Type::Array(Box::new(element))
+}
+
+#[test]
+fn test_valid_programs() {
+    let tests = [
+        "let x: int = 1; x + 2",
+        "let s: string = \"a\" + \"b\"; s",
+        "let b: bytes = b\"a\" + bytes(\"b\"); b[0] + 1",
+        "let xs: [int] = [1, 2, 3]; xs[0] * 2",
+        "let xs: [int] = []; len(xs)",
+        "let h: {string: int} = {\"a\": 1}; h[\"a\"] - 1",
+        "let f = fn(x: int, y: int) -> int { x + y }; f(1, 2) * 3",
+        "let f = fn(x) { x }; f(1) + f(\"a\")",
+        "let fact = fn(n: int) -> int { if (n < 2) { return 1; } n * fact(n - 1) }; fact(5)",
+        "let apply = fn(f: fn(int) -> int, x: int) -> int { f(x) }; apply(fn(x) { x * 2 }, 1)",
+        "let g = fn() -> int { yield 1; yield 2; }; g",
+        "let f = fn() -> int { let x = 1; }; f()",
+        "let x: any = 1; let x: string = \"a\"; x",
+        "let x = if (true) { 1 } else { \"a\" }; x + 1",
+        "let len = fn(x: string) -> string { x }; len(\"a\") + \"b\"",
+        "error(\"message\")?",
+        "quote(1 + \"a\")",
+    ];
+
+    for input in tests {
+        let program = parse::parse(input).unwrap();
+        assert_eq!(check(&program), Vec::new(), "{}", input);
+    }
+}
+
+#[test]
+fn test_errors() {
+    let tests = [
+        (
+            "let x: int = \"a\";",
+            vec![Error::Mismatch {
+                position: position(1, 1),
+                expected: Type::Int,
+                found: Type::String,
+            }],
+        ),
+        (
+            "let xs: [int] = [1, \"a\"];\nlet ys: [int] = [\"a\"];",
+            vec![Error::Mismatch {
+                position: position(2, 1),
+                expected: array(Type::Int),
+                found: array(Type::String),
+            }],
+        ),
+        (
+            "1 + \"a\"",
+            vec![Error::InvalidOperands {
+                position: position(1, 3),
+                operator: "+".to_owned(),
+                left: Type::Int,
+                right: Type::String,
+            }],
+        ),
+        (
+            "\"a\" < \"b\"",
+            vec![Error::InvalidOperands {
+                position: position(1, 5),
+                operator: "<".to_owned(),
+                left: Type::String,
+                right: Type::String,
+            }],
+        ),
+        (
+            "-true",
+            vec![Error::InvalidOperand {
+                position: position(1, 1),
+                operator: "-".to_owned(),
+                operand: Type::Bool,
+            }],
+        ),
+        (
+            "let f = fn(x: int) -> int { x }; f(\"a\")",
+            vec![Error::Mismatch {
+                position: position(1, 35),
+                expected: Type::Int,
+                found: Type::String,
+            }],
+        ),
+        (
+            "let f = fn(x: int) -> int { x }; f(1, 2)",
+            vec![Error::WrongNumberOfArguments {
+                position: position(1, 35),
+                expected: 1,
+                got: 2,
+            }],
+        ),
+        (
+            "let f = fn(x: int) -> string { x }",
+            vec![Error::Mismatch {
+                position: position(1, 9),
+                expected: Type::String,
+                found: Type::Int,
+            }],
+        ),
+        (
+            "let f = fn(x: int) -> int { if (x > 0) { return \"a\"; } x }",
+            vec![Error::Mismatch {
+                position: position(1, 9),
+                expected: Type::Int,
+                found: Type::String,
+            }],
+        ),
+        (
+            "let f = fn(x: int) -> int { x }; f(1) + \"a\"",
+            vec![Error::InvalidOperands {
+                position: position(1, 39),
+                operator: "+".to_owned(),
+                left: Type::Int,
+                right: Type::String,
+            }],
+        ),
+        (
+            "let x = 1; x(2)",
+            vec![Error::NotAFunction {
+                position: position(1, 13),
+                found: Type::Int,
+            }],
+        ),
+        (
+            "let xs = [1, 2]; xs[\"a\"]",
+            vec![Error::InvalidIndex {
+                position: position(1, 20),
+                left: array(Type::Int),
+                index: Type::String,
+            }],
+        ),
+        (
+            "len(\"abc\") + \"d\"",
+            vec![Error::InvalidOperands {
+                position: position(1, 12),
+                operator: "+".to_owned(),
+                left: Type::Int,
+                right: Type::String,
+            }],
+        ),
+        (
+            "let apply = fn(f: fn(int) -> int) { f(1) }; apply(fn(x: string) { x })",
+            vec![Error::Mismatch {
+                position: position(1, 50),
+                expected: Type::Function(vec![Type::Int], Box::new(Type::Int)),
+                found: Type::Function(vec![Type::String], Box::new(Type::String)),
+            }],
+        ),
+        (
+            "let x: int = true;\nlet y: bool = 1;",
+            vec![
+                Error::Mismatch {
+                    position: position(1, 1),
+                    expected: Type::Int,
+                    found: Type::Bool,
+                },
+                Error::Mismatch {
+                    position: position(2, 1),
+                    expected: Type::Bool,
+                    found: Type::Int,
+                },
+            ],
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+        assert_eq!(check(&program), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_error_display() {
+    let program = parse::parse("let x: [int] = [\"a\"];").unwrap();
+    let errors = check(&program);
+    assert_eq!(
+        errors.iter().map(|err| err.to_string()).collect::<Vec<_>>(),
+        vec!["1:1: expected [int], found [string]"]
+    );
+}
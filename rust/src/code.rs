@@ -1,6 +1,10 @@
 //! Bytecode implementation
 
-use std::rc::Rc;
+use std::{
+    fmt::{Debug, Display},
+    path::PathBuf,
+    rc::Rc,
+};
 
 use crate::object::{self, builtin};
 
@@ -13,6 +17,12 @@ pub enum Instruction {
     Mul,
     Div,
 
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+
     Pop,
 
     Null,
@@ -22,11 +32,15 @@ pub enum Instruction {
     Equal,
     NotEqual,
     GreaterThan,
+    LessThan,
 
     Minus,
     Bang,
+    BitNot,
 
+    /// Jumps to the instruction at the byte offset, if the condition is not truthy.
     JumpNotTruthy(u16),
+    /// Jumps to the instruction at the byte offset.
     Jump(u16),
 
     GetGlobal(u16),
@@ -42,17 +56,476 @@ pub enum Instruction {
     Index,
 
     Call(u8),
+    /// Call in a tail position, which replaces the frame
+    /// of the current function with the frame of the callee.
+    TailCall(u8),
     ReturnValue,
+    /// Returns the value on top of the stack from the current function
+    /// if it is an error. Otherwise the value is left on the stack.
+    ReturnIfError,
+    /// Suspends the generator of the current frame and returns
+    /// the value on top of the stack from `next`.
+    Yield,
+    /// Loads the module with the given index. The module is executed
+    /// the first time it is imported and its exports are cached.
+    Import(u16),
+    /// Returns the exports of the module from the module initializer.
+    ReturnModule,
 
     Closure {
         constant_index: u16,
         free_variables: u8,
     },
     CurrentClosure,
+    /// Replaces the first `unquote` call in the quote below the top of
+    /// the stack with the value on top of the stack.
+    Unquote,
+}
+
+impl Instruction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Constant(_) => "Constant",
+            Instruction::Add => "Add",
+            Instruction::Sub => "Sub",
+            Instruction::Mul => "Mul",
+            Instruction::Div => "Div",
+            Instruction::BitAnd => "BitAnd",
+            Instruction::BitOr => "BitOr",
+            Instruction::BitXor => "BitXor",
+            Instruction::ShiftLeft => "ShiftLeft",
+            Instruction::ShiftRight => "ShiftRight",
+            Instruction::Pop => "Pop",
+            Instruction::Null => "Null",
+            Instruction::True => "True",
+            Instruction::False => "False",
+            Instruction::Equal => "Equal",
+            Instruction::NotEqual => "NotEqual",
+            Instruction::GreaterThan => "GreaterThan",
+            Instruction::LessThan => "LessThan",
+            Instruction::Minus => "Minus",
+            Instruction::Bang => "Bang",
+            Instruction::BitNot => "BitNot",
+            Instruction::JumpNotTruthy(_) => "JumpNotTruthy",
+            Instruction::Jump(_) => "Jump",
+            Instruction::GetGlobal(_) => "GetGlobal",
+            Instruction::SetGlobal(_) => "SetGlobal",
+            Instruction::GetLocal(_) => "GetLocal",
+            Instruction::SetLocal(_) => "SetLocal",
+            Instruction::GetBuiltin(_) => "GetBuiltin",
+            Instruction::GetFree(_) => "GetFree",
+            Instruction::Array(_) => "Array",
+            Instruction::Hash(_) => "Hash",
+            Instruction::Index => "Index",
+            Instruction::Call(_) => "Call",
+            Instruction::TailCall(_) => "TailCall",
+            Instruction::ReturnValue => "ReturnValue",
+            Instruction::ReturnIfError => "ReturnIfError",
+            Instruction::Yield => "Yield",
+            Instruction::Import(_) => "Import",
+            Instruction::ReturnModule => "ReturnModule",
+            Instruction::Closure { .. } => "Closure",
+            Instruction::CurrentClosure => "CurrentClosure",
+            Instruction::Unquote => "Unquote",
+        }
+    }
+
+    /// Returns the number of bytes of the encoded instruction.
+    pub fn size(&self) -> usize {
+        opcode::size(self.opcode())
+    }
+
+    /// Returns the opcode, which is decoded by [`Instructions::decode`].
+    fn opcode(&self) -> u8 {
+        match self {
+            Instruction::Constant(_) => opcode::CONSTANT,
+            Instruction::Add => opcode::ADD,
+            Instruction::Sub => opcode::SUB,
+            Instruction::Mul => opcode::MUL,
+            Instruction::Div => opcode::DIV,
+            Instruction::BitAnd => opcode::BIT_AND,
+            Instruction::BitOr => opcode::BIT_OR,
+            Instruction::BitXor => opcode::BIT_XOR,
+            Instruction::ShiftLeft => opcode::SHIFT_LEFT,
+            Instruction::ShiftRight => opcode::SHIFT_RIGHT,
+            Instruction::Pop => opcode::POP,
+            Instruction::Null => opcode::NULL,
+            Instruction::True => opcode::TRUE,
+            Instruction::False => opcode::FALSE,
+            Instruction::Equal => opcode::EQUAL,
+            Instruction::NotEqual => opcode::NOT_EQUAL,
+            Instruction::GreaterThan => opcode::GREATER_THAN,
+            Instruction::LessThan => opcode::LESS_THAN,
+            Instruction::Minus => opcode::MINUS,
+            Instruction::Bang => opcode::BANG,
+            Instruction::BitNot => opcode::BIT_NOT,
+            Instruction::JumpNotTruthy(_) => opcode::JUMP_NOT_TRUTHY,
+            Instruction::Jump(_) => opcode::JUMP,
+            Instruction::GetGlobal(_) => opcode::GET_GLOBAL,
+            Instruction::SetGlobal(_) => opcode::SET_GLOBAL,
+            Instruction::GetLocal(_) => opcode::GET_LOCAL,
+            Instruction::SetLocal(_) => opcode::SET_LOCAL,
+            Instruction::GetBuiltin(_) => opcode::GET_BUILTIN,
+            Instruction::GetFree(_) => opcode::GET_FREE,
+            Instruction::Array(_) => opcode::ARRAY,
+            Instruction::Hash(_) => opcode::HASH,
+            Instruction::Index => opcode::INDEX,
+            Instruction::Call(_) => opcode::CALL,
+            Instruction::TailCall(_) => opcode::TAIL_CALL,
+            Instruction::ReturnValue => opcode::RETURN_VALUE,
+            Instruction::ReturnIfError => opcode::RETURN_IF_ERROR,
+            Instruction::Yield => opcode::YIELD,
+            Instruction::Import(_) => opcode::IMPORT,
+            Instruction::ReturnModule => opcode::RETURN_MODULE,
+            Instruction::Closure { .. } => opcode::CLOSURE,
+            Instruction::CurrentClosure => opcode::CURRENT_CLOSURE,
+            Instruction::Unquote => opcode::UNQUOTE,
+        }
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.opcode());
+
+        match *self {
+            Instruction::Constant(operand)
+            | Instruction::JumpNotTruthy(operand)
+            | Instruction::Jump(operand)
+            | Instruction::GetGlobal(operand)
+            | Instruction::SetGlobal(operand)
+            | Instruction::Array(operand)
+            | Instruction::Hash(operand)
+            | Instruction::Import(operand) => bytes.extend_from_slice(&operand.to_le_bytes()),
+            Instruction::GetLocal(operand)
+            | Instruction::SetLocal(operand)
+            | Instruction::GetFree(operand)
+            | Instruction::Call(operand)
+            | Instruction::TailCall(operand) => bytes.push(operand),
+            Instruction::GetBuiltin(builtin) => bytes.push(builtin.index()),
+            Instruction::Closure {
+                constant_index,
+                free_variables,
+            } => {
+                bytes.extend_from_slice(&constant_index.to_le_bytes());
+                bytes.push(free_variables);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Writes the name of the instruction followed by its operands, like `Closure 3 1`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())?;
+
+        match self {
+            Instruction::Constant(operand)
+            | Instruction::JumpNotTruthy(operand)
+            | Instruction::Jump(operand)
+            | Instruction::GetGlobal(operand)
+            | Instruction::SetGlobal(operand)
+            | Instruction::Array(operand)
+            | Instruction::Hash(operand)
+            | Instruction::Import(operand) => write!(f, " {}", operand),
+            Instruction::GetLocal(operand)
+            | Instruction::SetLocal(operand)
+            | Instruction::GetFree(operand)
+            | Instruction::Call(operand)
+            | Instruction::TailCall(operand) => write!(f, " {}", operand),
+            Instruction::GetBuiltin(builtin) => write!(f, " {}", builtin.ident()),
+            Instruction::Closure {
+                constant_index,
+                free_variables,
+            } => write!(f, " {} {}", constant_index, free_variables),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Opcodes of the encoded instructions, see [`Instructions`].
+pub mod opcode {
+    pub const CONSTANT: u8 = 0;
+    pub const ADD: u8 = 1;
+    pub const SUB: u8 = 2;
+    pub const MUL: u8 = 3;
+    pub const DIV: u8 = 4;
+    pub const BIT_AND: u8 = 5;
+    pub const BIT_OR: u8 = 6;
+    pub const BIT_XOR: u8 = 7;
+    pub const SHIFT_LEFT: u8 = 8;
+    pub const SHIFT_RIGHT: u8 = 9;
+    pub const POP: u8 = 10;
+    pub const NULL: u8 = 11;
+    pub const TRUE: u8 = 12;
+    pub const FALSE: u8 = 13;
+    pub const EQUAL: u8 = 14;
+    pub const NOT_EQUAL: u8 = 15;
+    pub const GREATER_THAN: u8 = 16;
+    pub const LESS_THAN: u8 = 17;
+    pub const MINUS: u8 = 18;
+    pub const BANG: u8 = 19;
+    pub const BIT_NOT: u8 = 20;
+    pub const JUMP_NOT_TRUTHY: u8 = 21;
+    pub const JUMP: u8 = 22;
+    pub const GET_GLOBAL: u8 = 23;
+    pub const SET_GLOBAL: u8 = 24;
+    pub const GET_LOCAL: u8 = 25;
+    pub const SET_LOCAL: u8 = 26;
+    pub const GET_BUILTIN: u8 = 27;
+    pub const GET_FREE: u8 = 28;
+    pub const ARRAY: u8 = 29;
+    pub const HASH: u8 = 30;
+    pub const INDEX: u8 = 31;
+    pub const CALL: u8 = 32;
+    pub const TAIL_CALL: u8 = 33;
+    pub const RETURN_VALUE: u8 = 34;
+    pub const RETURN_IF_ERROR: u8 = 35;
+    pub const YIELD: u8 = 36;
+    pub const IMPORT: u8 = 37;
+    pub const RETURN_MODULE: u8 = 38;
+    pub const CLOSURE: u8 = 39;
+    pub const CURRENT_CLOSURE: u8 = 40;
+    pub const UNQUOTE: u8 = 41;
+
+    /// Returns the number of bytes of the instruction with the opcode,
+    /// including the opcode itself.
+    #[inline]
+    pub fn size(opcode: u8) -> usize {
+        match opcode {
+            CONSTANT | JUMP_NOT_TRUTHY | JUMP | GET_GLOBAL | SET_GLOBAL | ARRAY | HASH | IMPORT => {
+                3
+            }
+            GET_LOCAL | SET_LOCAL | GET_BUILTIN | GET_FREE | CALL | TAIL_CALL => 2,
+            CLOSURE => 4,
+            _ => 1,
+        }
+    }
+}
+
+/// Instructions encoded as bytes, which are executed by the virtual machine.
+///
+/// Every instruction is an opcode followed by its operands. `u16` operands
+/// are little-endian and builtins are encoded by their index. Jump targets
+/// and the instruction pointer are byte offsets.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Instructions(Vec<u8>);
+
+impl Instructions {
+    pub fn new(instructions: &[Instruction]) -> Self {
+        let mut bytes = vec![];
+        for instruction in instructions {
+            instruction.encode(&mut bytes);
+        }
+
+        Self(bytes)
+    }
+
+    /// Creates the instructions from bytes, which are not checked.
+    /// Use [`crate::verify`] before running them.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Decodes the instruction at the byte offset and returns it together
+    /// with its size. Returns `None` if there's no valid instruction.
+    #[inline]
+    pub fn decode(&self, offset: usize) -> Option<(Instruction, usize)> {
+        let (&opcode, operands) = self.0.get(offset..)?.split_first()?;
+        let byte = |idx: usize| operands.get(idx - 1).copied();
+        let u16 = |idx: usize| {
+            let bytes = operands.get(idx - 1..idx + 1)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+
+        let instruction = match opcode {
+            opcode::CONSTANT => Instruction::Constant(u16(1)?),
+            opcode::ADD => Instruction::Add,
+            opcode::SUB => Instruction::Sub,
+            opcode::MUL => Instruction::Mul,
+            opcode::DIV => Instruction::Div,
+            opcode::BIT_AND => Instruction::BitAnd,
+            opcode::BIT_OR => Instruction::BitOr,
+            opcode::BIT_XOR => Instruction::BitXor,
+            opcode::SHIFT_LEFT => Instruction::ShiftLeft,
+            opcode::SHIFT_RIGHT => Instruction::ShiftRight,
+            opcode::POP => Instruction::Pop,
+            opcode::NULL => Instruction::Null,
+            opcode::TRUE => Instruction::True,
+            opcode::FALSE => Instruction::False,
+            opcode::EQUAL => Instruction::Equal,
+            opcode::NOT_EQUAL => Instruction::NotEqual,
+            opcode::GREATER_THAN => Instruction::GreaterThan,
+            opcode::LESS_THAN => Instruction::LessThan,
+            opcode::MINUS => Instruction::Minus,
+            opcode::BANG => Instruction::Bang,
+            opcode::BIT_NOT => Instruction::BitNot,
+            opcode::JUMP_NOT_TRUTHY => Instruction::JumpNotTruthy(u16(1)?),
+            opcode::JUMP => Instruction::Jump(u16(1)?),
+            opcode::GET_GLOBAL => Instruction::GetGlobal(u16(1)?),
+            opcode::SET_GLOBAL => Instruction::SetGlobal(u16(1)?),
+            opcode::GET_LOCAL => Instruction::GetLocal(byte(1)?),
+            opcode::SET_LOCAL => Instruction::SetLocal(byte(1)?),
+            opcode::GET_BUILTIN => {
+                Instruction::GetBuiltin(builtin::BuiltinFunction::from_index(byte(1)?)?)
+            }
+            opcode::GET_FREE => Instruction::GetFree(byte(1)?),
+            opcode::ARRAY => Instruction::Array(u16(1)?),
+            opcode::HASH => Instruction::Hash(u16(1)?),
+            opcode::INDEX => Instruction::Index,
+            opcode::CALL => Instruction::Call(byte(1)?),
+            opcode::TAIL_CALL => Instruction::TailCall(byte(1)?),
+            opcode::RETURN_VALUE => Instruction::ReturnValue,
+            opcode::RETURN_IF_ERROR => Instruction::ReturnIfError,
+            opcode::YIELD => Instruction::Yield,
+            opcode::IMPORT => Instruction::Import(u16(1)?),
+            opcode::RETURN_MODULE => Instruction::ReturnModule,
+            opcode::CLOSURE => Instruction::Closure {
+                constant_index: u16(1)?,
+                free_variables: byte(3)?,
+            },
+            opcode::CURRENT_CLOSURE => Instruction::CurrentClosure,
+            opcode::UNQUOTE => Instruction::Unquote,
+            _ => return None,
+        };
+
+        Some((instruction, instruction.size()))
+    }
+
+    /// Returns the offsets and the instructions up to the first invalid one.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Instruction)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            let (instruction, size) = self.decode(offset)?;
+            offset += size;
+            Some((offset - size, instruction))
+        })
+    }
+}
+
+/// Writes the decoded instructions by their offsets.
+impl Debug for Instructions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Source lines of the instructions. Every entry is the byte offset of the
+/// first instruction of a run of instructions on the same line, and the line.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LineTable(Vec<(usize, usize)>);
+
+impl LineTable {
+    /// Creates the table from the instructions and the line of every instruction.
+    pub fn new(instructions: &[Instruction], lines: &[usize]) -> Self {
+        let mut entries: Vec<(usize, usize)> = vec![];
+        let mut offset = 0;
+        for (instruction, line) in instructions.iter().zip(lines) {
+            if entries.last().map(|(_, last)| last) != Some(line) {
+                entries.push((offset, *line));
+            }
+            offset += instruction.size();
+        }
+
+        Self(entries)
+    }
+
+    /// Creates the table from entries returned by [`LineTable::entries`].
+    pub fn from_entries(entries: Vec<(usize, usize)>) -> Self {
+        Self(entries)
+    }
+
+    /// Returns the offset of the first instruction and the line of every run.
+    pub fn entries(&self) -> &[(usize, usize)] {
+        &self.0
+    }
+
+    /// Returns the line of the instruction at the given byte offset,
+    /// which can point to any byte of the instruction.
+    pub fn line(&self, ip: usize) -> usize {
+        match self.0.binary_search_by_key(&ip, |(idx, _)| *idx) {
+            Ok(entry) => self.0[entry].1,
+            Err(0) => 0,
+            Err(entry) => self.0[entry - 1].1,
+        }
+    }
+}
+
+/// Compiled module, imported with the `import` statement.
+///
+/// Every module has its own globals. The instructions initialize
+/// the globals and return a hash map of the exported values.
+#[derive(Debug, PartialEq)]
+pub struct Module {
+    pub path: PathBuf,
+    pub instructions: Rc<Instructions>,
+    pub lines: Rc<LineTable>,
+    pub num_globals: usize,
+    /// Names of the global slots, which are shown by the disassembler.
+    pub global_names: Rc<Vec<String>>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Bytecode<'a> {
-    pub instructions: Rc<Vec<Instruction>>,
+    pub instructions: Rc<Instructions>,
+    pub lines: Rc<LineTable>,
     pub constants: &'a [object::Object],
+    pub modules: &'a [Module],
+    /// Names of the global slots, which are shown by the disassembler.
+    pub global_names: Rc<Vec<String>>,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::object::builtin::BuiltinFunction;
+
+    use super::{Instruction, Instructions};
+
+    #[test]
+    fn test_encoding() {
+        let instructions = [
+            Instruction::Constant(65534),
+            Instruction::Add,
+            Instruction::JumpNotTruthy(3),
+            Instruction::GetLocal(255),
+            Instruction::GetBuiltin(BuiltinFunction::Slice),
+            Instruction::Call(2),
+            Instruction::Closure {
+                constant_index: 258,
+                free_variables: 3,
+            },
+            Instruction::ReturnValue,
+        ];
+
+        let encoded = Instructions::new(&instructions);
+        assert_eq!(
+            encoded.as_bytes(),
+            [0, 254, 255, 1, 21, 3, 0, 25, 255, 27, 15, 32, 2, 39, 2, 1, 3, 34]
+        );
+
+        let decoded: Vec<_> = encoded.iter().collect();
+        assert_eq!(
+            decoded,
+            [0, 3, 4, 7, 9, 11, 13, 17]
+                .into_iter()
+                .zip(instructions)
+                .collect::<Vec<_>>()
+        );
+
+        // Operands which are cut off or out of range are not decoded.
+        assert_eq!(Instructions::from_bytes(vec![0, 1]).decode(0), None);
+        assert_eq!(Instructions::from_bytes(vec![27, 16]).decode(0), None);
+        assert_eq!(Instructions::from_bytes(vec![42]).decode(0), None);
+    }
 }
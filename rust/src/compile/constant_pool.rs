@@ -0,0 +1,124 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use num_bigint::BigInt;
+
+use crate::{
+    code::{Instructions, LineTable},
+    object::{CompiledFunction, Object},
+};
+
+/// Identifies the constants that can share an index in the pool.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Integer(i64),
+    BigInteger(Rc<BigInt>),
+    String(Rc<String>),
+    Bytes(Rc<Vec<u8>>),
+    // Functions are only shared if they also have the same lines,
+    // so that stack traces show the lines of the right literal.
+    Function {
+        instructions: Rc<Instructions>,
+        num_locals: usize,
+        num_arguments: usize,
+        generator: bool,
+        name: Option<Rc<String>>,
+        lines: Rc<LineTable>,
+        local_names: Rc<Vec<String>>,
+    },
+}
+
+impl ConstantKey {
+    fn new(obj: &Object) -> Option<Self> {
+        let key = match obj {
+            Object::Integer(value) => Self::Integer(*value),
+            Object::BigInteger(value) => Self::BigInteger(value.clone()),
+            Object::String(value) => Self::String(value.clone()),
+            Object::Bytes(value) => Self::Bytes(value.clone()),
+            Object::CompiledFunction(CompiledFunction {
+                instructions,
+                num_locals,
+                num_arguments,
+                generator,
+                name,
+                lines,
+                local_names,
+            }) => Self::Function {
+                instructions: instructions.clone(),
+                num_locals: *num_locals,
+                num_arguments: *num_arguments,
+                generator: *generator,
+                name: name.clone(),
+                lines: lines.clone(),
+                local_names: local_names.clone(),
+            },
+            _ => return None,
+        };
+
+        Some(key)
+    }
+}
+
+/// Constants of the compiled programs. Identical integers, strings, bytes
+/// and compiled functions are stored once and share their index.
+#[derive(Debug, Default)]
+pub struct ConstantPool {
+    constants: Vec<Object>,
+    indices: HashMap<ConstantKey, usize>,
+    added: usize,
+}
+
+impl ConstantPool {
+    pub fn constants(&self) -> &[Object] {
+        &self.constants
+    }
+
+    /// Returns the index of an identical constant if there's one,
+    /// otherwise the index that the constant would be added at.
+    pub fn index(&self, obj: &Object) -> usize {
+        ConstantKey::new(obj)
+            .and_then(|key| self.indices.get(&key).copied())
+            .unwrap_or(self.constants.len())
+    }
+
+    /// Adds the constant at the index returned by [`Self::index`]
+    /// unless an identical constant is already in the pool.
+    pub fn add(&mut self, obj: Object) -> usize {
+        self.added += 1;
+
+        let idx = self.index(&obj);
+        if idx == self.constants.len() {
+            if let Some(key) = ConstantKey::new(&obj) {
+                self.indices.insert(key, idx);
+            }
+            self.constants.push(obj);
+        }
+        idx
+    }
+
+    pub fn stats(&self) -> ConstantStats {
+        ConstantStats {
+            added: self.added,
+            unique: self.constants.len(),
+        }
+    }
+}
+
+/// Size of the constant pool with and without deduplication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstantStats {
+    /// Number of constants added by the compiler,
+    /// which would be the size of the pool without deduplication.
+    pub added: usize,
+    /// Number of constants in the pool.
+    pub unique: usize,
+}
+
+impl fmt::Display for ConstantStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "constants: {} added, {} after deduplication",
+            self.added, self.unique
+        )
+    }
+}
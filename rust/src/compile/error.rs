@@ -1,9 +1,46 @@
 use thiserror::Error;
 
+use crate::{module, token::Position};
+
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum Error {
     #[error("undefined symbol: {0}")]
     UndefinedSymbol(String),
+    #[error("cannot assign to const {name} at {assigned}, it is defined at {defined}")]
+    AssignToConstant {
+        name: String,
+        defined: Position,
+        assigned: Position,
+    },
+    #[error("macro literal can only be bound with a top level let statement")]
+    UnexpandedMacro,
+    #[error("unquote in the argument of unquote is only supported inside of macros")]
+    UnsupportedUnquote,
+    #[error("wrong number of arguments: expected {expected}, got {got}")]
+    WrongNumberOfArguments { expected: usize, got: usize },
+    #[error("cannot bind to {0}, it is a free variable or the enclosing function")]
+    InvalidBinding(String),
+    #[error("too many constants, the limit is 65536")]
+    TooManyConstants,
+    #[error("too many global bindings, the limit is 65536")]
+    TooManyGlobals,
+    #[error("too many local bindings in a function, the limit is 256")]
+    TooManyLocals,
+    #[error("too many arguments in a call, the limit is 255")]
+    TooManyArguments,
+    #[error("too many free variables in a function, the limit is 255")]
+    TooManyFreeVariables,
+    #[error("too many elements in an array or hash literal, the limit is 65535")]
+    TooManyElements,
+    #[error("too many instructions in a function, the limit is 65535 bytes")]
+    TooManyInstructions,
+    #[error("too many imported modules, the limit is 65536")]
+    TooManyModules,
+    #[error("module error: {source}")]
+    Module {
+        #[from]
+        source: module::Error,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
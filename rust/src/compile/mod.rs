@@ -7,26 +7,65 @@ mod test;
 
 pub mod error;
 
+mod constant_pool;
+mod optimize;
 mod symbol_table;
 
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::ast;
-use crate::code::{Bytecode, Instruction};
+use crate::ast::{self, modify};
+use crate::code::{Bytecode, Instruction, Instructions, LineTable, Module};
+use crate::module;
 use crate::object::{builtin, CompiledFunction, Object};
+use crate::token::Position;
 
+use self::constant_pool::ConstantPool;
 use self::symbol_table::{Symbol, SymbolScope, SymbolTable};
 
+pub use constant_pool::ConstantStats;
 pub use error::*;
 
+/// Instructions of a function, a module or the main program,
+/// together with the line of every instruction.
+#[derive(Default)]
+struct Scope {
+    instructions: Vec<Instruction>,
+    lines: Vec<usize>,
+    // Size of the encoded instructions in bytes.
+    size: usize,
+}
+
+impl Scope {
+    /// Returns the encoded instructions and their line table.
+    fn finish(self) -> (Rc<Instructions>, Rc<LineTable>) {
+        (
+            Rc::new(Instructions::new(&self.instructions)),
+            Rc::new(LineTable::new(&self.instructions, &self.lines)),
+        )
+    }
+}
+
 /// Compiles AST to the bytecode.
 pub struct Compiler {
-    constants: Vec<Object>,
+    constants: ConstantPool,
 
     symbol_table: SymbolTable,
 
-    scopes: Vec<Vec<Instruction>>,
+    scopes: Vec<Scope>,
     scope_index: usize,
+    // Line of the innermost expression with a position,
+    // which is recorded for the emitted instructions.
+    line: usize,
+
+    modules: Vec<Module>,
+    module_indices: HashMap<PathBuf, usize>,
+    // Chain of files that are currently being compiled.
+    // The last one is the file that imports are resolved against.
+    files: Vec<PathBuf>,
+
+    optimize: bool,
 }
 
 impl Compiler {
@@ -34,34 +73,79 @@ impl Compiler {
     /// (globals, constants, ...).
     pub fn new() -> Self {
         Self {
-            constants: vec![],
+            constants: ConstantPool::default(),
             symbol_table: SymbolTable::new(),
-            scopes: vec![vec![]],
+            scopes: vec![Scope::default()],
             scope_index: 0,
+            line: 1,
+            modules: vec![],
+            module_indices: HashMap::new(),
+            files: vec![],
+            optimize: false,
         }
     }
 
+    /// Creates a new compiler for the file at the given path.
+    /// Imports are resolved relative to this file.
+    pub fn with_path(path: &Path) -> Self {
+        let mut compiler = Self::new();
+        compiler
+            .files
+            .push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        compiler
+    }
+
+    /// Enables the optimizer, which folds constant expressions
+    /// and removes unnecessary jumps and unreachable code.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
     /// Compiles a program.
     ///
     /// The instructions part of the bytecode is overriden,
     /// but the state (globals, constants, ...) is left unchanged.
     /// If you don't want to keep the state between compilations,
     /// initialize a new compiler.
-    pub fn compile(&mut self, program: &ast::Program) -> Result<Bytecode> {
-        self.scopes = vec![vec![]];
+    pub fn compile(&mut self, program: &ast::Program) -> Result<Bytecode<'_>> {
+        self.scopes = vec![Scope::default()];
         self.scope_index = 0;
+        self.line = 1;
 
         for stmt in &program.statements {
             self.compile_statement(stmt)?;
         }
 
+        // Like in the evaluator, a program that ends with
+        // a statement without a value evaluates to null.
+        if matches!(
+            program.statements.last(),
+            Some(
+                ast::Statement::Let { .. }
+                    | ast::Statement::Import { .. }
+                    | ast::Statement::Export(_)
+            )
+        ) {
+            self.emit(Instruction::Null);
+            self.emit(Instruction::Pop);
+        }
+
         // There should only be one scope if compiler works correctly
-        let instructions = self.scopes.pop().expect("Invalid number of scopes!");
+        let scope = self.scopes.pop().expect("Invalid number of scopes!");
+        let (instructions, lines) = self.finish_scope(scope, true)?;
         Ok(Bytecode {
-            instructions: Rc::new(instructions),
-            constants: &self.constants,
+            instructions,
+            lines,
+            constants: self.constants.constants(),
+            modules: &self.modules,
+            global_names: Rc::new(self.symbol_table.slot_names()),
         })
     }
+
+    /// Returns the size of the constant pool of all compiled programs.
+    pub fn constant_stats(&self) -> ConstantStats {
+        self.constants.stats()
+    }
 }
 
 impl Default for Compiler {
@@ -71,63 +155,232 @@ impl Default for Compiler {
 }
 
 impl Compiler {
-    fn add_constant(&mut self, obj: Object) -> usize {
-        self.constants.push(obj);
-        self.constants.len() - 1
+    /// Adds the constant to the pool, or returns the index
+    /// of an identical constant that is already in the pool.
+    fn add_constant(&mut self, obj: Object) -> Result<u16> {
+        let idx = operand(self.constants.index(&obj), Error::TooManyConstants)?;
+        self.constants.add(obj);
+        Ok(idx)
+    }
+
+    /// Optimizes the scope if the optimizer is enabled and encodes it.
+    /// The main program keeps the popped values, see [`optimize::optimize`].
+    fn finish_scope(
+        &mut self,
+        mut scope: Scope,
+        main: bool,
+    ) -> Result<(Rc<Instructions>, Rc<LineTable>)> {
+        if self.optimize {
+            optimize::optimize(&mut scope, &mut self.constants, main)?;
+        }
+        Ok(scope.finish())
     }
 
     fn current_instructions(&mut self) -> &mut Vec<Instruction> {
-        &mut self.scopes[self.scope_index]
+        &mut self.scopes[self.scope_index].instructions
     }
 
     fn emit(&mut self, instruction: Instruction) -> usize {
-        self.current_instructions().push(instruction);
-        self.current_instructions().len() - 1
+        let line = self.line;
+        let scope = &mut self.scopes[self.scope_index];
+        scope.instructions.push(instruction);
+        scope.lines.push(line);
+        scope.size += instruction.size();
+        scope.instructions.len() - 1
+    }
+
+    /// Removes the last instruction if it's a `Pop`.
+    fn remove_last_pop(&mut self) {
+        let scope = &mut self.scopes[self.scope_index];
+        if scope.instructions.last() == Some(&Instruction::Pop) {
+            scope.instructions.pop();
+            scope.lines.pop();
+            scope.size -= Instruction::Pop.size();
+        }
     }
 
     fn enter_scope(&mut self) {
-        self.scopes.push(vec![]);
+        self.scopes.push(Scope::default());
         self.scope_index += 1;
 
         self.symbol_table.enclose();
     }
 
-    fn leave_scope(&mut self) -> (Vec<Instruction>, Vec<Symbol>) {
+    fn leave_scope(&mut self) -> (Scope, Vec<Symbol>) {
         self.scope_index -= 1;
-        let instructions = self.scopes.pop().unwrap_or_default();
+        let scope = self.scopes.pop().unwrap_or_default();
 
         let free_symbols = self.symbol_table.leave();
 
-        (instructions, free_symbols)
+        (scope, free_symbols)
+    }
+
+    /// Compiles the expression with the line of its position, if it has one.
+    fn at_line(
+        &mut self,
+        expression: &ast::Expression,
+        compile: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let outer_line = self.line;
+        if let Some(position) = expression.position() {
+            self.line = position.line;
+        }
+
+        let res = compile(self);
+        self.line = outer_line;
+
+        res
     }
 
     fn compile_statement(&mut self, statement: &ast::Statement) -> Result<()> {
         match statement {
-            ast::Statement::Let { name, value } => {
-                let symbol = self.symbol_table.define(name.clone());
+            ast::Statement::Let {
+                name,
+                value,
+                constant,
+                position,
+                ..
+            } => {
+                if let Some(defined) = self.symbol_table.constant(name) {
+                    return Err(Error::AssignToConstant {
+                        name: name.clone(),
+                        defined,
+                        assigned: *position,
+                    });
+                }
 
+                // New globals are defined before the value is compiled, so that
+                // closures in the value can refer to the binding. The virtual machine
+                // fails if the global is read before it's set. Everything else
+                // is defined after, so that the value refers to the shadowed binding
+                // instead of a fresh slot, or a local slot reused from a finished block.
+                let global = (self.scope_index == 0 && self.symbol_table.resolve(name).is_none())
+                    .then(|| self.define_binding(name, *constant, *position));
+
+                let outer_line = std::mem::replace(&mut self.line, position.line);
                 self.compile_expression(value)?;
-                match symbol.scope {
-                    SymbolScope::Global => self.emit(Instruction::SetGlobal(symbol.index)),
-                    SymbolScope::Local => self.emit(Instruction::SetLocal(symbol.index as u8)),
-                    SymbolScope::Free => panic!("cannot bind to free variable"),
-                    SymbolScope::Function => panic!("cannot bind to function scope"),
-                };
-            }
-            ast::Statement::Return(expr) => {
-                self.compile_expression(expr)?;
-                self.emit(Instruction::ReturnValue);
+
+                let symbol =
+                    global.unwrap_or_else(|| self.define_binding(name, *constant, *position));
+                self.store_symbol(name, symbol)?;
+                self.line = outer_line;
             }
-            ast::Statement::Expression(expr) => {
-                self.compile_expression(expr)?;
-                self.emit(Instruction::Pop);
+            // The instructions after the expression get the line of the statement.
+            ast::Statement::Return(expr) => self.at_line(expr, |compiler| {
+                // Return from the main program is not a tail call,
+                // since there is no frame to reuse.
+                if compiler.scope_index > 0 {
+                    compiler.compile_tail_expression(expr)?;
+                } else {
+                    compiler.compile_expression(expr)?;
+                }
+                compiler.emit(Instruction::ReturnValue);
+                Ok(())
+            })?,
+            ast::Statement::Yield(expr) => self.at_line(expr, |compiler| {
+                compiler.compile_expression(expr)?;
+                compiler.emit(Instruction::Yield);
+                Ok(())
+            })?,
+            ast::Statement::Expression(expr) => self.at_line(expr, |compiler| {
+                compiler.compile_expression(expr)?;
+                compiler.emit(Instruction::Pop);
+                Ok(())
+            })?,
+            ast::Statement::Import { path, name } => {
+                let module_index = self.compile_module(path)?;
+                let symbol = self.symbol_table.define(name.clone());
+
+                let module_index = operand(module_index, Error::TooManyModules)?;
+                self.emit(Instruction::Import(module_index));
+                self.store_symbol(name, symbol)?;
             }
+            // Exports are collected when the module is compiled.
+            ast::Statement::Export(_) => (),
         }
 
         Ok(())
     }
 
+    /// Compiles the imported module, if it wasn't compiled yet,
+    /// and returns its index.
+    fn compile_module(&mut self, path: &str) -> Result<usize> {
+        let path = module::resolve(path, self.files.last().map(PathBuf::as_path))?;
+        module::check_cycle(&self.files, &path)?;
+
+        if let Some(index) = self.module_indices.get(&path) {
+            return Ok(*index);
+        }
+
+        let program = module::load(&path)?;
+
+        // Every module is compiled with its own root symbol table,
+        // so that it has its own global namespace.
+        let symbol_table = std::mem::take(&mut self.symbol_table);
+        let scopes = std::mem::replace(&mut self.scopes, vec![Scope::default()]);
+        let scope_index = std::mem::replace(&mut self.scope_index, 0);
+        let line = std::mem::replace(&mut self.line, 1);
+        self.files.push(path.clone());
+
+        let res = self.compile_module_body(&program);
+
+        self.files.pop();
+        self.symbol_table = symbol_table;
+        self.scopes = scopes;
+        self.scope_index = scope_index;
+        self.line = line;
+
+        let (scope, symbol_table) = res?;
+        let (instructions, lines) = self.finish_scope(scope, false)?;
+        self.modules.push(Module {
+            path: path.clone(),
+            instructions,
+            lines,
+            num_globals: symbol_table.num_definitions(),
+            global_names: Rc::new(symbol_table.slot_names()),
+        });
+
+        let index = self.modules.len() - 1;
+        self.module_indices.insert(path, index);
+        Ok(index)
+    }
+
+    /// Compiles the module and returns its instructions and symbol table.
+    fn compile_module_body(&mut self, program: &ast::Program) -> Result<(Scope, SymbolTable)> {
+        for stmt in &program.statements {
+            self.compile_statement(stmt)?;
+        }
+
+        let mut num_exports = 0;
+        for name in module::exports(program) {
+            let symbol = self
+                .symbol_table
+                .resolve(name)
+                .ok_or_else(|| Error::UndefinedSymbol(name.clone()))?;
+
+            let const_idx = self.add_constant(Object::String(Rc::new(name.clone())))?;
+            self.emit(Instruction::Constant(const_idx));
+            self.load_symbol(symbol)?;
+            num_exports += 1;
+        }
+
+        self.emit(Instruction::Hash(operand(
+            num_exports * 2,
+            Error::TooManyElements,
+        )?));
+        self.emit(Instruction::ReturnModule);
+
+        let scope = self.scopes.pop().expect("Invalid number of scopes!");
+        Ok((scope, std::mem::take(&mut self.symbol_table)))
+    }
+
     fn compile_expression(&mut self, expression: &ast::Expression) -> Result<()> {
+        self.at_line(expression, |compiler| {
+            compiler.compile_expression_node(expression)
+        })
+    }
+
+    fn compile_expression_node(&mut self, expression: &ast::Expression) -> Result<()> {
         match expression {
             ast::Expression::Identifier(ident) => {
                 let symbol = self.symbol_table.resolve(ident);
@@ -142,8 +395,12 @@ impl Compiler {
                 }
             }
             ast::Expression::IntegerLiteral(val) => {
-                let const_idx = self.add_constant(Object::Integer(*val));
-                self.emit(Instruction::Constant(const_idx as u16));
+                let const_idx = self.add_constant(Object::Integer(*val))?;
+                self.emit(Instruction::Constant(const_idx));
+            }
+            ast::Expression::BigIntegerLiteral(val) => {
+                let const_idx = self.add_constant(Object::BigInteger(Rc::new(val.clone())))?;
+                self.emit(Instruction::Constant(const_idx));
             }
             ast::Expression::BooleanLiteral(val) => {
                 if *val {
@@ -153,15 +410,22 @@ impl Compiler {
                 }
             }
             ast::Expression::StringLiteral(string) => {
-                let const_idx = self.add_constant(Object::String(Rc::new(string.clone())));
-                self.emit(Instruction::Constant(const_idx as u16));
+                let const_idx = self.add_constant(Object::String(Rc::new(string.clone())))?;
+                self.emit(Instruction::Constant(const_idx));
+            }
+            ast::Expression::BytesLiteral(bytes) => {
+                let const_idx = self.add_constant(Object::Bytes(Rc::new(bytes.clone())))?;
+                self.emit(Instruction::Constant(const_idx));
             }
             ast::Expression::ArrayLiteral(arr) => {
                 for expr in arr {
                     self.compile_expression(expr)?;
                 }
 
-                self.emit(Instruction::Array(arr.len() as u16));
+                self.emit(Instruction::Array(operand(
+                    arr.len(),
+                    Error::TooManyElements,
+                )?));
             }
             ast::Expression::HashLiteral(hash) => {
                 for pair in hash {
@@ -169,56 +433,167 @@ impl Compiler {
                     self.compile_expression(&pair.value)?;
                 }
 
-                let length = (hash.len() * 2) as u16;
+                let length = operand(hash.len() * 2, Error::TooManyElements)?;
                 self.emit(Instruction::Hash(length));
             }
-            ast::Expression::PrefixOperator { operator, right } => {
+            ast::Expression::PrefixOperator {
+                operator, right, ..
+            } => {
                 self.compile_expression(right)?;
                 match operator {
                     ast::PrefixOperatorKind::Not => self.emit(Instruction::Bang),
                     ast::PrefixOperatorKind::Negative => self.emit(Instruction::Minus),
+                    ast::PrefixOperatorKind::BitNot => self.emit(Instruction::BitNot),
                 };
             }
             ast::Expression::InfixOperator { .. } => self.compile_infix_operator(expression)?,
-            ast::Expression::If { .. } => self.compile_conditional(expression)?,
+            ast::Expression::If { .. } => self.compile_conditional(expression, false)?,
+            ast::Expression::Block(block) => self.compile_block_expression(block, false)?,
             ast::Expression::FunctionLiteral { .. } => self.compile_function_literal(expression)?,
-            ast::Expression::FunctionCall {
-                function,
-                arguments,
-            } => {
-                self.compile_expression(function)?;
-
-                for arg in arguments {
-                    self.compile_expression(arg)?;
-                }
-
-                self.emit(Instruction::Call(arguments.len() as u8));
+            ast::Expression::MacroLiteral { .. } => return Err(Error::UnexpandedMacro),
+            ast::Expression::FunctionCall { .. }
+                if expression.call_arguments("quote").is_some() =>
+            {
+                self.compile_quote(expression)?
             }
-            ast::Expression::Index { left, index } => {
+            ast::Expression::FunctionCall { .. } => self.compile_call(expression, false)?,
+            ast::Expression::Index { left, index, .. } => {
                 self.compile_expression(left)?;
                 self.compile_expression(index)?;
 
                 self.emit(Instruction::Index);
             }
+            ast::Expression::Try(expr) => {
+                self.compile_expression(expr)?;
+                self.emit(Instruction::ReturnIfError);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles `quote(expr)` to a constant. The arguments of `unquote` calls
+    /// in the quote are compiled in the order that the evaluator evaluates
+    /// them, and `Unquote` replaces the calls with their values.
+    fn compile_quote(&mut self, expression: &ast::Expression) -> Result<()> {
+        let Some(arguments) = expression.call_arguments("quote") else {
+            panic!("Expected quote call, got: {:?}", expression);
+        };
+
+        let [argument] = arguments else {
+            return Err(Error::WrongNumberOfArguments {
+                expected: 1,
+                got: arguments.len(),
+            });
+        };
+
+        let mut unquoted = vec![];
+        modify::modify_expression(argument.clone(), &mut |expr| {
+            if let Some(arguments) = expr.call_arguments("unquote") {
+                let [argument] = arguments else {
+                    return Err(Error::WrongNumberOfArguments {
+                        expected: 1,
+                        got: arguments.len(),
+                    });
+                };
+                // The evaluator replaces the inner calls before the argument is
+                // evaluated, which requires evaluation during the compilation.
+                modify::modify_expression(argument.clone(), &mut |expr| match expr
+                    .call_arguments("unquote")
+                {
+                    Some(_) => Err(Error::UnsupportedUnquote),
+                    None => Ok(expr),
+                })?;
+                unquoted.push(argument.clone());
+            }
+            Ok(expr)
+        })?;
+
+        let const_idx = self.add_constant(Object::Quote(Rc::new(argument.clone())))?;
+        self.emit(Instruction::Constant(const_idx));
+        for argument in &unquoted {
+            self.compile_expression(argument)?;
+            self.emit(Instruction::Unquote);
+        }
+
+        Ok(())
+    }
+
+    /// Compiles expression in a tail position of a function,
+    /// where calls can reuse the frame of the current function.
+    fn compile_tail_expression(&mut self, expression: &ast::Expression) -> Result<()> {
+        match expression {
+            ast::Expression::FunctionCall { .. }
+                if expression.call_arguments("quote").is_none() =>
+            {
+                self.at_line(expression, |compiler| {
+                    compiler.compile_call(expression, true)
+                })
+            }
+            ast::Expression::If { .. } => self.at_line(expression, |compiler| {
+                compiler.compile_conditional(expression, true)
+            }),
+            ast::Expression::Block(block) => self.compile_block_expression(block, true),
+            _ => self.compile_expression(expression),
+        }
+    }
+
+    fn compile_call(&mut self, expression: &ast::Expression, tail: bool) -> Result<()> {
+        let ast::Expression::FunctionCall {
+            function,
+            arguments,
+            ..
+        } = expression
+        else {
+            panic!("Expected FunctionCall expression, got: {:?}", expression);
+        };
+
+        self.compile_expression(function)?;
+
+        for arg in arguments {
+            self.compile_expression(arg)?;
+        }
+
+        let num_args = operand(arguments.len(), Error::TooManyArguments)?;
+        if tail {
+            self.emit(Instruction::TailCall(num_args));
+        } else {
+            self.emit(Instruction::Call(num_args));
         }
 
         Ok(())
     }
 
-    fn compile_block_statement(&mut self, statement: &ast::BlockStatement) -> Result<()> {
-        if statement.statements.len() == 0 {
+    /// Compiles the block statement. If the block is in a tail position,
+    /// the last expression statement is compiled as a tail expression.
+    fn compile_block_statement(
+        &mut self,
+        statement: &ast::BlockStatement,
+        tail: bool,
+    ) -> Result<()> {
+        if statement.statements.is_empty() {
             self.emit(Instruction::Null);
             self.emit(Instruction::Pop);
             return Ok(());
         }
 
-        for stmt in statement.statements.iter() {
-            self.compile_statement(stmt)?;
+        let last_idx = statement.statements.len() - 1;
+        for (idx, stmt) in statement.statements.iter().enumerate() {
+            match stmt {
+                ast::Statement::Expression(expr) if tail && idx == last_idx => {
+                    self.at_line(expr, |compiler| {
+                        compiler.compile_tail_expression(expr)?;
+                        compiler.emit(Instruction::Pop);
+                        Ok(())
+                    })?;
+                }
+                _ => self.compile_statement(stmt)?,
+            }
         }
 
         if matches!(
             statement.statements.last(),
-            Some(ast::Statement::Let { .. })
+            Some(ast::Statement::Let { .. } | ast::Statement::Yield(_))
         ) {
             self.emit(Instruction::Null);
             self.emit(Instruction::Pop);
@@ -227,25 +602,34 @@ impl Compiler {
         Ok(())
     }
 
+    /// Compiles the block in its own scope, so that
+    /// the names defined in it end with the block.
+    fn compile_scoped_block(&mut self, block: &ast::BlockStatement, tail: bool) -> Result<()> {
+        self.symbol_table.enter_block();
+        let res = self.compile_block_statement(block, tail);
+        self.symbol_table.leave_block();
+
+        res
+    }
+
+    fn compile_block_expression(&mut self, block: &ast::BlockStatement, tail: bool) -> Result<()> {
+        self.compile_scoped_block(block, tail)?;
+        self.remove_last_pop();
+
+        Ok(())
+    }
+
     fn compile_infix_operator(&mut self, expression: &ast::Expression) -> Result<()> {
         let ast::Expression::InfixOperator {
             operator,
             left,
             right,
+            ..
         } = expression
         else {
             panic!("Expected InfixOperator expression, got: {:?}", expression);
         };
 
-        // Handle LessThan as special case, since VM supports only
-        // GreaterThan instruction.
-        if *operator == ast::InfixOperatorKind::LessThan {
-            self.compile_expression(right)?;
-            self.compile_expression(left)?;
-            self.emit(Instruction::GreaterThan);
-            return Ok(());
-        }
-
         self.compile_expression(left)?;
         self.compile_expression(right)?;
 
@@ -257,17 +641,23 @@ impl Compiler {
             ast::InfixOperatorKind::Equal => self.emit(Instruction::Equal),
             ast::InfixOperatorKind::NotEqual => self.emit(Instruction::NotEqual),
             ast::InfixOperatorKind::GreaterThan => self.emit(Instruction::GreaterThan),
-            ast::InfixOperatorKind::LessThan => unreachable!(),
+            ast::InfixOperatorKind::LessThan => self.emit(Instruction::LessThan),
+            ast::InfixOperatorKind::BitAnd => self.emit(Instruction::BitAnd),
+            ast::InfixOperatorKind::BitOr => self.emit(Instruction::BitOr),
+            ast::InfixOperatorKind::BitXor => self.emit(Instruction::BitXor),
+            ast::InfixOperatorKind::ShiftLeft => self.emit(Instruction::ShiftLeft),
+            ast::InfixOperatorKind::ShiftRight => self.emit(Instruction::ShiftRight),
         };
 
         Ok(())
     }
 
-    fn compile_conditional(&mut self, expression: &ast::Expression) -> Result<()> {
+    fn compile_conditional(&mut self, expression: &ast::Expression, tail: bool) -> Result<()> {
         let ast::Expression::If {
             condition,
             consequence,
             alternative,
+            ..
         } = expression
         else {
             panic!("Expected If expression, got: {:?}", expression);
@@ -278,24 +668,20 @@ impl Compiler {
         // Dummy value, which we will change later
         let jump_not_truthy_pos = self.emit(Instruction::JumpNotTruthy(0));
 
-        self.compile_block_statement(consequence)?;
-        if self.current_instructions().last() == Some(&Instruction::Pop) {
-            self.current_instructions().pop();
-        }
+        self.compile_scoped_block(consequence, tail)?;
+        self.remove_last_pop();
 
         // Dummy value, which we will change later
         let jump_pos = self.emit(Instruction::Jump(0));
 
-        let after_consequence_pos = self.current_instructions().len() as u16;
+        let after_consequence_pos = self.jump_target()?;
         self.current_instructions()[jump_not_truthy_pos] =
             Instruction::JumpNotTruthy(after_consequence_pos);
 
-        self.compile_block_statement(alternative)?;
-        if self.current_instructions().last() == Some(&Instruction::Pop) {
-            self.current_instructions().pop();
-        }
+        self.compile_scoped_block(alternative, tail)?;
+        self.remove_last_pop();
 
-        let after_alternative_pos = self.current_instructions().len() as u16;
+        let after_alternative_pos = self.jump_target()?;
         self.current_instructions()[jump_pos] = Instruction::Jump(after_alternative_pos);
 
         Ok(())
@@ -306,6 +692,7 @@ impl Compiler {
             name,
             parameters,
             body,
+            ..
         } = expression
         else {
             panic!("Expected FunctionLiteral, got: {:?}", expression);
@@ -321,42 +708,102 @@ impl Compiler {
             self.symbol_table.define(par.clone());
         }
 
-        self.compile_block_statement(body)?;
+        self.compile_block_statement(body, true)?;
         if self.current_instructions().last() == Some(&Instruction::Pop) {
             let idx = self.current_instructions().len() - 1;
             self.current_instructions()[idx] = Instruction::ReturnValue;
         }
 
         let num_locals = self.symbol_table.num_definitions();
-        let (instructions, free_symbols) = self.leave_scope();
+        let local_names = self.symbol_table.slot_names();
+        let (scope, free_symbols) = self.leave_scope();
+        if num_locals > u8::MAX as usize + 1 {
+            return Err(Error::TooManyLocals);
+        }
+        let free_variables = operand(free_symbols.len(), Error::TooManyFreeVariables)?;
 
         for symbol in &free_symbols {
             self.load_symbol(*symbol)?;
         }
 
+        let (instructions, lines) = self.finish_scope(scope, false)?;
         let compiled_fn = Object::CompiledFunction(CompiledFunction {
-            instructions: Rc::new(instructions),
+            instructions,
             num_locals,
             num_arguments: parameters.len(),
+            generator: body.contains_yield(),
+            name: name.clone().map(Rc::new),
+            lines,
+            local_names: Rc::new(local_names),
         });
-        let constant_idx = self.add_constant(compiled_fn);
+        let constant_index = self.add_constant(compiled_fn)?;
 
         self.emit(Instruction::Closure {
-            constant_index: constant_idx as u16,
-            free_variables: free_symbols.len() as u8,
+            constant_index,
+            free_variables,
         });
 
         Ok(())
     }
 
+    fn define_binding(&mut self, name: &str, constant: bool, position: Position) -> Symbol {
+        if constant {
+            self.symbol_table
+                .define_constant(name.to_string(), position)
+        } else {
+            self.symbol_table.define(name.to_string())
+        }
+    }
+
+    fn store_symbol(&mut self, name: &str, symbol: Symbol) -> Result<()> {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Instruction::SetGlobal(operand(
+                symbol.index,
+                Error::TooManyGlobals,
+            )?)),
+            SymbolScope::Local => self.emit(Instruction::SetLocal(operand(
+                symbol.index,
+                Error::TooManyLocals,
+            )?)),
+            SymbolScope::Free | SymbolScope::Function => {
+                return Err(Error::InvalidBinding(name.to_owned()))
+            }
+        };
+
+        Ok(())
+    }
+
     fn load_symbol(&mut self, symbol: Symbol) -> Result<()> {
         match symbol.scope {
-            SymbolScope::Global => self.emit(Instruction::GetGlobal(symbol.index)),
-            SymbolScope::Local => self.emit(Instruction::GetLocal(symbol.index as u8)),
-            SymbolScope::Free => self.emit(Instruction::GetFree(symbol.index as u8)),
+            SymbolScope::Global => self.emit(Instruction::GetGlobal(operand(
+                symbol.index,
+                Error::TooManyGlobals,
+            )?)),
+            SymbolScope::Local => self.emit(Instruction::GetLocal(operand(
+                symbol.index,
+                Error::TooManyLocals,
+            )?)),
+            SymbolScope::Free => self.emit(Instruction::GetFree(operand(
+                symbol.index,
+                Error::TooManyFreeVariables,
+            )?)),
             SymbolScope::Function => self.emit(Instruction::CurrentClosure),
         };
 
         Ok(())
     }
+
+    /// Returns the byte offset of the next instruction as a jump target.
+    fn jump_target(&mut self) -> Result<u16> {
+        operand(
+            self.scopes[self.scope_index].size,
+            Error::TooManyInstructions,
+        )
+    }
+}
+
+/// Converts the operand of an instruction, which fails with the given
+/// error if the operand doesn't fit into the instruction.
+fn operand<T: TryFrom<usize>>(value: usize, err: Error) -> Result<T> {
+    T::try_from(value).map_err(|_| err)
 }
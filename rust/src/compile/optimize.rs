@@ -0,0 +1,356 @@
+//! Peephole optimizer, which rewrites the instructions of a scope
+//! before they are encoded.
+//!
+//! The optimizer repeats the following passes until nothing changes:
+//!
+//! - jumps to a `Jump` are redirected to its target,
+//! - unreachable instructions and jumps to the next instruction are removed,
+//! - operators with constant operands are replaced by their result and
+//!   `JumpNotTruthy` with a constant condition by a `Jump` or nothing,
+//! - values that are immediately popped are removed.
+//!
+//! Instructions are only combined if the ones after the first aren't jump
+//! targets, so every jump still finds the same values on the stack.
+//!
+//! Folded values are only added to the constant pool once the scope is
+//! optimized, so intermediate results of folding don't end up in the pool.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{code::Instruction, object::Object, vm};
+
+use super::{constant_pool::ConstantPool, operand, Error, Result, Scope};
+
+/// Optimizes the instructions of the scope. If `keep_popped` is set,
+/// values followed by `Pop` are kept, because the result
+/// of the main program is the last popped value.
+pub(super) fn optimize(
+    scope: &mut Scope,
+    constants: &mut ConstantPool,
+    keep_popped: bool,
+) -> Result<()> {
+    let mut code: Vec<_> = scope
+        .instructions
+        .iter()
+        .copied()
+        .zip(scope.lines.iter().copied())
+        .collect();
+
+    // Jump targets are instruction indices while the code is optimized.
+    let mut offsets = vec![];
+    let mut offset = 0;
+    for (instruction, _) in &code {
+        offsets.push(offset);
+        offset += instruction.size();
+    }
+    offsets.push(offset);
+    map_targets(&mut code, |target| {
+        offsets
+            .binary_search(&target)
+            .expect("jump to the start of an instruction")
+    })?;
+
+    let mut optimizer = Optimizer {
+        constants,
+        folded: vec![],
+        keep_popped,
+    };
+    while optimizer.pass(&mut code)? {}
+    optimizer.add_folded(&mut code)?;
+
+    let mut offsets = vec![];
+    let mut offset = 0;
+    for (instruction, _) in &code {
+        offsets.push(offset);
+        offset += instruction.size();
+    }
+    offsets.push(offset);
+    map_targets(&mut code, |target| offsets[target])?;
+
+    (scope.instructions, scope.lines) = code.into_iter().unzip();
+    scope.size = offset;
+    Ok(())
+}
+
+/// Replaces every jump target with the result of `f`. Fails if a new
+/// target doesn't fit into the operand.
+fn map_targets(code: &mut [(Instruction, usize)], f: impl Fn(usize) -> usize) -> Result<()> {
+    for (instruction, _) in code {
+        match instruction {
+            Instruction::Jump(target) | Instruction::JumpNotTruthy(target) => {
+                *target = operand(f(*target as usize), Error::TooManyInstructions)?
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+struct Optimizer<'a> {
+    constants: &'a mut ConstantPool,
+    // Values produced by folding, which `Constant` instructions refer to
+    // with the indices after the end of the constant pool.
+    folded: Vec<Object>,
+    keep_popped: bool,
+}
+
+impl Optimizer<'_> {
+    /// Optimizes the code once and returns whether it changed.
+    fn pass(&mut self, code: &mut Vec<(Instruction, usize)>) -> Result<bool> {
+        let mut changed = thread_jumps(code);
+
+        let reachable = reachable(code);
+        let targets: HashSet<_> = code
+            .iter()
+            .zip(&reachable)
+            .filter(|(_, reachable)| **reachable)
+            .filter_map(|((instruction, _), _)| match instruction {
+                Instruction::Jump(target) | Instruction::JumpNotTruthy(target) => {
+                    Some(*target as usize)
+                }
+                _ => None,
+            })
+            .collect();
+
+        // Indices of the optimized instructions that are jump targets. If an instruction
+        // is removed, the one that takes its place becomes the target.
+        let mut optimized_targets = HashSet::new();
+        let mut optimized = vec![];
+        let mut new_indices = Vec::with_capacity(code.len() + 1);
+
+        for (idx, &(instruction, line)) in code.iter().enumerate() {
+            new_indices.push(optimized.len());
+            if targets.contains(&idx) {
+                optimized_targets.insert(optimized.len());
+            }
+
+            let jumps_to_next =
+                matches!(instruction, Instruction::Jump(target) if target as usize == idx + 1);
+            if !reachable[idx] || jumps_to_next {
+                changed = true;
+                continue;
+            }
+
+            optimized.push((instruction, line));
+            while self.reduce(&mut optimized, &optimized_targets) {
+                changed = true;
+            }
+        }
+        new_indices.push(optimized.len());
+
+        map_targets(&mut optimized, |target| new_indices[target])?;
+        *code = optimized;
+
+        Ok(changed)
+    }
+
+    /// Combines the last instruction with the ones before it.
+    /// Returns whether the code changed.
+    fn reduce(&mut self, code: &mut Vec<(Instruction, usize)>, targets: &HashSet<usize>) -> bool {
+        let Some(&(last, _)) = code.last() else {
+            return false;
+        };
+        let len = code.len();
+        let can_combine = |count: usize| {
+            len >= count && (len - count + 1..len).all(|idx| !targets.contains(&idx))
+        };
+        let value = |idx: usize| self.value(code[idx].0);
+
+        match last {
+            operator @ (Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::BitAnd
+            | Instruction::BitOr
+            | Instruction::BitXor
+            | Instruction::ShiftLeft
+            | Instruction::ShiftRight
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::GreaterThan
+            | Instruction::LessThan)
+                if can_combine(3) =>
+            {
+                let (Some(left), Some(right)) = (value(len - 3), value(len - 2)) else {
+                    return false;
+                };
+                // Operations that fail are left for the virtual machine to report.
+                let Ok(result) = vm::binary_operation(operator, &left, &right) else {
+                    return false;
+                };
+                let Some(instruction) = self.push(result) else {
+                    return false;
+                };
+
+                code.truncate(len - 2);
+                code[len - 3].0 = instruction;
+                true
+            }
+            operator @ (Instruction::Bang | Instruction::Minus | Instruction::BitNot)
+                if can_combine(2) =>
+            {
+                let Some(operand) = value(len - 2) else {
+                    return false;
+                };
+                let Ok(result) = vm::prefix_operation(operator, &operand) else {
+                    return false;
+                };
+                let Some(instruction) = self.push(result) else {
+                    return false;
+                };
+
+                code.truncate(len - 1);
+                code[len - 2].0 = instruction;
+                true
+            }
+            Instruction::JumpNotTruthy(target) if can_combine(2) => {
+                let Some(condition) = value(len - 2) else {
+                    return false;
+                };
+
+                if condition.is_truthy() {
+                    code.truncate(len - 2);
+                } else {
+                    code.truncate(len - 1);
+                    code[len - 2].0 = Instruction::Jump(target);
+                }
+                true
+            }
+            Instruction::Pop if !self.keep_popped && can_combine(2) && value(len - 2).is_some() => {
+                code.truncate(len - 2);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the value that the instruction pushes if it's a constant.
+    fn value(&self, instruction: Instruction) -> Option<Object> {
+        match instruction {
+            Instruction::Constant(idx) => {
+                let constants = self.constants.constants();
+                let idx = idx as usize;
+                match idx.checked_sub(constants.len()) {
+                    Some(folded) => Some(self.folded[folded].clone()),
+                    None => Some(constants[idx].clone()),
+                }
+            }
+            Instruction::True => Some(Object::Boolean(true)),
+            Instruction::False => Some(Object::Boolean(false)),
+            Instruction::Null => Some(Object::Null),
+            _ => None,
+        }
+    }
+
+    /// Returns the instruction that pushes the value, which refers to an
+    /// identical constant or a new folded value. Returns `None` if the
+    /// index doesn't fit into the operand.
+    fn push(&mut self, value: Object) -> Option<Instruction> {
+        match value {
+            Object::Boolean(true) => Some(Instruction::True),
+            Object::Boolean(false) => Some(Instruction::False),
+            Object::Null => Some(Instruction::Null),
+            value => {
+                let len = self.constants.constants().len();
+                let idx = match self.constants.index(&value) {
+                    idx if idx < len => idx,
+                    _ => len + self.folded.len(),
+                };
+                let idx = u16::try_from(idx).ok()?;
+                if idx as usize >= len {
+                    self.folded.push(value);
+                }
+                Some(Instruction::Constant(idx))
+            }
+        }
+    }
+
+    /// Adds the folded values that are still used to the constant pool
+    /// and makes the instructions refer to them.
+    fn add_folded(&mut self, code: &mut [(Instruction, usize)]) -> Result<()> {
+        let len = self.constants.constants().len();
+        let mut indices = HashMap::new();
+
+        for (instruction, _) in code {
+            let Instruction::Constant(idx) = instruction else {
+                continue;
+            };
+            let Some(folded) = (*idx as usize).checked_sub(len) else {
+                continue;
+            };
+
+            *idx = match indices.get(&folded) {
+                Some(&new_idx) => new_idx,
+                None => {
+                    let value = self.folded[folded].clone();
+                    let new_idx = operand(self.constants.add(value), Error::TooManyConstants)?;
+                    indices.insert(folded, new_idx);
+                    new_idx
+                }
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Redirects jumps to a `Jump` to its target. Returns whether the code changed.
+fn thread_jumps(code: &mut [(Instruction, usize)]) -> bool {
+    let mut changed = false;
+
+    for idx in 0..code.len() {
+        let (Instruction::Jump(target) | Instruction::JumpNotTruthy(target)) = code[idx].0 else {
+            continue;
+        };
+
+        let mut visited = HashSet::new();
+        let mut next = target;
+        while let Some((Instruction::Jump(after), _)) = code.get(next as usize) {
+            // Jumps in a cycle never leave it, so they are left alone.
+            if !visited.insert(next) {
+                next = target;
+                break;
+            }
+            next = *after;
+        }
+
+        if next != target {
+            match &mut code[idx].0 {
+                Instruction::Jump(target) | Instruction::JumpNotTruthy(target) => *target = next,
+                _ => unreachable!(),
+            }
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+/// Returns which instructions can be reached from the start of the code.
+fn reachable(code: &[(Instruction, usize)]) -> Vec<bool> {
+    let mut reachable = vec![false; code.len() + 1];
+    let mut pending = vec![0];
+
+    while let Some(idx) = pending.pop() {
+        if reachable[idx] {
+            continue;
+        }
+        reachable[idx] = true;
+        if idx == code.len() {
+            continue;
+        }
+
+        match code[idx].0 {
+            Instruction::Jump(target) => pending.push(target as usize),
+            Instruction::JumpNotTruthy(target) => {
+                pending.push(target as usize);
+                pending.push(idx + 1);
+            }
+            Instruction::ReturnValue | Instruction::ReturnModule => (),
+            _ => pending.push(idx + 1),
+        }
+    }
+
+    reachable
+}
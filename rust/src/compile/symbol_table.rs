@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use crate::token::Position;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymbolScope {
     Global,
@@ -11,7 +13,18 @@ pub enum SymbolScope {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Symbol {
     pub scope: SymbolScope,
-    pub index: u16,
+    pub index: usize,
+    /// Position of the definition, if the symbol is a constant.
+    pub constant: Option<Position>,
+}
+
+/// Block inside of a function or the program. Names defined
+/// in the block are removed from the store at the end of the block.
+#[derive(Debug, Clone)]
+struct BlockScope {
+    // Symbols shadowed by the definitions in the block.
+    shadowed: HashMap<String, Option<Symbol>>,
+    next_index: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -19,7 +32,14 @@ pub struct SymbolTable {
     outer: Option<Box<SymbolTable>>,
     store: HashMap<String, Symbol>,
     free_symbols: Vec<Symbol>,
-    num_definitions: u16,
+    blocks: Vec<BlockScope>,
+    // Index of the next definition. Indices of locals
+    // are reused once the block defining them ends.
+    next_index: usize,
+    // Maximum number of definitions alive at the same time.
+    num_definitions: usize,
+    // Names defined in every slot, in the order of their definition.
+    slot_names: Vec<Vec<String>>,
 }
 
 impl SymbolTable {
@@ -28,7 +48,10 @@ impl SymbolTable {
             outer: None,
             store: HashMap::new(),
             free_symbols: vec![],
+            blocks: vec![],
+            next_index: 0,
             num_definitions: 0,
+            slot_names: vec![],
         }
     }
 
@@ -51,7 +74,41 @@ impl SymbolTable {
         }
     }
 
+    pub fn enter_block(&mut self) {
+        self.blocks.push(BlockScope {
+            shadowed: HashMap::new(),
+            next_index: self.next_index,
+        });
+    }
+
+    /// Removes the names defined in the block. Slots of locals are freed, but
+    /// globals keep their slots, since closures refer to globals by index.
+    pub fn leave_block(&mut self) {
+        let Some(block) = self.blocks.pop() else {
+            return;
+        };
+
+        for (name, symbol) in block.shadowed {
+            match symbol {
+                Some(symbol) => self.store.insert(name, symbol),
+                None => self.store.remove(&name),
+            };
+        }
+
+        if self.outer.is_some() {
+            self.next_index = block.next_index;
+        }
+    }
+
     pub fn define(&mut self, name: String) -> Symbol {
+        self.define_symbol(name, None)
+    }
+
+    pub fn define_constant(&mut self, name: String, position: Position) -> Symbol {
+        self.define_symbol(name, Some(position))
+    }
+
+    fn define_symbol(&mut self, name: String, constant: Option<Position>) -> Symbol {
         let scope = match self.outer {
             None => SymbolScope::Global,
             Some(_) => SymbolScope::Local,
@@ -59,11 +116,25 @@ impl SymbolTable {
 
         let symbol = Symbol {
             scope,
-            index: self.num_definitions,
+            index: self.next_index,
+            constant,
         };
 
+        if let Some(block) = self.blocks.last_mut() {
+            let shadowed = self.store.get(&name).copied();
+            block.shadowed.entry(name.clone()).or_insert(shadowed);
+        }
+
+        if self.slot_names.len() <= symbol.index {
+            self.slot_names.push(vec![]);
+        }
+        if !self.slot_names[symbol.index].contains(&name) {
+            self.slot_names[symbol.index].push(name.clone());
+        }
+
         self.store.insert(name, symbol);
-        self.num_definitions += 1;
+        self.next_index += 1;
+        self.num_definitions = self.num_definitions.max(self.next_index);
         symbol
     }
 
@@ -71,6 +142,7 @@ impl SymbolTable {
         let symbol = Symbol {
             scope: SymbolScope::Function,
             index: 0,
+            constant: None,
         };
         self.store.insert(name, symbol);
         symbol
@@ -81,13 +153,28 @@ impl SymbolTable {
 
         let symbol = Symbol {
             scope: SymbolScope::Free,
-            index: self.free_symbols.len() as u16 - 1,
+            index: self.free_symbols.len() - 1,
+            constant: None,
         };
 
         self.store.insert(name, symbol);
         symbol
     }
 
+    /// Returns the position of the definition, if the name
+    /// is a constant defined in the current block.
+    pub fn constant(&self, name: &str) -> Option<Position> {
+        let in_current_block = self
+            .blocks
+            .last()
+            .is_none_or(|block| block.shadowed.contains_key(name));
+        if !in_current_block {
+            return None;
+        }
+
+        self.store.get(name).and_then(|symbol| symbol.constant)
+    }
+
     pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
         if let Some(sym) = self.store.get(name) {
             return Some(*sym);
@@ -109,7 +196,16 @@ impl SymbolTable {
     }
 
     pub fn num_definitions(&self) -> usize {
-        self.num_definitions as usize
+        self.num_definitions
+    }
+
+    /// Returns the names of the global or local slots. Names of slots,
+    /// which are reused by several blocks, are separated by `/`.
+    pub fn slot_names(&self) -> Vec<String> {
+        self.slot_names
+            .iter()
+            .map(|names| names.join("/"))
+            .collect()
     }
 }
 
@@ -122,6 +218,7 @@ impl Default for SymbolTable {
 #[cfg(test)]
 mod test {
     use crate::compile::symbol_table::{Symbol, SymbolScope};
+    use crate::token::Position;
 
     use super::SymbolTable;
 
@@ -139,6 +236,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Global,
                 index: 0,
+                constant: None,
             }),
             table.resolve("a")
         );
@@ -146,6 +244,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Global,
                 index: 1,
+                constant: None,
             }),
             table.resolve("b")
         );
@@ -153,6 +252,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Local,
                 index: 0,
+                constant: None,
             }),
             table.resolve("c")
         );
@@ -160,6 +260,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Local,
                 index: 1,
+                constant: None,
             }),
             table.resolve("d")
         );
@@ -173,6 +274,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Global,
                 index: 0,
+                constant: None,
             }),
             table.resolve("a")
         );
@@ -180,6 +282,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Global,
                 index: 1,
+                constant: None,
             }),
             table.resolve("b")
         );
@@ -187,6 +290,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Free,
                 index: 0,
+                constant: None,
             }),
             table.resolve("c")
         );
@@ -194,6 +298,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Free,
                 index: 1,
+                constant: None,
             }),
             table.resolve("d")
         );
@@ -201,6 +306,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Local,
                 index: 0,
+                constant: None,
             }),
             table.resolve("e")
         );
@@ -208,6 +314,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Local,
                 index: 1,
+                constant: None,
             }),
             table.resolve("f")
         );
@@ -216,10 +323,12 @@ mod test {
                 Symbol {
                     scope: SymbolScope::Local,
                     index: 0,
+                    constant: None,
                 },
                 Symbol {
                     scope: SymbolScope::Local,
                     index: 1,
+                    constant: None,
                 },
             ],
             table.free_symbols
@@ -234,6 +343,7 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Function,
                 index: 0,
+                constant: None,
             }),
             table.resolve("a")
         )
@@ -248,8 +358,78 @@ mod test {
             Some(Symbol {
                 scope: SymbolScope::Global,
                 index: 0,
+                constant: None,
             }),
             table.resolve("a")
         )
     }
+
+    #[test]
+    fn define_constant() {
+        let position = Position { line: 1, column: 1 };
+
+        let mut table = SymbolTable::new();
+        table.define_constant("a".to_string(), position);
+        table.define("b".to_string());
+        assert_eq!(table.constant("a"), Some(position));
+        assert_eq!(table.constant("b"), None);
+
+        // Constants of the outer scope can be shadowed
+        table.enclose();
+        assert_eq!(
+            Some(Symbol {
+                scope: SymbolScope::Global,
+                index: 0,
+                constant: Some(position),
+            }),
+            table.resolve("a")
+        );
+        assert_eq!(table.constant("a"), None);
+    }
+
+    #[test]
+    fn block_scopes() {
+        let mut table = SymbolTable::new();
+        table.enclose();
+        let a = table.define("a".to_string());
+
+        table.enter_block();
+        let shadowed = table.define("a".to_string());
+        let b = table.define("b".to_string());
+        assert_eq!(table.resolve("a"), Some(shadowed));
+        assert_eq!(table.resolve("b"), Some(b));
+        table.leave_block();
+
+        assert_eq!(table.resolve("a"), Some(a));
+        assert_eq!(table.resolve("b"), None);
+
+        // Slots of a finished block are reused
+        table.enter_block();
+        let c = table.define("c".to_string());
+        assert_eq!(c.index, shadowed.index);
+        table.leave_block();
+
+        assert_eq!(table.num_definitions, 3);
+    }
+
+    #[test]
+    fn slot_names() {
+        let mut table = SymbolTable::new();
+        table.define("a".to_string());
+        table.enclose();
+        table.define("b".to_string());
+        table.enter_block();
+        table.define("c".to_string());
+        table.leave_block();
+        table.enter_block();
+        table.define("d".to_string());
+        table.define("c".to_string());
+        table.leave_block();
+        table.define("b".to_string());
+
+        assert_eq!(table.slot_names(), ["b", "c/d/b", "c"]);
+
+        table.leave();
+        assert_eq!(table.slot_names(), ["a"]);
+    }
 }
@@ -1,10 +1,13 @@
-use std::rc::Rc;
+use std::{path::Path, rc::Rc};
 
 use crate::{
-    code::{Bytecode, Instruction},
-    compile::{Compiler, Result},
+    ast,
+    code::{Bytecode, Instruction, Instructions},
+    compile::{Compiler, Error, Result},
+    module,
     object::{builtin::BuiltinFunction, CompiledFunction, Object},
     parse::parse,
+    token::Position,
 };
 
 struct TestCase {
@@ -19,11 +22,35 @@ fn run_test_case(case: TestCase) -> Result<()> {
     let mut compiler = Compiler::new();
     let bytecode = compiler.compile(&program)?;
 
+    // Names, lines and slot names are tested by `test_debug_info`.
+    let constants: Vec<_> = bytecode
+        .constants
+        .iter()
+        .map(|obj| match obj {
+            Object::CompiledFunction(fun) => Object::CompiledFunction(CompiledFunction {
+                name: None,
+                lines: Default::default(),
+                local_names: Default::default(),
+                ..fun.clone()
+            }),
+            obj => obj.clone(),
+        })
+        .collect();
+
     let expected_bytecode = Bytecode {
-        instructions: Rc::new(case.expected_instructions),
+        instructions: Rc::new(Instructions::new(&case.expected_instructions)),
+        lines: bytecode.lines.clone(),
         constants: &case.expected_constants,
+        modules: &[],
+        global_names: bytecode.global_names.clone(),
     };
-    assert_eq!(bytecode, expected_bytecode);
+    assert_eq!(
+        Bytecode {
+            constants: &constants,
+            ..bytecode
+        },
+        expected_bytecode
+    );
 
     Ok(())
 }
@@ -89,6 +116,58 @@ fn test_integer_arithemtic() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_bitwise_operators() -> Result<()> {
+    let tests = [
+        TestCase {
+            input: "1 & 2 | 3 ^ 4",
+            expected_constants: vec![
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::Integer(3),
+                Object::Integer(4),
+            ],
+            expected_instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::BitAnd,
+                Instruction::Constant(2),
+                Instruction::Constant(3),
+                Instruction::BitXor,
+                Instruction::BitOr,
+                Instruction::Pop,
+            ],
+        },
+        TestCase {
+            input: "1 << 2 >> 3",
+            expected_constants: vec![Object::Integer(1), Object::Integer(2), Object::Integer(3)],
+            expected_instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::ShiftLeft,
+                Instruction::Constant(2),
+                Instruction::ShiftRight,
+                Instruction::Pop,
+            ],
+        },
+        TestCase {
+            input: "~0xff",
+            expected_constants: vec![Object::Integer(255)],
+            expected_instructions: vec![
+                Instruction::Constant(0),
+                Instruction::BitNot,
+                Instruction::Pop,
+            ],
+        },
+    ];
+
+    for case in tests {
+        run_test_case(case)?;
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_boolean_expression() -> Result<()> {
     let tests = [
@@ -114,11 +193,11 @@ fn test_boolean_expression() -> Result<()> {
         },
         TestCase {
             input: "1 < 2",
-            expected_constants: vec![Object::Integer(2), Object::Integer(1)],
+            expected_constants: vec![Object::Integer(1), Object::Integer(2)],
             expected_instructions: vec![
                 Instruction::Constant(0),
                 Instruction::Constant(1),
-                Instruction::GreaterThan,
+                Instruction::LessThan,
                 Instruction::Pop,
             ],
         },
@@ -184,9 +263,9 @@ fn test_conditionals() -> Result<()> {
             expected_constants: vec![Object::Integer(10), Object::Integer(3333)],
             expected_instructions: vec![
                 Instruction::True,
-                Instruction::JumpNotTruthy(4),
+                Instruction::JumpNotTruthy(10),
                 Instruction::Constant(0),
-                Instruction::Jump(5),
+                Instruction::Jump(11),
                 Instruction::Null,
                 Instruction::Pop,
                 Instruction::Constant(1),
@@ -202,9 +281,9 @@ fn test_conditionals() -> Result<()> {
             ],
             expected_instructions: vec![
                 Instruction::True,
-                Instruction::JumpNotTruthy(4),
+                Instruction::JumpNotTruthy(10),
                 Instruction::Constant(0),
-                Instruction::Jump(5),
+                Instruction::Jump(13),
                 Instruction::Constant(1),
                 Instruction::Pop,
                 Instruction::Constant(2),
@@ -216,11 +295,11 @@ fn test_conditionals() -> Result<()> {
             expected_constants: vec![Object::Integer(10)],
             expected_instructions: vec![
                 Instruction::True,
-                Instruction::JumpNotTruthy(6),
+                Instruction::JumpNotTruthy(14),
                 Instruction::Constant(0),
                 Instruction::SetGlobal(0),
                 Instruction::Null,
-                Instruction::Jump(7),
+                Instruction::Jump(15),
                 Instruction::Null,
                 Instruction::Pop,
             ],
@@ -245,6 +324,8 @@ fn test_global_let_statements() -> Result<()> {
                 Instruction::SetGlobal(0),
                 Instruction::Constant(1),
                 Instruction::SetGlobal(1),
+                Instruction::Null,
+                Instruction::Pop,
             ],
         },
         TestCase {
@@ -426,20 +507,14 @@ fn test_index_expressions() -> Result<()> {
     let tests = [
         TestCase {
             input: "[1, 2, 3][1 + 1]",
-            expected_constants: vec![
-                Object::Integer(1),
-                Object::Integer(2),
-                Object::Integer(3),
-                Object::Integer(1),
-                Object::Integer(1),
-            ],
+            expected_constants: vec![Object::Integer(1), Object::Integer(2), Object::Integer(3)],
             expected_instructions: vec![
                 Instruction::Constant(0),
                 Instruction::Constant(1),
                 Instruction::Constant(2),
                 Instruction::Array(3),
-                Instruction::Constant(3),
-                Instruction::Constant(4),
+                Instruction::Constant(0),
+                Instruction::Constant(0),
                 Instruction::Add,
                 Instruction::Index,
                 Instruction::Pop,
@@ -447,18 +522,13 @@ fn test_index_expressions() -> Result<()> {
         },
         TestCase {
             input: "{1: 2}[2-1]",
-            expected_constants: vec![
-                Object::Integer(1),
-                Object::Integer(2),
-                Object::Integer(2),
-                Object::Integer(1),
-            ],
+            expected_constants: vec![Object::Integer(1), Object::Integer(2)],
             expected_instructions: vec![
                 Instruction::Constant(0),
                 Instruction::Constant(1),
                 Instruction::Hash(2),
-                Instruction::Constant(2),
-                Instruction::Constant(3),
+                Instruction::Constant(1),
+                Instruction::Constant(0),
                 Instruction::Sub,
                 Instruction::Index,
                 Instruction::Pop,
@@ -473,6 +543,45 @@ fn test_index_expressions() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_error_propagation() -> Result<()> {
+    let tests = [TestCase {
+        input: r#"fn() { error("oops")?; 1 }"#,
+        expected_constants: vec![
+            Object::String(Rc::new("oops".to_string())),
+            Object::Integer(1),
+            Object::CompiledFunction(CompiledFunction {
+                instructions: Rc::new(Instructions::new(&[
+                    Instruction::GetBuiltin(BuiltinFunction::Error),
+                    Instruction::Constant(0),
+                    Instruction::Call(1),
+                    Instruction::ReturnIfError,
+                    Instruction::Pop,
+                    Instruction::Constant(1),
+                    Instruction::ReturnValue,
+                ])),
+                num_locals: 0,
+                num_arguments: 0,
+                generator: false,
+                ..Default::default()
+            }),
+        ],
+        expected_instructions: vec![
+            Instruction::Closure {
+                constant_index: 2,
+                free_variables: 0,
+            },
+            Instruction::Pop,
+        ],
+    }];
+
+    for case in tests {
+        run_test_case(case)?;
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_functions() -> Result<()> {
     let tests = [
@@ -482,14 +591,16 @@ fn test_functions() -> Result<()> {
                 Object::Integer(5),
                 Object::Integer(10),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(0),
                         Instruction::Constant(1),
                         Instruction::Add,
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 0,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -506,14 +617,16 @@ fn test_functions() -> Result<()> {
                 Object::Integer(1),
                 Object::Integer(2),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(0),
                         Instruction::Pop,
                         Instruction::Constant(1),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 0,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -527,9 +640,14 @@ fn test_functions() -> Result<()> {
         TestCase {
             input: "fn() { }",
             expected_constants: vec![Object::CompiledFunction(CompiledFunction {
-                instructions: Rc::new(vec![Instruction::Null, Instruction::ReturnValue]),
+                instructions: Rc::new(Instructions::new(&[
+                    Instruction::Null,
+                    Instruction::ReturnValue,
+                ])),
                 num_locals: 0,
                 num_arguments: 0,
+                generator: false,
+                ..Default::default()
             })],
             expected_instructions: vec![
                 Instruction::Closure {
@@ -544,14 +662,16 @@ fn test_functions() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(42),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(0),
                         Instruction::SetLocal(0),
                         Instruction::Null,
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -579,9 +699,14 @@ fn test_function_calls() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(24),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![Instruction::Constant(0), Instruction::ReturnValue]),
+                    instructions: Rc::new(Instructions::new(&[
+                        Instruction::Constant(0),
+                        Instruction::ReturnValue,
+                    ])),
                     num_locals: 0,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -598,9 +723,14 @@ fn test_function_calls() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(24),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![Instruction::Constant(0), Instruction::ReturnValue]),
+                    instructions: Rc::new(Instructions::new(&[
+                        Instruction::Constant(0),
+                        Instruction::ReturnValue,
+                    ])),
                     num_locals: 0,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -618,9 +748,14 @@ fn test_function_calls() -> Result<()> {
             input: "let oneArg = fn(a) { a }; oneArg(24)",
             expected_constants: vec![
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![Instruction::GetLocal(0), Instruction::ReturnValue]),
+                    instructions: Rc::new(Instructions::new(&[
+                        Instruction::GetLocal(0),
+                        Instruction::ReturnValue,
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::Integer(24),
             ],
@@ -640,16 +775,18 @@ fn test_function_calls() -> Result<()> {
             input: "let manyArg = fn(a, b, c) { a; b; c }; manyArg(24, 25, 26)",
             expected_constants: vec![
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetLocal(0),
                         Instruction::Pop,
                         Instruction::GetLocal(1),
                         Instruction::Pop,
                         Instruction::GetLocal(2),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 3,
                     num_arguments: 3,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::Integer(24),
                 Object::Integer(25),
@@ -686,12 +823,14 @@ fn test_local_statement_scopes() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(55),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetGlobal(0),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 0,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -709,14 +848,16 @@ fn test_local_statement_scopes() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(55),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(0),
                         Instruction::SetLocal(0),
                         Instruction::GetLocal(0),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -738,7 +879,7 @@ fn test_local_statement_scopes() -> Result<()> {
                 Object::Integer(55),
                 Object::Integer(77),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(0),
                         Instruction::SetLocal(0),
                         Instruction::Constant(1),
@@ -747,9 +888,11 @@ fn test_local_statement_scopes() -> Result<()> {
                         Instruction::GetLocal(1),
                         Instruction::Add,
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 2,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -769,6 +912,67 @@ fn test_local_statement_scopes() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_block_scopes() -> Result<()> {
+    let tests = [TestCase {
+        input: "fn() { if (true) { let a = 1; a } else { let b = 2; b } }",
+        expected_constants: vec![
+            Object::Integer(1),
+            Object::Integer(2),
+            Object::CompiledFunction(CompiledFunction {
+                instructions: Rc::new(Instructions::new(&[
+                    Instruction::True,
+                    Instruction::JumpNotTruthy(14),
+                    Instruction::Constant(0),
+                    Instruction::SetLocal(0),
+                    Instruction::GetLocal(0),
+                    Instruction::Jump(21),
+                    Instruction::Constant(1),
+                    Instruction::SetLocal(0),
+                    Instruction::GetLocal(0),
+                    Instruction::ReturnValue,
+                ])),
+                num_locals: 1,
+                num_arguments: 0,
+                generator: false,
+                ..Default::default()
+            }),
+        ],
+        expected_instructions: vec![
+            Instruction::Closure {
+                constant_index: 2,
+                free_variables: 0,
+            },
+            Instruction::Pop,
+        ],
+    }];
+
+    for test in tests {
+        run_test_case(test)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_block_scope_errors() {
+    let tests = [
+        ("if (true) { let a = 1; } a;", "a"),
+        ("fn() { { let a = 1; }; a }", "a"),
+        ("let f = fn() { if (true) { let a = 1; a } }; a", "a"),
+    ];
+
+    for (input, name) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        assert_eq!(
+            compiler.compile(&program),
+            Err(Error::UndefinedSymbol(name.to_string()))
+        );
+    }
+}
+
 #[test]
 fn test_builtin() -> Result<()> {
     let tests = [
@@ -790,14 +994,16 @@ fn test_builtin() -> Result<()> {
         TestCase {
             input: "fn() { len([]) }",
             expected_constants: vec![Object::CompiledFunction(CompiledFunction {
-                instructions: Rc::new(vec![
+                instructions: Rc::new(Instructions::new(&[
                     Instruction::GetBuiltin(BuiltinFunction::Len),
                     Instruction::Array(0),
-                    Instruction::Call(1),
+                    Instruction::TailCall(1),
                     Instruction::ReturnValue,
-                ]),
+                ])),
                 num_locals: 0,
                 num_arguments: 0,
+                generator: false,
+                ..Default::default()
             })],
             expected_instructions: vec![
                 Instruction::Closure {
@@ -828,26 +1034,30 @@ fn closures() -> Result<()> {
                 }"#,
             expected_constants: vec![
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetFree(0),
                         Instruction::GetLocal(0),
                         Instruction::Add,
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetLocal(0),
                         Instruction::Closure {
                             constant_index: 0,
                             free_variables: 1,
                         },
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -869,19 +1079,21 @@ fn closures() -> Result<()> {
                 }"#,
             expected_constants: vec![
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetFree(0),
                         Instruction::GetFree(1),
                         Instruction::Add,
                         Instruction::GetLocal(0),
                         Instruction::Add,
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetFree(0),
                         Instruction::GetLocal(0),
                         Instruction::Closure {
@@ -889,21 +1101,25 @@ fn closures() -> Result<()> {
                             free_variables: 2,
                         },
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::GetLocal(0),
                         Instruction::Closure {
                             constant_index: 1,
                             free_variables: 1,
                         },
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -937,7 +1153,7 @@ fn closures() -> Result<()> {
                 Object::Integer(77),
                 Object::Integer(88),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(3),
                         Instruction::SetLocal(0),
                         Instruction::GetGlobal(0),
@@ -948,12 +1164,14 @@ fn closures() -> Result<()> {
                         Instruction::GetLocal(0),
                         Instruction::Add,
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(2),
                         Instruction::SetLocal(0),
                         Instruction::GetFree(0),
@@ -963,12 +1181,14 @@ fn closures() -> Result<()> {
                             free_variables: 2,
                         },
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Constant(1),
                         Instruction::SetLocal(0),
                         Instruction::GetLocal(0),
@@ -977,9 +1197,11 @@ fn closures() -> Result<()> {
                             free_variables: 1,
                         },
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
@@ -1009,18 +1231,19 @@ fn recursive_functions() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(1),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::CurrentClosure,
                         Instruction::GetLocal(0),
                         Instruction::Constant(0),
                         Instruction::Sub,
-                        Instruction::Call(1),
+                        Instruction::TailCall(1),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
-                Object::Integer(1),
             ],
             expected_instructions: vec![
                 Instruction::Closure {
@@ -1029,7 +1252,7 @@ fn recursive_functions() -> Result<()> {
                 },
                 Instruction::SetGlobal(0),
                 Instruction::GetGlobal(0),
-                Instruction::Constant(2),
+                Instruction::Constant(0),
                 Instruction::Call(1),
                 Instruction::Pop,
             ],
@@ -1044,37 +1267,40 @@ fn recursive_functions() -> Result<()> {
             expected_constants: vec![
                 Object::Integer(1),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::CurrentClosure,
                         Instruction::GetLocal(0),
                         Instruction::Constant(0),
                         Instruction::Sub,
-                        Instruction::Call(1),
+                        Instruction::TailCall(1),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
                 }),
-                Object::Integer(1),
                 Object::CompiledFunction(CompiledFunction {
-                    instructions: Rc::new(vec![
+                    instructions: Rc::new(Instructions::new(&[
                         Instruction::Closure {
                             constant_index: 1,
                             free_variables: 0,
                         },
                         Instruction::SetLocal(0),
                         Instruction::GetLocal(0),
-                        Instruction::Constant(2),
-                        Instruction::Call(1),
+                        Instruction::Constant(0),
+                        Instruction::TailCall(1),
                         Instruction::ReturnValue,
-                    ]),
+                    ])),
                     num_locals: 1,
                     num_arguments: 0,
+                    generator: false,
+                    ..Default::default()
                 }),
             ],
             expected_instructions: vec![
                 Instruction::Closure {
-                    constant_index: 3,
+                    constant_index: 2,
                     free_variables: 0,
                 },
                 Instruction::SetGlobal(0),
@@ -1091,3 +1317,572 @@ fn recursive_functions() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn tail_calls() -> Result<()> {
+    let tests = [
+        TestCase {
+            input: "let f = fn(x) { if (x) { return f(x); } else { x } };",
+            expected_constants: vec![Object::CompiledFunction(CompiledFunction {
+                instructions: Rc::new(Instructions::new(&[
+                    Instruction::GetLocal(0),
+                    Instruction::JumpNotTruthy(14),
+                    Instruction::CurrentClosure,
+                    Instruction::GetLocal(0),
+                    Instruction::TailCall(1),
+                    Instruction::ReturnValue,
+                    Instruction::Jump(16),
+                    Instruction::GetLocal(0),
+                    Instruction::ReturnValue,
+                ])),
+                num_locals: 1,
+                num_arguments: 1,
+                generator: false,
+                ..Default::default()
+            })],
+            expected_instructions: vec![
+                Instruction::Closure {
+                    constant_index: 0,
+                    free_variables: 0,
+                },
+                Instruction::SetGlobal(0),
+                Instruction::Null,
+                Instruction::Pop,
+            ],
+        },
+        TestCase {
+            input: "let f = fn(x) { f(x) + 1 };",
+            expected_constants: vec![
+                Object::Integer(1),
+                Object::CompiledFunction(CompiledFunction {
+                    instructions: Rc::new(Instructions::new(&[
+                        Instruction::CurrentClosure,
+                        Instruction::GetLocal(0),
+                        Instruction::Call(1),
+                        Instruction::Constant(0),
+                        Instruction::Add,
+                        Instruction::ReturnValue,
+                    ])),
+                    num_locals: 1,
+                    num_arguments: 1,
+                    generator: false,
+                    ..Default::default()
+                }),
+            ],
+            expected_instructions: vec![
+                Instruction::Closure {
+                    constant_index: 1,
+                    free_variables: 0,
+                },
+                Instruction::SetGlobal(0),
+                Instruction::Null,
+                Instruction::Pop,
+            ],
+        },
+    ];
+
+    for case in tests {
+        run_test_case(case)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn generators() -> Result<()> {
+    let tests = [TestCase {
+        input: "fn(x) { if (x) { yield 1; } yield x; }",
+        expected_constants: vec![
+            Object::Integer(1),
+            Object::CompiledFunction(CompiledFunction {
+                instructions: Rc::new(Instructions::new(&[
+                    Instruction::GetLocal(0),
+                    Instruction::JumpNotTruthy(13),
+                    Instruction::Constant(0),
+                    Instruction::Yield,
+                    Instruction::Null,
+                    Instruction::Jump(14),
+                    Instruction::Null,
+                    Instruction::Pop,
+                    Instruction::GetLocal(0),
+                    Instruction::Yield,
+                    Instruction::Null,
+                    Instruction::ReturnValue,
+                ])),
+                num_locals: 1,
+                num_arguments: 1,
+                generator: true,
+                ..Default::default()
+            }),
+        ],
+        expected_instructions: vec![
+            Instruction::Closure {
+                constant_index: 1,
+                free_variables: 0,
+            },
+            Instruction::Pop,
+        ],
+    }];
+
+    for case in tests {
+        run_test_case(case)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_modules() -> Result<()> {
+    let modules_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/modules");
+
+    let program = parse(
+        r#"
+        import "lib/math.monkey" as math;
+        import "lib/strings.monkey" as strings;
+        import "lib/math.monkey" as again;"#,
+    )
+    .unwrap();
+
+    let mut compiler = Compiler::with_path(&modules_dir.join("main.monkey"));
+    let bytecode = compiler.compile(&program)?;
+
+    // Math module is compiled only once.
+    assert_eq!(bytecode.modules.len(), 2);
+    assert_eq!(
+        bytecode.modules[0].path,
+        modules_dir.join("lib/math.monkey")
+    );
+    assert_eq!(bytecode.modules[0].num_globals, 3);
+    assert_eq!(
+        *bytecode.instructions,
+        Instructions::new(&[
+            Instruction::Import(0),
+            Instruction::SetGlobal(0),
+            Instruction::Import(1),
+            Instruction::SetGlobal(1),
+            Instruction::Import(0),
+            Instruction::SetGlobal(2),
+            Instruction::Null,
+            Instruction::Pop,
+        ])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_module_errors() {
+    let modules_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/modules");
+    let module_path = |path: &str| modules_dir.join(path).display().to_string();
+
+    let tests = [
+        (
+            r#"import "missing.monkey" as missing;"#,
+            Error::Module {
+                source: module::Error::Read {
+                    path: module_path("missing.monkey"),
+                    message: "No such file or directory (os error 2)".to_string(),
+                },
+            },
+        ),
+        (
+            r#"import "undefined_export.monkey" as undefined;"#,
+            Error::UndefinedSymbol("b".to_string()),
+        ),
+        (
+            r#"import "cycle/a.monkey" as a;"#,
+            Error::Module {
+                source: module::Error::Cycle(vec![
+                    module_path("cycle/a.monkey"),
+                    module_path("cycle/b.monkey"),
+                    module_path("cycle/a.monkey"),
+                ]),
+            },
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::with_path(&modules_dir.join("main.monkey"));
+        assert_eq!(compiler.compile(&program), Err(expected));
+    }
+}
+
+#[test]
+fn test_constants() {
+    let position = |line, column| Position { line, column };
+
+    let tests = [
+        ("const a = 1; a;", Ok(())),
+        ("const a = 1; fn() { let a = 2; a }", Ok(())),
+        ("let a = 1; const a = 2; a;", Ok(())),
+        ("const a = 1; if (true) { const a = 2; a }", Ok(())),
+        (
+            "const a = 1;\nlet a = 2;",
+            Err(Error::AssignToConstant {
+                name: "a".to_string(),
+                defined: position(1, 1),
+                assigned: position(2, 1),
+            }),
+        ),
+        (
+            "fn() { if (true) { const a = 1; let a = 2; } }",
+            Err(Error::AssignToConstant {
+                name: "a".to_string(),
+                defined: position(1, 20),
+                assigned: position(1, 33),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        assert_eq!(compiler.compile(&program).map(|_| ()), expected);
+    }
+}
+
+#[test]
+fn test_constant_deduplication() -> Result<()> {
+    // Every input is compiled twice by the same compiler, like in the REPL.
+    let tests = [
+        ("1; 1; 2; 1", 2, 8),
+        (r#""a"; "b"; "a"; b"a"; b"a""#, 3, 10),
+        ("fn(x) { x + 1 }; fn(x) { x + 1 }", 2, 8),
+        ("fn(x) { x + 1 };\nfn(x) { x + 1 }", 3, 8),
+        ("fn(x) { x + 1 }; fn(y) { y + 1 }", 3, 8),
+        ("1; quote(1); quote(1)", 5, 6),
+    ];
+
+    for (input, unique, added) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        compiler.compile(&program)?;
+        compiler.compile(&program)?;
+
+        let stats = compiler.constant_stats();
+        assert_eq!((stats.unique, stats.added), (unique, added), "{}", input);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_optimize() -> Result<()> {
+    let tests = [
+        ("2 * 3", vec![Instruction::Constant(2), Instruction::Pop]),
+        ("!true", vec![Instruction::False, Instruction::Pop]),
+        ("1 < 2 == true", vec![Instruction::True, Instruction::Pop]),
+        (
+            r#""a" + "b""#,
+            vec![Instruction::Constant(2), Instruction::Pop],
+        ),
+        ("-(1 + 2)", vec![Instruction::Constant(2), Instruction::Pop]),
+        (
+            "1 + 2 + 4",
+            vec![Instruction::Constant(3), Instruction::Pop],
+        ),
+        (
+            "1 / 0",
+            vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Div,
+                Instruction::Pop,
+            ],
+        ),
+        (
+            "if (true) { 1 } else { 2 }",
+            vec![Instruction::Constant(0), Instruction::Pop],
+        ),
+        (
+            "if (false) { 1 }",
+            vec![Instruction::Null, Instruction::Pop],
+        ),
+        (
+            "let x = true; if (x) { if (x) { 1 } else { 2 } } else { 3 }",
+            vec![
+                Instruction::True,
+                Instruction::SetGlobal(0),
+                Instruction::GetGlobal(0),
+                Instruction::JumpNotTruthy(28),
+                Instruction::GetGlobal(0),
+                Instruction::JumpNotTruthy(22),
+                Instruction::Constant(0),
+                Instruction::Jump(31),
+                Instruction::Constant(1),
+                Instruction::Jump(31),
+                Instruction::Constant(2),
+                Instruction::Pop,
+            ],
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(true);
+        let bytecode = compiler.compile(&program)?;
+
+        assert_eq!(
+            bytecode.instructions,
+            Rc::new(Instructions::new(&expected)),
+            "{}",
+            input
+        );
+    }
+
+    // Scopes can have more instructions than fit into a jump operand.
+    let count = u16::MAX as usize + 1;
+    let input = format!("let x = 0;{}", "x;".repeat(count));
+    let mut expected = vec![Instruction::Constant(0), Instruction::SetGlobal(0)];
+    for _ in 0..count {
+        expected.extend([Instruction::GetGlobal(0), Instruction::Pop]);
+    }
+    let mut compiler = Compiler::new();
+    compiler.set_optimize(true);
+    let bytecode = compiler.compile(&parse(&input).unwrap())?;
+    assert_eq!(bytecode.instructions, Rc::new(Instructions::new(&expected)));
+
+    // Intermediate results of folding aren't added to the constant pool.
+    let mut compiler = Compiler::new();
+    compiler.set_optimize(true);
+    compiler.compile(&parse("1 + 2 + 4; 5 * (6 + 7)").unwrap())?;
+    let stats = compiler.constant_stats();
+    assert_eq!((stats.unique, stats.added), (7, 7));
+
+    // The values of functions are returned, so values followed by `Pop` are removed.
+    let tests = [
+        (
+            "fn() { return 1; 2 }",
+            vec![Instruction::Constant(0), Instruction::ReturnValue],
+        ),
+        (
+            "fn() { if (false) { 1 }; 2 }",
+            vec![Instruction::Constant(1), Instruction::ReturnValue],
+        ),
+        (
+            "fn(x) { x; 1 + 2 }",
+            vec![
+                Instruction::GetLocal(0),
+                Instruction::Pop,
+                Instruction::Constant(2),
+                Instruction::ReturnValue,
+            ],
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(true);
+        let bytecode = compiler.compile(&program)?;
+
+        let Some(Object::CompiledFunction(function)) = bytecode.constants.last() else {
+            panic!("expected a function constant: {}", input);
+        };
+        assert_eq!(
+            function.instructions,
+            Rc::new(Instructions::new(&expected)),
+            "{}",
+            input
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_quote() -> Result<()> {
+    // The quoted expression of the input, which is the first constant.
+    let quoted = |input: &str| {
+        let program = parse(input).unwrap();
+        let ast::Statement::Expression(call) = &program.statements[0] else {
+            panic!("expected an expression: {}", input);
+        };
+        Object::Quote(Rc::new(call.call_arguments("quote").unwrap()[0].clone()))
+    };
+
+    let input = "quote(unquote(1) + unquote(2 + 3))";
+    let tests = [
+        TestCase {
+            input: "quote(1 + a)",
+            expected_constants: vec![Object::Quote(Rc::new(ast::Expression::InfixOperator {
+                operator: ast::InfixOperatorKind::Add,
+                left: Box::new(ast::Expression::IntegerLiteral(1)),
+                right: Box::new(ast::Expression::Identifier("a".to_string())),
+                position: Position { line: 1, column: 9 },
+            }))],
+            expected_instructions: vec![Instruction::Constant(0), Instruction::Pop],
+        },
+        TestCase {
+            input,
+            expected_constants: vec![
+                quoted(input),
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::Integer(3),
+            ],
+            expected_instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Unquote,
+                Instruction::Constant(2),
+                Instruction::Constant(3),
+                Instruction::Add,
+                Instruction::Unquote,
+                Instruction::Pop,
+            ],
+        },
+    ];
+
+    for case in tests {
+        run_test_case(case)?;
+    }
+
+    let errors = [
+        ("quote(unquote(unquote(1)))", Error::UnsupportedUnquote),
+        (
+            "quote(unquote(1, 2))",
+            Error::WrongNumberOfArguments {
+                expected: 1,
+                got: 2,
+            },
+        ),
+        ("let m = macro(x) { x };", Error::UnexpandedMacro),
+    ];
+
+    for (input, expected) in errors {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        assert_eq!(compiler.compile(&program), Err(expected));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_debug_info() -> Result<()> {
+    let input = "let add = fn(a, b) {\n  a +\n    b\n};\nadd(1,\n  -2)";
+    let program = parse(input).unwrap();
+
+    let mut compiler = Compiler::new();
+    let bytecode = compiler.compile(&program)?;
+
+    let lines: Vec<_> = bytecode
+        .instructions
+        .iter()
+        .map(|(offset, _)| bytecode.lines.line(offset))
+        .collect();
+    assert_eq!(lines, [1, 1, 5, 5, 6, 6, 5, 5]);
+
+    let Object::CompiledFunction(fun) = &bytecode.constants[0] else {
+        panic!(
+            "Expected CompiledFunction, got: {:?}",
+            bytecode.constants[0]
+        );
+    };
+    assert_eq!(fun.name.as_deref().map(String::as_str), Some("add"));
+    let lines: Vec<_> = fun
+        .instructions
+        .iter()
+        .map(|(offset, _)| fun.lines.line(offset))
+        .collect();
+    assert_eq!(lines, [2, 2, 2, 2]);
+    assert_eq!(*fun.local_names, ["a", "b"]);
+    assert_eq!(*bytecode.global_names, ["add"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_limits() {
+    // Identifiers can't contain digits, so the index is written with letters.
+    let name = |mut i: usize| {
+        let mut name = String::from("x");
+        loop {
+            name.push((b'a' + (i % 26) as u8) as char);
+            i /= 26;
+            if i == 0 {
+                return name;
+            }
+        }
+    };
+    let repeat = |count: usize, f: &dyn Fn(usize) -> String, separator: &str| {
+        (0..count).map(f).collect::<Vec<_>>().join(separator)
+    };
+    let names = |count| repeat(count, &name, ", ");
+    let lets = |count| repeat(count, &|i| format!("let {} = true;", name(i)), " ");
+    let trues = |count| repeat(count, &|_| "true".to_string(), ", ");
+
+    let tests = [
+        (repeat(65536, &|i| format!("{};", i), " "), Ok(())),
+        (
+            repeat(65537, &|i| format!("{};", i), " "),
+            Err(Error::TooManyConstants),
+        ),
+        (repeat(65537, &|i| format!("{};", i % 65536), " "), Ok(())),
+        (format!("{} {}", lets(65536), name(65535)), Ok(())),
+        (
+            format!("{} {}", lets(65537), name(65536)),
+            Err(Error::TooManyGlobals),
+        ),
+        (format!("fn() {{ {} {} }}", lets(256), name(255)), Ok(())),
+        (
+            format!("fn() {{ {} {} }}", lets(257), name(256)),
+            Err(Error::TooManyLocals),
+        ),
+        (
+            format!("fn({}) {{ 1 }}", names(257)),
+            Err(Error::TooManyLocals),
+        ),
+        (format!("len({})", trues(255)), Ok(())),
+        (format!("len({})", trues(256)), Err(Error::TooManyArguments)),
+        (
+            format!("fn() {{ {} fn() {{ [{}] }} }}", lets(255), names(255)),
+            Ok(()),
+        ),
+        (
+            format!("fn() {{ {} fn() {{ [{}] }} }}", lets(256), names(256)),
+            Err(Error::TooManyFreeVariables),
+        ),
+        (format!("[{}]", trues(65535)), Ok(())),
+        (format!("[{}]", trues(65536)), Err(Error::TooManyElements)),
+        (
+            format!("{{{}}}", repeat(32768, &|_| "true: true".to_string(), ", ")),
+            Err(Error::TooManyElements),
+        ),
+        (
+            format!(
+                "if (true) {{ {} }}",
+                repeat(32764, &|_| "true;".to_string(), " ")
+            ),
+            Ok(()),
+        ),
+        (
+            format!(
+                "if (true) {{ {} }}",
+                repeat(32765, &|_| "true;".to_string(), " ")
+            ),
+            Err(Error::TooManyInstructions),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(&input).unwrap();
+
+        let mut compiler = Compiler::new();
+        assert_eq!(
+            compiler.compile(&program).map(|_| ()),
+            expected,
+            "{}...",
+            &input[..40.min(input.len())]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Disassembler, which prints the bytecode in a readable form.
+//!
+//! Every instruction is printed with its byte offset, the source line where
+//! it starts a new line, and the resolved value of its operand: constants,
+//! names of global and local slots, jump targets and imported modules.
+//! Functions are listed after the code that creates them.
+
+use std::fmt::Write;
+
+use crate::{
+    code::{Bytecode, Instruction, Instructions, LineTable},
+    object::{CompiledFunction, Object},
+    runtime::trace,
+};
+
+/// Returns the disassembly of the main program, the imported
+/// modules and all of their functions.
+pub fn disassemble(bytecode: &Bytecode) -> String {
+    let mut disassembler = Disassembler {
+        bytecode,
+        output: String::new(),
+    };
+
+    disassembler.write_code(
+        trace::MAIN_NAME,
+        &bytecode.instructions,
+        &bytecode.lines,
+        &bytecode.global_names,
+        &[],
+    );
+    for module in bytecode.modules {
+        disassembler.write_code(
+            &trace::module_name(&module.path),
+            &module.instructions,
+            &module.lines,
+            &module.global_names,
+            &[],
+        );
+    }
+
+    disassembler.output
+}
+
+struct Disassembler<'a> {
+    bytecode: &'a Bytecode<'a>,
+    output: String,
+}
+
+impl Disassembler<'_> {
+    /// Writes the instructions followed by the functions they create.
+    fn write_code(
+        &mut self,
+        title: &str,
+        instructions: &Instructions,
+        lines: &LineTable,
+        global_names: &[String],
+        local_names: &[String],
+    ) {
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        writeln!(self.output, "== {} ==", title).unwrap();
+
+        let mut functions = vec![];
+        let mut last_line = None;
+        for (offset, instruction) in instructions.iter() {
+            let line = lines.line(offset);
+            let line_column = match last_line.replace(line) {
+                Some(last_line) if last_line == line => String::new(),
+                _ => line.to_string(),
+            };
+
+            let comment = match instruction {
+                Instruction::Constant(idx) => Some(self.describe_constant(idx as usize)),
+                Instruction::Closure { constant_index, .. } => {
+                    functions.push(constant_index as usize);
+                    Some(self.describe_constant(constant_index as usize))
+                }
+                Instruction::GetGlobal(idx) | Instruction::SetGlobal(idx) => {
+                    global_names.get(idx as usize).cloned()
+                }
+                Instruction::GetLocal(idx) | Instruction::SetLocal(idx) => {
+                    local_names.get(idx as usize).cloned()
+                }
+                Instruction::Jump(target) | Instruction::JumpNotTruthy(target) => {
+                    Some(format!("to {:04}", target))
+                }
+                Instruction::Import(idx) => self
+                    .bytecode
+                    .modules
+                    .get(idx as usize)
+                    .map(|module| module.path.display().to_string()),
+                _ => None,
+            };
+
+            let text = format!("{:04} {:>4} {}", offset, line_column, instruction);
+            match comment {
+                Some(comment) => writeln!(self.output, "{:<32} ; {}", text, comment),
+                None => writeln!(self.output, "{}", text),
+            }
+            .unwrap();
+        }
+
+        for idx in functions {
+            let Some(Object::CompiledFunction(function)) = self.bytecode.constants.get(idx) else {
+                continue;
+            };
+
+            let title = format!(
+                "{} (constant {}, {} arguments, {} locals)",
+                function_name(function),
+                idx,
+                function.num_arguments,
+                function.num_locals
+            );
+            self.write_code(
+                &title,
+                &function.instructions,
+                &function.lines,
+                global_names,
+                &function.local_names,
+            );
+        }
+    }
+
+    fn describe_constant(&self, idx: usize) -> String {
+        match self.bytecode.constants.get(idx) {
+            Some(Object::String(string)) => format!("{:?}", string),
+            Some(Object::CompiledFunction(function)) => format!("fn {}", function_name(function)),
+            Some(obj) => obj.inspect(),
+            None => "invalid constant".to_string(),
+        }
+    }
+}
+
+fn function_name(function: &CompiledFunction) -> &str {
+    function
+        .name
+        .as_ref()
+        .map_or(trace::ANONYMOUS_NAME, |name| name.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{compile::Compiler, parse::parse};
+
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        let input = r#"let greeting = "hi";
+let add = fn(a, b) {
+  let sum = a + b;
+  if (sum > 10) { return sum; }
+  sum * 2
+};
+add(1, len(greeting));
+add(2,
+  3)"#;
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        let bytecode = compiler.compile(&program).unwrap();
+
+        let expected = r#"== <main> ==
+0000    1 Constant 0             ; "hi"
+0003      SetGlobal 0            ; greeting
+0006    2 Closure 3 0            ; fn add
+0010      SetGlobal 1            ; add
+0013    7 GetGlobal 1            ; add
+0016      Constant 4             ; 1
+0019      GetBuiltin len
+0021      GetGlobal 0            ; greeting
+0024      Call 1
+0026      Call 2
+0028      Pop
+0029    8 GetGlobal 1            ; add
+0032      Constant 2             ; 2
+0035      Constant 5             ; 3
+0038      Call 2
+0040      Pop
+
+== add (constant 3, 2 arguments, 3 locals) ==
+0000    3 GetLocal 0             ; a
+0002      GetLocal 1             ; b
+0004      Add
+0005      SetLocal 2             ; sum
+0007    4 GetLocal 2             ; sum
+0009      Constant 1             ; 10
+0012      GreaterThan
+0013      JumpNotTruthy 22       ; to 0022
+0016      GetLocal 2             ; sum
+0018      ReturnValue
+0019      Jump 23                ; to 0023
+0022      Null
+0023      Pop
+0024    5 GetLocal 2             ; sum
+0026      Constant 2             ; 2
+0029      Mul
+0030      ReturnValue
+"#;
+        assert_eq!(disassemble(&bytecode), expected);
+    }
+}
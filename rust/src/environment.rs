@@ -2,10 +2,13 @@ use std::rc::{Rc, Weak};
 use std::{cell::RefCell, collections::HashMap};
 
 use crate::object::Object;
+use crate::token::Position;
 
 #[derive(Debug)]
 pub(super) struct EnvironmentInner {
     pub(super) store: HashMap<String, Object>,
+    // Positions of the definitions of constants in the store.
+    constants: HashMap<String, Position>,
     outer: Option<Weak<RefCell<EnvironmentInner>>>,
 }
 
@@ -31,25 +34,6 @@ impl EnvironmentInner {
     }
 }
 
-impl PartialEq for EnvironmentInner {
-    fn eq(&self, other: &Self) -> bool {
-        let outer_eq = match (&self.outer, &other.outer) {
-            (Some(outer1), Some(outer2)) => {
-                let outer1 = outer1
-                    .upgrade()
-                    .expect("Trying to access a dropped environment");
-                let outer2 = outer2
-                    .upgrade()
-                    .expect("Trying to access a dropped environment");
-                outer1 == outer2
-            }
-            (None, None) => true,
-            _ => false,
-        };
-        self.store == other.store && outer_eq
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Environment(pub(super) Weak<RefCell<EnvironmentInner>>);
 
@@ -78,6 +62,7 @@ impl Environment {
     pub fn new() -> (Self, EnvironmentOwner) {
         let env = Rc::new(RefCell::new(EnvironmentInner {
             store: HashMap::new(),
+            constants: HashMap::new(),
             outer: None,
         }));
 
@@ -103,9 +88,33 @@ impl Environment {
             .set(name, value);
     }
 
+    /// Returns the position of the definition, if the name is a constant
+    /// defined in this environment. Outer environments are not searched.
+    pub fn constant(&self, name: &str) -> Option<Position> {
+        self.0
+            .upgrade()
+            .expect("Trying to access a dropped environment")
+            .borrow()
+            .constants
+            .get(name)
+            .copied()
+    }
+
+    pub fn set_constant(&mut self, name: String, value: Object, position: Position) {
+        let env = self
+            .0
+            .upgrade()
+            .expect("Trying to access a dropped environment");
+        let mut env = env.borrow_mut();
+
+        env.constants.insert(name.clone(), position);
+        env.set(name, value);
+    }
+
     pub fn extend(&self) -> (Environment, EnvironmentOwner) {
         let env = Rc::new(RefCell::new(EnvironmentInner {
             store: HashMap::new(),
+            constants: HashMap::new(),
             outer: Some(self.0.clone()),
         }));
 
@@ -114,22 +123,25 @@ impl Environment {
         (Environment(env_weak), EnvironmentOwner(env))
     }
 
+    pub(super) fn outer(&self) -> Option<Environment> {
+        self.0
+            .upgrade()
+            .expect("Trying to access a dropped environment")
+            .borrow()
+            .outer
+            .clone()
+            .map(Environment)
+    }
+
     pub(super) fn upgrade(&self) -> Option<EnvironmentOwner> {
         self.0.upgrade().map(EnvironmentOwner)
     }
 }
 
+// Environments can contain functions that refer to the environment,
+// so they are compared by identity.
 impl PartialEq for Environment {
     fn eq(&self, other: &Self) -> bool {
-        let self_rc = self
-            .0
-            .upgrade()
-            .expect("Trying to access a dropped environment");
-        let other_rc = other
-            .0
-            .upgrade()
-            .expect("Trying to access a dropped environment");
-
-        self_rc == other_rc
+        Weak::ptr_eq(&self.0, &other.0)
     }
 }
@@ -1,23 +1,70 @@
-mod error;
-
 #[cfg(test)]
 mod test;
 
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::ast;
+use indexmap::IndexMap;
+
+use crate::ast::{self, modify};
 use crate::environment::{Environment, EnvironmentOwner};
+use crate::module;
+use crate::object::integer::Integer;
 use crate::object::*;
+use crate::runtime::{self, trace, StackFrame, StackTrace, DEFAULT_MAX_DEPTH};
+use crate::token::Position;
 
-pub use error::*;
+pub use crate::runtime::{Error, Result};
 
 use self::builtin::BuiltinFunction;
 
+// Remaining native stack, below which a new stack segment is allocated
+// for the next call, and the size of the allocated segments.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Result of evaluating a function body. Calls in a tail position
+/// are not applied, but returned together with the evaluated arguments.
+enum Evaluated {
+    Value(Object),
+    TailCall {
+        function: Object,
+        arguments: Vec<Object>,
+        line: usize,
+    },
+}
+
+/// Call of a function, a module or the main program, used for stack traces.
+struct CallFrame {
+    name: Option<Rc<String>>,
+    // Line of the innermost expression with a position that is being evaluated.
+    line: usize,
+}
+
+/// Result of running a generator until it's suspended or finished.
+enum GeneratorStep {
+    Yielded(Object),
+    Finished(Object),
+}
+
 pub struct Evaluator {
     environment: Environment,
 
     environment_owners: HashSet<EnvironmentOwner>,
+
+    // Cached exports of imported modules.
+    modules: HashMap<PathBuf, Object>,
+    // Chain of files that are currently being evaluated.
+    // The last one is the file that imports are resolved against.
+    files: Vec<PathBuf>,
+
+    // Number of nested calls and its maximum, see `set_max_depth`.
+    depth: usize,
+    max_depth: usize,
+
+    // Calls of the current evaluation, which are left in place by an error.
+    call_stack: Vec<CallFrame>,
 }
 
 impl Evaluator {
@@ -27,36 +74,89 @@ impl Evaluator {
         let mut evaluator = Self {
             environment: env,
             environment_owners: HashSet::new(),
+            modules: HashMap::new(),
+            files: vec![],
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            call_stack: vec![],
         };
         evaluator.environment_owners.insert(env_owner);
 
         evaluator
     }
 
-    pub fn evaluate(&mut self, program: &ast::Program) -> Result<Object> {
-        let mut res = Object::Null;
+    /// Creates a new evaluator for the file at the given path.
+    /// Imports are resolved relative to this file.
+    pub fn with_path(path: &Path) -> Self {
+        let mut evaluator = Self::new();
+        evaluator
+            .files
+            .push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        evaluator
+    }
+
+    /// Sets the maximum number of nested function calls. Calls above
+    /// the limit fail with `RecursionLimit` instead of overflowing the stack.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Returns the calls that were active when `evaluate` failed.
+    pub fn stack_trace(&self) -> StackTrace {
+        let frames = self.call_stack.iter().rev().map(|frame| {
+            StackFrame::new(frame.name.as_ref().map(|name| name.as_str()), frame.line)
+        });
 
+        StackTrace(frames.collect())
+    }
+
+    pub fn evaluate(&mut self, program: &ast::Program) -> Result<Object> {
         // Have to work on cloned environment, because we can't
         // have two &mut references to self. This doesn't matter
         // anyway, since environment is reference counted underneath.
         let mut env = self.environment.clone();
 
+        self.call_stack = vec![CallFrame {
+            name: Some(Rc::new(trace::MAIN_NAME.to_owned())),
+            line: 1,
+        }];
+
+        let res = self.evaluate_program(program, &mut env);
+
+        self.collect_garbage();
+        res
+    }
+
+    fn evaluate_program(
+        &mut self,
+        program: &ast::Program,
+        environment: &mut Environment,
+    ) -> Result<Object> {
+        let mut res = Object::Null;
+
         for stmt in &program.statements {
-            res = self.evaluate_statement(stmt, &mut env)?;
+            res = match self.evaluate_statement(stmt, environment) {
+                Err(Error::PropagatedError(err)) => Object::Return(Rc::new(err)),
+                res => res?,
+            };
 
             if let Object::Return(obj) = res {
-                self.collect_garbage();
                 return Ok((*obj).clone());
             }
         }
 
-        self.collect_garbage();
         Ok(res)
     }
 
+    // Environment owners are hashed by pointer, so interior mutability
+    // doesn't affect the hash.
+    #[allow(clippy::mutable_key_type)]
     fn collect_garbage(&mut self) {
         let mut used = HashSet::new();
         Self::collect_used_environments(&self.environment, &mut used);
+        for exports in self.modules.values() {
+            Self::collect_used_environments_from_obj(exports, &mut used);
+        }
 
         let mut to_remove = Vec::new();
         for env in self.environment_owners.iter() {
@@ -70,6 +170,7 @@ impl Evaluator {
         }
     }
 
+    #[allow(clippy::mutable_key_type)]
     fn collect_used_environments(env: &Environment, used: &mut HashSet<EnvironmentOwner>) {
         let inserted = used.insert(
             env.upgrade()
@@ -87,12 +188,35 @@ impl Evaluator {
         for val in env_rc.borrow().store.values() {
             Self::collect_used_environments_from_obj(val, used);
         }
+
+        if let Some(outer) = env.outer() {
+            Self::collect_used_environments(&outer, used);
+        }
     }
 
+    #[allow(clippy::mutable_key_type)]
     fn collect_used_environments_from_obj(obj: &Object, used: &mut HashSet<EnvironmentOwner>) {
         match obj {
             Object::Function(func) => Self::collect_used_environments(&func.environment, used),
             Object::Return(obj) => Self::collect_used_environments_from_obj(obj, used),
+            Object::Error(err) => Self::collect_used_environments_from_obj(&err.data, used),
+            Object::Generator(generator) => {
+                if let GeneratorState::Evaluated { blocks, .. } = &*generator.state() {
+                    for (_, _, environment, _) in blocks {
+                        Self::collect_used_environments(environment, used);
+                    }
+                }
+            }
+            Object::Array(arr) => {
+                for obj in arr.iter() {
+                    Self::collect_used_environments_from_obj(obj, used);
+                }
+            }
+            Object::HashMap(map) => {
+                for obj in map.values() {
+                    Self::collect_used_environments_from_obj(obj, used);
+                }
+            }
             _ => {}
         }
     }
@@ -103,9 +227,29 @@ impl Evaluator {
         environment: &mut Environment,
     ) -> Result<Object> {
         match stmt {
-            ast::Statement::Let { name, value } => {
-                let val = self.evaluate_expression(value, environment)?;
-                environment.set(name.clone(), val);
+            ast::Statement::Let {
+                name,
+                value,
+                constant,
+                position,
+                ..
+            } => {
+                if let Some(defined) = environment.constant(name) {
+                    return Err(Error::AssignToConstant {
+                        name: name.clone(),
+                        defined,
+                        assigned: *position,
+                    });
+                }
+
+                let val = self.at_line(Some(*position), |evaluator| {
+                    evaluator.evaluate_expression(value, environment)
+                })?;
+                if *constant {
+                    environment.set_constant(name.clone(), val, *position);
+                } else {
+                    environment.set(name.clone(), val);
+                }
 
                 Ok(Object::Null)
             }
@@ -114,13 +258,77 @@ impl Evaluator {
                 Ok(Object::Return(Rc::new(val)))
             }
             ast::Statement::Expression(expr) => self.evaluate_expression(expr, environment),
+            // Yield statements of generators are evaluated by `run_generator`.
+            ast::Statement::Yield(_) => Err(Error::YieldOutsideGenerator),
+            ast::Statement::Import { path, name } => {
+                let exports = self.evaluate_import(path)?;
+                environment.set(name.clone(), exports);
+
+                Ok(Object::Null)
+            }
+            // Exports are collected when the module is evaluated.
+            ast::Statement::Export(_) => Ok(Object::Null),
+        }
+    }
+
+    /// Evaluates the imported module, if it wasn't evaluated yet,
+    /// and returns its exports.
+    fn evaluate_import(&mut self, path: &str) -> Result<Object> {
+        let path = module::resolve(path, self.files.last().map(PathBuf::as_path))?;
+        module::check_cycle(&self.files, &path)?;
+
+        if let Some(exports) = self.modules.get(&path) {
+            return Ok(exports.clone());
+        }
+
+        let program = module::load(&path)?;
+
+        // Every module is evaluated in its own root environment,
+        // so that it has its own global namespace.
+        let (mut env, env_owner) = Environment::new();
+        self.environment_owners.insert(env_owner);
+
+        self.files.push(path.clone());
+        let frame = CallFrame {
+            name: Some(Rc::new(trace::module_name(&path))),
+            line: 1,
+        };
+        let res = self.enter_call(|evaluator| {
+            evaluator.call_stack.push(frame);
+            evaluator.evaluate_program(&program, &mut env)
+        });
+        self.files.pop();
+        res?;
+        self.call_stack.pop();
+
+        let mut exports = IndexMap::new();
+        for name in module::exports(&program) {
+            let value = env
+                .get(name)
+                .ok_or_else(|| Error::UnknownIdentifier(name.clone()))?;
+
+            exports.insert(HashKey::String(Rc::new(name.clone())), value);
         }
+
+        let exports = Object::HashMap(Rc::new(exports));
+        self.modules.insert(path, exports.clone());
+        Ok(exports)
     }
 
     fn evaluate_expression(
         &mut self,
         expr: &ast::Expression,
         environment: &mut Environment,
+    ) -> Result<Object> {
+        self.at_line(expr.position(), |evaluator| {
+            evaluator.evaluate_expression_node(expr, environment)
+        })
+    }
+
+    fn evaluate_expression_node(
+        &mut self,
+        expr: &ast::Expression,
+        environment: &mut Environment,
     ) -> Result<Object> {
         match expr {
             ast::Expression::Identifier(ident) => {
@@ -135,7 +343,9 @@ impl Evaluator {
                 Err(Error::UnknownIdentifier(ident.clone()))
             }
             ast::Expression::StringLiteral(val) => Ok(Object::String(Rc::new(val.clone()))),
+            ast::Expression::BytesLiteral(val) => Ok(Object::Bytes(Rc::new(val.clone()))),
             ast::Expression::IntegerLiteral(val) => Ok(Object::Integer(*val)),
+            ast::Expression::BigIntegerLiteral(val) => Ok(Object::BigInteger(Rc::new(val.clone()))),
             ast::Expression::BooleanLiteral(val) => Ok(Object::Boolean(*val)),
             ast::Expression::ArrayLiteral(arr) => {
                 let res: Result<Vec<_>> = arr
@@ -153,17 +363,31 @@ impl Evaluator {
                 self.evaluate_infix_operator(expr, environment)
             }
             ast::Expression::If { .. } => self.evaluate_if_expression(expr, environment),
+            ast::Expression::Block(block) => {
+                let mut block_env = self.extend_environment(environment);
+                self.evaluate_block_statement(block, &mut block_env)
+            }
             ast::Expression::FunctionLiteral {
-                name: _,
+                name,
                 parameters,
                 body,
+                position,
+                ..
             } => Ok(Object::Function(FunctionObject {
                 parameters: Rc::new(parameters.clone()),
                 body: body.clone(),
                 environment: environment.clone(),
+                generator: body.contains_yield(),
+                name: name.clone().map(Rc::new),
+                line: position.line,
             })),
+            ast::Expression::MacroLiteral { .. } => Err(Error::UnexpandedMacro),
             ast::Expression::FunctionCall { .. } => self.evaluate_function_call(expr, environment),
             ast::Expression::Index { .. } => self.evaluate_index(expr, environment),
+            ast::Expression::Try(expr) => match self.evaluate_expression(expr, environment)? {
+                Object::Error(err) => Err(Error::PropagatedError(Object::Error(err))),
+                obj => Ok(obj),
+            },
         }
     }
 
@@ -172,7 +396,10 @@ impl Evaluator {
         expr: &ast::Expression,
         environment: &mut Environment,
     ) -> Result<Object> {
-        let ast::Expression::PrefixOperator { operator, right } = expr else {
+        let ast::Expression::PrefixOperator {
+            operator, right, ..
+        } = expr
+        else {
             panic!("Expected PrefixOperator expression, got {:?}", expr);
         };
 
@@ -184,12 +411,13 @@ impl Evaluator {
                 Object::Null => Ok(Object::Boolean(true)),
                 _ => Ok(Object::Boolean(false)),
             },
-            ast::PrefixOperatorKind::Negative => match right {
-                Object::Integer(val) => Ok(Object::Integer(-val)),
-                _ => Err(Error::UnknownOperator(format!(
-                    "-{}",
-                    DataType::from(right)
-                ))),
+            ast::PrefixOperatorKind::Negative => match Integer::from_object(&right) {
+                Some(value) => Ok(value.negate()),
+                None => Err(Error::unsupported_operand("-", right.into())),
+            },
+            ast::PrefixOperatorKind::BitNot => match Integer::from_object(&right) {
+                Some(value) => Ok(value.bit_not()),
+                None => Err(Error::unsupported_operand("~", right.into())),
             },
         }
     }
@@ -203,6 +431,7 @@ impl Evaluator {
             operator,
             left,
             right,
+            ..
         } = expr
         else {
             panic!("Expected InfixOperator expression, got {:?}", expr);
@@ -211,73 +440,49 @@ impl Evaluator {
         let left = self.evaluate_expression(left, environment)?;
         let right = self.evaluate_expression(right, environment)?;
 
-        if let (Object::Integer(left), Object::Integer(right)) = (&left, &right) {
+        if let (Some(left), Some(right)) =
+            (Integer::from_object(&left), Integer::from_object(&right))
+        {
             let res = match operator {
-                ast::InfixOperatorKind::Add => Object::Integer(left + right),
-                ast::InfixOperatorKind::Subtract => Object::Integer(left - right),
-                ast::InfixOperatorKind::Multiply => Object::Integer(left * right),
-                ast::InfixOperatorKind::Divide => Object::Integer(left / right),
-                ast::InfixOperatorKind::Equal => Object::Boolean(left == right),
-                ast::InfixOperatorKind::NotEqual => Object::Boolean(left != right),
-                ast::InfixOperatorKind::GreaterThan => Object::Boolean(left > right),
-                ast::InfixOperatorKind::LessThan => Object::Boolean(left < right),
-            };
-
-            return Ok(res);
-        }
-
-        if let (Object::Boolean(left_bool), Object::Boolean(right_bool)) = (&left, &right) {
-            let res = match operator {
-                ast::InfixOperatorKind::Equal => Object::Boolean(left_bool == right_bool),
-                ast::InfixOperatorKind::NotEqual => Object::Boolean(left_bool != right_bool),
-                _ => {
-                    return Err(Error::UnknownOperator(format!(
-                        "{} {} {}",
-                        DataType::from(left),
-                        operator.debug_str(),
-                        DataType::from(right),
-                    )))
-                }
-            };
-
-            return Ok(res);
-        }
-
-        if let (Object::String(left_str), Object::String(right_str)) = (&left, &right) {
-            let res = match operator {
-                ast::InfixOperatorKind::Add => {
-                    let mut res_str = left_str.to_string();
-                    res_str.push_str(right_str);
-                    Object::String(Rc::new(res_str))
-                }
-                _ => {
-                    return Err(Error::UnknownOperator(format!(
-                        "{} {} {}",
-                        DataType::from(left),
-                        operator.debug_str(),
-                        DataType::from(right),
-                    )));
+                ast::InfixOperatorKind::Add => left.plus(right),
+                ast::InfixOperatorKind::Subtract => left.minus(right),
+                ast::InfixOperatorKind::Multiply => left.times(right),
+                ast::InfixOperatorKind::Divide => {
+                    left.divide(right).ok_or(Error::DivisionByZero)?
                 }
+                ast::InfixOperatorKind::Equal => Object::Boolean(left.compare(right).is_eq()),
+                ast::InfixOperatorKind::NotEqual => Object::Boolean(left.compare(right).is_ne()),
+                ast::InfixOperatorKind::GreaterThan => Object::Boolean(left.compare(right).is_gt()),
+                ast::InfixOperatorKind::LessThan => Object::Boolean(left.compare(right).is_lt()),
+                ast::InfixOperatorKind::BitAnd => left.bit_and(right),
+                ast::InfixOperatorKind::BitOr => left.bit_or(right),
+                ast::InfixOperatorKind::BitXor => left.bit_xor(right),
+                ast::InfixOperatorKind::ShiftLeft => left
+                    .shift_left(right)
+                    .ok_or_else(|| Error::InvalidShiftAmount(right.to_string()))?,
+                ast::InfixOperatorKind::ShiftRight => left
+                    .shift_right(right)
+                    .ok_or_else(|| Error::InvalidShiftAmount(right.to_string()))?,
             };
 
             return Ok(res);
         }
 
-        if DataType::from(&left) != DataType::from(&right) {
-            return Err(Error::TypeMismatch(format!(
-                "{} {} {}",
-                DataType::from(left),
-                operator.debug_str(),
-                DataType::from(right),
-            )));
+        match (operator, &left, &right) {
+            (ast::InfixOperatorKind::Equal, _, _) => Ok(Object::Boolean(left == right)),
+            (ast::InfixOperatorKind::NotEqual, _, _) => Ok(Object::Boolean(left != right)),
+            (ast::InfixOperatorKind::Add, Object::String(left), Object::String(right)) => {
+                Ok(Object::String(Rc::new(format!("{}{}", left, right))))
+            }
+            (ast::InfixOperatorKind::Add, Object::Bytes(left), Object::Bytes(right)) => Ok(
+                Object::Bytes(Rc::new([left.as_slice(), right.as_slice()].concat())),
+            ),
+            _ => Err(Error::unsupported_operands(
+                &operator.debug_str(),
+                left.into(),
+                right.into(),
+            )),
         }
-
-        Err(Error::UnknownOperator(format!(
-            "{} {} {}",
-            DataType::from(&left),
-            operator.debug_str(),
-            DataType::from(&right),
-        )))
     }
 
     fn evaluate_if_expression(
@@ -289,6 +494,7 @@ impl Evaluator {
             condition,
             consequence,
             alternative,
+            ..
         } = expr
         else {
             panic!("Expected If expression, got {:?}", expr);
@@ -296,13 +502,22 @@ impl Evaluator {
 
         let condition = self.evaluate_expression(condition, environment)?;
 
+        let mut block_env = self.extend_environment(environment);
         if condition.is_truthy() {
-            self.evaluate_block_statement(consequence, environment)
+            self.evaluate_block_statement(consequence, &mut block_env)
         } else {
-            self.evaluate_block_statement(alternative, environment)
+            self.evaluate_block_statement(alternative, &mut block_env)
         }
     }
 
+    /// Creates a new scope enclosed by the environment.
+    fn extend_environment(&mut self, environment: &Environment) -> Environment {
+        let (env, env_owner) = environment.extend();
+        self.environment_owners.insert(env_owner);
+
+        env
+    }
+
     fn evaluate_block_statement(
         &mut self,
         stmt: &ast::BlockStatement,
@@ -326,39 +541,456 @@ impl Evaluator {
         expr: &ast::Expression,
         environment: &mut Environment,
     ) -> Result<Object> {
+        if let Some(arguments) = expr.call_arguments("quote") {
+            return self.evaluate_quote(arguments, environment);
+        }
+
+        let (function, args) = self.evaluate_call_operands(expr, environment)?;
+        self.apply_function(function, args)
+    }
+
+    fn evaluate_call_operands(
+        &mut self,
+        expr: &ast::Expression,
+        environment: &mut Environment,
+    ) -> Result<(Object, Vec<Object>)> {
         let ast::Expression::FunctionCall {
             function,
             arguments,
+            ..
         } = expr
         else {
             panic!("Expected FunctionCall expression, got {:?}", expr);
         };
 
+        let function = self.evaluate_expression(function, environment)?;
+
         let args = arguments
             .iter()
             .map(|expr| self.evaluate_expression(expr, environment))
             .collect::<Result<Vec<_>>>()?;
 
-        let function = self.evaluate_expression(function, environment)?;
+        Ok((function, args))
+    }
+
+    /// Applies the function to the arguments. Calls in a tail position
+    /// are returned from the function body instead of being evaluated,
+    /// and are applied here in a loop, so that tail recursion runs
+    /// in a constant stack.
+    fn apply_function(&mut self, mut function: Object, mut args: Vec<Object>) -> Result<Object> {
+        // Whether the frame on top of the call stack belongs to the function
+        // that made the tail call. It's replaced by the frame of the called
+        // function, like in the VM, and left in place for builtins.
+        let mut tail_call = false;
+
+        loop {
+            let function_obj = match function {
+                Object::Function(function) => function,
+                Object::Builtin(BuiltinFunction::Next) => {
+                    let generator = builtin::next_argument(&args)?.clone();
+                    let res = self.resume_generator(generator)?;
+                    self.leave_tail_call(tail_call);
+                    return Ok(res);
+                }
+                Object::Builtin(fun) => {
+                    let res = fun.execute(&args)?;
+                    self.leave_tail_call(tail_call);
+                    return Ok(res);
+                }
+                _ => return Err(Error::NotAFunction(function.into())),
+            };
 
-        match function {
-            Object::Function(function) => {
-                let (mut extended_env, extended_env_owner) = function.environment.extend();
-                self.environment_owners.insert(extended_env_owner);
+            if args.len() != function_obj.parameters.len() {
+                return Err(Error::WrongNumberOfArguments {
+                    expected: function_obj.parameters.len(),
+                    got: args.len(),
+                });
+            }
+
+            let mut extended_env = self.extend_environment(&function_obj.environment);
+
+            for (param, arg) in function_obj.parameters.iter().zip(args) {
+                extended_env.set(param.clone(), arg);
+            }
 
-                for (index, param) in function.parameters.iter().enumerate() {
-                    extended_env.set(param.clone(), args[index].clone());
+            if function_obj.generator {
+                self.leave_tail_call(tail_call);
+                return Ok(Object::Generator(Generator::new(
+                    GeneratorState::Evaluated {
+                        blocks: vec![(
+                            function_obj.body.clone(),
+                            0,
+                            extended_env,
+                            function_obj.line,
+                        )],
+                        name: function_obj.name.clone(),
+                    },
+                )));
+            }
+
+            let frame = CallFrame {
+                name: function_obj.name.clone(),
+                line: function_obj.line,
+            };
+            let evaluated = self.enter_call(|evaluator| {
+                if tail_call {
+                    *evaluator.call_stack.last_mut().expect("Missing call frame") = frame;
+                } else {
+                    evaluator.call_stack.push(frame);
                 }
 
-                let evaluated = self.evaluate_block_statement(&function.body, &mut extended_env)?;
-                match evaluated {
-                    Object::Return(obj) => Ok((*obj).clone()),
-                    _ => Ok(evaluated),
+                evaluator.evaluate_tail_block(&function_obj.body, &mut extended_env, true)
+            });
+            let evaluated = match evaluated {
+                Err(Error::PropagatedError(err)) => Evaluated::Value(err),
+                evaluated => evaluated?,
+            };
+
+            match evaluated {
+                Evaluated::TailCall {
+                    function: next_function,
+                    arguments,
+                    line,
+                } => {
+                    self.set_line(line);
+                    tail_call = true;
+                    function = next_function;
+                    args = arguments;
+                }
+                Evaluated::Value(Object::Return(obj)) => {
+                    self.call_stack.pop();
+                    return Ok((*obj).clone());
+                }
+                Evaluated::Value(obj) => {
+                    self.call_stack.pop();
+                    return Ok(obj);
+                }
+            }
+        }
+    }
+
+    /// Pops the frame of the function that made the tail call,
+    /// once the called builtin or generator function returned.
+    fn leave_tail_call(&mut self, tail_call: bool) {
+        if tail_call {
+            self.call_stack.pop();
+        }
+    }
+
+    /// Evaluates with the line of the position in the current frame. The line
+    /// is restored afterwards, unless there is an error for the stack trace.
+    fn at_line<T>(
+        &mut self,
+        position: Option<Position>,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let (Some(position), Some(frame)) = (position, self.call_stack.last_mut()) else {
+            return f(self);
+        };
+        let outer_line = std::mem::replace(&mut frame.line, position.line);
+
+        let res = f(self);
+        if res.is_ok() {
+            self.set_line(outer_line);
+        }
+
+        res
+    }
+
+    fn set_line(&mut self, line: usize) {
+        if let Some(frame) = self.call_stack.last_mut() {
+            frame.line = line;
+        }
+    }
+
+    /// Runs the body of a function, generator or module one call deeper.
+    /// The native stack is grown on the heap when it runs out, so the depth
+    /// is only limited by `max_depth`.
+    fn enter_call<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= self.max_depth {
+            return Err(Error::RecursionLimit {
+                depth: self.max_depth,
+            });
+        }
+
+        self.depth += 1;
+        let res = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || f(self));
+        self.depth -= 1;
+
+        res
+    }
+
+    /// Evaluates a block in a function body. Return statements are always
+    /// in a tail position, and the last expression statement is in a tail
+    /// position if the block itself is.
+    fn evaluate_tail_block(
+        &mut self,
+        block: &ast::BlockStatement,
+        environment: &mut Environment,
+        tail: bool,
+    ) -> Result<Evaluated> {
+        let mut res = Evaluated::Value(Object::Null);
+
+        let last_idx = block.statements.len().saturating_sub(1);
+        for (idx, stmt) in block.statements.iter().enumerate() {
+            res = match stmt {
+                ast::Statement::Return(expr) => {
+                    return match self.evaluate_tail_expression(expr, environment, true)? {
+                        Evaluated::Value(obj) => Ok(Evaluated::Value(Object::Return(Rc::new(obj)))),
+                        tail_call => Ok(tail_call),
+                    };
                 }
+                ast::Statement::Expression(expr) => {
+                    self.evaluate_tail_expression(expr, environment, tail && idx == last_idx)?
+                }
+                _ => Evaluated::Value(self.evaluate_statement(stmt, environment)?),
+            };
+
+            if matches!(
+                res,
+                Evaluated::Value(Object::Return(_)) | Evaluated::TailCall { .. }
+            ) {
+                return Ok(res);
             }
-            Object::Builtin(fun) => Ok(fun.execute(&args)?),
-            _ => Err(Error::NotAFunction(function.into())),
         }
+
+        Ok(res)
+    }
+
+    fn evaluate_tail_expression(
+        &mut self,
+        expr: &ast::Expression,
+        environment: &mut Environment,
+        tail: bool,
+    ) -> Result<Evaluated> {
+        match expr {
+            ast::Expression::FunctionCall { position, .. }
+                if tail && expr.call_arguments("quote").is_none() =>
+            {
+                let (function, arguments) = self.at_line(Some(*position), |evaluator| {
+                    evaluator.evaluate_call_operands(expr, environment)
+                })?;
+                Ok(Evaluated::TailCall {
+                    function,
+                    arguments,
+                    line: position.line,
+                })
+            }
+            ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                position,
+            } => self.at_line(Some(*position), |evaluator| {
+                let condition = evaluator.evaluate_expression(condition, environment)?;
+
+                let mut block_env = evaluator.extend_environment(environment);
+                if condition.is_truthy() {
+                    evaluator.evaluate_tail_block(consequence, &mut block_env, tail)
+                } else {
+                    evaluator.evaluate_tail_block(alternative, &mut block_env, tail)
+                }
+            }),
+            ast::Expression::Block(block) => {
+                let mut block_env = self.extend_environment(environment);
+                self.evaluate_tail_block(block, &mut block_env, tail)
+            }
+            _ => Ok(Evaluated::Value(
+                self.evaluate_expression(expr, environment)?,
+            )),
+        }
+    }
+
+    /// Runs the generator until the next `yield` and returns the yielded
+    /// value. A returned generator is continued in place of the finished one,
+    /// a returned error is returned from `next` and anything else finishes
+    /// the generator.
+    fn resume_generator(&mut self, generator: Generator) -> Result<Object> {
+        loop {
+            let (mut blocks, name) = match generator.replace(GeneratorState::Running) {
+                GeneratorState::Evaluated { blocks, name } => (blocks, name),
+                GeneratorState::Done => {
+                    generator.replace(GeneratorState::Done);
+                    return Ok(Object::Done);
+                }
+                GeneratorState::Running => return Err(Error::GeneratorRunning),
+                GeneratorState::Compiled { .. } => {
+                    unreachable!("generator of the VM resumed by the evaluator")
+                }
+            };
+
+            let frame = CallFrame {
+                name: name.clone(),
+                line: 0,
+            };
+            let res = self.enter_call(|evaluator| {
+                evaluator.call_stack.push(frame);
+                evaluator.run_generator(&mut blocks)
+            });
+            let res = match res {
+                Err(Error::PropagatedError(err)) => Ok(GeneratorStep::Finished(err)),
+                res => res,
+            };
+            if res.is_ok() {
+                self.call_stack.pop();
+            }
+
+            match res {
+                Ok(GeneratorStep::Yielded(value)) => {
+                    generator.replace(GeneratorState::Evaluated { blocks, name });
+                    return Ok(value);
+                }
+                Ok(GeneratorStep::Finished(Object::Generator(next))) if next != generator => {
+                    let state = next.replace(GeneratorState::Done);
+                    generator.replace(state);
+                }
+                Ok(GeneratorStep::Finished(value)) => {
+                    generator.replace(GeneratorState::Done);
+                    return match value {
+                        Object::Error(_) => Ok(value),
+                        _ => Ok(Object::Done),
+                    };
+                }
+                Err(err) => {
+                    generator.replace(GeneratorState::Done);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Evaluates the statements of the generator body from the suspended
+    /// position. Blocks of `if` statements and block statements are pushed
+    /// onto `blocks`, so that the evaluation can be suspended inside them.
+    fn run_generator(
+        &mut self,
+        blocks: &mut Vec<(ast::BlockStatement, usize, Environment, usize)>,
+    ) -> Result<GeneratorStep> {
+        let mut res = Object::Null;
+
+        while let Some((block, idx, environment, line)) = blocks.last_mut() {
+            let Some(stmt) = block.statements.get(*idx) else {
+                blocks.pop();
+                continue;
+            };
+            *idx += 1;
+            self.set_line(*line);
+
+            match stmt {
+                ast::Statement::Yield(expr) => {
+                    let value = self.evaluate_expression(expr, environment)?;
+                    return Ok(GeneratorStep::Yielded(value));
+                }
+                ast::Statement::Expression(ast::Expression::If {
+                    condition,
+                    consequence,
+                    alternative,
+                    position,
+                }) => {
+                    let condition = self.at_line(Some(*position), |evaluator| {
+                        evaluator.evaluate_expression(condition, environment)
+                    })?;
+                    let block = if condition.is_truthy() {
+                        consequence.clone()
+                    } else {
+                        alternative.clone()
+                    };
+
+                    let line = position.line;
+                    let environment = self.extend_environment(environment);
+                    blocks.push((block, 0, environment, line));
+                    res = Object::Null;
+                }
+                ast::Statement::Expression(ast::Expression::Block(block)) => {
+                    let block = block.clone();
+                    let line = *line;
+                    let environment = self.extend_environment(environment);
+                    blocks.push((block, 0, environment, line));
+                    res = Object::Null;
+                }
+                _ => {
+                    res = self.evaluate_statement(stmt, environment)?;
+                    if let Object::Return(obj) = res {
+                        return Ok(GeneratorStep::Finished((*obj).clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(GeneratorStep::Finished(res))
+    }
+
+    fn evaluate_quote(
+        &mut self,
+        arguments: &[ast::Expression],
+        environment: &mut Environment,
+    ) -> Result<Object> {
+        if arguments.len() != 1 {
+            return Err(Error::WrongNumberOfArguments {
+                expected: 1,
+                got: arguments.len(),
+            });
+        }
+
+        let expr = modify::modify_expression(arguments[0].clone(), &mut |expr| {
+            self.evaluate_unquote(expr, environment)
+        })?;
+
+        Ok(Object::Quote(Rc::new(expr)))
+    }
+
+    /// Replaces `unquote(expr)` calls with the AST node
+    /// of the evaluated `expr`.
+    fn evaluate_unquote(
+        &mut self,
+        expr: ast::Expression,
+        environment: &mut Environment,
+    ) -> Result<ast::Expression> {
+        let Some(arguments) = expr.call_arguments("unquote") else {
+            return Ok(expr);
+        };
+
+        if arguments.len() != 1 {
+            return Err(Error::WrongNumberOfArguments {
+                expected: 1,
+                got: arguments.len(),
+            });
+        }
+
+        let value = self.evaluate_expression(&arguments[0], environment)?;
+        runtime::unquote(value)
+    }
+
+    /// Evaluates the body of a macro with the quoted arguments
+    /// bound to the parameters.
+    pub(crate) fn evaluate_macro(
+        &mut self,
+        parameters: &[String],
+        body: &ast::BlockStatement,
+        arguments: Vec<Object>,
+    ) -> Result<Object> {
+        if parameters.len() != arguments.len() {
+            return Err(Error::WrongNumberOfArguments {
+                expected: parameters.len(),
+                got: arguments.len(),
+            });
+        }
+
+        let (mut env, env_owner) = self.environment.extend();
+        self.environment_owners.insert(env_owner);
+
+        for (param, arg) in parameters.iter().zip(arguments) {
+            env.set(param.clone(), arg);
+        }
+
+        let res = match self.evaluate_block_statement(body, &mut env) {
+            Err(Error::PropagatedError(err)) => Ok(err),
+            Ok(Object::Return(obj)) => Ok((*obj).clone()),
+            res => res,
+        };
+
+        self.collect_garbage();
+        res
     }
 
     fn evaluate_hash_literal(
@@ -370,12 +1002,15 @@ impl Evaluator {
             panic!("Expected HashLiteral expression, got: {:?}", expr);
         };
 
-        let mut res = HashMap::new();
-
+        let mut evaluated = Vec::with_capacity(pairs.len());
         for pair in pairs {
             let key = self.evaluate_expression(&pair.key, environment)?;
             let value = self.evaluate_expression(&pair.value, environment)?;
+            evaluated.push((key, value));
+        }
 
+        let mut res = IndexMap::new();
+        for (key, value) in evaluated {
             res.insert(key.try_into().map_err(Error::NotHashable)?, value);
         }
 
@@ -387,7 +1022,7 @@ impl Evaluator {
         expr: &ast::Expression,
         environment: &mut Environment,
     ) -> Result<Object> {
-        let ast::Expression::Index { left, index } = expr else {
+        let ast::Expression::Index { left, index, .. } = expr else {
             panic!("Expected Index expression, got {:?}", expr);
         };
 
@@ -413,8 +1048,21 @@ impl Evaluator {
 
                 Ok(arr[idx as usize].clone())
             }
+            Object::Bytes(bytes) => {
+                let Object::Integer(idx) = index_obj else {
+                    return Err(Error::IndexOperatorNotSupported(
+                        left_obj.into(),
+                        index_obj.into(),
+                    ));
+                };
+
+                Ok(usize::try_from(idx)
+                    .ok()
+                    .and_then(|idx| bytes.get(idx))
+                    .map_or(Object::Null, |byte| Object::Integer(*byte as i64)))
+            }
             Object::HashMap(map) => {
-                let key = index_obj.try_into().map_err(Error::NotHashable)?;
+                let key: HashKey = index_obj.try_into().map_err(Error::NotHashable)?;
                 match map.get(&key) {
                     Some(obj) => Ok(obj.clone()),
                     None => Ok(Object::Null),
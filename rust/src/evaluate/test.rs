@@ -1,11 +1,12 @@
-use std::rc::Rc;
+use std::{path::Path, rc::Rc};
 
 use crate::{
     evaluate::{Error, Evaluator, HashKey, Object, Result},
-    parse,
+    module, parse,
+    token::Position,
 };
 
-use super::{builtin::ExecutionError, DataType};
+use super::{builtin::ExecutionError, DataType, ErrorObject};
 
 #[test]
 fn test_eval_integer() -> Result<()> {
@@ -38,6 +39,95 @@ fn test_eval_integer() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_big_integers() -> Result<()> {
+    let big = |value: &str| Object::BigInteger(Rc::new(value.parse().unwrap()));
+
+    let tests = [
+        (
+            "let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; f(25)",
+            big("15511210043330985984000000"),
+        ),
+        ("9223372036854775807 + 1", big("9223372036854775808")),
+        ("9223372036854775808", big("9223372036854775808")),
+        ("-9223372036854775808", Object::Integer(i64::MIN)),
+        ("0x1_0000_0000_0000_0000 - 1", big("18446744073709551615")),
+        (
+            "18446744073709551616 / 4294967296",
+            Object::Integer(4294967296),
+        ),
+        ("9223372036854775807 + 1 - 1", Object::Integer(i64::MAX)),
+        ("-9223372036854775807 - 1", Object::Integer(i64::MIN)),
+        ("-(-9223372036854775807 - 1)", big("9223372036854775808")),
+        (
+            "(-9223372036854775807 - 1) / -1",
+            big("9223372036854775808"),
+        ),
+        (
+            "4611686018427387904 * 4 / 8",
+            Object::Integer(2305843009213693952),
+        ),
+        (
+            "9223372036854775807 * 2 > 9223372036854775807",
+            Object::Boolean(true),
+        ),
+        ("9223372036854775807 * 2 < 1", Object::Boolean(false)),
+        (
+            "9223372036854775807 * 2 == 9223372036854775807 + 9223372036854775807",
+            Object::Boolean(true),
+        ),
+        (
+            "{9223372036854775807 * 3: 1}[9223372036854775807 * 2 + 9223372036854775807]",
+            Object::Integer(1),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate(&program)?;
+        assert_eq!(result, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bitwise_operators() -> Result<()> {
+    let tests = [
+        ("0b1100 & 0b1010", Object::Integer(0b1000)),
+        ("0b1100 | 0b1010", Object::Integer(0b1110)),
+        ("0b1100 ^ 0b1010", Object::Integer(0b0110)),
+        ("~0", Object::Integer(-1)),
+        ("~0xff & 0x1ff", Object::Integer(0x100)),
+        ("1 << 10", Object::Integer(1024)),
+        ("-16 >> 2", Object::Integer(-4)),
+        ("-1 >> 100", Object::Integer(-1)),
+        ("1 >> 100", Object::Integer(0)),
+        ("(1 | 2) == 3", Object::Boolean(true)),
+        ("0x10 + 0o10 + 0b10 + 1_0", Object::Integer(36)),
+        ("(1 << 64) >> 64", Object::Integer(1)),
+        (
+            "(1 << 64) - 1 == 0xffff_ffff * 0x1_0000_0001",
+            Object::Boolean(true),
+        ),
+        ("(1 << 64 | 1) & 3", Object::Integer(1)),
+        ("~(1 << 64) + (1 << 64)", Object::Integer(-1)),
+        ("(1 << 100) ^ (1 << 100)", Object::Integer(0)),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate(&program)?;
+        assert_eq!(result, expected);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_eval_string() -> Result<()> {
     let input = r#""Hello World!"#;
@@ -222,6 +312,10 @@ fn test_error_handling() {
             Error::UnknownOperator("BOOLEAN + BOOLEAN".to_string()),
         ),
         ("foobar", Error::UnknownIdentifier("foobar".to_string())),
+        ("1 / 0", Error::DivisionByZero),
+        ("1 << -1", Error::InvalidShiftAmount("-1".to_string())),
+        ("~true", Error::UnknownOperator("~BOOLEAN".to_string())),
+        ("9223372036854775807 * 2 / 0", Error::DivisionByZero),
         (
             "\"Hello\" - \"World\"",
             Error::UnknownOperator(String::from("STRING - STRING")),
@@ -230,6 +324,10 @@ fn test_error_handling() {
             r#"{"name": "Monkey"}[fn(x) { x }];"#,
             Error::NotHashable(DataType::Function),
         ),
+        (
+            r#"{[1, fn(x) { x }]: 1}"#,
+            Error::NotHashable(DataType::Function),
+        ),
     ];
 
     for (input, expected) in tests {
@@ -435,6 +533,26 @@ fn test_array_index() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_hash_order() -> Result<()> {
+    let tests = [
+        (r#"{"b": 1, "a": 2, "c": 3}"#, "{b: 1, a: 2, c: 3}"),
+        ("{3: 1, 1: 2, 2: 3, 1: 4}", "{3: 1, 1: 4, 2: 3}"),
+        (r#"{[2, 1]: {"z": 1, "y": 2}}"#, "{[2, 1]: {z: 1, y: 2}}"),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let res = evaluator.evaluate(&program)?;
+
+        assert_eq!(res.inspect(), expected);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_hash_index() -> Result<()> {
     let tests = [
@@ -445,6 +563,14 @@ fn test_hash_index() -> Result<()> {
         (r#"{5:5}[5]"#, Object::Integer(5)),
         (r#"{true : 5}[true]"#, Object::Integer(5)),
         (r#"{false: 5}[false]"#, Object::Integer(5)),
+        (r#"let p = [1, "a"]; {p: 5}[[1, "a"]]"#, Object::Integer(5)),
+        (r#"{[1, 2]: 5}[[2, 1]]"#, Object::Null),
+        (r#"let n = [][0]; {n: 5}[n]"#, Object::Integer(5)),
+        (
+            r#"{{"a": 1, "b": 2}: 5}[{"b": 2, "a": 1}]"#,
+            Object::Integer(5),
+        ),
+        (r#"{[{"a": [1]}]: 5}[[{"a": [1]}]]"#, Object::Integer(5)),
     ];
 
     for (input, expected) in tests {
@@ -459,6 +585,73 @@ fn test_hash_index() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_bytes() {
+    let bytes = |bytes: &[u8]| Ok(Object::Bytes(Rc::new(bytes.to_vec())));
+    let string = |s: &str| Ok(Object::String(Rc::new(s.to_string())));
+    let invalid_argument = |message: &str| {
+        Err(Error::BuiltinFunction {
+            source: ExecutionError::InvalidArgument(message.to_string()),
+        })
+    };
+
+    let tests = [
+        (r#"b"\x00\xff""#, bytes(&[0x00, 0xff])),
+        (r#"b"\x00\xff"[1]"#, Ok(Object::Integer(255))),
+        (r#"b"\x00\xff"[2]"#, Ok(Object::Null)),
+        (r#"b"\x00\xff"[-1]"#, Ok(Object::Null)),
+        (r#"len(b"abc")"#, Ok(Object::Integer(3))),
+        (r#"b"ab" + b"\x00""#, bytes(b"ab\0")),
+        (r#"b"ab" == b"ab""#, Ok(Object::Boolean(true))),
+        (r#"b"ab" != b"ab\x00""#, Ok(Object::Boolean(true))),
+        (r#"{b"k": 1}[b"k"]"#, Ok(Object::Integer(1))),
+        (r#"bytes("é")"#, bytes(&[0xc3, 0xa9])),
+        (r#"bytes([104, 105])"#, bytes(b"hi")),
+        (r#"string(b"\xc3\xa9")"#, string("é")),
+        (
+            r#"array(b"\x01\x02")"#,
+            Ok(Object::Array(Rc::new(vec![
+                Object::Integer(1),
+                Object::Integer(2),
+            ]))),
+        ),
+        (r#"hex(b"\x00\xab")"#, string("00ab")),
+        (r#"from_hex("00AB")"#, bytes(&[0x00, 0xab])),
+        (r#"slice(b"hello", 1, 3)"#, bytes(b"el")),
+        (r#"slice(b"hello", -5, 50)"#, bytes(b"hello")),
+        (r#"slice(b"hello", 3, 1)"#, bytes(b"")),
+        (
+            "slice([1, 2, 3], 1, 5)",
+            Ok(Object::Array(Rc::new(vec![
+                Object::Integer(2),
+                Object::Integer(3),
+            ]))),
+        ),
+        ("bytes([256])", invalid_argument("256 is not a byte")),
+        (
+            r#"string(b"\xff")"#,
+            invalid_argument("invalid utf-8 sequence of 1 bytes from index 0"),
+        ),
+        (
+            r#"from_hex("abc")"#,
+            invalid_argument(r#"invalid hex string "abc""#),
+        ),
+        (
+            r#"hex("ab")"#,
+            Err(Error::BuiltinFunction {
+                source: ExecutionError::TypeMismatch(DataType::String.to_string()),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        assert_eq!(evaluator.evaluate(&program), expected, "{input}");
+    }
+}
+
 #[test]
 fn test_builtin_functions() {
     let tests = [
@@ -534,3 +727,405 @@ fn test_builtin_functions() {
         assert_eq!(res, t.1);
     }
 }
+
+#[test]
+fn test_tail_calls() -> Result<()> {
+    let tests = [
+        (
+            r#"
+            let countDown = fn(x, acc) {
+                if (x == 0) { return acc; }
+                return countDown(x - 1, acc + 1);
+            };
+            countDown(100000, 0);"#,
+            Object::Integer(100000),
+        ),
+        (
+            r#"
+            let isEven = fn(x) { if (x < 2) { x == 0 } else { isEven(x - 2) } };
+            isEven(100001);"#,
+            Object::Boolean(false),
+        ),
+        (
+            r#"
+            let sum = fn(x) { if (x == 0) { 0 } else { x + sum(x - 1) } };
+            sum(100);"#,
+            Object::Integer(5050),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let res = evaluator.evaluate(&program)?;
+
+        assert_eq!(res, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_block_scopes() -> Result<()> {
+    let tests = [
+        ("{ let x = 1; x + 1 }", Object::Integer(2)),
+        ("let x = 1; { let x = 2; x } + x", Object::Integer(3)),
+        (
+            "let x = 1; if (true) { let x = x + 1; x } + x",
+            Object::Integer(3),
+        ),
+        (
+            "fn() { let x = 1; { let x = x * 10; x } + x }()",
+            Object::Integer(11),
+        ),
+        (
+            "fn() { let f = { let y = 5; fn() { y } }; let z = 6; f() + z }()",
+            Object::Integer(11),
+        ),
+        (
+            "let f = fn(x) { if (x > 0) { let y = x; y } else { let z = 0 - x; z } }; f(2) + f(-3)",
+            Object::Integer(5),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let res = evaluator.evaluate(&program)?;
+
+        assert_eq!(res, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_error_values() -> Result<()> {
+    let tests = [
+        (
+            r#"error("not found", 404)"#,
+            Object::Error(ErrorObject {
+                message: Rc::new("not found".to_string()),
+                data: Rc::new(Object::Integer(404)),
+            }),
+        ),
+        (r#"is_error(error("oops"))"#, Object::Boolean(true)),
+        ("is_error(1)", Object::Boolean(false)),
+        (
+            r#"
+            let check = fn(x) {
+                if (x > 10) { error("too big", x) } else { x }
+            };
+            let double = fn(x) { check(x)? * 2 };
+            double(3);"#,
+            Object::Integer(6),
+        ),
+        (
+            r#"
+            let check = fn(x) {
+                if (x > 10) { error("too big", x) } else { x }
+            };
+            let double = fn(x) {
+                let checked = check(x)?;
+                puts("unreachable");
+                checked * 2
+            };
+            is_error(double(20));"#,
+            Object::Boolean(true),
+        ),
+        (
+            r#"
+            let fail = fn() { error("failed") };
+            let outer = fn() {
+                let inner = fn() { [1, fail()?, 3] };
+                let res = inner();
+                if (is_error(res)) { 0 } else { 1 }
+            };
+            outer();"#,
+            Object::Integer(0),
+        ),
+        (
+            r#"let x = error("top level")?; x + 1"#,
+            Object::Error(ErrorObject {
+                message: Rc::new("top level".to_string()),
+                data: Rc::new(Object::Null),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let res = evaluator.evaluate(&program)?;
+
+        assert_eq!(res, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_generators() -> Result<()> {
+    let tests = [
+        (
+            r#"
+            let gen = fn() { yield 1; yield 2; };
+            let g = gen();
+            [next(g), next(g), next(g), next(g)];"#,
+            Object::Array(Rc::new(vec![
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::Done,
+                Object::Done,
+            ])),
+        ),
+        (
+            r#"
+            let counter = fn(n) { let x = n * 2; yield x; yield x + 1; };
+            let g = counter(5);
+            next(g) + next(g);"#,
+            Object::Integer(21),
+        ),
+        (
+            r#"
+            let sign = fn(x) {
+                if (x > 0) { yield "positive"; } else { yield "negative"; }
+                yield "end";
+            };
+            let g = sign(1);
+            [next(g), next(g), is_done(next(g))];"#,
+            Object::Array(Rc::new(vec![
+                Object::String(Rc::new("positive".to_string())),
+                Object::String(Rc::new("end".to_string())),
+                Object::Boolean(true),
+            ])),
+        ),
+        (
+            r#"
+            let gen = fn() { yield 1; return 2; yield 3; };
+            let g = gen();
+            [next(g), next(g), next(g)];"#,
+            Object::Array(Rc::new(vec![
+                Object::Integer(1),
+                Object::Done,
+                Object::Done,
+            ])),
+        ),
+        (
+            r#"
+            let naturals = fn(n) { yield n; naturals(n + 1) };
+            let sum = fn(g, n, acc) {
+                if (n == 0) { acc } else { sum(g, n - 1, acc + next(g)) }
+            };
+            sum(naturals(1), 10000, 0);"#,
+            Object::Integer(50005000),
+        ),
+        (
+            r#"
+            let gen = fn() { yield 1; error("failed") };
+            let g = gen();
+            next(g);
+            [is_error(next(g)), is_done(next(g))];"#,
+            Object::Array(Rc::new(vec![Object::Boolean(true), Object::Boolean(true)])),
+        ),
+        (
+            r#"
+            let gen = fn(x) { let y = x?; yield y; };
+            let g = gen(error("failed"));
+            [is_error(next(g)), is_done(next(g))];"#,
+            Object::Array(Rc::new(vec![Object::Boolean(true), Object::Boolean(true)])),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let res = evaluator.evaluate(&program)?;
+
+        assert_eq!(res, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_generator_errors() {
+    let tests = [
+        (
+            "next(1)",
+            Error::BuiltinFunction {
+                source: ExecutionError::TypeMismatch(DataType::Integer.to_string()),
+            },
+        ),
+        (
+            "let gen = fn(f) { yield f(); }; let g = gen(fn() { next(g) }); next(g);",
+            Error::GeneratorRunning,
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        assert_eq!(evaluator.evaluate(&program), Err(expected));
+    }
+}
+
+#[test]
+fn test_constants() {
+    let position = |line, column| Position { line, column };
+
+    let tests = [
+        ("const a = 1; a;", Ok(Object::Integer(1))),
+        (
+            "const a = 1; fn() { let a = 2; a }() + a",
+            Ok(Object::Integer(3)),
+        ),
+        ("let a = 1; const a = 2; a;", Ok(Object::Integer(2))),
+        (
+            "const a = 1; if (true) { const a = 2; a } + a",
+            Ok(Object::Integer(3)),
+        ),
+        (
+            "const a = 1;\nlet a = 2;",
+            Err(Error::AssignToConstant {
+                name: "a".to_string(),
+                defined: position(1, 1),
+                assigned: position(2, 1),
+            }),
+        ),
+        (
+            "fn() { if (true) { const a = 1; let a = 2; } }()",
+            Err(Error::AssignToConstant {
+                name: "a".to_string(),
+                defined: position(1, 20),
+                assigned: position(1, 33),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        assert_eq!(evaluator.evaluate(&program), expected);
+    }
+}
+
+#[test]
+fn test_modules() {
+    let modules_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/modules");
+
+    let tests = [
+        (
+            r#"import "lib/strings.monkey" as strings; strings.twice("ab")"#,
+            Ok(Object::String(Rc::new("abab".to_string()))),
+        ),
+        (
+            r#"
+            let double = 5;
+            import "lib/math.monkey" as math;
+            [double, math.double(3), math.answer(), math.secret]"#,
+            Ok(Object::Array(Rc::new(vec![
+                Object::Integer(5),
+                Object::Integer(6),
+                Object::Integer(42),
+                Object::Null,
+            ]))),
+        ),
+        (
+            r#"
+            import "lib/math.monkey" as math;
+            import "lib/strings.monkey" as strings;
+            import "lib/math.monkey" as again;
+            again.double(len(strings.repeat("a", 3)))"#,
+            Ok(Object::Integer(6)),
+        ),
+        (
+            r#"import "missing.monkey" as missing;"#,
+            Err(Error::Module {
+                source: module::Error::Read {
+                    path: modules_dir.join("missing.monkey").display().to_string(),
+                    message: "No such file or directory (os error 2)".to_string(),
+                },
+            }),
+        ),
+        (
+            r#"import "undefined_export.monkey" as undefined;"#,
+            Err(Error::UnknownIdentifier("b".to_string())),
+        ),
+        (
+            r#"import "cycle/a.monkey" as a;"#,
+            Err(Error::Module {
+                source: module::Error::Cycle(vec![
+                    modules_dir.join("cycle/a.monkey").display().to_string(),
+                    modules_dir.join("cycle/b.monkey").display().to_string(),
+                    modules_dir.join("cycle/a.monkey").display().to_string(),
+                ]),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::with_path(&modules_dir.join("main.monkey"));
+        let res = evaluator.evaluate(&program);
+
+        assert_eq!(res, expected);
+    }
+}
+
+#[test]
+fn test_quote_unquote() {
+    let tests = [
+        ("quote(5)", Ok("5")),
+        ("quote(5 + 8)", Ok("(5 + 8)")),
+        ("quote(foobar)", Ok("foobar")),
+        ("quote(foobar + barfoo)", Ok("(foobar + barfoo)")),
+        ("quote(unquote(4))", Ok("4")),
+        ("quote(unquote(4 + 4))", Ok("8")),
+        ("quote(8 + unquote(4 + 4))", Ok("(8 + 8)")),
+        ("quote(unquote(4 + 4) + 8)", Ok("(8 + 8)")),
+        ("let foobar = 8; quote(foobar)", Ok("foobar")),
+        ("let foobar = 8; quote(unquote(foobar))", Ok("8")),
+        ("quote(unquote(true))", Ok("true")),
+        ("quote(unquote(true == false))", Ok("false")),
+        ("quote(unquote(quote(4 + 4)))", Ok("(4 + 4)")),
+        (
+            "let quotedInfixExpression = quote(4 + 4);
+            quote(unquote(4 + 4) + unquote(quotedInfixExpression))",
+            Ok("(8 + (4 + 4))"),
+        ),
+        (
+            "quote(unquote([1]))",
+            Err(Error::NotUnquotable(DataType::Array)),
+        ),
+        (
+            "quote(1, 2)",
+            Err(Error::WrongNumberOfArguments {
+                expected: 1,
+                got: 2,
+            }),
+        ),
+        ("let m = macro(x) { x }; 1", Err(Error::UnexpandedMacro)),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let res = evaluator.evaluate(&program).map(|obj| {
+            let Object::Quote(expr) = obj else {
+                panic!("Expected quote, got: {:?}", obj);
+            };
+            expr.debug_str()
+        });
+
+        assert_eq!(res, expected.map(String::from));
+    }
+}
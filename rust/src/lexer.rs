@@ -1,10 +1,15 @@
-use crate::token::Token;
+use crate::token::{Position, Token};
 
 pub struct Lexer<'a> {
     input: &'a [u8],
     position: usize,
     read_position: usize,
     ch: u8,
+
+    line: usize,
+    column: usize,
+    // Position of the last returned token.
+    token_position: Position,
 }
 
 impl<'a> Lexer<'a> {
@@ -14,12 +19,27 @@ impl<'a> Lexer<'a> {
             position: 0,
             read_position: 0,
             ch: 0,
+            line: 1,
+            column: 0,
+            token_position: Position::default(),
         };
         lexer.read_char();
         lexer
     }
 
+    /// Returns the position of the last returned token.
+    pub fn position(&self) -> Position {
+        self.token_position
+    }
+
     fn read_char(&mut self) {
+        if self.ch == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
         if self.read_position >= self.input.len() {
             self.ch = 0;
         } else {
@@ -39,10 +59,20 @@ impl<'a> Lexer<'a> {
         std::str::from_utf8(&self.input[start_position..self.position]).unwrap()
     }
 
+    /// Reads a decimal, `0x`, `0o` or `0b` literal with optional `_` separators.
+    /// Invalid digits are included in the literal and rejected by the parser.
     fn read_number(&mut self) -> &str {
         let start_position = self.position;
-        while self.ch.is_ascii_digit() {
+        if self.ch == b'0' && matches!(self.peek_char(), b'x' | b'o' | b'b') {
+            self.read_char();
             self.read_char();
+            while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
+                self.read_char();
+            }
+        } else {
+            while self.ch.is_ascii_digit() || self.ch == b'_' {
+                self.read_char();
+            }
         }
 
         std::str::from_utf8(&self.input[start_position..self.position]).unwrap()
@@ -54,7 +84,7 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn peek_char(&mut self) -> u8 {
+    fn peek_char(&self) -> u8 {
         if self.read_position >= self.input.len() {
             0
         } else {
@@ -73,6 +103,27 @@ impl<'a> Lexer<'a> {
 
         std::str::from_utf8(&self.input[start_position..self.position]).unwrap()
     }
+
+    /// Reads the contents of a bytes literal. Unlike strings, the literal
+    /// can contain escaped quotes, so escapes are kept for the parser.
+    fn read_bytes(&mut self) -> &str {
+        // Skip the `b` prefix.
+        self.read_char();
+        let start_position = self.position + 1;
+        loop {
+            self.read_char();
+            if self.ch == b'\\' {
+                self.read_char();
+            } else if self.ch == b'"' {
+                break;
+            }
+            if self.ch == 0 {
+                break;
+            }
+        }
+
+        std::str::from_utf8(&self.input[start_position..self.position]).unwrap()
+    }
 }
 
 impl Iterator for Lexer<'_> {
@@ -80,6 +131,10 @@ impl Iterator for Lexer<'_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.skip_whitespace();
+        self.token_position = Position {
+            line: self.line,
+            column: self.column,
+        };
 
         let token = match self.ch {
             b'=' => match self.peek_char() {
@@ -91,11 +146,18 @@ impl Iterator for Lexer<'_> {
             },
             b';' => Token::Semicolon,
             b':' => Token::Colon,
+            b'.' => Token::Dot,
             b'(' => Token::Lparen,
             b')' => Token::Rparen,
             b',' => Token::Comma,
             b'+' => Token::Plus,
-            b'-' => Token::Minus,
+            b'-' => match self.peek_char() {
+                b'>' => {
+                    self.read_char();
+                    Token::Arrow
+                }
+                _ => Token::Minus,
+            },
             b'{' => Token::Lsquigly,
             b'}' => Token::Rsquigly,
             b'[' => Token::LBracket,
@@ -108,10 +170,28 @@ impl Iterator for Lexer<'_> {
                 _ => Token::Bang,
             },
             b'"' => Token::String(self.read_string().to_string()),
+            b'b' if self.peek_char() == b'"' => Token::Bytes(self.read_bytes().to_string()),
             b'/' => Token::Slash,
             b'*' => Token::Asterisk,
-            b'<' => Token::Lt,
-            b'>' => Token::Gt,
+            b'<' => match self.peek_char() {
+                b'<' => {
+                    self.read_char();
+                    Token::ShiftLeft
+                }
+                _ => Token::Lt,
+            },
+            b'>' => match self.peek_char() {
+                b'>' => {
+                    self.read_char();
+                    Token::ShiftRight
+                }
+                _ => Token::Gt,
+            },
+            b'&' => Token::Ampersand,
+            b'|' => Token::Pipe,
+            b'^' => Token::Caret,
+            b'~' => Token::Tilde,
+            b'?' => Token::Question,
             b'\0' => return None,
             _ => {
                 if is_letter(self.ch) {
@@ -167,6 +247,13 @@ if (5 < 10) {
 "foo bar"
 [1, 2];
 {"foo": "bar"}
+foo()?
+import "lib.monkey" as lib;
+export foo, bar;
+lib.foo
+macro(x) { x }
+yield 1;
+const c = 1;
 "#;
 
         let expected_values = vec![
@@ -256,10 +343,144 @@ if (5 < 10) {
             Token::Colon,
             Token::String("bar".to_string()),
             Token::Rsquigly,
+            Token::Ident("foo".to_string()),
+            Token::Lparen,
+            Token::Rparen,
+            Token::Question,
+            Token::Import,
+            Token::String("lib.monkey".to_string()),
+            Token::As,
+            Token::Ident("lib".to_string()),
+            Token::Semicolon,
+            Token::Export,
+            Token::Ident("foo".to_string()),
+            Token::Comma,
+            Token::Ident("bar".to_string()),
+            Token::Semicolon,
+            Token::Ident("lib".to_string()),
+            Token::Dot,
+            Token::Ident("foo".to_string()),
+            Token::Macro,
+            Token::Lparen,
+            Token::Ident("x".to_string()),
+            Token::Rparen,
+            Token::Lsquigly,
+            Token::Ident("x".to_string()),
+            Token::Rsquigly,
+            Token::Yield,
+            Token::Int("1".to_string()),
+            Token::Semicolon,
+            Token::Const,
+            Token::Ident("c".to_string()),
+            Token::Assign,
+            Token::Int("1".to_string()),
+            Token::Semicolon,
         ];
 
         let lexer = Lexer::new(input);
         let tokens: Vec<Token> = lexer.collect();
         assert_eq!(tokens, expected_values);
     }
+
+    #[test]
+    fn test_token_positions() {
+        let input = "let x = 5;\n  x + 10";
+
+        let mut lexer = Lexer::new(input);
+        let mut positions = vec![];
+        while lexer.next().is_some() {
+            let position = lexer.position();
+            positions.push((position.line, position.column));
+        }
+
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1),
+                (1, 5),
+                (1, 7),
+                (1, 9),
+                (1, 10),
+                (2, 3),
+                (2, 5),
+                (2, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers_and_bitwise_operators() {
+        let input = "0xff_ff 0o17 0b1010 1_000 0xfg ~a & b | c ^ d << 2 >> 1 < >";
+
+        let tokens: Vec<_> = Lexer::new(input).collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Int("0xff_ff".to_string()),
+                Token::Int("0o17".to_string()),
+                Token::Int("0b1010".to_string()),
+                Token::Int("1_000".to_string()),
+                Token::Int("0xfg".to_string()),
+                Token::Tilde,
+                Token::Ident("a".to_string()),
+                Token::Ampersand,
+                Token::Ident("b".to_string()),
+                Token::Pipe,
+                Token::Ident("c".to_string()),
+                Token::Caret,
+                Token::Ident("d".to_string()),
+                Token::ShiftLeft,
+                Token::Int("2".to_string()),
+                Token::ShiftRight,
+                Token::Int("1".to_string()),
+                Token::Lt,
+                Token::Gt,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bytes_literal() {
+        let input = r#"b"\x00\xff" b"a\"b" b bytes"#;
+
+        let tokens: Vec<_> = Lexer::new(input).collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Bytes(r#"\x00\xff"#.to_string()),
+                Token::Bytes(r#"a\"b"#.to_string()),
+                Token::Ident("b".to_string()),
+                Token::Ident("bytes".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_annotation_tokens() {
+        let input = "fn(x: int) -> int { x - -1 }";
+
+        let tokens: Vec<_> = Lexer::new(input).collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Function,
+                Token::Lparen,
+                Token::Ident("x".to_string()),
+                Token::Colon,
+                Token::Ident("int".to_string()),
+                Token::Rparen,
+                Token::Arrow,
+                Token::Ident("int".to_string()),
+                Token::Lsquigly,
+                Token::Ident("x".to_string()),
+                Token::Minus,
+                Token::Minus,
+                Token::Int("1".to_string()),
+                Token::Rsquigly,
+            ]
+        );
+    }
 }
@@ -1,11 +1,20 @@
 pub mod ast;
+pub mod check;
 pub mod code;
 pub mod compile;
+pub mod disasm;
 pub mod environment;
 pub mod evaluate;
 pub mod lexer;
+pub mod lint;
+pub mod macro_expansion;
+pub mod module;
 pub mod object;
+pub mod optimize;
 pub mod parse;
 pub mod repl;
+pub mod runtime;
+pub mod serialize;
 pub mod token;
+pub mod verify;
 pub mod vm;
@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("unknown lint rule: {0}")]
+    UnknownRule(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
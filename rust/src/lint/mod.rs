@@ -0,0 +1,397 @@
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::{collections::HashSet, fmt::Display};
+
+use crate::{
+    ast::{self, Expression, Statement},
+    module,
+    object::builtin::BuiltinFunction,
+    token::Position,
+};
+
+pub use error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// `let` binding, parameter or import that is never used.
+    /// Names starting with `_` are ignored.
+    UnusedBinding,
+    /// Binding with the name of a builtin function.
+    ShadowedBuiltin,
+    /// Statements after a `return` in the same block.
+    UnreachableCode,
+    /// `if` condition made only of literals.
+    ConstantCondition,
+    /// Identifier that isn't defined at the point of its use,
+    /// which the compiler rejects with `UndefinedSymbol`.
+    UndefinedIdentifier,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::UnusedBinding,
+        Rule::ShadowedBuiltin,
+        Rule::UnreachableCode,
+        Rule::ConstantCondition,
+        Rule::UndefinedIdentifier,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedBinding => "unused-binding",
+            Rule::ShadowedBuiltin => "shadowed-builtin",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::UndefinedIdentifier => "undefined-identifier",
+        }
+    }
+}
+
+/// Finding of the linter. The position is the one of the closest enclosing
+/// node that has a position, since identifiers and literals don't have one.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub rule: Rule,
+    pub position: Option<Position>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(position) = self.position {
+            write!(f, "{}: ", position)?;
+        }
+        write!(f, "{} [{}]", self.message, self.rule.name())
+    }
+}
+
+/// Reports common mistakes in a program with expanded macros.
+/// All rules are enabled by default.
+pub struct Linter {
+    rules: HashSet<Rule>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self {
+            rules: Rule::ALL.into_iter().collect(),
+        }
+    }
+
+    /// Disables the rule with the given name.
+    pub fn allow(&mut self, name: &str) -> Result<()> {
+        let rule = Rule::from_name(name).ok_or_else(|| Error::UnknownRule(name.to_owned()))?;
+        self.rules.remove(&rule);
+
+        Ok(())
+    }
+
+    pub fn lint(&self, program: &ast::Program) -> Vec<Warning> {
+        let mut walker = Walker {
+            scopes: vec![Vec::new()],
+            position: None,
+            warnings: Vec::new(),
+        };
+        walker.walk_program(program);
+
+        // Unused bindings are only found when their scope ends, so the warnings
+        // are sorted by position. The ones without a position come last.
+        let mut warnings = walker.warnings;
+        warnings.retain(|warning| self.rules.contains(&warning.rule));
+        warnings.sort_by_key(|warning| {
+            warning
+                .position
+                .map_or((1, 0, 0), |position| (0, position.line, position.column))
+        });
+        warnings
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindingKind {
+    Let,
+    Parameter,
+    Import,
+    /// Name of the function inside of its own body.
+    Function,
+}
+
+struct Binding {
+    name: String,
+    kind: BindingKind,
+    position: Option<Position>,
+    used: bool,
+}
+
+/// Walks the program with the scoping rules of the compiler: blocks
+/// have their own scopes and names are visible after their definition.
+struct Walker {
+    scopes: Vec<Vec<Binding>>,
+    position: Option<Position>,
+    warnings: Vec<Warning>,
+}
+
+impl Walker {
+    fn walk_program(&mut self, program: &ast::Program) {
+        self.walk_statements(&program.statements);
+
+        for name in module::exports(program) {
+            self.use_name(name);
+        }
+        self.leave_scope();
+    }
+
+    fn walk_statements(&mut self, statements: &[Statement]) {
+        let mut returned = false;
+        for stmt in statements {
+            if returned {
+                let position = statement_position(stmt).or(self.position);
+                self.warn(
+                    Rule::UnreachableCode,
+                    position,
+                    "unreachable code after return",
+                );
+                returned = false;
+            }
+            if matches!(stmt, Statement::Return(_)) {
+                returned = true;
+            }
+            self.walk_statement(stmt);
+        }
+    }
+
+    fn walk_block(&mut self, block: &ast::BlockStatement) {
+        self.scopes.push(Vec::new());
+        self.walk_statements(&block.statements);
+        self.leave_scope();
+    }
+
+    fn walk_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Let {
+                name,
+                value,
+                position,
+                ..
+            } => {
+                let outer_position = self.position.replace(*position);
+                // Globals can refer to themselves, like the compiler allows.
+                if self.scopes.len() == 1 && !self.is_defined(name) {
+                    self.define(name, BindingKind::Let, Some(*position));
+                    self.walk_expression(value);
+                } else {
+                    self.walk_expression(value);
+                    self.define(name, BindingKind::Let, Some(*position));
+                }
+                self.position = outer_position;
+            }
+            Statement::Return(expr) | Statement::Yield(expr) | Statement::Expression(expr) => {
+                self.walk_expression(expr)
+            }
+            Statement::Import { name, .. } => {
+                self.define(name, BindingKind::Import, self.position);
+            }
+            // Exports are checked at the end of the program.
+            Statement::Export(_) => (),
+        }
+    }
+
+    fn walk_expression(&mut self, expr: &Expression) {
+        let outer_position = self.position;
+        if let Some(position) = expr.position() {
+            self.position = Some(position);
+        }
+
+        match expr {
+            Expression::Identifier(name) => self.use_name(name),
+            Expression::IntegerLiteral(_)
+            | Expression::BigIntegerLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::BytesLiteral(_) => (),
+            Expression::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.walk_expression(element);
+                }
+            }
+            Expression::HashLiteral(pairs) => {
+                for pair in pairs {
+                    self.walk_expression(&pair.key);
+                    self.walk_expression(&pair.value);
+                }
+            }
+            Expression::PrefixOperator { right, .. } => self.walk_expression(right),
+            Expression::InfixOperator { left, right, .. } => {
+                self.walk_expression(left);
+                self.walk_expression(right);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                position,
+            } => {
+                if is_constant(condition) {
+                    self.warn(
+                        Rule::ConstantCondition,
+                        Some(*position),
+                        "if condition is always the same",
+                    );
+                }
+                self.walk_expression(condition);
+                self.walk_block(consequence);
+                self.walk_block(alternative);
+            }
+            Expression::Block(block) => self.walk_block(block),
+            Expression::FunctionLiteral {
+                name,
+                parameters,
+                body,
+                position,
+                ..
+            } => {
+                self.scopes.push(Vec::new());
+                if let Some(name) = name {
+                    self.define(name, BindingKind::Function, Some(*position));
+                }
+                for parameter in parameters {
+                    self.define(parameter, BindingKind::Parameter, Some(*position));
+                }
+                self.walk_statements(&body.statements);
+                self.leave_scope();
+            }
+            // Macros are removed by the expansion, and quoted code isn't evaluated.
+            Expression::MacroLiteral { .. } => (),
+            Expression::FunctionCall { .. } if expr.call_arguments("quote").is_some() => (),
+            Expression::FunctionCall {
+                function,
+                arguments,
+                ..
+            } => {
+                self.walk_expression(function);
+                for argument in arguments {
+                    self.walk_expression(argument);
+                }
+            }
+            Expression::Index { left, index, .. } => {
+                self.walk_expression(left);
+                self.walk_expression(index);
+            }
+            Expression::Try(expr) => self.walk_expression(expr),
+        }
+
+        self.position = outer_position;
+    }
+
+    fn define(&mut self, name: &str, kind: BindingKind, position: Option<Position>) {
+        if kind != BindingKind::Function && BuiltinFunction::from_ident(name).is_some() {
+            self.warn(
+                Rule::ShadowedBuiltin,
+                position,
+                &format!("{} shadows the builtin function", name),
+            );
+        }
+
+        self.scopes.last_mut().unwrap().push(Binding {
+            name: name.to_owned(),
+            kind,
+            position,
+            used: false,
+        });
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .flatten()
+            .any(|binding| binding.name == name)
+    }
+
+    fn use_name(&mut self, name: &str) {
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|binding| binding.name == name));
+
+        match binding {
+            Some(binding) => binding.used = true,
+            None if BuiltinFunction::from_ident(name).is_some() => (),
+            None => self.warn(
+                Rule::UndefinedIdentifier,
+                self.position,
+                &format!("undefined identifier {}", name),
+            ),
+        }
+    }
+
+    fn leave_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        for binding in scope {
+            if binding.used || binding.name.starts_with('_') {
+                continue;
+            }
+
+            let kind = match binding.kind {
+                BindingKind::Let => "binding",
+                BindingKind::Parameter => "parameter",
+                BindingKind::Import => "import",
+                BindingKind::Function => continue,
+            };
+            self.warn(
+                Rule::UnusedBinding,
+                binding.position,
+                &format!("unused {} {}", kind, binding.name),
+            );
+        }
+    }
+
+    fn warn(&mut self, rule: Rule, position: Option<Position>, message: &str) {
+        self.warnings.push(Warning {
+            rule,
+            position,
+            message: message.to_owned(),
+        });
+    }
+}
+
+fn statement_position(stmt: &Statement) -> Option<Position> {
+    match stmt {
+        Statement::Let { position, .. } => Some(*position),
+        Statement::Return(expr) | Statement::Yield(expr) | Statement::Expression(expr) => {
+            expr.position()
+        }
+        Statement::Import { .. } | Statement::Export(_) => None,
+    }
+}
+
+/// Returns true if the expression is made only of literals.
+fn is_constant(expr: &Expression) -> bool {
+    match expr {
+        Expression::IntegerLiteral(_)
+        | Expression::BigIntegerLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::BytesLiteral(_)
+        | Expression::FunctionLiteral { .. } => true,
+        Expression::ArrayLiteral(elements) => elements.iter().all(is_constant),
+        Expression::HashLiteral(pairs) => pairs
+            .iter()
+            .all(|pair| is_constant(&pair.key) && is_constant(&pair.value)),
+        Expression::PrefixOperator { right, .. } => is_constant(right),
+        Expression::InfixOperator { left, right, .. } => is_constant(left) && is_constant(right),
+        _ => false,
+    }
+}
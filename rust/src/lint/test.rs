@@ -0,0 +1,241 @@
+use crate::{parse, token::Position};
+
+use super::{Error, Linter, Rule, Warning};
+
+fn warning(rule: Rule, position: Option<(usize, usize)>, message: &str) -> Warning {
+    Warning {
+        rule,
+        position: position.map(|(line, column)| Position { line, column }),
+        message: message.to_owned(),
+    }
+}
+
+#[test]
+fn test_clean_programs() {
+    let tests = [
+        "let x = 1; x",
+        "let f = fn(n) { if (n < 2) { return 1; } n * f(n - 1) }; f(5)",
+        "let x = 1; let x = x + 1; x",
+        "let add = fn(a, _b) { a }; add(1, 2)",
+        "let g = fn() { let n = 1; yield n; }; next(g())",
+        "let x = 1; if (x > 0) { puts(x) }",
+        "let counter = fn() { let count = 0; fn() { count + 1 } }; counter()",
+        "let v = 1; export v",
+        "quote(undefined)",
+    ];
+
+    for input in tests {
+        let program = parse::parse(input).unwrap();
+        assert_eq!(Linter::new().lint(&program), Vec::new(), "{}", input);
+    }
+}
+
+#[test]
+fn test_warnings() {
+    let tests = [
+        (
+            "let x = 1;\nlet y = 2; y",
+            vec![warning(
+                Rule::UnusedBinding,
+                Some((1, 1)),
+                "unused binding x",
+            )],
+        ),
+        (
+            "let f = fn(a, b) { a }; f(1, 2)",
+            vec![warning(
+                Rule::UnusedBinding,
+                Some((1, 9)),
+                "unused parameter b",
+            )],
+        ),
+        (
+            "let f = fn() { let x = 1; let x = 2; x }; f()",
+            vec![warning(
+                Rule::UnusedBinding,
+                Some((1, 16)),
+                "unused binding x",
+            )],
+        ),
+        (
+            "import \"lib\" as lib;",
+            vec![warning(Rule::UnusedBinding, None, "unused import lib")],
+        ),
+        (
+            "let len = fn(x) { x }; len(1)",
+            vec![warning(
+                Rule::ShadowedBuiltin,
+                Some((1, 1)),
+                "len shadows the builtin function",
+            )],
+        ),
+        (
+            "let f = fn(first) { first }; f(1)",
+            vec![warning(
+                Rule::ShadowedBuiltin,
+                Some((1, 9)),
+                "first shadows the builtin function",
+            )],
+        ),
+        (
+            "let f = fn(x) { return x; puts(x); x }; f(1)",
+            vec![warning(
+                Rule::UnreachableCode,
+                Some((1, 31)),
+                "unreachable code after return",
+            )],
+        ),
+        (
+            "let f = fn(x) { if (x) { return 1; x } else { 2 } }; f(1)",
+            vec![warning(
+                Rule::UnreachableCode,
+                Some((1, 17)),
+                "unreachable code after return",
+            )],
+        ),
+        (
+            "if (true) { 1 }",
+            vec![warning(
+                Rule::ConstantCondition,
+                Some((1, 1)),
+                "if condition is always the same",
+            )],
+        ),
+        (
+            "let x = 1;\nif (1 < 2 * 3) { x }",
+            vec![warning(
+                Rule::ConstantCondition,
+                Some((2, 1)),
+                "if condition is always the same",
+            )],
+        ),
+        (
+            "let x = 1; x + y",
+            vec![warning(
+                Rule::UndefinedIdentifier,
+                Some((1, 14)),
+                "undefined identifier y",
+            )],
+        ),
+        (
+            "let f = fn() { g() }; let g = fn() { 1 }; f() + g()",
+            vec![warning(
+                Rule::UndefinedIdentifier,
+                Some((1, 17)),
+                "undefined identifier g",
+            )],
+        ),
+        (
+            "if (1 > 0) { let x = 1; } x",
+            vec![
+                warning(
+                    Rule::ConstantCondition,
+                    Some((1, 1)),
+                    "if condition is always the same",
+                ),
+                warning(Rule::UnusedBinding, Some((1, 14)), "unused binding x"),
+                warning(Rule::UndefinedIdentifier, None, "undefined identifier x"),
+            ],
+        ),
+        (
+            "let x = 1; x; z",
+            vec![warning(
+                Rule::UndefinedIdentifier,
+                None,
+                "undefined identifier z",
+            )],
+        ),
+        (
+            "export missing",
+            vec![warning(
+                Rule::UndefinedIdentifier,
+                None,
+                "undefined identifier missing",
+            )],
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse::parse(input).unwrap();
+        assert_eq!(Linter::new().lint(&program), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_warning_order() {
+    let input = "let unused = 1;
+let f = fn(x, len) { if (true) { return x; y }; z };
+f(1, 2)";
+    let program = parse::parse(input).unwrap();
+    assert_eq!(
+        Linter::new().lint(&program),
+        vec![
+            warning(Rule::UnusedBinding, Some((1, 1)), "unused binding unused"),
+            warning(
+                Rule::ShadowedBuiltin,
+                Some((2, 9)),
+                "len shadows the builtin function",
+            ),
+            warning(
+                Rule::UndefinedIdentifier,
+                Some((2, 9)),
+                "undefined identifier z",
+            ),
+            warning(Rule::UnusedBinding, Some((2, 9)), "unused parameter len"),
+            warning(
+                Rule::ConstantCondition,
+                Some((2, 22)),
+                "if condition is always the same",
+            ),
+            warning(
+                Rule::UnreachableCode,
+                Some((2, 22)),
+                "unreachable code after return",
+            ),
+            warning(
+                Rule::UndefinedIdentifier,
+                Some((2, 22)),
+                "undefined identifier y",
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_allow_rules() {
+    let program = parse::parse("let len = 1; if (true) { y }").unwrap();
+
+    let mut linter = Linter::new();
+    linter.allow("unused-binding").unwrap();
+    linter.allow("constant-condition").unwrap();
+    assert_eq!(
+        linter.lint(&program),
+        vec![
+            warning(
+                Rule::ShadowedBuiltin,
+                Some((1, 1)),
+                "len shadows the builtin function",
+            ),
+            warning(
+                Rule::UndefinedIdentifier,
+                Some((1, 14)),
+                "undefined identifier y",
+            ),
+        ]
+    );
+
+    assert_eq!(
+        linter.allow("unused"),
+        Err(Error::UnknownRule("unused".to_owned()))
+    );
+}
+
+#[test]
+fn test_warning_display() {
+    let program = parse::parse("let x = 1;").unwrap();
+    let warnings = Linter::new().lint(&program);
+    assert_eq!(
+        warnings[0].to_string(),
+        "1:1: unused binding x [unused-binding]"
+    );
+}
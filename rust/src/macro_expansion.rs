@@ -0,0 +1,241 @@
+//! Macro expansion, which runs before the program is
+//! evaluated or compiled.
+//!
+//! Macros are defined with top level `let` statements binding
+//! macro literals. Every call of a macro is replaced with the AST
+//! returned by the macro body, which is evaluated with the quoted
+//! arguments bound to the parameters.
+
+use std::{collections::HashMap, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{
+    ast::{self, modify},
+    evaluate::{self, Evaluator},
+    object::{DataType, Object},
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("macro {name} returned {data_type}, expected QUOTE")]
+    NotAQuote { name: String, data_type: DataType },
+    #[error("failed to expand macro {name}: {source}")]
+    Evaluation {
+        name: String,
+        source: evaluate::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+struct Macro {
+    parameters: Vec<String>,
+    body: ast::BlockStatement,
+}
+
+/// Expands macros in programs. Macros defined in one program
+/// are also available in programs expanded afterwards.
+pub struct MacroExpander {
+    macros: HashMap<String, Rc<Macro>>,
+    evaluator: Evaluator,
+}
+
+impl MacroExpander {
+    pub fn new() -> Self {
+        Self {
+            macros: HashMap::new(),
+            evaluator: Evaluator::new(),
+        }
+    }
+
+    /// Removes macro definitions from the program and replaces
+    /// macro calls with the expanded AST.
+    pub fn expand(&mut self, program: ast::Program) -> Result<ast::Program> {
+        let program = self.define_macros(program);
+        if self.macros.is_empty() {
+            return Ok(program);
+        }
+
+        modify::modify_program(program, &mut |expr| self.expand_call(expr))
+    }
+
+    fn define_macros(&mut self, program: ast::Program) -> ast::Program {
+        let mut statements = Vec::with_capacity(program.statements.len());
+
+        for stmt in program.statements {
+            match stmt {
+                ast::Statement::Let {
+                    name,
+                    value: ast::Expression::MacroLiteral { parameters, body },
+                    ..
+                } => {
+                    self.macros
+                        .insert(name, Rc::new(Macro { parameters, body }));
+                }
+                stmt => statements.push(stmt),
+            }
+        }
+
+        ast::Program { statements }
+    }
+
+    fn expand_call(&mut self, expr: ast::Expression) -> Result<ast::Expression> {
+        let ast::Expression::FunctionCall {
+            function,
+            arguments,
+            ..
+        } = &expr
+        else {
+            return Ok(expr);
+        };
+
+        let ast::Expression::Identifier(name) = function.as_ref() else {
+            return Ok(expr);
+        };
+
+        let Some(mac) = self.macros.get(name).cloned() else {
+            return Ok(expr);
+        };
+
+        let arguments = arguments
+            .iter()
+            .map(|arg| Object::Quote(Rc::new(arg.clone())))
+            .collect();
+
+        let res = self
+            .evaluator
+            .evaluate_macro(&mac.parameters, &mac.body, arguments)
+            .map_err(|source| Error::Evaluation {
+                name: name.clone(),
+                source,
+            })?;
+
+        match res {
+            Object::Quote(expanded) => Ok((*expanded).clone()),
+            obj => Err(Error::NotAQuote {
+                name: name.clone(),
+                data_type: obj.into(),
+            }),
+        }
+    }
+}
+
+impl Default for MacroExpander {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{evaluate, object::DataType, parse::parse};
+
+    use super::{Error, MacroExpander};
+
+    #[test]
+    fn test_define_macros() {
+        let input = r#"
+            let number = 1;
+            let function = fn(x, y) { x + y };
+            let mymacro = macro(x, y) { x + y; };
+        "#;
+
+        let program = parse(input).unwrap();
+        let mut expander = MacroExpander::new();
+        let program = expander.expand(program).unwrap();
+
+        assert_eq!(program.statements.len(), 2);
+        assert!(!expander.macros.contains_key("number"));
+        assert!(!expander.macros.contains_key("function"));
+
+        let mac = &expander.macros["mymacro"];
+        assert_eq!(mac.parameters, vec!["x", "y"]);
+        assert_eq!(mac.body.debug_str(), "(x + y);");
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let tests = [
+            (
+                r#"
+                let infixExpression = macro() { quote(1 + 2); };
+                infixExpression();
+                "#,
+                "(1 + 2);",
+            ),
+            (
+                r#"
+                let reverse = macro(a, b) { quote(unquote(b) - unquote(a)); };
+                reverse(2 + 2, 10 - 5);
+                "#,
+                "((10 - 5) - (2 + 2));",
+            ),
+            (
+                r#"
+                let unless = macro(condition, consequence, alternative) {
+                    quote(if (!(unquote(condition))) {
+                        unquote(consequence);
+                    } else {
+                        unquote(alternative);
+                    });
+                };
+                unless(10 > 5, puts("not greater"), puts("greater"));
+                "#,
+                r#"if ((!(10 > 5))) {puts(not greater);} else {puts(greater);};"#,
+            ),
+            (
+                r#"
+                let double = macro(x) { quote(unquote(x) * 2) };
+                let twice = fn(x) { double(x) + double(1) };
+                "#,
+                "let twice = fn<twice>(x) {((x * 2) + (1 * 2));};",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input).unwrap();
+            let mut expander = MacroExpander::new();
+            let expanded = expander.expand(program).unwrap();
+
+            assert_eq!(expanded.debug_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_expand_macros_errors() {
+        let tests = [
+            (
+                "let m = macro(x) { x + 1 }; m(1)",
+                Error::Evaluation {
+                    name: "m".to_string(),
+                    source: evaluate::Error::TypeMismatch("QUOTE + INTEGER".to_string()),
+                },
+            ),
+            (
+                "let m = macro(x) { 1 }; m(2)",
+                Error::NotAQuote {
+                    name: "m".to_string(),
+                    data_type: DataType::Integer,
+                },
+            ),
+            (
+                "let m = macro(x) { x }; m()",
+                Error::Evaluation {
+                    name: "m".to_string(),
+                    source: evaluate::Error::WrongNumberOfArguments {
+                        expected: 1,
+                        got: 0,
+                    },
+                },
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input).unwrap();
+            let mut expander = MacroExpander::new();
+
+            assert_eq!(expander.expand(program), Err(expected));
+        }
+    }
+}
@@ -1,18 +1,24 @@
 use std::{
     fs,
     io::{stdin, stdout},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process,
 };
 
-use clap::{Parser, Subcommand, ValueEnum};
-use monkey::{compile::Compiler, evaluate::Evaluator, parse, repl, vm::VirtualMachine};
-
-#[derive(Debug, Clone, Copy, ValueEnum)]
-enum Runtime {
-    Eval,
-    Vm,
-}
+use clap::{Parser, Subcommand};
+use monkey::{
+    ast::Program,
+    check,
+    compile::Compiler,
+    disasm,
+    evaluate::Evaluator,
+    lint::Linter,
+    macro_expansion::MacroExpander,
+    optimize, parse, repl,
+    runtime::{Runtime, DEFAULT_MAX_DEPTH},
+    serialize,
+    vm::VirtualMachine,
+};
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -20,34 +26,84 @@ struct Cli {
     #[arg(long, value_enum, default_value_t = Runtime::Vm)]
     runtime: Runtime,
 
+    /// Maximum number of nested function calls
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Optimize the bytecode of the vm runtime
+    #[arg(short = 'O', long)]
+    optimize: bool,
+
+    /// Optimize the syntax tree before it's run by either runtime
+    #[arg(long)]
+    optimize_ast: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-    /// Run file and print the result
+    /// Run file and print the result. Files with the `.mbc`
+    /// extension are run as bytecode on the virtual machine
     Run { path: PathBuf },
+    /// Compile file and write the bytecode
+    Build {
+        path: PathBuf,
+        /// Path of the bytecode file [default: the path with the `.mbc` extension]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Print the size of the constant pool
+        #[arg(long)]
+        stats: bool,
+    },
+    /// Check the type annotations of the file and print the errors
+    Check { path: PathBuf },
+    /// Report common mistakes in the file
+    Lint {
+        path: PathBuf,
+        /// Disable the rule with this name
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+    },
+    /// Compile file and print the bytecode
+    Disasm { path: PathBuf },
 }
 
 fn main() {
     let cli = Cli::parse();
     match cli.command {
-        None => interactive(cli.runtime),
-        Some(Commands::Run { path }) => run_file(path, cli.runtime),
+        None => interactive(cli.runtime, cli.optimize, cli.optimize_ast),
+        Some(Commands::Run { path }) => run_file(
+            path,
+            cli.runtime,
+            cli.max_depth,
+            cli.optimize,
+            cli.optimize_ast,
+        ),
+        Some(Commands::Build {
+            path,
+            output,
+            stats,
+        }) => build_file(path, output, stats, cli.optimize, cli.optimize_ast),
+        Some(Commands::Check { path }) => check_file(path),
+        Some(Commands::Lint { path, allow }) => lint_file(path, &allow),
+        Some(Commands::Disasm { path }) => disasm_file(path, cli.optimize, cli.optimize_ast),
     }
 }
 
-fn interactive(runtime: Runtime) {
+fn interactive(runtime: Runtime, optimize: bool, optimize_ast: bool) {
     println!("Hello! This is the Monkey programming language!");
     println!("Feel free to type in commands");
     match runtime {
-        Runtime::Eval => repl::start_eval(stdin(), stdout()),
-        Runtime::Vm => repl::start_vm(stdin(), stdout()),
+        Runtime::Eval => repl::start_eval(stdin(), stdout(), optimize_ast),
+        Runtime::Vm => repl::start_vm(stdin(), stdout(), optimize, optimize_ast),
     }
 }
 
-fn run_file(path: PathBuf, runtime: Runtime) {
+/// Reads, parses and expands the macros of the program in the file and
+/// optimizes its syntax tree if `optimize_ast` is set. Exits on errors.
+fn load_program(path: &Path, optimize_ast: bool) -> Program {
     let input = fs::read_to_string(path).unwrap_or_else(|err| {
         println!("{}", err);
         process::exit(1);
@@ -58,26 +114,157 @@ fn run_file(path: PathBuf, runtime: Runtime) {
         process::exit(1);
     });
 
+    let program = MacroExpander::new().expand(program).unwrap_or_else(|err| {
+        println!("Failed to expand macros: {}", err);
+        process::exit(1);
+    });
+
+    if optimize_ast {
+        optimize::optimize(program)
+    } else {
+        program
+    }
+}
+
+fn check_file(path: PathBuf) {
+    let program = load_program(&path, false);
+
+    let errors = check::check(&program);
+    for err in &errors {
+        println!("{}:{}", path.display(), err);
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn lint_file(path: PathBuf, allow: &[String]) {
+    let mut linter = Linter::new();
+    for rule in allow {
+        linter.allow(rule).unwrap_or_else(|err| {
+            println!("{}", err);
+            process::exit(1);
+        });
+    }
+
+    let program = load_program(&path, false);
+
+    let warnings = linter.lint(&program);
+    for warning in &warnings {
+        println!("{}:{}", path.display(), warning);
+    }
+    if !warnings.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn disasm_file(path: PathBuf, optimize: bool, optimize_ast: bool) {
+    let program = load_program(&path, optimize_ast);
+
+    let mut compiler = Compiler::with_path(&path);
+    compiler.set_optimize(optimize);
+    let bytecode = compiler.compile(&program).unwrap_or_else(|err| {
+        println!("Failed to compile the program: {}", err);
+        process::exit(1);
+    });
+
+    print!("{}", disasm::disassemble(&bytecode));
+}
+
+fn build_file(
+    path: PathBuf,
+    output: Option<PathBuf>,
+    stats: bool,
+    optimize: bool,
+    optimize_ast: bool,
+) {
+    let program = load_program(&path, optimize_ast);
+
+    let mut compiler = Compiler::with_path(&path);
+    compiler.set_optimize(optimize);
+    let bytecode = compiler.compile(&program).unwrap_or_else(|err| {
+        println!("Failed to compile the program: {}", err);
+        process::exit(1);
+    });
+
+    let bytes = serialize::serialize(&bytecode).unwrap_or_else(|err| {
+        println!("Failed to write the bytecode: {}", err);
+        process::exit(1);
+    });
+
+    let output = output.unwrap_or_else(|| path.with_extension(serialize::EXTENSION));
+    fs::write(&output, bytes).unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+
+    if stats {
+        println!("{}", compiler.constant_stats());
+    }
+}
+
+fn run_bytecode_file(path: &Path, runtime: Runtime, max_depth: usize) {
+    if runtime != Runtime::Vm {
+        println!("Bytecode files can only be run by the vm runtime");
+        process::exit(1);
+    }
+
+    let bytes = fs::read(path).unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+
+    let loaded = serialize::deserialize(&bytes).unwrap_or_else(|err| {
+        println!("Failed to load the bytecode: {}", err);
+        process::exit(1);
+    });
+
+    let mut vm = VirtualMachine::new();
+    vm.set_max_depth(max_depth);
+    vm.run(&loaded.bytecode()).unwrap_or_else(|err| {
+        println!("Failed to run the program: {}", err);
+        println!("{}", vm.stack_trace());
+        process::exit(1);
+    });
+
+    println!("{}", vm.last_popped().inspect());
+}
+
+fn run_file(path: PathBuf, runtime: Runtime, max_depth: usize, optimize: bool, optimize_ast: bool) {
+    if path
+        .extension()
+        .is_some_and(|ext| ext == serialize::EXTENSION)
+    {
+        return run_bytecode_file(&path, runtime, max_depth);
+    }
+
+    let program = load_program(&path, optimize_ast);
+
     match runtime {
         Runtime::Eval => {
-            let mut evaluator = Evaluator::new();
+            let mut evaluator = Evaluator::with_path(&path);
+            evaluator.set_max_depth(max_depth);
             let res = evaluator.evaluate(&program).unwrap_or_else(|err| {
                 println!("Failed to run the program: {}", err);
+                println!("{}", evaluator.stack_trace());
                 process::exit(1);
             });
 
             println!("{}", res.inspect());
         }
         Runtime::Vm => {
-            let mut compiler = Compiler::new();
+            let mut compiler = Compiler::with_path(&path);
+            compiler.set_optimize(optimize);
             let bytecode = compiler.compile(&program).unwrap_or_else(|err| {
                 println!("Failed to compile the program: {}", err);
                 process::exit(1);
             });
 
             let mut vm = VirtualMachine::new();
+            vm.set_max_depth(max_depth);
             vm.run(&bytecode).unwrap_or_else(|err| {
                 println!("Failed to run the program: {}", err);
+                println!("{}", vm.stack_trace());
                 process::exit(1);
             });
 
@@ -0,0 +1,169 @@
+//! Loading of modules imported with the `import` statement.
+//!
+//! Module resolution is shared by the evaluator and the compiler,
+//! so that both runtimes resolve paths and report errors in the same way.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{ast, macro_expansion::MacroExpander, parse};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("failed to read module {path}: {message}")]
+    Read { path: String, message: String },
+    #[error("failed to parse module {path}: {message}")]
+    Parse { path: String, message: String },
+    #[error("failed to expand macros in module {path}: {message}")]
+    MacroExpansion { path: String, message: String },
+    #[error("import cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("return outside of a function in module {path}")]
+    TopLevelReturn { path: String },
+    #[error("? operator outside of a function in module {path}")]
+    TopLevelTry { path: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Resolves the imported path relative to the directory of the importing file.
+/// If there is no importing file (for example in the REPL), the path is
+/// resolved relative to the current working directory.
+pub fn resolve(path: &str, importer: Option<&Path>) -> Result<PathBuf> {
+    let dir = importer.and_then(Path::parent).unwrap_or(Path::new(""));
+    let joined = dir.join(path);
+
+    fs::canonicalize(&joined).map_err(|err| Error::Read {
+        path: joined.display().to_string(),
+        message: err.to_string(),
+    })
+}
+
+/// Returns an error with the full import chain if importing `path`
+/// from the last module in `chain` would create a cycle.
+pub fn check_cycle(chain: &[PathBuf], path: &Path) -> Result<()> {
+    let Some(start) = chain.iter().position(|p| p == path) else {
+        return Ok(());
+    };
+
+    let cycle = chain[start..]
+        .iter()
+        .map(|p| p.as_path())
+        .chain([path])
+        .map(|p| p.display().to_string())
+        .collect();
+
+    Err(Error::Cycle(cycle))
+}
+
+/// Reads, parses and expands macros of the module at the resolved path.
+/// Modules can't return early, so `return` and `?` are only allowed in functions.
+pub fn load(path: &Path) -> Result<ast::Program> {
+    let input = fs::read_to_string(path).map_err(|err| Error::Read {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    })?;
+
+    let program = parse::parse(&input).map_err(|err| Error::Parse {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    })?;
+
+    let program = MacroExpander::new()
+        .expand(program)
+        .map_err(|err| Error::MacroExpansion {
+            path: path.display().to_string(),
+            message: err.to_string(),
+        })?;
+
+    check_statements(&program.statements, path)?;
+    Ok(program)
+}
+
+/// Checks that the statements outside of functions don't return.
+fn check_statements(statements: &[ast::Statement], path: &Path) -> Result<()> {
+    for stmt in statements {
+        match stmt {
+            ast::Statement::Return(_) => {
+                return Err(Error::TopLevelReturn {
+                    path: path.display().to_string(),
+                })
+            }
+            ast::Statement::Let { value: expr, .. }
+            | ast::Statement::Yield(expr)
+            | ast::Statement::Expression(expr) => check_expression(expr, path)?,
+            ast::Statement::Import { .. } | ast::Statement::Export(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn check_expression(expression: &ast::Expression, path: &Path) -> Result<()> {
+    // Quoted code isn't evaluated.
+    if expression.call_arguments("quote").is_some() {
+        return Ok(());
+    }
+
+    match expression {
+        ast::Expression::Identifier(_)
+        | ast::Expression::IntegerLiteral(_)
+        | ast::Expression::BigIntegerLiteral(_)
+        | ast::Expression::BooleanLiteral(_)
+        | ast::Expression::StringLiteral(_)
+        | ast::Expression::BytesLiteral(_)
+        | ast::Expression::FunctionLiteral { .. }
+        | ast::Expression::MacroLiteral { .. } => Ok(()),
+        ast::Expression::Try(_) => Err(Error::TopLevelTry {
+            path: path.display().to_string(),
+        }),
+        ast::Expression::ArrayLiteral(elements) => elements
+            .iter()
+            .try_for_each(|expr| check_expression(expr, path)),
+        ast::Expression::HashLiteral(pairs) => pairs.iter().try_for_each(|pair| {
+            check_expression(&pair.key, path)?;
+            check_expression(&pair.value, path)
+        }),
+        ast::Expression::PrefixOperator { right, .. } => check_expression(right, path),
+        ast::Expression::InfixOperator { left, right, .. }
+        | ast::Expression::Index {
+            left, index: right, ..
+        } => {
+            check_expression(left, path)?;
+            check_expression(right, path)
+        }
+        ast::Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            check_expression(condition, path)?;
+            check_statements(&consequence.statements, path)?;
+            check_statements(&alternative.statements, path)
+        }
+        ast::Expression::Block(block) => check_statements(&block.statements, path),
+        ast::Expression::FunctionCall {
+            function,
+            arguments,
+            ..
+        } => {
+            check_expression(function, path)?;
+            arguments
+                .iter()
+                .try_for_each(|expr| check_expression(expr, path))
+        }
+    }
+}
+
+/// Returns names exported by the module with `export` statements.
+pub fn exports(program: &ast::Program) -> impl Iterator<Item = &String> {
+    program.statements.iter().flat_map(|stmt| match stmt {
+        ast::Statement::Export(names) => names.as_slice(),
+        _ => &[],
+    })
+}
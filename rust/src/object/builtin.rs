@@ -2,7 +2,7 @@ use std::rc::Rc;
 
 use thiserror::Error;
 
-use super::{DataType, Object};
+use super::{DataType, ErrorObject, Generator, Object};
 
 #[derive(Debug, PartialEq, Clone, Error)]
 pub enum ExecutionError {
@@ -10,6 +10,10 @@ pub enum ExecutionError {
     TypeMismatch(String),
     #[error("wrong number of arguments: expected {expected}, got {got}")]
     WrongNumberOfArguments { expected: usize, got: usize },
+    #[error("wrong number of arguments: expected {min} to {max}, got {got}")]
+    WrongNumberOfArgumentsRange { min: usize, max: usize, got: usize },
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -20,9 +24,48 @@ pub enum BuiltinFunction {
     Rest,
     Push,
     Puts,
+    Error,
+    IsError,
+    Next,
+    IsDone,
+    Bytes,
+    String,
+    Array,
+    Hex,
+    FromHex,
+    Slice,
 }
 
 impl BuiltinFunction {
+    /// All builtin functions in the order of their index.
+    pub const ALL: [BuiltinFunction; 16] = [
+        BuiltinFunction::Len,
+        BuiltinFunction::First,
+        BuiltinFunction::Last,
+        BuiltinFunction::Rest,
+        BuiltinFunction::Push,
+        BuiltinFunction::Puts,
+        BuiltinFunction::Error,
+        BuiltinFunction::IsError,
+        BuiltinFunction::Next,
+        BuiltinFunction::IsDone,
+        BuiltinFunction::Bytes,
+        BuiltinFunction::String,
+        BuiltinFunction::Array,
+        BuiltinFunction::Hex,
+        BuiltinFunction::FromHex,
+        BuiltinFunction::Slice,
+    ];
+
+    /// Returns the index of the builtin, which is its operand in the instructions.
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
     pub fn from_ident(ident: &str) -> Option<Self> {
         match ident {
             "len" => Some(Self::Len),
@@ -31,6 +74,16 @@ impl BuiltinFunction {
             "rest" => Some(Self::Rest),
             "push" => Some(Self::Push),
             "puts" => Some(Self::Puts),
+            "error" => Some(Self::Error),
+            "is_error" => Some(Self::IsError),
+            "next" => Some(Self::Next),
+            "is_done" => Some(Self::IsDone),
+            "bytes" => Some(Self::Bytes),
+            "string" => Some(Self::String),
+            "array" => Some(Self::Array),
+            "hex" => Some(Self::Hex),
+            "from_hex" => Some(Self::FromHex),
+            "slice" => Some(Self::Slice),
             _ => None,
         }
     }
@@ -43,6 +96,16 @@ impl BuiltinFunction {
             BuiltinFunction::Rest => "rest",
             BuiltinFunction::Push => "push",
             BuiltinFunction::Puts => "puts",
+            BuiltinFunction::Error => "error",
+            BuiltinFunction::IsError => "is_error",
+            BuiltinFunction::Next => "next",
+            BuiltinFunction::IsDone => "is_done",
+            BuiltinFunction::Bytes => "bytes",
+            BuiltinFunction::String => "string",
+            BuiltinFunction::Array => "array",
+            BuiltinFunction::Hex => "hex",
+            BuiltinFunction::FromHex => "from_hex",
+            BuiltinFunction::Slice => "slice",
         }
     }
 
@@ -54,6 +117,16 @@ impl BuiltinFunction {
             BuiltinFunction::Rest => execute_rest(args),
             BuiltinFunction::Push => execute_push(args),
             BuiltinFunction::Puts => execute_puts(args),
+            BuiltinFunction::Error => execute_error(args),
+            BuiltinFunction::IsError => execute_is_error(args),
+            BuiltinFunction::Next => unreachable!("next is executed by the runtime"),
+            BuiltinFunction::IsDone => execute_is_done(args),
+            BuiltinFunction::Bytes => execute_bytes(args),
+            BuiltinFunction::String => execute_string(args),
+            BuiltinFunction::Array => execute_array(args),
+            BuiltinFunction::Hex => execute_hex(args),
+            BuiltinFunction::FromHex => execute_from_hex(args),
+            BuiltinFunction::Slice => execute_slice(args),
         }
     }
 }
@@ -69,6 +142,7 @@ fn execute_len(args: &[Object]) -> Result<Object, ExecutionError> {
     match &args[0] {
         Object::String(s) => Ok(Object::Integer(s.len() as i64)),
         Object::Array(arr) => Ok(Object::Integer(arr.len() as i64)),
+        Object::Bytes(bytes) => Ok(Object::Integer(bytes.len() as i64)),
         _ => Err(ExecutionError::TypeMismatch(
             DataType::from(&args[0]).to_string(),
         )),
@@ -163,3 +237,219 @@ fn execute_puts(args: &[Object]) -> Result<Object, ExecutionError> {
     }
     Ok(Object::Null)
 }
+
+fn execute_error(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(ExecutionError::WrongNumberOfArgumentsRange {
+            min: 1,
+            max: 2,
+            got: args.len(),
+        });
+    }
+
+    let Object::String(message) = &args[0] else {
+        return Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        ));
+    };
+
+    let data = args.get(1).cloned().unwrap_or(Object::Null);
+    Ok(Object::Error(ErrorObject {
+        message: message.clone(),
+        data: Rc::new(data),
+    }))
+}
+
+fn execute_is_error(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    Ok(Object::Boolean(matches!(args[0], Object::Error(_))))
+}
+
+/// Returns the generator passed to `next`. Resuming the generator
+/// needs the runtime, so `next` is executed by the evaluator and the VM.
+pub fn next_argument(args: &[Object]) -> Result<&Generator, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    let Object::Generator(generator) = &args[0] else {
+        return Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        ));
+    };
+
+    Ok(generator)
+}
+
+fn execute_is_done(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    Ok(Object::Boolean(matches!(args[0], Object::Done)))
+}
+
+/// Converts a string to its UTF-8 bytes and an array of integers to bytes.
+fn execute_bytes(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    match &args[0] {
+        Object::String(s) => Ok(Object::Bytes(Rc::new(s.as_bytes().to_vec()))),
+        Object::Bytes(bytes) => Ok(Object::Bytes(bytes.clone())),
+        Object::Array(arr) => {
+            let bytes = arr
+                .iter()
+                .map(|obj| match obj {
+                    Object::Integer(i) => u8::try_from(*i).map_err(|_| {
+                        ExecutionError::InvalidArgument(format!("{} is not a byte", i))
+                    }),
+                    _ => Err(ExecutionError::TypeMismatch(
+                        DataType::from(obj).to_string(),
+                    )),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Object::Bytes(Rc::new(bytes)))
+        }
+        _ => Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        )),
+    }
+}
+
+/// Decodes UTF-8 bytes to a string.
+fn execute_string(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    match &args[0] {
+        Object::String(s) => Ok(Object::String(s.clone())),
+        Object::Bytes(bytes) => {
+            let s = String::from_utf8(bytes.to_vec())
+                .map_err(|err| ExecutionError::InvalidArgument(err.to_string()))?;
+            Ok(Object::String(Rc::new(s)))
+        }
+        _ => Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        )),
+    }
+}
+
+fn execute_array(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    let Object::Bytes(bytes) = &args[0] else {
+        return Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        ));
+    };
+
+    let arr = bytes
+        .iter()
+        .map(|byte| Object::Integer(*byte as i64))
+        .collect();
+    Ok(Object::Array(Rc::new(arr)))
+}
+
+fn execute_hex(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    let Object::Bytes(bytes) = &args[0] else {
+        return Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        ));
+    };
+
+    let hex = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(Object::String(Rc::new(hex)))
+}
+
+fn execute_from_hex(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 1 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 1,
+            got: args.len(),
+        });
+    }
+
+    let Object::String(hex) = &args[0] else {
+        return Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        ));
+    };
+
+    let invalid = || ExecutionError::InvalidArgument(format!("invalid hex string {:?}", hex));
+    if hex.len() % 2 != 0 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    Ok(Object::Bytes(Rc::new(bytes)))
+}
+
+/// Returns the elements of an array or bytes from `start` up to `end`.
+/// Bounds outside of the value are clamped instead of being an error.
+fn execute_slice(args: &[Object]) -> Result<Object, ExecutionError> {
+    if args.len() != 3 {
+        return Err(ExecutionError::WrongNumberOfArguments {
+            expected: 3,
+            got: args.len(),
+        });
+    }
+
+    let bound = |obj: &Object, len: usize| match obj {
+        Object::Integer(i) => Ok((*i).clamp(0, len as i64) as usize),
+        _ => Err(ExecutionError::TypeMismatch(
+            DataType::from(obj).to_string(),
+        )),
+    };
+
+    match &args[0] {
+        Object::Array(arr) => {
+            let start = bound(&args[1], arr.len())?;
+            let end = bound(&args[2], arr.len())?.max(start);
+            Ok(Object::Array(Rc::new(arr[start..end].to_vec())))
+        }
+        Object::Bytes(bytes) => {
+            let start = bound(&args[1], bytes.len())?;
+            let end = bound(&args[2], bytes.len())?.max(start);
+            Ok(Object::Bytes(Rc::new(bytes[start..end].to_vec())))
+        }
+        _ => Err(ExecutionError::TypeMismatch(
+            DataType::from(&args[0]).to_string(),
+        )),
+    }
+}
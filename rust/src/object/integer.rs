@@ -0,0 +1,171 @@
+//! Integer arithmetic shared by both runtimes. Integers are stored as `i64`
+//! and promoted to big integers on overflow. Results, which fit into `i64`,
+//! are demoted again, so every value has exactly one representation.
+
+use std::{cmp::Ordering, fmt::Display, rc::Rc};
+
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+
+use super::Object;
+
+/// Borrowed integer object of either representation.
+#[derive(Debug, Clone, Copy)]
+pub enum Integer<'a> {
+    Small(i64),
+    Big(&'a BigInt),
+}
+
+impl<'a> Integer<'a> {
+    pub fn from_object(object: &'a Object) -> Option<Self> {
+        match object {
+            Object::Integer(i) => Some(Self::Small(*i)),
+            Object::BigInteger(i) => Some(Self::Big(i)),
+            _ => None,
+        }
+    }
+
+    pub fn plus(self, other: Self) -> Object {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => match left.checked_add(right) {
+                Some(res) => Object::Integer(res),
+                None => normalize(BigInt::from(left) + right),
+            },
+            _ => normalize(self.to_big() + other.to_big()),
+        }
+    }
+
+    pub fn minus(self, other: Self) -> Object {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => match left.checked_sub(right) {
+                Some(res) => Object::Integer(res),
+                None => normalize(BigInt::from(left) - right),
+            },
+            _ => normalize(self.to_big() - other.to_big()),
+        }
+    }
+
+    pub fn times(self, other: Self) -> Object {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => match left.checked_mul(right) {
+                Some(res) => Object::Integer(res),
+                None => normalize(BigInt::from(left) * right),
+            },
+            _ => normalize(self.to_big() * other.to_big()),
+        }
+    }
+
+    /// Truncating division. Returns `None` for a division by zero.
+    pub fn divide(self, other: Self) -> Option<Object> {
+        match (self, other) {
+            (_, Self::Small(0)) => None,
+            (Self::Small(left), Self::Small(right)) => match left.checked_div(right) {
+                Some(res) => Some(Object::Integer(res)),
+                None => Some(normalize(BigInt::from(left) / right)),
+            },
+            _ => {
+                let divisor = other.to_big();
+                (!divisor.is_zero()).then(|| normalize(self.to_big() / divisor))
+            }
+        }
+    }
+
+    pub fn negate(self) -> Object {
+        match self {
+            Self::Small(i) => match i.checked_neg() {
+                Some(res) => Object::Integer(res),
+                None => normalize(-BigInt::from(i)),
+            },
+            Self::Big(i) => normalize(-i),
+        }
+    }
+
+    pub fn bit_and(self, other: Self) -> Object {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => Object::Integer(left & right),
+            _ => normalize(self.to_big() & other.to_big()),
+        }
+    }
+
+    pub fn bit_or(self, other: Self) -> Object {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => Object::Integer(left | right),
+            _ => normalize(self.to_big() | other.to_big()),
+        }
+    }
+
+    pub fn bit_xor(self, other: Self) -> Object {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => Object::Integer(left ^ right),
+            _ => normalize(self.to_big() ^ other.to_big()),
+        }
+    }
+
+    pub fn bit_not(self) -> Object {
+        match self {
+            Self::Small(i) => Object::Integer(!i),
+            Self::Big(i) => normalize(!i),
+        }
+    }
+
+    /// Returns `None` if the shift amount is negative or doesn't fit into `u32`.
+    pub fn shift_left(self, amount: Self) -> Option<Object> {
+        let amount = amount.shift_amount()?;
+        let res = match self {
+            Self::Small(i) if amount < 64 && (i << amount) >> amount == i => {
+                Object::Integer(i << amount)
+            }
+            _ => normalize(self.to_big() << amount),
+        };
+        Some(res)
+    }
+
+    /// Arithmetic shift, which rounds towards negative infinity. Returns `None`
+    /// if the shift amount is negative or doesn't fit into `u32`.
+    pub fn shift_right(self, amount: Self) -> Option<Object> {
+        let amount = amount.shift_amount()?;
+        let res = match self {
+            Self::Small(i) => Object::Integer(i >> amount.min(63)),
+            Self::Big(i) => normalize(i >> amount),
+        };
+        Some(res)
+    }
+
+    pub fn compare(self, other: Self) -> Ordering {
+        match (self, other) {
+            (Self::Small(left), Self::Small(right)) => left.cmp(&right),
+            _ => self.to_big().cmp(&other.to_big()),
+        }
+    }
+
+    fn shift_amount(self) -> Option<u32> {
+        match self {
+            Self::Small(i) => u32::try_from(i).ok(),
+            Self::Big(_) => None,
+        }
+    }
+
+    fn to_big(self) -> BigInt {
+        match self {
+            Self::Small(i) => BigInt::from(i),
+            Self::Big(i) => i.clone(),
+        }
+    }
+}
+
+impl Display for Integer<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Small(i) => write!(f, "{}", i),
+            Self::Big(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+/// Creates an integer object, which is only big if it doesn't fit into `i64`.
+pub fn normalize(value: BigInt) -> Object {
+    match value.to_i64() {
+        Some(i) => Object::Integer(i),
+        None => Object::BigInteger(Rc::new(value)),
+    }
+}
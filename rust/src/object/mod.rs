@@ -1,29 +1,54 @@
 pub mod builtin;
+pub mod integer;
 
-use std::{collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    cell::{Ref, RefCell},
+    cmp::Ordering,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
-use crate::{ast, code::Instruction, environment::Environment};
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+
+use crate::{
+    ast,
+    code::{Instructions, LineTable},
+    environment::Environment,
+};
 use builtin::*;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
     Integer(i64),
+    /// Integer, which doesn't fit into `i64`. Results of the arithmetic
+    /// in [`integer`] are only big if they have to be.
+    BigInteger(Rc<BigInt>),
     String(Rc<String>),
+    Bytes(Rc<Vec<u8>>),
     Boolean(bool),
     Return(Rc<Object>),
     Function(FunctionObject),
     Builtin(BuiltinFunction),
     Array(Rc<Vec<Object>>),
-    HashMap(Rc<HashMap<HashKey, Object>>),
+    /// Hash map, which iterates in insertion order.
+    HashMap(Rc<IndexMap<HashKey, Object>>),
     Null,
     CompiledFunction(CompiledFunction),
     Closure(Closure),
+    Error(ErrorObject),
+    Quote(Rc<ast::Expression>),
+    Generator(Generator),
+    /// Returned by `next` once the generator is finished.
+    Done,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DataType {
     Integer,
     String,
+    Bytes,
     Boolean,
     Return,
     Function,
@@ -31,24 +56,32 @@ pub enum DataType {
     Array,
     HashMap,
     Null,
-    CompiledFunction,
-    Closure,
+    Error,
+    Quote,
+    Generator,
+    Done,
 }
 
 impl From<&Object> for DataType {
     fn from(value: &Object) -> Self {
         match value {
-            Object::Integer(_) => Self::Integer,
+            Object::Integer(_) | Object::BigInteger(_) => Self::Integer,
             Object::String(_) => Self::String,
+            Object::Bytes(_) => Self::Bytes,
             Object::Boolean(_) => Self::Boolean,
             Object::Return(_) => Self::Return,
-            Object::Function(_) => Self::Function,
+            // Functions of both runtimes have the same type.
+            Object::Function(_) | Object::CompiledFunction(_) | Object::Closure { .. } => {
+                Self::Function
+            }
             Object::Builtin(_) => Self::Builtin,
             Object::Array(_) => Self::Array,
             Object::HashMap(_) => Self::HashMap,
             Object::Null => Self::Null,
-            Object::CompiledFunction(_) => Self::CompiledFunction,
-            Object::Closure { .. } => Self::Closure,
+            Object::Error(_) => Self::Error,
+            Object::Quote(_) => Self::Quote,
+            Object::Generator(_) => Self::Generator,
+            Object::Done => Self::Done,
         }
     }
 }
@@ -64,6 +97,7 @@ impl Display for DataType {
         let string = match self {
             DataType::Integer => "INTEGER",
             DataType::String => "STRING",
+            DataType::Bytes => "BYTES",
             DataType::Boolean => "BOOLEAN",
             DataType::Return => "RETURN",
             DataType::Function => "FUNCTION",
@@ -71,8 +105,10 @@ impl Display for DataType {
             DataType::Array => "ARRAY",
             DataType::HashMap => "HASH_MAP",
             DataType::Null => "NULL",
-            DataType::CompiledFunction => "COMPILED_FUNCTION",
-            DataType::Closure => "CLOSURE",
+            DataType::Error => "ERROR",
+            DataType::Quote => "QUOTE",
+            DataType::Generator => "GENERATOR",
+            DataType::Done => "DONE",
         };
 
         f.write_str(string)
@@ -83,7 +119,9 @@ impl Object {
     pub fn inspect(&self) -> String {
         match self {
             Object::Integer(i) => i.to_string(),
+            Object::BigInteger(i) => i.to_string(),
             Object::String(s) => (**s).clone(),
+            Object::Bytes(bytes) => ast::bytes_debug_str(bytes),
             Object::Boolean(b) => b.to_string(),
             Object::Return(o) => o.inspect(),
             Object::Function(fun) => fun.inspect(),
@@ -107,11 +145,21 @@ impl Object {
             }
             Object::Null => "null".to_string(),
             Object::CompiledFunction(fun) => {
-                format!("compiled function: {:?}", fun.instructions.as_ptr())
+                format!(
+                    "compiled function: {:?}",
+                    fun.instructions.as_bytes().as_ptr()
+                )
             }
             Object::Closure(closure) => {
-                format!("closure: {:?}", closure.function.instructions.as_ptr())
+                format!(
+                    "closure: {:?}",
+                    closure.function.instructions.as_bytes().as_ptr()
+                )
             }
+            Object::Error(err) => err.inspect(),
+            Object::Quote(expr) => format!("quote({})", expr.debug_str()),
+            Object::Generator(generator) => format!("generator: {:?}", generator.0.as_ptr()),
+            Object::Done => "done".to_string(),
         }
     }
 
@@ -120,24 +168,121 @@ impl Object {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct CompiledFunction {
-    pub instructions: Rc<Vec<Instruction>>,
+    pub instructions: Rc<Instructions>,
     pub num_locals: usize,
     pub num_arguments: usize,
+    /// Calling a generator function creates a generator
+    /// instead of executing the body.
+    pub generator: bool,
+    /// Name of the function in stack traces, see [`ast::Expression::FunctionLiteral`].
+    pub name: Option<Rc<String>>,
+    pub lines: Rc<LineTable>,
+    /// Names of the local slots, which are shown by the disassembler.
+    pub local_names: Rc<Vec<String>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Closure {
     pub function: CompiledFunction,
     pub free: Rc<Vec<Object>>,
+    /// Globals of the module in which the closure was created.
+    /// Index 0 are the globals of the main program and index `i + 1`
+    /// are the globals of the `i`-th module in the bytecode.
+    pub module: usize,
 }
 
+/// Error value created by the `error` builtin. Errors are regular
+/// values, which can be propagated with the `?` operator.
 #[derive(Debug, PartialEq, Clone)]
+pub struct ErrorObject {
+    pub message: Rc<String>,
+    pub data: Rc<Object>,
+}
+
+impl ErrorObject {
+    fn inspect(&self) -> String {
+        match *self.data {
+            Object::Null => format!("error({})", self.message),
+            ref data => format!("error({}, {})", self.message, data.inspect()),
+        }
+    }
+}
+
+/// Generator created by calling a function, which contains `yield`.
+/// The generator is shared, so resuming it through one reference
+/// advances all of them.
+#[derive(Clone)]
+pub struct Generator(Rc<RefCell<GeneratorState>>);
+
+#[derive(Debug, Clone)]
+pub enum GeneratorState {
+    /// Generator of the evaluator. Every block is stored together with
+    /// the index of the next statement, its environment and the line of
+    /// the enclosing expression, innermost block last. The name of the
+    /// generator function is used for stack traces.
+    Evaluated {
+        blocks: Vec<(ast::BlockStatement, usize, Environment, usize)>,
+        name: Option<Rc<String>>,
+    },
+    /// Generator of the virtual machine. The stack holds the locals
+    /// of the suspended frame.
+    Compiled {
+        closure: Closure,
+        ip: usize,
+        stack: Vec<Object>,
+    },
+    Running,
+    Done,
+}
+
+impl Generator {
+    pub fn new(state: GeneratorState) -> Self {
+        Self(Rc::new(RefCell::new(state)))
+    }
+
+    pub fn state(&self) -> Ref<'_, GeneratorState> {
+        self.0.borrow()
+    }
+
+    pub fn replace(&self, state: GeneratorState) -> GeneratorState {
+        self.0.replace(state)
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// Generators can contain themselves, so the state is not printed.
+impl std::fmt::Debug for Generator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Generator({:?})", self.0.as_ptr())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct FunctionObject {
     pub parameters: Rc<Vec<String>>,
     pub body: ast::BlockStatement,
     pub environment: Environment,
+    pub generator: bool,
+    /// Name of the function in stack traces, see [`ast::Expression::FunctionLiteral`].
+    pub name: Option<Rc<String>>,
+    /// Line of the function literal.
+    pub line: usize,
+}
+
+// Functions are equal if they are created by the same literal
+// in the same environment.
+impl PartialEq for FunctionObject {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.body.statements, &other.body.statements)
+            && self.environment == other.environment
+    }
 }
 
 impl FunctionObject {
@@ -150,20 +295,63 @@ impl FunctionObject {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Key of a hash map. Keys are immutable values, which are hashed and
+/// compared structurally, so `[1, 2]` finds the entry of any other `[1, 2]`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum HashKey {
     String(Rc<String>),
+    Bytes(Rc<Vec<u8>>),
     Integer(i64),
+    BigInteger(Rc<BigInt>),
     Boolean(bool),
+    Null,
+    Array(Rc<Vec<HashKey>>),
+    HashMap(HashKeyPairs),
+}
+
+/// Pairs of a hash map key in insertion order, which is kept for printing.
+/// They are compared and hashed sorted by key, so that equal hash maps
+/// are the same key regardless of their iteration order.
+#[derive(Debug, Clone)]
+pub struct HashKeyPairs(pub Rc<Vec<(HashKey, HashKey)>>);
+
+impl HashKeyPairs {
+    fn sorted(&self) -> Vec<&(HashKey, HashKey)> {
+        let mut pairs: Vec<_> = self.0.iter().collect();
+        pairs.sort();
+        pairs
+    }
+}
+
+impl PartialEq for HashKeyPairs {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.sorted() == other.sorted()
+    }
+}
+
+impl Eq for HashKeyPairs {}
+
+impl PartialOrd for HashKeyPairs {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HashKeyPairs {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sorted().cmp(&other.sorted())
+    }
+}
+
+impl Hash for HashKeyPairs {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sorted().hash(state)
+    }
 }
 
 impl HashKey {
     fn inspect(&self) -> String {
-        match self {
-            Self::String(s) => (**s).clone(),
-            Self::Integer(i) => i.to_string(),
-            Self::Boolean(b) => b.to_string(),
-        }
+        Object::from(self.clone()).inspect()
     }
 }
 
@@ -173,9 +361,49 @@ impl TryFrom<Object> for HashKey {
     fn try_from(value: Object) -> Result<Self, Self::Error> {
         match value {
             Object::String(str) => Ok(Self::String(str)),
+            Object::Bytes(bytes) => Ok(Self::Bytes(bytes)),
             Object::Integer(i) => Ok(Self::Integer(i)),
+            Object::BigInteger(i) => Ok(Self::BigInteger(i)),
             Object::Boolean(b) => Ok(Self::Boolean(b)),
+            Object::Null => Ok(Self::Null),
+            Object::Array(arr) => {
+                let elements = arr
+                    .iter()
+                    .map(|obj| obj.clone().try_into())
+                    .collect::<Result<_, _>>()?;
+                Ok(Self::Array(Rc::new(elements)))
+            }
+            Object::HashMap(map) => {
+                let pairs = map
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), value.clone().try_into()?)))
+                    .collect::<Result<Vec<_>, DataType>>()?;
+                Ok(Self::HashMap(HashKeyPairs(Rc::new(pairs))))
+            }
             _ => Err(value.into()),
         }
     }
 }
+
+impl From<HashKey> for Object {
+    fn from(value: HashKey) -> Self {
+        match value {
+            HashKey::String(str) => Object::String(str),
+            HashKey::Bytes(bytes) => Object::Bytes(bytes),
+            HashKey::Integer(i) => Object::Integer(i),
+            HashKey::BigInteger(i) => Object::BigInteger(i),
+            HashKey::Boolean(b) => Object::Boolean(b),
+            HashKey::Null => Object::Null,
+            HashKey::Array(arr) => {
+                Object::Array(Rc::new(arr.iter().cloned().map(Object::from).collect()))
+            }
+            HashKey::HashMap(pairs) => Object::HashMap(Rc::new(
+                pairs
+                    .0
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone().into()))
+                    .collect(),
+            )),
+        }
+    }
+}
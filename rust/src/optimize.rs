@@ -0,0 +1,668 @@
+//! Optimizer of the AST, which runs after the macro expansion and
+//! before the program is evaluated or compiled.
+//!
+//! The optimizer replaces identifiers bound to literals with the literals,
+//! inlines calls of small functions and replaces `if` expressions whose
+//! condition is a literal with the branch that is taken.
+//!
+//! Only names that are bound once in the program are replaced, so a
+//! replaced name always refers to the same binding. Top level bindings
+//! can be rebound by the following programs of the REPL, so they are only
+//! replaced in the top level code and not in function bodies, which may
+//! be called after the binding changed.
+//!
+//! Inlined calls have no stack frame, so only functions whose body can't
+//! fail are inlined and the stack traces of errors don't change. Inlined
+//! calls also don't count towards the recursion limit.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{
+        BlockStatement, Expression, HashLiteralPair, InfixOperatorKind, PrefixOperatorKind,
+        Program, Statement,
+    },
+    object::builtin::BuiltinFunction,
+};
+
+/// Maximum number of expressions in the body of an inlined function.
+const INLINE_SIZE: usize = 16;
+
+/// Returns the optimized program.
+pub fn optimize(program: Program) -> Program {
+    let mut bindings = HashMap::new();
+    for stmt in &program.statements {
+        count_bindings(stmt, &mut bindings);
+    }
+
+    let mut optimizer = Optimizer {
+        bindings,
+        scopes: vec![HashMap::new()],
+        functions: 0,
+    };
+    let statements = program
+        .statements
+        .into_iter()
+        .map(|stmt| optimizer.statement(stmt))
+        .collect();
+
+    Program { statements }
+}
+
+/// Value of a binding, which replaces its uses.
+enum Value {
+    Literal(Expression),
+    /// Function that is inlined. The body only uses the
+    /// parameters and builtins, so it can be moved to the call.
+    Function {
+        parameters: Vec<String>,
+        body: Expression,
+    },
+}
+
+struct Binding {
+    value: Option<Value>,
+    top_level: bool,
+}
+
+struct Optimizer {
+    // Number of times that every name is bound in the program.
+    bindings: HashMap<String, usize>,
+    // Bindings of the enclosing blocks, the innermost one is last.
+    scopes: Vec<HashMap<String, Binding>>,
+    // Number of enclosing function literals.
+    functions: usize,
+}
+
+impl Optimizer {
+    fn statement(&mut self, statement: Statement) -> Statement {
+        match statement {
+            Statement::Let {
+                name,
+                type_annotation,
+                value,
+                constant,
+                position,
+            } => {
+                let value = self.expression(value);
+                let replacement = match &value {
+                    _ if self.bindings[&name] > 1 => None,
+                    value if literal_truthiness(value).is_some() => {
+                        Some(Value::Literal(value.clone()))
+                    }
+                    Expression::FunctionLiteral {
+                        parameters, body, ..
+                    } => self
+                        .inlined_body(body, parameters)
+                        .map(|body| Value::Function {
+                            parameters: parameters.clone(),
+                            body,
+                        }),
+                    _ => None,
+                };
+                self.define(name.clone(), replacement);
+
+                Statement::Let {
+                    name,
+                    type_annotation,
+                    value,
+                    constant,
+                    position,
+                }
+            }
+            Statement::Return(expr) => Statement::Return(self.expression(expr)),
+            Statement::Yield(expr) => Statement::Yield(self.expression(expr)),
+            Statement::Expression(expr) => Statement::Expression(self.expression(expr)),
+            Statement::Import { path, name } => {
+                self.define(name.clone(), None);
+                Statement::Import { path, name }
+            }
+            Statement::Export(_) => statement,
+        }
+    }
+
+    fn block(&mut self, block: BlockStatement) -> BlockStatement {
+        self.scopes.push(HashMap::new());
+        let statements = Rc::unwrap_or_clone(block.statements)
+            .into_iter()
+            .map(|stmt| self.statement(stmt))
+            .collect();
+        self.scopes.pop();
+
+        BlockStatement {
+            statements: Rc::new(statements),
+        }
+    }
+
+    fn expression(&mut self, expression: Expression) -> Expression {
+        let mut boxed = |expr: Box<Expression>| Box::new(self.expression(*expr));
+
+        match expression {
+            Expression::Identifier(name) => match self.resolve(&name) {
+                Some(Value::Literal(literal)) => literal.clone(),
+                _ => Expression::Identifier(name),
+            },
+            Expression::IntegerLiteral(_)
+            | Expression::BigIntegerLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::BytesLiteral(_)
+            | Expression::MacroLiteral { .. } => expression,
+            // The quoted expression is kept as it's written.
+            Expression::FunctionCall { .. } if expression.call_arguments("quote").is_some() => {
+                expression
+            }
+            Expression::ArrayLiteral(elements) => Expression::ArrayLiteral(
+                elements
+                    .into_iter()
+                    .map(|expr| self.expression(expr))
+                    .collect(),
+            ),
+            Expression::HashLiteral(pairs) => Expression::HashLiteral(
+                pairs
+                    .into_iter()
+                    .map(|pair| HashLiteralPair {
+                        key: self.expression(pair.key),
+                        value: self.expression(pair.value),
+                    })
+                    .collect(),
+            ),
+            Expression::PrefixOperator {
+                operator,
+                right,
+                position,
+            } => Expression::PrefixOperator {
+                operator,
+                right: boxed(right),
+                position,
+            },
+            Expression::InfixOperator {
+                operator,
+                left,
+                right,
+                position,
+            } => Expression::InfixOperator {
+                operator,
+                left: boxed(left),
+                right: boxed(right),
+                position,
+            },
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                position,
+            } => {
+                let condition = boxed(condition);
+                match literal_truthiness(&condition) {
+                    Some(true) => Expression::Block(self.block(consequence)),
+                    Some(false) => Expression::Block(self.block(alternative)),
+                    _ => Expression::If {
+                        condition,
+                        consequence: self.block(consequence),
+                        alternative: self.block(alternative),
+                        position,
+                    },
+                }
+            }
+            Expression::Block(block) => Expression::Block(self.block(block)),
+            Expression::FunctionLiteral {
+                name,
+                parameters,
+                parameter_types,
+                return_type,
+                body,
+                position,
+            } => {
+                self.functions += 1;
+                self.scopes.push(HashMap::new());
+                for parameter in &parameters {
+                    self.define(parameter.clone(), None);
+                }
+                let body = self.block(body);
+                self.scopes.pop();
+                self.functions -= 1;
+
+                Expression::FunctionLiteral {
+                    name,
+                    parameters,
+                    parameter_types,
+                    return_type,
+                    body,
+                    position,
+                }
+            }
+            Expression::FunctionCall {
+                function,
+                arguments,
+                position,
+            } => {
+                let function = boxed(function);
+                let arguments: Vec<_> = arguments
+                    .into_iter()
+                    .map(|expr| self.expression(expr))
+                    .collect();
+
+                if let Some(inlined) = self.inline(&function, &arguments) {
+                    return inlined;
+                }
+
+                Expression::FunctionCall {
+                    function,
+                    arguments,
+                    position,
+                }
+            }
+            Expression::Index {
+                left,
+                index,
+                position,
+            } => Expression::Index {
+                left: boxed(left),
+                index: boxed(index),
+                position,
+            },
+            Expression::Try(expr) => Expression::Try(boxed(expr)),
+        }
+    }
+
+    /// Returns the body of the called function with the parameters replaced by
+    /// the arguments. Arguments can only be literals and bound identifiers,
+    /// which have no side effects and can be evaluated any number of times.
+    fn inline(&self, function: &Expression, arguments: &[Expression]) -> Option<Expression> {
+        let Expression::Identifier(name) = function else {
+            return None;
+        };
+        let Some(Value::Function { parameters, body }) = self.resolve(name) else {
+            return None;
+        };
+        if parameters.len() != arguments.len() {
+            return None;
+        }
+
+        let simple = arguments.iter().all(|arg| match arg {
+            Expression::Identifier(name) => self.scopes.iter().any(|s| s.contains_key(name)),
+            arg => literal_truthiness(arg).is_some(),
+        });
+        if !simple {
+            return None;
+        }
+
+        let arguments: HashMap<_, _> = parameters.iter().zip(arguments).collect();
+        Some(replace_parameters(body.clone(), &arguments))
+    }
+
+    /// Returns the expression of the function body if the function can be inlined.
+    /// The body has to be a single small expression using only the parameters and
+    /// builtins, which also means that the function isn't recursive, and it must
+    /// not fail for any arguments.
+    fn inlined_body(&self, body: &BlockStatement, parameters: &[String]) -> Option<Expression> {
+        let [Statement::Expression(expr)] = body.statements.as_slice() else {
+            return None;
+        };
+
+        let mut size = 0;
+        self.can_inline(expr, parameters, &mut size)
+            .then(|| expr.clone())
+    }
+
+    fn can_inline(&self, expression: &Expression, parameters: &[String], size: &mut usize) -> bool {
+        *size += 1;
+        if *size > INLINE_SIZE {
+            return false;
+        }
+
+        let mut can_inline = |expr: &Expression| self.can_inline(expr, parameters, size);
+        match expression {
+            Expression::Identifier(name) => {
+                parameters.contains(name)
+                    || (BuiltinFunction::from_ident(name).is_some()
+                        && !self.bindings.contains_key(name))
+            }
+            Expression::IntegerLiteral(_)
+            | Expression::BigIntegerLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::BytesLiteral(_) => true,
+            Expression::ArrayLiteral(elements) => elements.iter().all(can_inline),
+            // Literal keys are always hashable.
+            Expression::HashLiteral(pairs) => pairs
+                .iter()
+                .all(|pair| literal_truthiness(&pair.key).is_some() && can_inline(&pair.value)),
+            // Only the operators that accept all operands.
+            Expression::PrefixOperator {
+                operator: PrefixOperatorKind::Not,
+                right,
+                ..
+            } => can_inline(right),
+            Expression::InfixOperator {
+                operator: InfixOperatorKind::Equal | InfixOperatorKind::NotEqual,
+                left,
+                right,
+                ..
+            } => can_inline(left) && can_inline(right),
+            // Other operators, indexes and calls can fail. Blocks
+            // and `?` could return from the calling function.
+            Expression::PrefixOperator { .. }
+            | Expression::InfixOperator { .. }
+            | Expression::Index { .. }
+            | Expression::FunctionCall { .. }
+            | Expression::If { .. }
+            | Expression::Block(_)
+            | Expression::FunctionLiteral { .. }
+            | Expression::MacroLiteral { .. }
+            | Expression::Try(_) => false,
+        }
+    }
+
+    fn define(&mut self, name: String, value: Option<Value>) {
+        let binding = Binding {
+            value,
+            top_level: self.functions == 0,
+        };
+        self.scopes
+            .last_mut()
+            .expect("scope of the binding")
+            .insert(name, binding);
+    }
+
+    /// Returns the value that replaces the identifier, if there's one.
+    fn resolve(&self, name: &str) -> Option<&Value> {
+        let binding = self.scopes.iter().rev().find_map(|scope| scope.get(name))?;
+        if binding.top_level && self.functions > 0 {
+            return None;
+        }
+
+        binding.value.as_ref()
+    }
+}
+
+/// Returns whether the expression is truthy if it's a literal.
+fn literal_truthiness(expression: &Expression) -> Option<bool> {
+    match expression {
+        Expression::BooleanLiteral(value) => Some(*value),
+        Expression::IntegerLiteral(_)
+        | Expression::BigIntegerLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::BytesLiteral(_) => Some(true),
+        _ => None,
+    }
+}
+
+/// Replaces the parameters in the body of an inlined function.
+fn replace_parameters(body: Expression, arguments: &HashMap<&String, &Expression>) -> Expression {
+    let replace = |expr| replace_parameters(expr, arguments);
+    let boxed = |expr: Box<Expression>| Box::new(replace_parameters(*expr, arguments));
+
+    match body {
+        Expression::Identifier(ref name) => match arguments.get(name) {
+            Some(argument) => (*argument).clone(),
+            None => body,
+        },
+        Expression::ArrayLiteral(elements) => {
+            Expression::ArrayLiteral(elements.into_iter().map(replace).collect())
+        }
+        Expression::HashLiteral(pairs) => Expression::HashLiteral(
+            pairs
+                .into_iter()
+                .map(|pair| HashLiteralPair {
+                    key: replace(pair.key),
+                    value: replace(pair.value),
+                })
+                .collect(),
+        ),
+        Expression::PrefixOperator {
+            operator,
+            right,
+            position,
+        } => Expression::PrefixOperator {
+            operator,
+            right: boxed(right),
+            position,
+        },
+        Expression::InfixOperator {
+            operator,
+            left,
+            right,
+            position,
+        } => Expression::InfixOperator {
+            operator,
+            left: boxed(left),
+            right: boxed(right),
+            position,
+        },
+        Expression::Index {
+            left,
+            index,
+            position,
+        } => Expression::Index {
+            left: boxed(left),
+            index: boxed(index),
+            position,
+        },
+        Expression::FunctionCall {
+            function,
+            arguments: call_arguments,
+            position,
+        } => Expression::FunctionCall {
+            function: boxed(function),
+            arguments: call_arguments.into_iter().map(replace).collect(),
+            position,
+        },
+        // Inlined bodies don't contain other expressions, see `Optimizer::can_inline`.
+        body => body,
+    }
+}
+
+/// Counts the bindings of every name in the statement.
+fn count_bindings(statement: &Statement, bindings: &mut HashMap<String, usize>) {
+    match statement {
+        Statement::Let { name, value, .. } => {
+            *bindings.entry(name.clone()).or_default() += 1;
+            count_expression_bindings(value, bindings);
+        }
+        Statement::Import { name, .. } => *bindings.entry(name.clone()).or_default() += 1,
+        Statement::Return(expr) | Statement::Yield(expr) | Statement::Expression(expr) => {
+            count_expression_bindings(expr, bindings)
+        }
+        Statement::Export(_) => (),
+    }
+}
+
+fn count_expression_bindings(expression: &Expression, bindings: &mut HashMap<String, usize>) {
+    let count_block = |block: &BlockStatement, bindings: &mut HashMap<String, usize>| {
+        for stmt in block.statements.iter() {
+            count_bindings(stmt, bindings);
+        }
+    };
+
+    match expression {
+        Expression::Identifier(_)
+        | Expression::IntegerLiteral(_)
+        | Expression::BigIntegerLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::BytesLiteral(_)
+        | Expression::MacroLiteral { .. } => (),
+        Expression::ArrayLiteral(elements) => {
+            for expr in elements {
+                count_expression_bindings(expr, bindings);
+            }
+        }
+        Expression::HashLiteral(pairs) => {
+            for pair in pairs {
+                count_expression_bindings(&pair.key, bindings);
+                count_expression_bindings(&pair.value, bindings);
+            }
+        }
+        Expression::PrefixOperator { right, .. } => count_expression_bindings(right, bindings),
+        Expression::InfixOperator { left, right, .. }
+        | Expression::Index {
+            left, index: right, ..
+        } => {
+            count_expression_bindings(left, bindings);
+            count_expression_bindings(right, bindings);
+        }
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            count_expression_bindings(condition, bindings);
+            count_block(consequence, bindings);
+            count_block(alternative, bindings);
+        }
+        Expression::Block(block) => count_block(block, bindings),
+        Expression::FunctionLiteral {
+            parameters, body, ..
+        } => {
+            for parameter in parameters {
+                *bindings.entry(parameter.clone()).or_default() += 1;
+            }
+            count_block(body, bindings);
+        }
+        Expression::FunctionCall {
+            function,
+            arguments,
+            ..
+        } => {
+            count_expression_bindings(function, bindings);
+            for expr in arguments {
+                count_expression_bindings(expr, bindings);
+            }
+        }
+        Expression::Try(expr) => count_expression_bindings(expr, bindings),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        compile::Compiler,
+        evaluate::Evaluator,
+        object::Object,
+        parse::parse,
+        runtime::{Result, StackTrace},
+        vm::VirtualMachine,
+    };
+
+    use super::optimize;
+
+    #[test]
+    fn test_optimize() {
+        let tests = [
+            ("let x = 1; x + x", "let x = 1;(1 + 1);"),
+            ("x; let x = 1; x", "x;let x = 1;1;"),
+            ("{ let x = 1; }; x", "{let x = 1;};x;"),
+            ("let x = 1; let x = 2; x", "let x = 1;let x = 2;x;"),
+            ("let x = 1; fn() { x }", "let x = 1;fn() {x;};"),
+            (
+                "fn() { let y = 2; fn() { y } }",
+                "fn() {let y = 2;fn() {2;};};",
+            ),
+            ("let q = 1; quote(q)", "let q = 1;quote(q);"),
+            ("if (true) { 1 } else { 2 }", "{1;};"),
+            ("let d = false; if (d) { 1 }", "let d = false;{};"),
+            ("fn() { if (true) { yield 1; } }", "fn() {{yield 1;};};"),
+            (
+                "let pair = fn(x) { [x, x] }; pair(3)",
+                "let pair = fn<pair>(x) {[x, x];};[3, 3];",
+            ),
+            (
+                "let same = fn(a, b) { !(a != b) }; let n = 1; same(n, true)",
+                "let same = fn<same>(a, b) {(!(a != b));};let n = 1;(!(1 != true));",
+            ),
+            (
+                r#"let h = fn(v) { {"k": v, 1: len} }; h(2)"#,
+                "let h = fn<h>(v) {{k: v, 1: len};};{k: 2, 1: len};",
+            ),
+            // Bodies that can fail aren't inlined, so errors keep the stack frame.
+            (
+                "let sq = fn(x) { x * x }; sq(3)",
+                "let sq = fn<sq>(x) {(x * x);};sq(3);",
+            ),
+            (
+                r#"let l = fn(s) { len(s) }; l("ab")"#,
+                "let l = fn<l>(s) {len(s);};l(ab);",
+            ),
+            (
+                "let k = fn(h) { {h: 1} }; k(1)",
+                "let k = fn<k>(h) {{h: 1};};k(1);",
+            ),
+            (
+                "let pair = fn(x) { [x, x] }; let a = [1]; pair(a[0])",
+                "let pair = fn<pair>(x) {[x, x];};let a = [1];pair((a[0]));",
+            ),
+            (
+                "let pair = fn(x) { [x, x] }; fn(y) { pair(y) }",
+                "let pair = fn<pair>(x) {[x, x];};fn(y) {pair(y);};",
+            ),
+            (
+                "fn() { let not = fn(x) { !x }; not(2) }",
+                "fn() {let not = fn<not>(x) {(!x);};(!2);};",
+            ),
+            (
+                "let r = fn(n) { r(n) }; r(1)",
+                "let r = fn<r>(n) {r(n);};r(1);",
+            ),
+            (
+                "let b = fn(x) { if (x) { 1 } }; b(1)",
+                "let b = fn<b>(x) {if (x) {1;} else {};};b(1);",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input).unwrap();
+
+            assert_eq!(optimize(program).debug_str(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_same_results() {
+        let tests = [
+            "let debug = false; if (debug) { 1 }",
+            "let limit = 10; if (limit) { limit * 2 } else { 0 }",
+            "let sq = fn(x) { x * x }; let n = 4; [sq(3), sq(n), sq(n + 1)]",
+            "let f = fn(n) { let k = 2; if (true) { return n * k; } 0 }; f(3)",
+            "let div = fn(a, b) { a / b }; div(1, 0)",
+            r#"let first = fn(a) { a[0] }; first("abc")"#,
+            "let gen = fn() { if (true) { yield 1; } }; next(gen())",
+            "let x = 1; let x = x + 1; let f = fn() { x }; f()",
+            "let f = fn(x) {\n x + \n \"a\"\n}; f(1)",
+            "let pair = fn(x) { [x, x] };\nlet g = fn(n) { pair(n)[0] + true };\ng(1)",
+        ];
+
+        // Results are compared together with the stack traces of errors.
+        let evaluate = |input: &str, optimized: bool| -> (Result<Object>, StackTrace) {
+            let program = parse(input).unwrap();
+            let program = if optimized {
+                optimize(program)
+            } else {
+                program
+            };
+            let mut evaluator = Evaluator::new();
+            let res = evaluator.evaluate(&program);
+            (res, evaluator.stack_trace())
+        };
+        let run = |input: &str, optimized: bool| -> (Result<Object>, StackTrace) {
+            let program = parse(input).unwrap();
+            let program = if optimized {
+                optimize(program)
+            } else {
+                program
+            };
+            let mut compiler = Compiler::new();
+            let bytecode = compiler.compile(&program).unwrap();
+            let mut vm = VirtualMachine::new();
+            let res = vm.run(&bytecode).map(|_| vm.last_popped().clone());
+            (res, vm.stack_trace())
+        };
+
+        for input in tests {
+            assert_eq!(evaluate(input, true), evaluate(input, false), "{}", input);
+            assert_eq!(run(input, true), run(input, false), "{}", input);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::token::Token;
-use std::num::ParseIntError;
+use num_bigint::ParseBigIntError;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -15,9 +15,17 @@ pub enum Error {
     #[error("Expected an expression, got: {0:?}")]
     NotAnExpression(Option<Token>),
     #[error(transparent)]
-    NotANumber(#[from] ParseIntError),
+    NotANumber(#[from] ParseBigIntError),
+    #[error("Invalid escape in bytes literal: \\{0}")]
+    InvalidEscape(String),
+    #[error("Unknown type: {0}")]
+    UnknownType(String),
     #[error("Expected a left expression, got None")]
     ExpectedLeftExpression,
+    #[error("{0:?} statement is only allowed at the top level")]
+    NotAtTopLevel(Token),
+    #[error("yield is only allowed in a function body and in its if statements")]
+    MisplacedYield,
 }
 
 impl Error {
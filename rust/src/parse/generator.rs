@@ -0,0 +1,92 @@
+//! Validation of `yield` statements.
+//!
+//! A function containing `yield` is a generator. To keep the suspended
+//! state of a generator simple, `yield` is only allowed directly in the
+//! function body or in `if` statements and blocks of the body, but not
+//! inside other expressions.
+
+use super::{Error, Result};
+use crate::ast;
+
+pub(super) fn check_yields(program: &ast::Program) -> Result<()> {
+    check_statements(&program.statements, false)
+}
+
+fn check_statements(statements: &[ast::Statement], allowed: bool) -> Result<()> {
+    for stmt in statements {
+        match stmt {
+            ast::Statement::Yield(expr) => {
+                if !allowed {
+                    return Err(Error::MisplacedYield);
+                }
+                check_expression(expr)?;
+            }
+            ast::Statement::Expression(ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            }) => {
+                check_expression(condition)?;
+                check_statements(&consequence.statements, allowed)?;
+                check_statements(&alternative.statements, allowed)?;
+            }
+            ast::Statement::Expression(ast::Expression::Block(block)) => {
+                check_statements(&block.statements, allowed)?;
+            }
+            ast::Statement::Let { value: expr, .. }
+            | ast::Statement::Return(expr)
+            | ast::Statement::Expression(expr) => check_expression(expr)?,
+            ast::Statement::Import { .. } | ast::Statement::Export(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn check_expression(expression: &ast::Expression) -> Result<()> {
+    match expression {
+        ast::Expression::Identifier(_)
+        | ast::Expression::IntegerLiteral(_)
+        | ast::Expression::BigIntegerLiteral(_)
+        | ast::Expression::BooleanLiteral(_)
+        | ast::Expression::StringLiteral(_)
+        | ast::Expression::BytesLiteral(_) => Ok(()),
+        ast::Expression::ArrayLiteral(elements) => elements.iter().try_for_each(check_expression),
+        ast::Expression::HashLiteral(pairs) => pairs.iter().try_for_each(|pair| {
+            check_expression(&pair.key)?;
+            check_expression(&pair.value)
+        }),
+        ast::Expression::PrefixOperator { right: expr, .. } | ast::Expression::Try(expr) => {
+            check_expression(expr)
+        }
+        ast::Expression::InfixOperator { left, right, .. }
+        | ast::Expression::Index {
+            left, index: right, ..
+        } => {
+            check_expression(left)?;
+            check_expression(right)
+        }
+        ast::Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            check_expression(condition)?;
+            check_statements(&consequence.statements, false)?;
+            check_statements(&alternative.statements, false)
+        }
+        ast::Expression::Block(block) => check_statements(&block.statements, false),
+        ast::Expression::FunctionLiteral { body, .. } => check_statements(&body.statements, true),
+        ast::Expression::MacroLiteral { body, .. } => check_statements(&body.statements, false),
+        ast::Expression::FunctionCall {
+            function,
+            arguments,
+            ..
+        } => {
+            check_expression(function)?;
+            arguments.iter().try_for_each(check_expression)
+        }
+    }
+}
@@ -1,12 +1,16 @@
 mod error;
+mod generator;
 mod precedence;
 
 use std::rc::Rc;
 
+use num_bigint::BigInt;
+use num_traits::{Num, ToPrimitive};
+
 use crate::{
     ast::{self, InfixOperatorKind, PrefixOperatorKind},
     lexer::Lexer,
-    token::Token,
+    token::{Position, Token},
 };
 use precedence::Precedence;
 
@@ -22,17 +26,24 @@ struct Parser<'a> {
 
     current_token: Option<Token>,
     peek_token: Option<Token>,
+
+    current_position: Position,
+    peek_position: Position,
 }
 
 impl<'a> Parser<'a> {
     fn new(mut lexer: Lexer<'a>) -> Self {
         let current_token = lexer.next();
+        let current_position = lexer.position();
         let peek_token = lexer.next();
+        let peek_position = lexer.position();
 
         Parser {
             lexer,
             current_token,
             peek_token,
+            current_position,
+            peek_position,
         }
     }
 }
@@ -40,7 +51,9 @@ impl<'a> Parser<'a> {
 impl Parser<'_> {
     pub fn step(&mut self) {
         self.current_token = self.lexer.next();
+        self.current_position = self.lexer.position();
         std::mem::swap(&mut self.current_token, &mut self.peek_token);
+        std::mem::swap(&mut self.current_position, &mut self.peek_position);
     }
 
     pub fn peek_precedence(&self) -> Option<Precedence> {
@@ -57,13 +70,19 @@ impl Parser<'_> {
             self.step();
         }
 
-        Ok(ast::Program { statements })
+        let program = ast::Program { statements };
+        generator::check_yields(&program)?;
+
+        Ok(program)
     }
 
     fn parse_statement(&mut self) -> Result<ast::Statement> {
         match &self.current_token {
-            Some(Token::Let) => self.parse_let_statement(),
+            Some(Token::Let | Token::Const) => self.parse_let_statement(),
             Some(Token::Return) => self.parse_return_statement(),
+            Some(Token::Yield) => self.parse_yield_statement(),
+            Some(Token::Import) => self.parse_import_statement(),
+            Some(Token::Export) => self.parse_export_statement(),
             _ => {
                 let expression = self.parse_expression(Precedence::Lowest)?;
 
@@ -77,12 +96,16 @@ impl Parser<'_> {
     }
 
     fn parse_let_statement(&mut self) -> Result<ast::Statement> {
-        self.step(); // consume `let`
+        let constant = self.current_token == Some(Token::Const);
+        let position = self.current_position;
+        self.step(); // consume `let` or `const`
         let name_token = self.current_token.take();
         let Some(Token::Ident(name)) = name_token else {
             return Err(Error::unexpected_token(&name_token));
         };
 
+        let type_annotation = self.parse_type_annotation()?;
+
         if self.peek_token != Some(Token::Assign) {
             return Err(Error::unexpected_token(&self.peek_token));
         }
@@ -99,7 +122,13 @@ impl Parser<'_> {
             *fn_name = Some(name.clone());
         }
 
-        Ok(ast::Statement::Let { name, value })
+        Ok(ast::Statement::Let {
+            name,
+            type_annotation,
+            value,
+            constant,
+            position,
+        })
     }
 
     fn parse_return_statement(&mut self) -> Result<ast::Statement> {
@@ -114,13 +143,65 @@ impl Parser<'_> {
         Ok(ast::Statement::Return(value))
     }
 
+    fn parse_yield_statement(&mut self) -> Result<ast::Statement> {
+        self.step(); // consume `yield`
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token == Some(Token::Semicolon) {
+            self.step();
+        }
+
+        Ok(ast::Statement::Yield(value))
+    }
+
+    fn parse_import_statement(&mut self) -> Result<ast::Statement> {
+        self.step(); // consume `import`
+        let path_token = self.current_token.take();
+        let Some(Token::String(path)) = path_token else {
+            return Err(Error::unexpected_token(&path_token));
+        };
+
+        if self.peek_token != Some(Token::As) {
+            return Err(Error::unexpected_token(&self.peek_token));
+        }
+        self.step();
+        self.step();
+
+        let name = self.parse_ident()?;
+
+        if self.peek_token == Some(Token::Semicolon) {
+            self.step();
+        }
+
+        Ok(ast::Statement::Import { path, name })
+    }
+
+    fn parse_export_statement(&mut self) -> Result<ast::Statement> {
+        self.step(); // consume `export`
+
+        let mut names = vec![self.parse_ident()?];
+        while self.peek_token == Some(Token::Comma) {
+            self.step();
+            self.step();
+
+            names.push(self.parse_ident()?);
+        }
+
+        if self.peek_token == Some(Token::Semicolon) {
+            self.step();
+        }
+
+        Ok(ast::Statement::Export(names))
+    }
+
     fn parse_expression(&mut self, precedence: Precedence) -> Result<ast::Expression> {
         let mut left = self.parse_prefix()?;
 
         while self.peek_token != Some(Token::Semicolon)
             && precedence < self.peek_precedence().unwrap_or(Precedence::Lowest)
         {
-            if !self.peek_token.as_ref().map_or(false, |t| t.is_infix()) {
+            if !self.peek_token.as_ref().is_some_and(|t| t.is_infix()) {
                 return Ok(left);
             }
 
@@ -149,15 +230,19 @@ impl Parser<'_> {
 
                 ast::Expression::StringLiteral(value)
             }
-            Some(Token::Int(value)) => ast::Expression::IntegerLiteral(value.parse()?),
-            Some(Token::Bang) | Some(Token::Minus) => self.parse_prefix_operator()?,
+            Some(Token::Bytes(value)) => ast::Expression::BytesLiteral(parse_bytes(value)?),
+            Some(Token::Int(value)) => parse_integer(value)?,
+            Some(Token::Bang) | Some(Token::Minus) | Some(Token::Tilde) => {
+                self.parse_prefix_operator()?
+            }
             Some(Token::True) => ast::Expression::BooleanLiteral(true),
             Some(Token::False) => ast::Expression::BooleanLiteral(false),
             Some(Token::Lparen) => self.parse_grouped()?,
             Some(Token::LBracket) => self.parse_array_literal()?,
-            Some(Token::Lsquigly) => self.parse_hash_literal()?,
+            Some(Token::Lsquigly) => self.parse_hash_or_block()?,
             Some(Token::If) => self.parse_if_expression()?,
             Some(Token::Function) => self.parse_function_literal()?,
+            Some(Token::Macro) => self.parse_macro_literal()?,
             token => return Err(Error::NotAnExpression(token.clone())),
         };
 
@@ -173,9 +258,16 @@ impl Parser<'_> {
             | Some(Token::Eq)
             | Some(Token::NotEq)
             | Some(Token::Lt)
-            | Some(Token::Gt) => self.parse_infix_operator(left)?,
+            | Some(Token::Gt)
+            | Some(Token::Ampersand)
+            | Some(Token::Pipe)
+            | Some(Token::Caret)
+            | Some(Token::ShiftLeft)
+            | Some(Token::ShiftRight) => self.parse_infix_operator(left)?,
             Some(Token::Lparen) => self.parse_call_expression(left)?,
             Some(Token::LBracket) => self.parse_index_expression(left)?,
+            Some(Token::Question) => ast::Expression::Try(Box::new(left)),
+            Some(Token::Dot) => self.parse_member_expression(left)?,
             _ => left,
         };
 
@@ -184,11 +276,13 @@ impl Parser<'_> {
 
     fn parse_prefix_operator(&mut self) -> Result<ast::Expression> {
         let operator = PrefixOperatorKind::try_from(&self.current_token)?;
+        let position = self.current_position;
         self.step();
 
         Ok(ast::Expression::PrefixOperator {
             operator,
             right: Box::new(self.parse_expression(Precedence::Prefix)?),
+            position,
         })
     }
 
@@ -206,6 +300,7 @@ impl Parser<'_> {
     }
 
     fn parse_if_expression(&mut self) -> Result<ast::Expression> {
+        let position = self.current_position;
         if self.peek_token != Some(Token::Lparen) {
             return Err(Error::unexpected_token(&self.peek_token));
         }
@@ -248,6 +343,7 @@ impl Parser<'_> {
                 condition: Box::new(condition),
                 consequence,
                 alternative,
+                position,
             })
         } else {
             Ok(ast::Expression::If {
@@ -256,17 +352,27 @@ impl Parser<'_> {
                 alternative: ast::BlockStatement {
                     statements: Rc::new(vec![]),
                 },
+                position,
             })
         }
     }
 
     fn parse_function_literal(&mut self) -> Result<ast::Expression> {
+        let position = self.current_position;
         if self.peek_token != Some(Token::Lparen) {
             return Err(Error::unexpected_token(&self.peek_token));
         }
         self.step();
 
-        let parameters = self.parse_function_parameters()?;
+        let (parameters, parameter_types) = self.parse_function_parameters()?.into_iter().unzip();
+
+        let return_type = if self.peek_token == Some(Token::Arrow) {
+            self.step();
+            self.step();
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
 
         if self.peek_token != Some(Token::Lsquigly) {
             return Err(Error::unexpected_token(&self.peek_token));
@@ -278,10 +384,118 @@ impl Parser<'_> {
         Ok(ast::Expression::FunctionLiteral {
             name: None,
             parameters,
+            parameter_types,
+            return_type,
             body,
+            position,
         })
     }
 
+    fn parse_macro_literal(&mut self) -> Result<ast::Expression> {
+        if self.peek_token != Some(Token::Lparen) {
+            return Err(Error::unexpected_token(&self.peek_token));
+        }
+        self.step();
+
+        let parameters = self.parse_function_parameters()?;
+        if parameters
+            .iter()
+            .any(|(_, annotation)| annotation.is_some())
+        {
+            return Err(Error::UnexpectedToken(Token::Colon));
+        }
+        let parameters = parameters.into_iter().map(|(name, _)| name).collect();
+
+        if self.peek_token != Some(Token::Lsquigly) {
+            return Err(Error::unexpected_token(&self.peek_token));
+        }
+        self.step();
+
+        let body = self.parse_block_statement()?;
+
+        Ok(ast::Expression::MacroLiteral { parameters, body })
+    }
+
+    fn parse_parameter(&mut self) -> Result<(String, Option<ast::Type>)> {
+        let name = self.parse_ident()?;
+        let annotation = self.parse_type_annotation()?;
+
+        Ok((name, annotation))
+    }
+
+    /// Parses an optional `: type` after the current token.
+    fn parse_type_annotation(&mut self) -> Result<Option<ast::Type>> {
+        if self.peek_token != Some(Token::Colon) {
+            return Ok(None);
+        }
+        self.step();
+        self.step();
+
+        Ok(Some(self.parse_type()?))
+    }
+
+    /// Parses a type such as `int`, `[string]`, `{string: int}` or `fn(int) -> bool`.
+    fn parse_type(&mut self) -> Result<ast::Type> {
+        match self.current_token.take() {
+            Some(Token::Ident(name)) => ast::Type::from_name(&name).ok_or(Error::UnknownType(name)),
+            Some(Token::LBracket) => {
+                self.step();
+                let element = self.parse_type()?;
+                self.expect_peek(Token::RBracket)?;
+
+                Ok(ast::Type::Array(Box::new(element)))
+            }
+            Some(Token::Lsquigly) => {
+                self.step();
+                let key = self.parse_type()?;
+                self.expect_peek(Token::Colon)?;
+                self.step();
+                let value = self.parse_type()?;
+                self.expect_peek(Token::Rsquigly)?;
+
+                Ok(ast::Type::Hash(Box::new(key), Box::new(value)))
+            }
+            Some(Token::Function) => {
+                self.expect_peek(Token::Lparen)?;
+                let mut parameters = vec![];
+                if self.peek_token == Some(Token::Rparen) {
+                    self.step();
+                } else {
+                    loop {
+                        self.step();
+                        parameters.push(self.parse_type()?);
+                        if self.peek_token != Some(Token::Comma) {
+                            break;
+                        }
+                        self.step();
+                    }
+                    self.expect_peek(Token::Rparen)?;
+                }
+
+                let return_type = if self.peek_token == Some(Token::Arrow) {
+                    self.step();
+                    self.step();
+                    self.parse_type()?
+                } else {
+                    ast::Type::Any
+                };
+
+                Ok(ast::Type::Function(parameters, Box::new(return_type)))
+            }
+            token => Err(Error::unexpected_token(&token)),
+        }
+    }
+
+    /// Steps to the peek token if it is the expected one.
+    fn expect_peek(&mut self, token: Token) -> Result<()> {
+        if self.peek_token.as_ref() != Some(&token) {
+            return Err(Error::unexpected_token(&self.peek_token));
+        }
+        self.step();
+
+        Ok(())
+    }
+
     fn parse_ident(&mut self) -> Result<String> {
         let name_token = self.current_token.take();
         let Some(Token::Ident(name)) = name_token else {
@@ -291,7 +505,8 @@ impl Parser<'_> {
         Ok(name)
     }
 
-    fn parse_function_parameters(&mut self) -> Result<Vec<String>> {
+    /// Parses the parameters together with their optional type annotations.
+    fn parse_function_parameters(&mut self) -> Result<Vec<(String, Option<ast::Type>)>> {
         let mut identifiers = vec![];
 
         self.step();
@@ -299,13 +514,13 @@ impl Parser<'_> {
             return Ok(identifiers);
         }
 
-        identifiers.push(self.parse_ident()?);
+        identifiers.push(self.parse_parameter()?);
 
         while self.peek_token == Some(Token::Comma) {
             self.step();
             self.step();
 
-            identifiers.push(self.parse_ident()?);
+            identifiers.push(self.parse_parameter()?);
         }
 
         if self.peek_token != Some(Token::Rparen) {
@@ -318,10 +533,19 @@ impl Parser<'_> {
 
     fn parse_block_statement(&mut self) -> Result<ast::BlockStatement> {
         self.step();
+        self.parse_block_statements(vec![])
+    }
 
-        let mut statements = vec![];
-
+    /// Parses statements until the end of the block, following the already parsed ones.
+    fn parse_block_statements(
+        &mut self,
+        mut statements: Vec<ast::Statement>,
+    ) -> Result<ast::BlockStatement> {
         while self.current_token.is_some() && self.current_token != Some(Token::Rsquigly) {
+            if let Some(token @ (Token::Import | Token::Export)) = &self.current_token {
+                return Err(Error::NotAtTopLevel(token.clone()));
+            }
+
             let stmt = self.parse_statement()?;
             statements.push(stmt);
 
@@ -336,6 +560,7 @@ impl Parser<'_> {
     fn parse_infix_operator(&mut self, left: ast::Expression) -> Result<ast::Expression> {
         let operator = InfixOperatorKind::try_from(&self.current_token)?;
         let precedence = Precedence::from(&self.current_token);
+        let position = self.current_position;
 
         self.step();
 
@@ -345,13 +570,17 @@ impl Parser<'_> {
             operator,
             left: Box::new(left),
             right: Box::new(right),
+            position,
         })
     }
 
     fn parse_call_expression(&mut self, left: ast::Expression) -> Result<ast::Expression> {
+        let position = self.current_position;
+
         Ok(ast::Expression::FunctionCall {
             function: Box::new(left),
             arguments: self.parse_expression_list(Token::Rparen)?,
+            position,
         })
     }
 
@@ -386,11 +615,65 @@ impl Parser<'_> {
         Ok(ast::Expression::ArrayLiteral(elements))
     }
 
-    fn parse_hash_literal(&mut self) -> Result<ast::Expression> {
-        let mut pairs = Vec::new();
-        while self.peek_token != Some(Token::Rsquigly) {
+    /// Parses `{`, which starts either a hash literal or a block expression.
+    /// Empty braces are a hash literal. Otherwise it's a block, if it starts
+    /// with a statement keyword or if the first expression is not a hash key.
+    fn parse_hash_or_block(&mut self) -> Result<ast::Expression> {
+        match &self.peek_token {
+            Some(Token::Rsquigly) => return self.parse_hash_literal(None),
+            Some(
+                Token::Let
+                | Token::Const
+                | Token::Return
+                | Token::Yield
+                | Token::Import
+                | Token::Export,
+            ) => {
+                let block = self.parse_block_statement()?;
+                return self.finish_block_expression(block);
+            }
+            _ => (),
+        }
+
+        self.step();
+        let first = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token == Some(Token::Colon) {
+            return self.parse_hash_literal(Some(first));
+        }
+
+        if self.peek_token == Some(Token::Semicolon) {
             self.step();
-            let key = self.parse_expression(Precedence::Lowest)?;
+        }
+        self.step();
+
+        let block = self.parse_block_statements(vec![ast::Statement::Expression(first)])?;
+        self.finish_block_expression(block)
+    }
+
+    fn finish_block_expression(&mut self, block: ast::BlockStatement) -> Result<ast::Expression> {
+        if self.current_token != Some(Token::Rsquigly) {
+            return Err(Error::unexpected_token(&self.current_token));
+        }
+
+        Ok(ast::Expression::Block(block))
+    }
+
+    /// Parses the hash literal. The first key is already parsed,
+    /// if the literal was parsed as a block before.
+    fn parse_hash_literal(
+        &mut self,
+        mut first_key: Option<ast::Expression>,
+    ) -> Result<ast::Expression> {
+        let mut pairs = Vec::new();
+        while first_key.is_some() || self.peek_token != Some(Token::Rsquigly) {
+            let key = match first_key.take() {
+                Some(key) => key,
+                None => {
+                    self.step();
+                    self.parse_expression(Precedence::Lowest)?
+                }
+            };
 
             if self.peek_token != Some(Token::Colon) {
                 return Err(Error::unexpected_token(&self.peek_token));
@@ -417,7 +700,21 @@ impl Parser<'_> {
         Ok(ast::Expression::HashLiteral(pairs))
     }
 
+    /// Parses `left.name`, which is a shorthand for `left["name"]`.
+    fn parse_member_expression(&mut self, left: ast::Expression) -> Result<ast::Expression> {
+        let position = self.current_position;
+        self.step();
+        let name = self.parse_ident()?;
+
+        Ok(ast::Expression::Index {
+            left: Box::new(left),
+            index: Box::new(ast::Expression::StringLiteral(name)),
+            position,
+        })
+    }
+
     fn parse_index_expression(&mut self, left: ast::Expression) -> Result<ast::Expression> {
+        let position = self.current_position;
         self.step();
         let index = self.parse_expression(Precedence::Lowest)?;
 
@@ -429,34 +726,99 @@ impl Parser<'_> {
         Ok(ast::Expression::Index {
             left: Box::new(left),
             index: Box::new(index),
+            position,
         })
     }
 }
 
+/// Parses the text of an integer token, which can have a radix prefix and `_` separators.
+/// Integers that don't fit into `i64` are parsed as big integer literals.
+fn parse_integer(literal: &str) -> Result<ast::Expression> {
+    let (digits, radix) = match literal.get(..2) {
+        Some("0x") => (&literal[2..], 16),
+        Some("0o") => (&literal[2..], 8),
+        Some("0b") => (&literal[2..], 2),
+        _ => (literal, 10),
+    };
+
+    let value = BigInt::from_str_radix(&digits.replace('_', ""), radix)?;
+    let literal = match value.to_i64() {
+        Some(value) => ast::Expression::IntegerLiteral(value),
+        None => ast::Expression::BigIntegerLiteral(value),
+    };
+
+    Ok(literal)
+}
+
+/// Decodes the escapes `\xHH`, `\n`, `\r`, `\t`, `\0`, `\\` and `\"` of a bytes literal.
+fn parse_bytes(literal: &str) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut input = literal.bytes();
+    while let Some(byte) = input.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let byte = match input.next() {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'0') => b'\0',
+            Some(b'\\') => b'\\',
+            Some(b'"') => b'"',
+            Some(b'x') => {
+                let digits: Vec<u8> = input.by_ref().take(2).collect();
+                let digits = String::from_utf8_lossy(&digits);
+                if digits.len() != 2 || !digits.bytes().all(|d| d.is_ascii_hexdigit()) {
+                    return Err(Error::InvalidEscape(format!("x{}", digits)));
+                }
+                u8::from_str_radix(&digits, 16).unwrap()
+            }
+            escape => {
+                let escape = escape.map(|e| (e as char).to_string()).unwrap_or_default();
+                return Err(Error::InvalidEscape(escape));
+            }
+        };
+        bytes.push(byte);
+    }
+
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod test {
     use crate::ast;
-    use crate::parse::{parse, Result};
+    use crate::parse::{parse, Error, Result};
+    use crate::token::Position;
 
     #[test]
     fn test_let_statements() -> Result<()> {
         let tests = [
-            ("let x = 5;", "x", "5"),
-            ("let y = 10;", "y", "10"),
-            ("let foobar = y;", "foobar", "y"),
+            ("let x = 5;", "x", "5", false),
+            ("let y = 10;", "y", "10", false),
+            ("let foobar = y;", "foobar", "y", false),
+            ("const z = 1;", "z", "1", true),
         ];
 
-        for (input, expected_name, expected_expr) in tests {
+        for (input, expected_name, expected_expr, expected_constant) in tests {
             let program = parse(input)?;
 
             assert_eq!(program.statements.len(), 1);
 
-            let ast::Statement::Let { name, value } = &program.statements[0] else {
+            let ast::Statement::Let {
+                name,
+                value,
+                constant,
+                ..
+            } = &program.statements[0]
+            else {
                 panic!("Expected let statement, got: {:?}", program.statements[0]);
             };
 
             assert_eq!(name, expected_name);
             assert_eq!(value.debug_str(), expected_expr);
+            assert_eq!(*constant, expected_constant);
         }
 
         Ok(())
@@ -499,6 +861,83 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_import_export_statements() -> Result<()> {
+        let input = r#"import "lib/strings.monkey" as strings; export a, b"#;
+
+        let program = parse(input)?;
+
+        assert_eq!(
+            program.statements,
+            vec![
+                ast::Statement::Import {
+                    path: "lib/strings.monkey".to_string(),
+                    name: "strings".to_string(),
+                },
+                ast::Statement::Export(vec!["a".to_string(), "b".to_string()]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_import_export_statements() {
+        let inputs = [
+            "import strings;",
+            r#"import "strings.monkey";"#,
+            r#"fn() { import "strings.monkey" as strings; }"#,
+            "if (true) { export a; }",
+            "export;",
+        ];
+
+        for (index, input) in inputs.iter().enumerate() {
+            let program = parse(input);
+
+            assert!(program.is_err(), "test case {} should have failed", index);
+        }
+    }
+
+    #[test]
+    fn test_yield_statements() -> Result<()> {
+        let tests = [
+            ("fn() { yield 1; }", "fn() {yield 1;};"),
+            (
+                "fn(x) { if (x) { yield x; } else { yield 2 } }",
+                "fn(x) {if (x) {yield x;} else {yield 2;};};",
+            ),
+            ("fn() { { yield 1; } }", "fn() {{yield 1;};};"),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input)?;
+
+            assert_eq!(program.debug_str(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_misplaced_yield_statements() {
+        let inputs = [
+            "yield 1;",
+            "if (true) { yield 1; }",
+            "fn() { let x = if (true) { yield 1; }; }",
+            "fn() { 1 + if (true) { yield 1; } }",
+            "fn() { let x = { yield 1; }; }",
+            "macro() { yield 1; }",
+        ];
+
+        for input in inputs {
+            assert!(
+                matches!(parse(input), Err(Error::MisplacedYield)),
+                "{} should have failed",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_identifier_expression() -> Result<()> {
         let input = "foobar;";
@@ -538,6 +977,70 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_big_integer_literal() -> Result<()> {
+        let tests = [
+            ("9223372036854775808", "9223372036854775808"),
+            ("0x8000_0000_0000_0000", "9223372036854775808"),
+            ("0o1_000_000_000_000_000_000_000", "9223372036854775808"),
+            (
+                "0b1000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000",
+                "9223372036854775808",
+            ),
+            ("0xffff_ffff_ffff_ffff_ffff", "1208925819614629174706175"),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input)?;
+
+            let ast::Statement::Expression(ast::Expression::BigIntegerLiteral(ref literal)) =
+                program.statements[0]
+            else {
+                panic!(
+                    "Expected big integer literal, got: {:?}",
+                    program.statements[0]
+                );
+            };
+
+            assert_eq!(literal.to_string(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_integer_literal_radix() -> Result<()> {
+        let tests = [
+            ("1_000_000", 1_000_000),
+            ("0xff", 0xff),
+            ("0xDEAD_beef", 0xdead_beef),
+            ("0o755", 0o755),
+            ("0b1010_0101", 0b1010_0101),
+            ("0x7fff_ffff_ffff_ffff", i64::MAX),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input)?;
+
+            let ast::Statement::Expression(ast::Expression::IntegerLiteral(literal)) =
+                program.statements[0]
+            else {
+                panic!("Expected integer literal, got: {:?}", program.statements[0]);
+            };
+
+            assert_eq!(literal, expected);
+        }
+
+        for input in ["0x", "0xfg", "0b102", "0o8"] {
+            assert!(
+                matches!(parse(input), Err(Error::NotANumber(_))),
+                "{input} should not be a number"
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_string_literal_expression() -> Result<()> {
         let input = r#""hello world""#;
@@ -555,6 +1058,44 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_bytes_literal_expression() -> Result<()> {
+        let tests = [
+            (r#"b"""#, vec![]),
+            (r#"b"\x00\xFFa""#, vec![0x00, 0xff, b'a']),
+            (
+                r#"b"\n\r\t\0\\\"""#,
+                vec![b'\n', b'\r', b'\t', 0, b'\\', b'"'],
+            ),
+            (r#"b"é""#, "é".as_bytes().to_vec()),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input)?;
+
+            let ast::Statement::Expression(ast::Expression::BytesLiteral(ref literal)) =
+                program.statements[0]
+            else {
+                panic!("Expected bytes literal, got: {:?}", program.statements[0]);
+            };
+
+            assert_eq!(*literal, expected);
+        }
+
+        for (input, escape) in [
+            (r#"b"\q""#, "q"),
+            (r#"b"\x4""#, "x4"),
+            (r#"b"\xzz""#, "xzz"),
+        ] {
+            let Err(Error::InvalidEscape(invalid)) = parse(input) else {
+                panic!("Expected invalid escape for {input}");
+            };
+            assert_eq!(invalid, escape);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_boolean_literal_expression() -> Result<()> {
         let tests = [("true;", true), ("false", false)];
@@ -582,6 +1123,7 @@ mod test {
                 ast::Expression::PrefixOperator {
                     operator: ast::PrefixOperatorKind::Not,
                     right: Box::new(ast::Expression::IntegerLiteral(5)),
+                    position: Position { line: 1, column: 1 },
                 },
             ),
             (
@@ -589,6 +1131,7 @@ mod test {
                 ast::Expression::PrefixOperator {
                     operator: ast::PrefixOperatorKind::Negative,
                     right: Box::new(ast::Expression::IntegerLiteral(15)),
+                    position: Position { line: 1, column: 1 },
                 },
             ),
             (
@@ -596,6 +1139,7 @@ mod test {
                 ast::Expression::PrefixOperator {
                     operator: ast::PrefixOperatorKind::Not,
                     right: Box::new(ast::Expression::BooleanLiteral(false)),
+                    position: Position { line: 1, column: 1 },
                 },
             ),
             (
@@ -603,6 +1147,7 @@ mod test {
                 ast::Expression::PrefixOperator {
                     operator: ast::PrefixOperatorKind::Not,
                     right: Box::new(ast::Expression::BooleanLiteral(true)),
+                    position: Position { line: 1, column: 1 },
                 },
             ),
         ];
@@ -617,6 +1162,7 @@ mod test {
             };
 
             assert_eq!(*expr, expected);
+            assert_eq!(expr.position(), expected.position());
         }
 
         Ok(())
@@ -645,6 +1191,7 @@ mod test {
                 operator,
                 left,
                 right,
+                ..
             }) = stmt
             else {
                 panic!("Expected infix operator expression, got: {:?}", stmt);
@@ -716,6 +1263,15 @@ mod test {
                 "add(a * b[2], b[1], 2 * [1, 2][1])",
                 "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])));",
             ),
+            ("a + b(c)?", "(a + (b(c)?));"),
+            ("-a?", "(-(a?));"),
+            ("a[0]?[1]", "(((a[0])?)[1]);"),
+            ("a.b.c(1)", "((a[b])[c])(1);"),
+            ("a | b ^ c & d", "(a | (b ^ (c & d)));"),
+            ("a & b == c", "(a & (b == c));"),
+            ("1 << 2 + 3 < 4", "((1 << (2 + 3)) < 4);"),
+            ("a >> b >> c", "((a >> b) >> c);"),
+            ("~a & -b", "((~a) & (-b));"),
         ];
 
         for (input, expected) in tests {
@@ -739,12 +1295,14 @@ mod test {
             condition,
             consequence,
             alternative,
+            position,
         }) = &program.statements[0]
         else {
             panic!("Expected if expression, got: {:?}", program.statements[0]);
         };
 
         assert_eq!(condition.debug_str(), "(x < y)");
+        assert_eq!(*position, Position { line: 1, column: 1 });
         assert_eq!(consequence.statements.len(), 1);
         assert_eq!(alternative.statements.len(), 0);
 
@@ -773,12 +1331,14 @@ mod test {
             condition,
             consequence,
             alternative,
+            position,
         }) = &program.statements[0]
         else {
             panic!("Expected if expression, got: {:?}", program.statements[0]);
         };
 
         assert_eq!(condition.debug_str(), "(x < y)");
+        assert_eq!(*position, Position { line: 1, column: 1 });
         assert_eq!(consequence.statements.len(), 1);
         assert_eq!(alternative.statements.len(), 1);
 
@@ -817,6 +1377,7 @@ mod test {
             name: _,
             parameters,
             body,
+            ..
         }) = &program.statements[0]
         else {
             panic!(
@@ -852,6 +1413,7 @@ mod test {
                 name: _,
                 parameters,
                 body: _,
+                ..
             }) = &program.statements[0]
             else {
                 panic!(
@@ -874,7 +1436,7 @@ mod test {
 
         assert_eq!(program.statements.len(), 1);
 
-        let ast::Statement::Let { name: _, value } = &program.statements[0] else {
+        let ast::Statement::Let { value, .. } = &program.statements[0] else {
             panic!("Expected let statement , got: {:?}", program.statements[0]);
         };
 
@@ -887,6 +1449,73 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_macro_literal_parsing() -> Result<()> {
+        let input = "macro(x, y) { x + y; }";
+
+        let program = parse(input)?;
+
+        assert_eq!(program.statements.len(), 1);
+
+        let ast::Statement::Expression(ast::Expression::MacroLiteral { parameters, body }) =
+            &program.statements[0]
+        else {
+            panic!("Expected macro literal, got: {:?}", program.statements[0]);
+        };
+
+        assert_eq!(*parameters, vec!["x", "y"]);
+        assert_eq!(body.debug_str(), "(x + y);");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_annotations() -> Result<()> {
+        let tests = [
+            ("let x: int = 1;", "let x: int = 1;"),
+            ("const s: string = \"a\";", "const s: string = a;"),
+            ("let xs: [int] = [];", "let xs: [int] = [];"),
+            (
+                "let h: {string: [bool]} = {};",
+                "let h: {string: [bool]} = {};",
+            ),
+            (
+                "let f = fn(x: int, y) -> bytes { x };",
+                "let f = fn<f>(x: int, y) -> bytes {x;};",
+            ),
+            (
+                "let f: fn(int, any) -> fn() -> int = g;",
+                "let f: fn(int, any) -> fn() -> int = g;",
+            ),
+            ("fn(x: {int: int}) {}", "fn(x: {int: int}) {};"),
+            ("1 - -2 > 3", "((1 - (-2)) > 3);"),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input)?;
+            assert_eq!(program.debug_str(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_type_annotations() {
+        let tests = [
+            ("let x: float = 1;", "Unknown type: float"),
+            ("let x: [int = 1;", "Unexpected token: Assign"),
+            ("fn(x:) {}", "Unexpected token: Rparen"),
+            ("macro(x: int) {}", "Unexpected token: Colon"),
+        ];
+
+        for (input, expected) in tests {
+            match parse(input) {
+                Err(err) => assert_eq!(err.to_string(), expected),
+                Ok(program) => panic!("{} should have failed, got: {:?}", input, program),
+            }
+        }
+    }
+
     #[test]
     fn test_function_call_expression() -> Result<()> {
         let input = "add(1, 2*3, 4 + 5);";
@@ -898,6 +1527,7 @@ mod test {
         let ast::Statement::Expression(ast::Expression::FunctionCall {
             function,
             arguments,
+            ..
         }) = &program.statements[0]
         else {
             panic!("Expected call expression, got: {:?}", program.statements[0]);
@@ -928,6 +1558,7 @@ mod test {
             let ast::Statement::Expression(ast::Expression::FunctionCall {
                 function: _,
                 arguments,
+                ..
             }) = &program.statements[0]
             else {
                 panic!(
@@ -999,6 +1630,10 @@ mod test {
                             operator: ast::InfixOperatorKind::Add,
                             left: Box::new(ast::Expression::IntegerLiteral(0)),
                             right: Box::new(ast::Expression::IntegerLiteral(1)),
+                            position: Position {
+                                line: 1,
+                                column: 11,
+                            },
                         },
                     },
                     ast::HashLiteralPair {
@@ -1007,6 +1642,10 @@ mod test {
                             operator: ast::InfixOperatorKind::Subtract,
                             left: Box::new(ast::Expression::IntegerLiteral(10)),
                             right: Box::new(ast::Expression::IntegerLiteral(8)),
+                            position: Position {
+                                line: 1,
+                                column: 26,
+                            },
                         },
                     },
                     ast::HashLiteralPair {
@@ -1015,6 +1654,10 @@ mod test {
                             operator: ast::InfixOperatorKind::Divide,
                             left: Box::new(ast::Expression::IntegerLiteral(15)),
                             right: Box::new(ast::Expression::IntegerLiteral(5)),
+                            position: Position {
+                                line: 1,
+                                column: 43,
+                            },
                         },
                     },
                 ],
@@ -1032,6 +1675,29 @@ mod test {
             };
 
             assert_eq!(*hash_lit, expected);
+            let positions = |pairs: &[ast::HashLiteralPair]| -> Vec<_> {
+                pairs.iter().map(|pair| pair.value.position()).collect()
+            };
+            assert_eq!(positions(hash_lit), positions(&expected));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_expression() -> Result<()> {
+        let tests = [
+            ("{ let x = 1; x + 1 }", "{let x = 1;(x + 1);};"),
+            ("{ x }", "{x;};"),
+            ("{ x; y }", "{x;y;};"),
+            ("let a = { return 1; };", "let a = {return 1;};"),
+            ("{ 1: 2 }", "{1: 2};"),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input)?;
+
+            assert_eq!(program.debug_str(), expected);
         }
 
         Ok(())
@@ -1044,7 +1710,7 @@ mod test {
         let program = parse(input)?;
         assert_eq!(program.statements.len(), 1);
 
-        let ast::Statement::Expression(ast::Expression::Index { left, index }) =
+        let ast::Statement::Expression(ast::Expression::Index { left, index, .. }) =
             &program.statements[0]
         else {
             panic!("Expected index expression, got {:?}", program.statements[0])
@@ -1056,9 +1722,20 @@ mod test {
             ast::Expression::InfixOperator {
                 operator: ast::InfixOperatorKind::Add,
                 left: Box::new(ast::Expression::IntegerLiteral(1)),
-                right: Box::new(ast::Expression::IntegerLiteral(1))
+                right: Box::new(ast::Expression::IntegerLiteral(1)),
+                position: Position {
+                    line: 1,
+                    column: 11
+                },
             }
         );
+        assert_eq!(
+            index.position(),
+            Some(Position {
+                line: 1,
+                column: 11
+            })
+        );
 
         Ok(())
     }
@@ -3,8 +3,12 @@ use crate::token::Token;
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy)]
 pub enum Precedence {
     Lowest,
+    BitOr,
+    BitXor,
+    BitAnd,
     Equals,
     LessGreater,
+    Shift,
     Sum,
     Product,
     Prefix,
@@ -15,12 +19,16 @@ pub enum Precedence {
 impl From<&Token> for Precedence {
     fn from(value: &Token) -> Self {
         match value {
+            Token::Pipe => Self::BitOr,
+            Token::Caret => Self::BitXor,
+            Token::Ampersand => Self::BitAnd,
             Token::Eq | Token::NotEq => Self::Equals,
             Token::Lt | Token::Gt => Self::LessGreater,
+            Token::ShiftLeft | Token::ShiftRight => Self::Shift,
             Token::Plus | Token::Minus => Self::Sum,
             Token::Slash | Token::Asterisk => Self::Product,
             Token::Lparen => Self::Call,
-            Token::LBracket => Self::Index,
+            Token::LBracket | Token::Question | Token::Dot => Self::Index,
             _ => Self::Lowest,
         }
     }
@@ -4,11 +4,19 @@ use std::{
 };
 
 use crate::{
-    ast, compile::Compiler, evaluate::Evaluator, object::Object, parse::parse, vm::VirtualMachine,
+    ast, compile::Compiler, disasm::disassemble, evaluate::Evaluator,
+    macro_expansion::MacroExpander, object::Object, optimize, parse::parse, vm::VirtualMachine,
 };
 
 const PROMPT: &str = ">> ";
 
+/// Command of the VM REPL, which toggles printing
+/// the disassembly of every line before it runs.
+const DISASM_COMMAND: &str = ":disasm";
+
+/// Command of the VM REPL, which prints the size of the constant pool.
+const STATS_COMMAND: &str = ":stats";
+
 const MONKEY_FACE: &str = r#"
             __,__
    .--.  .-"     "-.  .--.
@@ -23,17 +31,31 @@ const MONKEY_FACE: &str = r#"
            '-----'
 "#;
 
-fn parse_line<R: io::Read, W: io::Write>(
-    reader: &mut BufReader<R>,
-    output: &mut W,
-) -> Option<ast::Program> {
+fn read_line<R: io::Read, W: io::Write>(reader: &mut BufReader<R>, output: &mut W) -> String {
     write!(output, "{}", PROMPT).unwrap();
     output.flush().unwrap();
 
     let mut line = String::new();
     reader.read_line(&mut line).unwrap();
+    line
+}
+
+fn parse_line<W: io::Write>(
+    line: &str,
+    output: &mut W,
+    expander: &mut MacroExpander,
+    optimize_ast: bool,
+) -> Option<ast::Program> {
+    let program = match parse(line) {
+        Ok(p) => p,
+        Err(err) => {
+            write_err(output, err);
+            return None;
+        }
+    };
 
-    match parse(&line) {
+    match expander.expand(program) {
+        Ok(p) if optimize_ast => Some(optimize::optimize(p)),
         Ok(p) => Some(p),
         Err(err) => {
             write_err(output, err);
@@ -42,12 +64,14 @@ fn parse_line<R: io::Read, W: io::Write>(
     }
 }
 
-pub fn start_eval(input: impl io::Read, mut output: impl io::Write) {
+pub fn start_eval(input: impl io::Read, mut output: impl io::Write, optimize_ast: bool) {
     let mut reader = io::BufReader::new(input);
     let mut evaluator = Evaluator::new();
+    let mut expander = MacroExpander::new();
 
     loop {
-        let Some(program) = parse_line(&mut reader, &mut output) else {
+        let line = read_line(&mut reader, &mut output);
+        let Some(program) = parse_line(&line, &mut output, &mut expander, optimize_ast) else {
             continue;
         };
 
@@ -55,20 +79,40 @@ pub fn start_eval(input: impl io::Read, mut output: impl io::Write) {
             Ok(result) if result != Object::Null => {
                 writeln!(output, "{}", result.inspect()).unwrap()
             }
-            Err(err) => writeln!(output, "{}", err).unwrap(),
+            Err(err) => writeln!(output, "{}\n{}", err, evaluator.stack_trace()).unwrap(),
             _ => (),
         }
     }
 }
 
-pub fn start_vm(input: impl io::Read, mut output: impl io::Write) {
+pub fn start_vm(
+    input: impl io::Read,
+    mut output: impl io::Write,
+    optimize: bool,
+    optimize_ast: bool,
+) {
     let mut reader = io::BufReader::new(input);
 
     let mut compiler = Compiler::new();
+    compiler.set_optimize(optimize);
     let mut vm = VirtualMachine::new();
+    let mut expander = MacroExpander::new();
+    let mut show_disassembly = false;
 
     loop {
-        let Some(program) = parse_line(&mut reader, &mut output) else {
+        let line = read_line(&mut reader, &mut output);
+        if line.trim() == DISASM_COMMAND {
+            show_disassembly = !show_disassembly;
+            let state = if show_disassembly { "on" } else { "off" };
+            writeln!(output, "Disassembly is {}", state).unwrap();
+            continue;
+        }
+        if line.trim() == STATS_COMMAND {
+            writeln!(output, "{}", compiler.constant_stats()).unwrap();
+            continue;
+        }
+
+        let Some(program) = parse_line(&line, &mut output, &mut expander, optimize_ast) else {
             continue;
         };
 
@@ -80,8 +124,13 @@ pub fn start_vm(input: impl io::Read, mut output: impl io::Write) {
             }
         };
 
+        if show_disassembly {
+            write!(output, "{}", disassemble(&bytecode)).unwrap();
+        }
+
         if let Err(err) = vm.run(&bytecode) {
             writeln!(output, "Woops! Executing bytecode failed: {}", err).unwrap();
+            writeln!(output, "{}", vm.stack_trace()).unwrap();
             continue;
         }
 
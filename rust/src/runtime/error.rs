@@ -0,0 +1,87 @@
+use thiserror::Error;
+
+use crate::{
+    module,
+    object::{builtin, DataType, Object},
+    token::Position,
+    verify,
+};
+
+/// Error of the evaluator or the virtual machine. Both runtimes
+/// report the same error for the same program.
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("type mismatch: {0}")]
+    TypeMismatch(String),
+    #[error("unknown operator: {0}")]
+    UnknownOperator(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("invalid shift amount: {0}")]
+    InvalidShiftAmount(String),
+    #[error("identifier not found: {0}")]
+    UnknownIdentifier(String),
+    #[error("cannot assign to const {name} at {assigned}, it is defined at {defined}")]
+    AssignToConstant {
+        name: String,
+        defined: Position,
+        assigned: Position,
+    },
+    #[error("not a function: {0}")]
+    NotAFunction(DataType),
+    #[error("index operator not supported: {0}[{1}]")]
+    IndexOperatorNotSupported(DataType, DataType),
+    #[error("not hashable: {0}")]
+    NotHashable(DataType),
+    #[error("builtin function error: {source}")]
+    BuiltinFunction {
+        #[from]
+        source: builtin::ExecutionError,
+    },
+    #[error("wrong number of arguments: expected {expected}, got {got}")]
+    WrongNumberOfArguments { expected: usize, got: usize },
+    #[error("can't unquote {0}")]
+    NotUnquotable(DataType),
+    #[error("macro literal can only be bound with a top level let statement")]
+    UnexpandedMacro,
+    #[error("yield outside of a generator")]
+    YieldOutsideGenerator,
+    #[error("generator is already running")]
+    GeneratorRunning,
+    #[error("maximum recursion depth of {depth} exceeded")]
+    RecursionLimit { depth: usize },
+    #[error("module error: {source}")]
+    Module {
+        #[from]
+        source: module::Error,
+    },
+    #[error("invalid bytecode: {source}")]
+    InvalidBytecode {
+        #[from]
+        source: verify::Error,
+    },
+    /// Error value propagated by the `?` operator. This is used to unwind
+    /// to the enclosing function call and is never returned by the evaluator.
+    #[error("propagated error: {}", .0.inspect())]
+    PropagatedError(Object),
+}
+
+impl Error {
+    /// Returns the error for an infix operator that doesn't support
+    /// its operands. Operands of different types are a type mismatch.
+    pub fn unsupported_operands(operator: &str, left: DataType, right: DataType) -> Self {
+        let operation = format!("{} {} {}", left, operator, right);
+        if left == right {
+            Error::UnknownOperator(operation)
+        } else {
+            Error::TypeMismatch(operation)
+        }
+    }
+
+    /// Returns the error for a prefix operator that doesn't support its operand.
+    pub fn unsupported_operand(operator: &str, operand: DataType) -> Self {
+        Error::UnknownOperator(format!("{}{}", operator, operand))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
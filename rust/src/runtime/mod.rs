@@ -0,0 +1,68 @@
+//! Semantics shared by the evaluator and the virtual machine.
+//!
+//! Both runtimes produce the same value or the same [`Error`] for every
+//! program. Expressions are evaluated in this order, and an error stops
+//! the evaluation before the next step:
+//!
+//! 1. Statements run from top to bottom. The value of a `let` is evaluated
+//!    before the name is bound, so reading the name while its value is
+//!    evaluated fails with [`Error::UnknownIdentifier`]. The result of
+//!    the program is the value of the last statement, which is null for
+//!    `let`, `import` and `export`.
+//! 2. Prefix operators evaluate the operand, infix operators evaluate the
+//!    left operand and then the right one, before the operator is applied.
+//! 3. Array elements are evaluated from left to right. Hash literals
+//!    evaluate the key and then the value of every pair in the source
+//!    order, and the keys are checked to be hashable afterwards.
+//! 4. Index expressions evaluate the indexed value and then the index.
+//! 5. Calls evaluate the callee and then the arguments from left to right.
+//!    Then the callee is checked to be a function and the number
+//!    of arguments is checked against its parameters.
+//! 6. `if` evaluates the condition and then only the chosen branch.
+//!
+//! Values of different types are never equal, so `==` and `!=` don't fail.
+//! Other operators fail with [`Error::TypeMismatch`] for operands of
+//! different types and with [`Error::UnknownOperator`] for unsupported
+//! operands of the same type.
+//!
+//! After an error, both runtimes return the same [`StackTrace`]. The line
+//! of a call is the line of the innermost enclosing expression that has a
+//! position. Functions called in a tail position replace the frame of
+//! the caller, like they do in the VM.
+
+pub mod error;
+#[cfg(test)]
+mod test;
+pub mod trace;
+
+use clap::ValueEnum;
+
+use crate::{ast, object::Object};
+
+pub use error::*;
+pub use trace::{StackFrame, StackTrace};
+
+/// Default maximum number of nested function calls.
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// Runtime that executes the programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Runtime {
+    /// Tree-walking evaluator.
+    Eval,
+    /// Compiler and virtual machine.
+    Vm,
+}
+
+/// Converts the value of `unquote(expr)` to the node that replaces the call in the quote.
+pub fn unquote(value: Object) -> Result<ast::Expression> {
+    match value {
+        Object::Integer(val) => Ok(ast::Expression::IntegerLiteral(val)),
+        Object::BigInteger(val) => Ok(ast::Expression::BigIntegerLiteral((*val).clone())),
+        Object::Boolean(val) => Ok(ast::Expression::BooleanLiteral(val)),
+        Object::String(val) => Ok(ast::Expression::StringLiteral((*val).clone())),
+        Object::Bytes(val) => Ok(ast::Expression::BytesLiteral((*val).clone())),
+        Object::Quote(expr) => Ok((*expr).clone()),
+        obj => Err(Error::NotUnquotable(obj.into())),
+    }
+}
@@ -0,0 +1,566 @@
+use std::{fs, path::Path};
+
+use crate::{
+    compile::{self, Compiler},
+    evaluate::Evaluator,
+    macro_expansion::MacroExpander,
+    module,
+    object::{builtin::ExecutionError, DataType},
+    parse,
+    vm::VirtualMachine,
+};
+
+use super::{Error, Result, Runtime, StackFrame, StackTrace, DEFAULT_MAX_DEPTH};
+
+/// Runs the program and returns the inspected result, so that the values
+/// of both runtimes can be compared, together with the stack trace of
+/// an error. Programs must compile for the VM.
+fn run(runtime: Runtime, input: &str, max_depth: usize) -> (Result<String>, StackTrace) {
+    run_with_path(runtime, input, None, max_depth)
+}
+
+/// Runs the program with imports resolved against the path.
+/// Errors of the module resolution are reported by the compiler
+/// for the VM and they are returned like the evaluator's.
+fn run_with_path(
+    runtime: Runtime,
+    input: &str,
+    path: Option<&Path>,
+    max_depth: usize,
+) -> (Result<String>, StackTrace) {
+    let program = parse::parse(input).unwrap();
+    let program = MacroExpander::new().expand(program).unwrap();
+
+    match runtime {
+        Runtime::Eval => {
+            let mut evaluator = path.map_or_else(Evaluator::new, Evaluator::with_path);
+            evaluator.set_max_depth(max_depth);
+            let res = evaluator.evaluate(&program).map(|obj| obj.inspect());
+            (res, evaluator.stack_trace())
+        }
+        Runtime::Vm => {
+            let mut compiler = path.map_or_else(Compiler::new, Compiler::with_path);
+            let bytecode = match compiler.compile(&program) {
+                Ok(bytecode) => bytecode,
+                Err(compile::Error::Module { source }) => {
+                    return (Err(Error::Module { source }), StackTrace(vec![]))
+                }
+                Err(err) => panic!("{} failed to compile: {}", input, err),
+            };
+
+            let mut vm = VirtualMachine::new();
+            vm.set_max_depth(max_depth);
+            let res = vm.run(&bytecode).map(|_| vm.last_popped().inspect());
+            (res, vm.stack_trace())
+        }
+    }
+}
+
+fn assert_parity(input: &str) -> Result<String> {
+    assert_parity_with_depth(input, DEFAULT_MAX_DEPTH)
+}
+
+fn assert_parity_with_depth(input: &str, max_depth: usize) -> Result<String> {
+    let (evaluated, evaluated_trace) = run(Runtime::Eval, input, max_depth);
+    let (executed, executed_trace) = run(Runtime::Vm, input, max_depth);
+    assert_eq!(evaluated, executed, "runtimes differ for {}", input);
+    if evaluated.is_err() {
+        assert_eq!(
+            evaluated_trace, executed_trace,
+            "stack traces differ for {}",
+            input
+        );
+    }
+
+    evaluated
+}
+
+/// Runs the inputs one after another in the same runtime, like the
+/// lines of the REPL, and returns the inspected result of each input.
+fn run_session(runtime: Runtime, inputs: &[&str]) -> Vec<Result<String>> {
+    let mut evaluator = Evaluator::new();
+    let mut compiler = Compiler::new();
+    let mut vm = VirtualMachine::new();
+
+    inputs
+        .iter()
+        .map(|input| {
+            let program = parse::parse(input).unwrap();
+            let program = MacroExpander::new().expand(program).unwrap();
+
+            match runtime {
+                Runtime::Eval => evaluator.evaluate(&program).map(|obj| obj.inspect()),
+                Runtime::Vm => {
+                    let bytecode = compiler.compile(&program).unwrap();
+                    vm.run(&bytecode).map(|_| vm.last_popped().inspect())
+                }
+            }
+        })
+        .collect()
+}
+
+/// Returns the stack trace of the failing program, which has to be the same for both runtimes.
+fn assert_trace_parity(input: &str) -> StackTrace {
+    assert!(assert_parity(input).is_err(), "{} didn't fail", input);
+    run(Runtime::Eval, input, DEFAULT_MAX_DEPTH).1
+}
+
+/// Returns the paths of the programs in the directory and its subdirectories.
+fn programs(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            paths.extend(programs(&path));
+        } else if path.extension().is_some_and(|ext| ext == "monkey") {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_file_parity() {
+    let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let paths = programs(&testdata);
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let input = fs::read_to_string(&path).unwrap();
+        let (evaluated, evaluated_trace) =
+            run_with_path(Runtime::Eval, &input, Some(&path), DEFAULT_MAX_DEPTH);
+        let (executed, executed_trace) =
+            run_with_path(Runtime::Vm, &input, Some(&path), DEFAULT_MAX_DEPTH);
+
+        assert_eq!(
+            evaluated,
+            executed,
+            "runtimes differ for {}",
+            path.display()
+        );
+        if evaluated.is_err() && !matches!(evaluated, Err(Error::Module { .. })) {
+            assert_eq!(
+                evaluated_trace,
+                executed_trace,
+                "stack traces differ for {}",
+                path.display()
+            );
+        }
+
+        let module_path = |name: &str| {
+            let path = testdata.join("modules/lib").join(name);
+            path.canonicalize().unwrap().display().to_string()
+        };
+        let expected = match path.strip_prefix(&testdata).unwrap().to_str().unwrap() {
+            "modules/main.monkey" => Some(Ok("abababab".to_owned())),
+            // Programs that end with a statement without a value return null.
+            "modules/undefined_export.monkey" | "modules/lib/math.monkey" => {
+                Some(Ok("null".to_owned()))
+            }
+            // Modules can't return early, but the main program can.
+            "modules/lib/early_return.monkey" => Some(Ok("5".to_owned())),
+            "modules/top_level_return.monkey" => {
+                Some(Err(Error::from(module::Error::TopLevelReturn {
+                    path: module_path("early_return.monkey"),
+                })))
+            }
+            "modules/top_level_try.monkey" => Some(Err(Error::from(module::Error::TopLevelTry {
+                path: module_path("early_error.monkey"),
+            }))),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            assert_eq!(evaluated, expected, "{}", path.display());
+        }
+    }
+}
+
+#[test]
+fn test_value_parity() {
+    let tests = [
+        ("1 + 2 * 3 - 4 / 2", "5"),
+        ("-(5) + ~3", "-9"),
+        ("9223372036854775807 + 1", "9223372036854775808"),
+        ("0xff & 0b1010 | 1 << 4 ^ 3 >> 1", "27"),
+        ("1 < 2", "true"),
+        ("2 < 1", "false"),
+        ("1 > 2 == false", "true"),
+        ("!5", "false"),
+        ("!if (false) { 1 }", "true"),
+        ("true == true", "true"),
+        ("true != false", "true"),
+        ("1 == true", "false"),
+        ("\"a\" == \"a\"", "true"),
+        ("\"a\" != \"b\"", "true"),
+        ("[1, 2] == [1, 2]", "true"),
+        ("{1: 2} == {1: 3}", "false"),
+        ("\"a\" + \"b\"", "ab"),
+        ("b\"a\" + b\"b\" == b\"ab\"", "true"),
+        ("if (1) { 10 }", "10"),
+        ("if (false) { 10 }", "null"),
+        ("if (if (false) { 1 }) { 1 } else { 2 }", "2"),
+        ("let x = 5; let y = x * 2; y", "10"),
+        ("let x = 1; { let x = 2; x } + x", "3"),
+        ("[1, 2 * 2, 3][1]", "4"),
+        ("[1][5]", "null"),
+        ("{\"a\": 1, 2: true}[2]", "true"),
+        ("{[1]: 2}[[1]]", "2"),
+        ("b\"ab\"[1]", "98"),
+        (
+            "let add = fn(a, b) { a + b }; add(1, add(2, 3))",
+            "6",
+        ),
+        ("let f = fn() { return 1; 2 }; f()", "1"),
+        ("return 1; 2", "1"),
+        (
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+            "610",
+        ),
+        (
+            "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(10000)",
+            "0",
+        ),
+        (
+            "let adder = fn(x) { fn(y) { x + y } }; adder(2)(3)",
+            "5",
+        ),
+        ("let x = 10; let f = fn() { x }; f()", "10"),
+        ("len(\"four\") + len([1, 2])", "6"),
+        ("first([1, 2]) + last([1, 2])", "3"),
+        ("rest(push([1], 2))", "[2]"),
+        ("string(bytes(\"hi\"))", "hi"),
+        ("hex(from_hex(\"0aff\"))", "0aff"),
+        ("slice([1, 2, 3, 4], 1, 3)", "[2, 3]"),
+        ("error(\"oops\")", "error(oops)"),
+        ("is_error(error(\"x\"))", "true"),
+        (
+            "let f = fn() { error(\"inner\")?; 1 }; is_error(f())",
+            "true",
+        ),
+        ("let f = fn() { 5? }; f()", "5"),
+        ("error(\"top\")?; 1", "error(top)"),
+        (
+            "let g = fn() { yield 1; yield 2; }; let it = g(); [next(it), next(it), is_done(next(it))]",
+            "[1, 2, true]",
+        ),
+        (
+            "let g = fn(n) { if (n > 0) { yield n; } }; let it = g(1); [next(it), is_done(next(it))]",
+            "[1, true]",
+        ),
+        (
+            "let g = fn(n) { { let m = n + 1; yield m; } yield n; }; let it = g(1); [next(it), next(it), is_done(next(it))]",
+            "[2, 1, true]",
+        ),
+        ("let f = fn() { 1 }; [f == f, f != f]", "[true, false]"),
+        (
+            "let a = fn() { 1 }; let b = fn() { 1 }; [a == b, a == fn() { 1 }]",
+            "[false, false]",
+        ),
+        (
+            "let f = fn(n) { if (n == 0) { f } else { f(n - 1) } }; f(2) == f",
+            "true",
+        ),
+        ("let h = {\"b\": 1, \"a\": 2}; h", "{b: 1, a: 2}"),
+        ("{{\"b\": 1, \"a\": 2}: 1}", "{{b: 1, a: 2}: 1}"),
+        ("let h = {{\"b\": 1, \"a\": 2}: 1}; h[{\"a\": 2, \"b\": 1}]", "1"),
+        ("{{1: 2, 3: 4}: 1, {3: 4, 1: 2}: 2}", "{{1: 2, 3: 4}: 2}"),
+        ("quote(1 + 2)", "quote((1 + 2))"),
+        ("quote(1 + 2) == quote(1 + 2)", "true"),
+        ("quote(1 + 2) == quote(2 + 1)", "false"),
+        ("let f = fn() { quote(x) }; quote(x) == f()", "true"),
+        ("quote(1 + unquote(2 + 3))", "quote((1 + 5))"),
+        ("quote(unquote(4 + 4) + 8)", "quote((8 + 8))"),
+        ("let foobar = 8; quote(unquote(foobar))", "quote(8)"),
+        ("quote(unquote(true == false))", "quote(false)"),
+        (
+            "let q = quote(4 + 4); quote(unquote(4 + 4) + unquote(q))",
+            "quote((8 + (4 + 4)))",
+        ),
+        (
+            "quote(unquote(9223372036854775807 + 1))",
+            "quote(9223372036854775808)",
+        ),
+        (
+            "let f = fn(x) { quote(unquote(x) * unquote(x + 1)) }; f(2)",
+            "quote((2 * 3))",
+        ),
+        ("quote(quote(unquote(1)))", "quote(quote(1))"),
+        (
+            "let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) }; unless(false, 1, 2)",
+            "1",
+        ),
+        ("const c = 3; c * c", "9"),
+        ("puts(\"parity\")", "null"),
+        ("let a = 1;", "null"),
+        ("1; let a = 2;", "null"),
+        ("let a = 1; export a", "null"),
+        ("let a = [fn() { a }]; len(a[0]())", "1"),
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(assert_parity(input), Ok(expected.to_owned()), "{}", input);
+    }
+}
+
+#[test]
+fn test_error_parity() {
+    let tests = [
+        (
+            "1 + true",
+            Error::TypeMismatch("INTEGER + BOOLEAN".to_owned()),
+        ),
+        (
+            "true + false",
+            Error::UnknownOperator("BOOLEAN + BOOLEAN".to_owned()),
+        ),
+        (
+            "\"a\" - \"b\"",
+            Error::UnknownOperator("STRING - STRING".to_owned()),
+        ),
+        (
+            "b\"a\" * b\"b\"",
+            Error::UnknownOperator("BYTES * BYTES".to_owned()),
+        ),
+        (
+            "\"a\" < 1",
+            Error::TypeMismatch("STRING < INTEGER".to_owned()),
+        ),
+        (
+            "\"a\" > \"b\"",
+            Error::UnknownOperator("STRING > STRING".to_owned()),
+        ),
+        ("-true", Error::UnknownOperator("-BOOLEAN".to_owned())),
+        ("~\"a\"", Error::UnknownOperator("~STRING".to_owned())),
+        ("1 / 0", Error::DivisionByZero),
+        ("1 << -1", Error::InvalidShiftAmount("-1".to_owned())),
+        ("5(1)", Error::NotAFunction(DataType::Integer)),
+        (
+            "1[0]",
+            Error::IndexOperatorNotSupported(DataType::Integer, DataType::Integer),
+        ),
+        (
+            "[1][\"a\"]",
+            Error::IndexOperatorNotSupported(DataType::Array, DataType::String),
+        ),
+        ("{1: 2}[fn() {}]", Error::NotHashable(DataType::Function)),
+        ("{fn() {}: 1}", Error::NotHashable(DataType::Function)),
+        (
+            "fn(a, b) { a }(1)",
+            Error::WrongNumberOfArguments {
+                expected: 2,
+                got: 1,
+            },
+        ),
+        (
+            "fn() { 1 }(1)",
+            Error::WrongNumberOfArguments {
+                expected: 0,
+                got: 1,
+            },
+        ),
+        (
+            "len(1)",
+            Error::BuiltinFunction {
+                source: ExecutionError::TypeMismatch("INTEGER".to_owned()),
+            },
+        ),
+        ("quote(unquote([1]))", Error::NotUnquotable(DataType::Array)),
+        ("let a = a + 1", Error::UnknownIdentifier("a".to_owned())),
+        (
+            "let a = fn() { a }();",
+            Error::UnknownIdentifier("a".to_owned()),
+        ),
+        (
+            "let a = 1; let b = { let c = c; c };",
+            Error::UnknownIdentifier("c".to_owned()),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(assert_parity(input), Err(expected), "{}", input);
+    }
+}
+
+#[test]
+fn test_evaluation_order() {
+    // The first failing step determines the error.
+    let tests = [
+        (
+            "(1 + true) + (1 / 0)",
+            Error::TypeMismatch("INTEGER + BOOLEAN".to_owned()),
+        ),
+        ("(1 / 0) < (1 + true)", Error::DivisionByZero),
+        ("5(1 / 0)", Error::DivisionByZero),
+        ("(1 / 0)(1 + true)", Error::DivisionByZero),
+        (
+            "(1 + true)(1 / 0)",
+            Error::TypeMismatch("INTEGER + BOOLEAN".to_owned()),
+        ),
+        ("[1 / 0, 1 + true]", Error::DivisionByZero),
+        ("{fn() {}: 1 / 0}", Error::DivisionByZero),
+        (
+            "(-true)[1 / 0]",
+            Error::UnknownOperator("-BOOLEAN".to_owned()),
+        ),
+        ("fn(a) { a }(1 / 0, 2)", Error::DivisionByZero),
+        (
+            "quote(unquote(fn() {}) + unquote(1 / 0))",
+            Error::NotUnquotable(DataType::Function),
+        ),
+        (
+            "quote(unquote(1 / 0) + unquote([1]))",
+            Error::DivisionByZero,
+        ),
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(assert_parity(input), Err(expected), "{}", input);
+    }
+}
+
+#[test]
+fn test_recursion_limit() {
+    let sum = "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } };";
+    let tests = [
+        (
+            format!("{} sum(5000)", sum),
+            10_000,
+            Ok("12502500".to_owned()),
+        ),
+        (format!("{} sum(99)", sum), 100, Ok("4950".to_owned())),
+        (
+            format!("{} sum(100)", sum),
+            100,
+            Err(Error::RecursionLimit { depth: 100 }),
+        ),
+        (
+            "let f = fn() { f() + 1 }; f()".to_owned(),
+            10,
+            Err(Error::RecursionLimit { depth: 10 }),
+        ),
+        // Tail calls don't nest.
+        (
+            "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(1000)".to_owned(),
+            10,
+            Ok("0".to_owned()),
+        ),
+        (
+            "let g = fn(n) { yield n; }; let f = fn(n) { next(g(n)) + 1 }; f(1)".to_owned(),
+            2,
+            Ok("2".to_owned()),
+        ),
+        (
+            "let g = fn(n) { yield n; }; let f = fn(n) { next(g(n)) + 1 }; fn() { f(1) + 0 }()"
+                .to_owned(),
+            2,
+            Err(Error::RecursionLimit { depth: 2 }),
+        ),
+    ];
+
+    for (input, max_depth, expected) in tests {
+        assert_eq!(
+            assert_parity_with_depth(&input, max_depth),
+            expected,
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_generator_after_error() {
+    let tests = [
+        (
+            vec![
+                "let g = fn() { yield 1 / 0; }; let it = g(); 0",
+                "next(it)",
+                "next(it)",
+            ],
+            vec![
+                Ok("0".to_owned()),
+                Err(Error::DivisionByZero),
+                Ok("done".to_owned()),
+            ],
+        ),
+        (
+            vec![
+                "let g = fn() { yield 1; 1 / 0 }; let it = g(); next(it)",
+                "next(it)",
+                "next(it)",
+            ],
+            vec![
+                Ok("1".to_owned()),
+                Err(Error::DivisionByZero),
+                Ok("done".to_owned()),
+            ],
+        ),
+    ];
+
+    for (inputs, expected) in tests {
+        let evaluated = run_session(Runtime::Eval, &inputs);
+        let executed = run_session(Runtime::Vm, &inputs);
+        assert_eq!(evaluated, executed, "runtimes differ for {:?}", inputs);
+        assert_eq!(evaluated, expected, "{:?}", inputs);
+    }
+}
+
+#[test]
+fn test_stack_trace() {
+    let frame = |function: &str, line| StackFrame {
+        function: function.to_owned(),
+        line,
+    };
+
+    let tests = [
+        ("1 +\n  true", vec![frame("<main>", 1)]),
+        ("let x = 1;\nlet y = {fn() {}: x};", vec![frame("<main>", 2)]),
+        (
+            "let inner = fn(x) {\n  x[0]\n};\nlet outer = fn(x) {\n  inner(x) + 1\n};\nouter(5)",
+            vec![frame("inner", 2), frame("outer", 5), frame("<main>", 7)],
+        ),
+        (
+            "let f = fn() {\n  fn(a) {\n    -a\n  }(true)\n};\n\nf() + 1",
+            vec![frame("<anonymous>", 3), frame("<main>", 7)],
+        ),
+        (
+            "let f = fn(x) {\n  len(x)\n};\nlet g = fn() { f(1) + 1 };\ng()",
+            vec![frame("f", 2), frame("g", 4), frame("<main>", 5)],
+        ),
+        (
+            "let f = fn() {\n  5(1)\n};\nf()",
+            vec![frame("f", 2), frame("<main>", 4)],
+        ),
+        (
+            "let gen = fn(x) {\n  yield 1;\n  if (x) {\n    yield {fn() {}: 2};\n  }\n};\nlet it = gen(true);\nnext(it);\nnext(it)",
+            vec![frame("gen", 3), frame("<main>", 9)],
+        ),
+        (
+            "let gen = fn() {\n  yield 1 / 0;\n};\nlet f = fn() {\n  next(gen())\n};\nf()",
+            vec![frame("gen", 2), frame("f", 5), frame("<main>", 7)],
+        ),
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(
+            assert_trace_parity(input),
+            StackTrace(expected),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_stack_trace_display() {
+    let trace = assert_trace_parity("let f = fn(n) {\n  f(n + 1) + 1\n};\nf(0)");
+    assert_eq!(trace.0.len(), DEFAULT_MAX_DEPTH + 1);
+
+    let lines: Vec<_> = trace.to_string().lines().map(str::to_owned).collect();
+    assert_eq!(lines.len(), 22);
+    assert_eq!(lines[0], "stack trace (innermost call first):");
+    assert_eq!(lines[1], "    at f, line 2");
+    assert_eq!(lines[11], "    ... 9981 more calls");
+    assert_eq!(lines[21], "    at <main>, line 4");
+}
@@ -0,0 +1,59 @@
+use std::{fmt::Display, path::Path};
+
+/// Name of the main program in stack traces.
+pub const MAIN_NAME: &str = "<main>";
+/// Name of functions without a name in stack traces.
+pub const ANONYMOUS_NAME: &str = "<anonymous>";
+
+// Longer traces only show the innermost and outermost calls.
+const MAX_SHOWN_FRAMES: usize = 20;
+
+/// Returns the name of the imported module in stack traces.
+pub fn module_name(path: &Path) -> String {
+    format!("<module {}>", path.display())
+}
+
+/// Call that was active when a runtime error occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    pub function: String,
+    /// Line that was being executed in the function.
+    pub line: usize,
+}
+
+impl StackFrame {
+    pub fn new(function: Option<&str>, line: usize) -> Self {
+        Self {
+            function: function.unwrap_or(ANONYMOUS_NAME).to_owned(),
+            line,
+        }
+    }
+}
+
+/// Calls that were active when a runtime error occurred, innermost call first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StackTrace(pub Vec<StackFrame>);
+
+impl Display for StackTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stack trace (innermost call first):")?;
+
+        let half = MAX_SHOWN_FRAMES / 2;
+        for (idx, frame) in self.0.iter().enumerate() {
+            if self.0.len() > MAX_SHOWN_FRAMES && idx >= half && idx < self.0.len() - half {
+                if idx == half {
+                    write!(
+                        f,
+                        "\n    ... {} more calls",
+                        self.0.len() - MAX_SHOWN_FRAMES
+                    )?;
+                }
+                continue;
+            }
+
+            write!(f, "\n    at {}, line {}", frame.function, frame.line)?;
+        }
+
+        Ok(())
+    }
+}
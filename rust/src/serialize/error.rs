@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+use crate::object::DataType;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("constants of type {0} cannot be written to a bytecode file")]
+    UnsupportedConstant(DataType),
+    #[error("not a bytecode file")]
+    InvalidMagic,
+    #[error("incompatible bytecode version {found}, expected version {expected}")]
+    IncompatibleVersion { found: u16, expected: u16 },
+    #[error("unexpected end of the bytecode file")]
+    UnexpectedEnd,
+    #[error("unexpected data at the end of the bytecode file")]
+    TrailingData,
+    #[error("invalid constant tag {0}")]
+    InvalidConstantTag(u8),
+    #[error("invalid UTF-8 in a string")]
+    InvalidString,
+    #[error("length {0} is too large")]
+    InvalidLength(u64),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,327 @@
+//! Binary file format of the compiled bytecode.
+//!
+//! A bytecode file starts with the magic bytes `MBC\0` and the format
+//! [`VERSION`] as little-endian `u16`. Files of a different version are
+//! rejected when they are loaded. The header is followed by the code of the
+//! main program, the constant pool and the imported modules. Compiled
+//! functions in the constant pool are stored with their code, so
+//! a bytecode file is executed without the source files.
+//!
+//! Integers are little-endian, lengths and counts are `u64` and strings are
+//! UTF-8 prefixed with their length. The code of the main program, modules
+//! and functions is stored as the encoded [`Instructions`], the line table
+//! and the names of the global or local slots. The instructions are not
+//! checked when they are loaded, they are checked by [`crate::verify`]
+//! before they are run.
+
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::{path::PathBuf, rc::Rc};
+
+use num_bigint::BigInt;
+
+use crate::{
+    code::{Bytecode, Instructions, LineTable, Module},
+    object::{CompiledFunction, Object},
+};
+
+pub use error::*;
+
+/// Extension of bytecode files, which are run without compiling them.
+pub const EXTENSION: &str = "mbc";
+
+const MAGIC: &[u8; 4] = b"MBC\0";
+
+/// Version of the file format. It has to be increased
+/// with every change of the format or of the instructions.
+pub const VERSION: u16 = 2;
+
+const INTEGER_TAG: u8 = 0;
+const BIG_INTEGER_TAG: u8 = 1;
+const STRING_TAG: u8 = 2;
+const BYTES_TAG: u8 = 3;
+const FUNCTION_TAG: u8 = 4;
+
+/// Bytecode loaded from a file, which owns the constants and modules.
+#[derive(Debug, PartialEq)]
+pub struct LoadedBytecode {
+    pub instructions: Rc<Instructions>,
+    pub lines: Rc<LineTable>,
+    pub constants: Vec<Object>,
+    pub modules: Vec<Module>,
+    pub global_names: Rc<Vec<String>>,
+}
+
+impl LoadedBytecode {
+    pub fn bytecode(&self) -> Bytecode<'_> {
+        Bytecode {
+            instructions: self.instructions.clone(),
+            lines: self.lines.clone(),
+            constants: &self.constants,
+            modules: &self.modules,
+            global_names: self.global_names.clone(),
+        }
+    }
+}
+
+/// Returns the bytecode encoded in the file format.
+pub fn serialize(bytecode: &Bytecode) -> Result<Vec<u8>> {
+    let mut writer = Writer { bytes: vec![] };
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u16(VERSION);
+
+    writer.code(
+        &bytecode.instructions,
+        &bytecode.lines,
+        &bytecode.global_names,
+    );
+
+    writer.length(bytecode.constants.len());
+    for constant in bytecode.constants {
+        writer.constant(constant)?;
+    }
+
+    writer.length(bytecode.modules.len());
+    for module in bytecode.modules {
+        writer.string(&module.path.to_string_lossy());
+        writer.length(module.num_globals);
+        writer.code(&module.instructions, &module.lines, &module.global_names);
+    }
+
+    Ok(writer.bytes)
+}
+
+/// Decodes the bytecode from the file format.
+pub fn deserialize(bytes: &[u8]) -> Result<LoadedBytecode> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(Error::InvalidMagic);
+    }
+
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(Error::IncompatibleVersion {
+            found: version,
+            expected: VERSION,
+        });
+    }
+
+    let (instructions, lines, global_names) = reader.code()?;
+
+    let constants = (0..reader.length()?)
+        .map(|_| reader.constant())
+        .collect::<Result<_>>()?;
+
+    let modules = (0..reader.length()?)
+        .map(|_| {
+            let path = PathBuf::from(reader.string()?);
+            let num_globals = reader.length()?;
+            let (instructions, lines, global_names) = reader.code()?;
+
+            Ok(Module {
+                path,
+                instructions: Rc::new(instructions),
+                lines: Rc::new(lines),
+                num_globals,
+                global_names: Rc::new(global_names),
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    if !reader.bytes.is_empty() {
+        return Err(Error::TrailingData);
+    }
+
+    Ok(LoadedBytecode {
+        instructions: Rc::new(instructions),
+        lines: Rc::new(lines),
+        constants,
+        modules,
+        global_names: Rc::new(global_names),
+    })
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn length(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.length(value.len());
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    fn code(&mut self, instructions: &Instructions, lines: &LineTable, names: &[String]) {
+        self.bytes(instructions.as_bytes());
+
+        self.length(lines.entries().len());
+        for (idx, line) in lines.entries() {
+            self.length(*idx);
+            self.length(*line);
+        }
+
+        self.length(names.len());
+        for name in names {
+            self.string(name);
+        }
+    }
+
+    fn constant(&mut self, constant: &Object) -> Result<()> {
+        match constant {
+            Object::Integer(value) => {
+                self.u8(INTEGER_TAG);
+                self.bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Object::BigInteger(value) => {
+                self.u8(BIG_INTEGER_TAG);
+                self.bytes(&value.to_signed_bytes_le());
+            }
+            Object::String(value) => {
+                self.u8(STRING_TAG);
+                self.string(value);
+            }
+            Object::Bytes(value) => {
+                self.u8(BYTES_TAG);
+                self.bytes(value);
+            }
+            Object::CompiledFunction(function) => {
+                self.u8(FUNCTION_TAG);
+                self.length(function.num_locals);
+                self.length(function.num_arguments);
+                self.u8(function.generator as u8);
+                match &function.name {
+                    Some(name) => {
+                        self.u8(1);
+                        self.string(name);
+                    }
+                    None => self.u8(0),
+                }
+                self.code(
+                    &function.instructions,
+                    &function.lines,
+                    &function.local_names,
+                );
+            }
+            obj => return Err(Error::UnsupportedConstant(obj.into())),
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::UnexpectedEnd);
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        let value = self.u64()?;
+        usize::try_from(value).map_err(|_| Error::InvalidLength(value))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.length()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidString)
+    }
+
+    fn code(&mut self) -> Result<(Instructions, LineTable, Vec<String>)> {
+        let instructions = Instructions::from_bytes(self.bytes()?.to_vec());
+
+        let lines = (0..self.length()?)
+            .map(|_| Ok((self.length()?, self.length()?)))
+            .collect::<Result<_>>()?;
+
+        let names = (0..self.length()?)
+            .map(|_| self.string())
+            .collect::<Result<_>>()?;
+
+        Ok((instructions, LineTable::from_entries(lines), names))
+    }
+
+    fn constant(&mut self) -> Result<Object> {
+        let constant = match self.u8()? {
+            INTEGER_TAG => Object::Integer(i64::from_le_bytes(self.array()?)),
+            BIG_INTEGER_TAG => {
+                Object::BigInteger(Rc::new(BigInt::from_signed_bytes_le(self.bytes()?)))
+            }
+            STRING_TAG => Object::String(Rc::new(self.string()?)),
+            BYTES_TAG => Object::Bytes(Rc::new(self.bytes()?.to_vec())),
+            FUNCTION_TAG => {
+                let num_locals = self.length()?;
+                let num_arguments = self.length()?;
+                let generator = self.u8()? != 0;
+                let name = match self.u8()? {
+                    0 => None,
+                    _ => Some(Rc::new(self.string()?)),
+                };
+                let (instructions, lines, local_names) = self.code()?;
+
+                Object::CompiledFunction(CompiledFunction {
+                    instructions: Rc::new(instructions),
+                    num_locals,
+                    num_arguments,
+                    generator,
+                    name,
+                    lines: Rc::new(lines),
+                    local_names: Rc::new(local_names),
+                })
+            }
+            tag => return Err(Error::InvalidConstantTag(tag)),
+        };
+
+        Ok(constant)
+    }
+}
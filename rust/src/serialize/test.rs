@@ -0,0 +1,105 @@
+use std::{path::Path, rc::Rc};
+
+use crate::{
+    compile::Compiler,
+    object::{DataType, Object},
+    parse::parse,
+    vm::VirtualMachine,
+};
+
+use super::{deserialize, serialize, Error, VERSION};
+
+#[test]
+fn test_round_trip() {
+    let main_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/modules/main.monkey");
+
+    let tests = [
+        ("1 + 2", Object::Integer(3)),
+        (
+            "9223372036854775807 + 1",
+            Object::BigInteger(Rc::new("9223372036854775808".parse().unwrap())),
+        ),
+        (
+            r#"let x = "ab"; x + "c""#,
+            Object::String(Rc::new("abc".to_string())),
+        ),
+        (r#"len(b"\x00\xff")"#, Object::Integer(2)),
+        (
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10)",
+            Object::Integer(55),
+        ),
+        (
+            "let adder = fn(a) { fn(b) { a + b } }; adder(1)(2)",
+            Object::Integer(3),
+        ),
+        (
+            "let gen = fn() { yield 1; yield 2; }; let g = gen(); next(g); next(g)",
+            Object::Integer(2),
+        ),
+        (
+            r#"import "lib/math.monkey" as math; math.double(21)"#,
+            Object::Integer(42),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::with_path(&main_path);
+        let bytecode = compiler.compile(&program).unwrap();
+
+        let bytes = serialize(&bytecode).unwrap();
+        let loaded = deserialize(&bytes).unwrap();
+        assert_eq!(loaded.bytecode(), bytecode, "{}", input);
+
+        let mut vm = VirtualMachine::new();
+        vm.run(&loaded.bytecode()).unwrap();
+        assert_eq!(*vm.last_popped(), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_unsupported_constant() {
+    let program = parse("quote(1 + 2)").unwrap();
+
+    let mut compiler = Compiler::new();
+    let bytecode = compiler.compile(&program).unwrap();
+
+    assert_eq!(
+        serialize(&bytecode),
+        Err(Error::UnsupportedConstant(DataType::Quote))
+    );
+}
+
+#[test]
+fn test_invalid_files() {
+    let program = parse("let f = fn(x) { len(x) }; f([1, 2])").unwrap();
+
+    let mut compiler = Compiler::new();
+    let bytecode = compiler.compile(&program).unwrap();
+    let bytes = serialize(&bytecode).unwrap();
+
+    let mut newer = bytes.clone();
+    newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+
+    let tests = [
+        (b"\x7fELF".to_vec(), Error::InvalidMagic),
+        (b"MB".to_vec(), Error::InvalidMagic),
+        (
+            newer,
+            Error::IncompatibleVersion {
+                found: VERSION + 1,
+                expected: VERSION,
+            },
+        ),
+        (bytes[..bytes.len() - 1].to_vec(), Error::UnexpectedEnd),
+        (trailing, Error::TrailingData),
+    ];
+
+    for (bytes, expected) in tests {
+        assert_eq!(deserialize(&bytes), Err(expected));
+    }
+}
@@ -1,10 +1,13 @@
+use std::fmt::Display;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Illegal(u8),
     // Identifiers + literals
     Ident(String), // add, foobar, x, y, ...
-    Int(String),   // 1343456
+    Int(String),   // 1343456, 0xff, 1_000
     String(String),
+    Bytes(String), // b"\x00\xff", escapes are decoded by the parser
     // Operators
     Assign,
     Plus,
@@ -16,10 +19,19 @@ pub enum Token {
     Gt,
     Eq,
     NotEq,
+    Question,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+    Arrow,
     // Delimiters
     Comma,
     Semicolon,
     Colon,
+    Dot,
     Lparen,
     Rparen,
     Lsquigly,
@@ -28,24 +40,36 @@ pub enum Token {
     RBracket,
     // Keywords
     Function,
+    Macro,
     Let,
+    Const,
     True,
     False,
     If,
     Else,
     Return,
+    Yield,
+    Import,
+    Export,
+    As,
 }
 
 impl Token {
     pub fn lookup_ident(ident: &str) -> Token {
         match ident {
             "fn" => Token::Function,
+            "macro" => Token::Macro,
             "let" => Token::Let,
+            "const" => Token::Const,
             "true" => Token::True,
             "false" => Token::False,
             "if" => Token::If,
             "else" => Token::Else,
             "return" => Token::Return,
+            "yield" => Token::Yield,
+            "import" => Token::Import,
+            "export" => Token::Export,
+            "as" => Token::As,
             _ => Token::Ident(ident.to_string()),
         }
     }
@@ -61,8 +85,29 @@ impl Token {
                 | Self::NotEq
                 | Self::Lt
                 | Self::Gt
+                | Self::Ampersand
+                | Self::Pipe
+                | Self::Caret
+                | Self::ShiftLeft
+                | Self::ShiftRight
                 | Self::Lparen
                 | Self::LBracket
+                | Self::Question
+                | Self::Dot
         )
     }
 }
+
+/// Position of a token in the source code. Lines
+/// and columns are numbered from 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+use thiserror::Error;
+
+use crate::vm::GLOBALS_SIZE;
+
+use super::{MAX_ARGUMENTS, MAX_LOCALS};
+
+/// Instruction at which the verification failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// Name of the code like in stack traces, for example `<main>` or the function name.
+    pub code: String,
+    /// Byte offset of the instruction.
+    pub offset: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, offset {}", self.code, self.offset)
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("{location}: invalid instruction")]
+    InvalidInstruction { location: Location },
+    #[error("{location}: constant {index} doesn't exist")]
+    InvalidConstant { location: Location, index: usize },
+    #[error("{location}: constant {index} is not a function")]
+    NotAFunction { location: Location, index: usize },
+    #[error("{location}: global {index} doesn't exist")]
+    InvalidGlobal { location: Location, index: usize },
+    #[error("{location}: local {index} doesn't exist")]
+    InvalidLocal { location: Location, index: usize },
+    #[error("{location}: free variable {index} doesn't exist")]
+    InvalidFreeVariable { location: Location, index: usize },
+    #[error("{location}: module {index} doesn't exist")]
+    InvalidModule { location: Location, index: usize },
+    #[error("{location}: jump target {target} is out of range")]
+    InvalidJumpTarget { location: Location, target: usize },
+    #[error("{location}: hash literal with an odd number of elements {length}")]
+    OddHashLength { location: Location, length: usize },
+    #[error("{location}: function has {num_arguments} arguments, but only {num_locals} locals")]
+    TooFewLocals {
+        location: Location,
+        num_arguments: usize,
+        num_locals: usize,
+    },
+    #[error("{location}: function has {num_locals} locals, the limit is {MAX_LOCALS}")]
+    TooManyLocals {
+        location: Location,
+        num_locals: usize,
+    },
+    #[error("{location}: function has {num_arguments} arguments, the limit is {MAX_ARGUMENTS}")]
+    TooManyArguments {
+        location: Location,
+        num_arguments: usize,
+    },
+    #[error("{location}: module has {num_globals} globals, the limit is {GLOBALS_SIZE}")]
+    TooManyGlobals {
+        location: Location,
+        num_globals: usize,
+    },
+    #[error("{location}: module return outside of a module")]
+    ReturnModuleOutsideModule { location: Location },
+    #[error("{location}: instruction pops from an empty stack")]
+    StackUnderflow { location: Location },
+    #[error("{location}: stack depth {found} differs from the depth {expected} on another path")]
+    StackMismatch {
+        location: Location,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{location}: end of the code is reached without returning")]
+    MissingReturn { location: Location },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,383 @@
+//! Verifier, which checks the bytecode before the virtual machine runs it.
+//!
+//! The virtual machine doesn't check the operands of the instructions, so
+//! invalid bytecode, for example a hand-built one or one loaded from a file,
+//! could make it index out of range. The verifier checks that:
+//!
+//! - constants, globals, locals, free variables and modules exist,
+//! - the instructions can be decoded, jumps target the start of an instruction
+//!   and closures are created from functions,
+//! - every instruction finds enough values on the stack, the stack has the
+//!   same depth on all paths to an instruction and functions and modules
+//!   return instead of running past their end.
+//!
+//! Functions are verified in the context of the closures that create them,
+//! which determines their globals and free variables. Functions that are
+//! never created can't run, so they aren't verified.
+
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::collections::HashSet;
+
+use crate::{
+    code::{Bytecode, Instruction, Instructions},
+    object::Object,
+    runtime::trace,
+    vm::GLOBALS_SIZE,
+};
+
+pub use error::*;
+
+/// Maximum number of locals of a function, which are addressed by a byte.
+pub const MAX_LOCALS: usize = u8::MAX as usize + 1;
+/// Maximum number of arguments of a function, which are counted by a byte.
+pub const MAX_ARGUMENTS: usize = u8::MAX as usize;
+
+/// Checks that the virtual machine can run the bytecode without
+/// reading out of range. It doesn't check the types of the values.
+pub fn verify(bytecode: &Bytecode) -> Result<()> {
+    let mut verifier = Verifier {
+        bytecode,
+        verified: HashSet::new(),
+    };
+
+    verifier.verify_code(&Code {
+        name: trace::MAIN_NAME.to_string(),
+        instructions: &bytecode.instructions,
+        kind: Kind::Main,
+        module: 0,
+        num_globals: GLOBALS_SIZE,
+        num_locals: 0,
+        num_free: 0,
+    })?;
+
+    for (idx, module) in bytecode.modules.iter().enumerate() {
+        let name = trace::module_name(&module.path);
+        // The virtual machine allocates the globals when the module is imported.
+        if module.num_globals > GLOBALS_SIZE {
+            return Err(Error::TooManyGlobals {
+                location: Location {
+                    code: name,
+                    offset: 0,
+                },
+                num_globals: module.num_globals,
+            });
+        }
+
+        verifier.verify_code(&Code {
+            name,
+            instructions: &module.instructions,
+            kind: Kind::Module,
+            module: idx + 1,
+            num_globals: module.num_globals,
+            num_locals: 0,
+            num_free: 0,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Main,
+    Module,
+    Function,
+}
+
+/// Instructions of the main program, a module or a function
+/// together with the slots that they can access.
+struct Code<'a> {
+    name: String,
+    instructions: &'a Instructions,
+    kind: Kind,
+    /// Module whose globals are accessed, see [`crate::object::Closure::module`].
+    module: usize,
+    num_globals: usize,
+    num_locals: usize,
+    num_free: usize,
+}
+
+impl Code<'_> {
+    fn location(&self, offset: usize) -> Location {
+        Location {
+            code: self.name.clone(),
+            offset,
+        }
+    }
+}
+
+struct Verifier<'a> {
+    bytecode: &'a Bytecode<'a>,
+    /// Verified functions as the constant index, the module
+    /// and the number of free variables of the closure.
+    verified: HashSet<(usize, usize, usize)>,
+}
+
+impl Verifier<'_> {
+    /// Verifies the code and then the functions it creates.
+    fn verify_code(&mut self, code: &Code) -> Result<()> {
+        let mut decoded = vec![];
+        let mut offset = 0;
+        while offset < code.instructions.len() {
+            let Some((instruction, size)) = code.instructions.decode(offset) else {
+                return Err(Error::InvalidInstruction {
+                    location: code.location(offset),
+                });
+            };
+            decoded.push((offset, instruction));
+            offset += size;
+        }
+
+        let mut functions = vec![];
+        for &(offset, instruction) in &decoded {
+            let location = || code.location(offset);
+            let check = |index: usize, len: usize, err: fn(Location, usize) -> Error| {
+                if index < len {
+                    Ok(())
+                } else {
+                    Err(err(location(), index))
+                }
+            };
+
+            match instruction {
+                Instruction::Constant(idx) => check(
+                    idx as usize,
+                    self.bytecode.constants.len(),
+                    |location, index| Error::InvalidConstant { location, index },
+                )?,
+                Instruction::Closure {
+                    constant_index,
+                    free_variables,
+                } => {
+                    let index = constant_index as usize;
+                    match self.bytecode.constants.get(index) {
+                        Some(Object::CompiledFunction(_)) => {
+                            functions.push((index, free_variables as usize))
+                        }
+                        Some(_) => {
+                            return Err(Error::NotAFunction {
+                                location: location(),
+                                index,
+                            })
+                        }
+                        None => {
+                            return Err(Error::InvalidConstant {
+                                location: location(),
+                                index,
+                            })
+                        }
+                    }
+                }
+                Instruction::GetGlobal(idx) | Instruction::SetGlobal(idx) => {
+                    check(idx as usize, code.num_globals, |location, index| {
+                        Error::InvalidGlobal { location, index }
+                    })?
+                }
+                Instruction::GetLocal(idx) | Instruction::SetLocal(idx) => {
+                    check(idx as usize, code.num_locals, |location, index| {
+                        Error::InvalidLocal { location, index }
+                    })?
+                }
+                Instruction::GetFree(idx) => {
+                    check(idx as usize, code.num_free, |location, index| {
+                        Error::InvalidFreeVariable { location, index }
+                    })?
+                }
+                Instruction::Import(idx) => check(
+                    idx as usize,
+                    self.bytecode.modules.len(),
+                    |location, index| Error::InvalidModule { location, index },
+                )?,
+                // Jumps can only target the start of an instruction or the end of the code.
+                Instruction::Jump(target) | Instruction::JumpNotTruthy(target)
+                    if target as usize != code.instructions.len()
+                        && decoded
+                            .binary_search_by_key(&(target as usize), |(offset, _)| *offset)
+                            .is_err() =>
+                {
+                    return Err(Error::InvalidJumpTarget {
+                        location: location(),
+                        target: target as usize,
+                    })
+                }
+                Instruction::Hash(length) if length % 2 != 0 => {
+                    return Err(Error::OddHashLength {
+                        location: location(),
+                        length: length as usize,
+                    })
+                }
+                Instruction::ReturnModule if code.kind != Kind::Module => {
+                    return Err(Error::ReturnModuleOutsideModule {
+                        location: location(),
+                    })
+                }
+                _ => (),
+            }
+        }
+
+        check_stack(code)?;
+
+        for (index, num_free) in functions {
+            self.verify_function(index, num_free, code)?;
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the function of a closure created in the parent code.
+    fn verify_function(&mut self, index: usize, num_free: usize, parent: &Code) -> Result<()> {
+        if !self.verified.insert((index, parent.module, num_free)) {
+            return Ok(());
+        }
+
+        let constants = self.bytecode.constants;
+        let Object::CompiledFunction(function) = &constants[index] else {
+            unreachable!("closure of constant {} is not a function", index);
+        };
+
+        let code = Code {
+            name: function
+                .name
+                .as_ref()
+                .map_or(trace::ANONYMOUS_NAME.to_string(), |name| name.to_string()),
+            instructions: &function.instructions,
+            kind: Kind::Function,
+            module: parent.module,
+            num_globals: parent.num_globals,
+            num_locals: function.num_locals,
+            num_free,
+        };
+
+        // The virtual machine allocates the locals when the function is called.
+        if function.num_locals > MAX_LOCALS {
+            return Err(Error::TooManyLocals {
+                location: code.location(0),
+                num_locals: function.num_locals,
+            });
+        }
+        if function.num_arguments > MAX_ARGUMENTS {
+            return Err(Error::TooManyArguments {
+                location: code.location(0),
+                num_arguments: function.num_arguments,
+            });
+        }
+        if function.num_arguments > function.num_locals {
+            return Err(Error::TooFewLocals {
+                location: code.location(0),
+                num_arguments: function.num_arguments,
+                num_locals: function.num_locals,
+            });
+        }
+
+        self.verify_code(&code)
+    }
+}
+
+/// Follows all paths through the code and checks the depth of the stack
+/// above the locals. The instructions and jump targets have to be checked before.
+fn check_stack(code: &Code) -> Result<()> {
+    let len = code.instructions.len();
+    let mut depths = vec![None; len + 1];
+    let mut pending = vec![(0, 0)];
+
+    while let Some((offset, depth)) = pending.pop() {
+        match depths[offset] {
+            Some(expected) if expected != depth => {
+                return Err(Error::StackMismatch {
+                    location: code.location(offset),
+                    expected,
+                    found: depth,
+                })
+            }
+            Some(_) => continue,
+            None => depths[offset] = Some(depth),
+        }
+
+        // The main program stops at the end, but the other code has to return.
+        if offset == len {
+            if code.kind != Kind::Main {
+                return Err(Error::MissingReturn {
+                    location: code.location(offset),
+                });
+            }
+            continue;
+        }
+
+        let (instruction, size) = code
+            .instructions
+            .decode(offset)
+            .expect("decoded instruction");
+        let (pops, pushes) = stack_effect(instruction);
+        let Some(depth) = depth.checked_sub(pops) else {
+            return Err(Error::StackUnderflow {
+                location: code.location(offset),
+            });
+        };
+        let depth = depth + pushes;
+
+        match instruction {
+            Instruction::Jump(target) => pending.push((target as usize, depth)),
+            Instruction::JumpNotTruthy(target) => {
+                pending.push((target as usize, depth));
+                pending.push((offset + size, depth));
+            }
+            Instruction::ReturnValue | Instruction::ReturnModule => (),
+            _ => pending.push((offset + size, depth)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the number of values that the instruction pops from the stack
+/// and the number of values that it pushes, once execution continues
+/// with the next instruction of the same code.
+fn stack_effect(instruction: Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Constant(_)
+        | Instruction::Null
+        | Instruction::True
+        | Instruction::False
+        | Instruction::GetGlobal(_)
+        | Instruction::GetLocal(_)
+        | Instruction::GetBuiltin(_)
+        | Instruction::GetFree(_)
+        | Instruction::Import(_)
+        | Instruction::CurrentClosure => (0, 1),
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::BitAnd
+        | Instruction::BitOr
+        | Instruction::BitXor
+        | Instruction::ShiftLeft
+        | Instruction::ShiftRight
+        | Instruction::Equal
+        | Instruction::NotEqual
+        | Instruction::GreaterThan
+        | Instruction::LessThan
+        | Instruction::Index
+        | Instruction::Unquote => (2, 1),
+        Instruction::Minus | Instruction::Bang | Instruction::BitNot => (1, 1),
+        // The value stays on the stack unless it's returned.
+        Instruction::ReturnIfError => (1, 1),
+        Instruction::Pop
+        | Instruction::JumpNotTruthy(_)
+        | Instruction::SetGlobal(_)
+        | Instruction::SetLocal(_)
+        | Instruction::ReturnValue
+        | Instruction::ReturnModule
+        // The value is returned from `next`, the generator continues without it.
+        | Instruction::Yield => (1, 0),
+        Instruction::Jump(_) => (0, 0),
+        Instruction::Array(length) | Instruction::Hash(length) => (length as usize, 1),
+        Instruction::Call(num_args) | Instruction::TailCall(num_args) => {
+            (num_args as usize + 1, 1)
+        }
+        Instruction::Closure { free_variables, .. } => (free_variables as usize, 1),
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    code::{Bytecode, Instruction, Instructions, LineTable, Module},
+    compile::Compiler,
+    object::{CompiledFunction, Object},
+    parse::parse,
+    vm::GLOBALS_SIZE,
+};
+
+use super::{verify, Error, Location};
+
+fn bytecode<'a>(
+    instructions: Vec<Instruction>,
+    constants: &'a [Object],
+    modules: &'a [Module],
+) -> Bytecode<'a> {
+    Bytecode {
+        instructions: Rc::new(Instructions::new(&instructions)),
+        lines: Rc::new(LineTable::default()),
+        constants,
+        modules,
+        global_names: Rc::new(vec![]),
+    }
+}
+
+fn function(instructions: Vec<Instruction>, num_locals: usize, num_arguments: usize) -> Object {
+    Object::CompiledFunction(CompiledFunction {
+        instructions: Rc::new(Instructions::new(&instructions)),
+        num_locals,
+        num_arguments,
+        name: Some(Rc::new("f".to_string())),
+        ..Default::default()
+    })
+}
+
+fn location(code: &str, offset: usize) -> Location {
+    Location {
+        code: code.to_string(),
+        offset,
+    }
+}
+
+#[test]
+fn test_compiled_programs() {
+    let main_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/modules/main.monkey");
+
+    let tests = [
+        "let x = 1; if (x > 0) { x } else { -x }",
+        "let f = fn(a, b) { let c = a + b; if (c) { return c; } c * 2 }; f(1, 2)",
+        "let adder = fn(a) { fn(b) { a + b } }; adder(1)(2)",
+        "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(3)",
+        "let gen = fn() { yield 1; let x = 2; yield x; }; next(gen())",
+        r#"let f = fn(x) { let y = x?; y }; f(error("e"))"#,
+        r#"{"a": [1, 2], "b": fn() { puts(1) }}["a"][0]"#,
+        r#"import "lib/math.monkey" as math; math.double(21)"#,
+    ];
+
+    for input in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::with_path(&main_path);
+        let bytecode = compiler.compile(&program).unwrap();
+
+        assert_eq!(verify(&bytecode), Ok(()), "{}", input);
+    }
+}
+
+#[test]
+fn test_invalid_bytecode() {
+    let constants = [
+        Object::Integer(1),
+        function(
+            vec![Instruction::GetLocal(1), Instruction::ReturnValue],
+            1,
+            1,
+        ),
+        function(
+            vec![Instruction::GetFree(0), Instruction::ReturnValue],
+            0,
+            0,
+        ),
+        function(vec![Instruction::Null], 0, 0),
+        function(vec![Instruction::Null, Instruction::ReturnValue], 0, 1),
+    ];
+
+    let tests = [
+        (
+            vec![Instruction::Constant(5)],
+            Error::InvalidConstant {
+                location: location("<main>", 0),
+                index: 5,
+            },
+        ),
+        (
+            vec![Instruction::Closure {
+                constant_index: 0,
+                free_variables: 0,
+            }],
+            Error::NotAFunction {
+                location: location("<main>", 0),
+                index: 0,
+            },
+        ),
+        (
+            vec![
+                Instruction::Null,
+                Instruction::Pop,
+                Instruction::GetLocal(0),
+            ],
+            Error::InvalidLocal {
+                location: location("<main>", 2),
+                index: 0,
+            },
+        ),
+        (
+            vec![Instruction::Import(0)],
+            Error::InvalidModule {
+                location: location("<main>", 0),
+                index: 0,
+            },
+        ),
+        (
+            vec![Instruction::Jump(4)],
+            Error::InvalidJumpTarget {
+                location: location("<main>", 0),
+                target: 4,
+            },
+        ),
+        (
+            vec![Instruction::Null, Instruction::Jump(2)],
+            Error::InvalidJumpTarget {
+                location: location("<main>", 1),
+                target: 2,
+            },
+        ),
+        (
+            vec![Instruction::Null, Instruction::Hash(1)],
+            Error::OddHashLength {
+                location: location("<main>", 1),
+                length: 1,
+            },
+        ),
+        (
+            vec![Instruction::Null, Instruction::ReturnModule],
+            Error::ReturnModuleOutsideModule {
+                location: location("<main>", 1),
+            },
+        ),
+        (
+            vec![Instruction::Constant(0), Instruction::Add],
+            Error::StackUnderflow {
+                location: location("<main>", 3),
+            },
+        ),
+        (
+            vec![
+                Instruction::True,
+                Instruction::JumpNotTruthy(5),
+                Instruction::Null,
+                Instruction::Null,
+                Instruction::Pop,
+            ],
+            Error::StackMismatch {
+                location: location("<main>", 5),
+                expected: 1,
+                found: 0,
+            },
+        ),
+        (
+            vec![Instruction::Closure {
+                constant_index: 1,
+                free_variables: 0,
+            }],
+            Error::InvalidLocal {
+                location: location("f", 0),
+                index: 1,
+            },
+        ),
+        (
+            vec![Instruction::Closure {
+                constant_index: 2,
+                free_variables: 0,
+            }],
+            Error::InvalidFreeVariable {
+                location: location("f", 0),
+                index: 0,
+            },
+        ),
+        (
+            vec![Instruction::Closure {
+                constant_index: 3,
+                free_variables: 0,
+            }],
+            Error::MissingReturn {
+                location: location("f", 1),
+            },
+        ),
+        (
+            vec![Instruction::Closure {
+                constant_index: 4,
+                free_variables: 0,
+            }],
+            Error::TooFewLocals {
+                location: location("f", 0),
+                num_arguments: 1,
+                num_locals: 0,
+            },
+        ),
+    ];
+
+    for (instructions, expected) in tests {
+        assert_eq!(
+            verify(&bytecode(instructions, &constants, &[])),
+            Err(expected)
+        );
+    }
+}
+
+#[test]
+fn test_too_large_sizes() {
+    let constants = [
+        function(vec![Instruction::Null, Instruction::ReturnValue], 256, 255),
+        function(vec![Instruction::Null, Instruction::ReturnValue], 257, 0),
+        function(vec![Instruction::Null, Instruction::ReturnValue], 256, 256),
+    ];
+
+    let tests = [
+        (0, Ok(())),
+        (
+            1,
+            Err(Error::TooManyLocals {
+                location: location("f", 0),
+                num_locals: 257,
+            }),
+        ),
+        (
+            2,
+            Err(Error::TooManyArguments {
+                location: location("f", 0),
+                num_arguments: 256,
+            }),
+        ),
+    ];
+
+    for (constant_index, expected) in tests {
+        let instructions = vec![
+            Instruction::Closure {
+                constant_index,
+                free_variables: 0,
+            },
+            Instruction::Pop,
+        ];
+        assert_eq!(
+            verify(&bytecode(instructions, &constants, &[])),
+            expected,
+            "{}",
+            constant_index
+        );
+    }
+
+    let module = |num_globals| Module {
+        path: PathBuf::from("lib.monkey"),
+        instructions: Rc::new(Instructions::new(&[
+            Instruction::Null,
+            Instruction::ReturnModule,
+        ])),
+        lines: Rc::new(LineTable::default()),
+        num_globals,
+        global_names: Rc::new(vec![]),
+    };
+    let tests = [
+        (GLOBALS_SIZE, Ok(())),
+        (
+            GLOBALS_SIZE + 1,
+            Err(Error::TooManyGlobals {
+                location: location("<module lib.monkey>", 0),
+                num_globals: GLOBALS_SIZE + 1,
+            }),
+        ),
+    ];
+
+    for (num_globals, expected) in tests {
+        let modules = [module(num_globals)];
+        assert_eq!(
+            verify(&bytecode(
+                vec![Instruction::Import(0), Instruction::Pop],
+                &[],
+                &modules
+            )),
+            expected,
+            "{}",
+            num_globals
+        );
+    }
+}
+
+#[test]
+fn test_invalid_instructions() {
+    let tests = [
+        (vec![255], location("<main>", 0)),
+        (vec![11, 10, 0, 1], location("<main>", 2)),
+        (vec![27, 16], location("<main>", 0)),
+    ];
+
+    for (bytes, location) in tests {
+        let bytecode = Bytecode {
+            instructions: Rc::new(Instructions::from_bytes(bytes)),
+            ..bytecode(vec![], &[], &[])
+        };
+        assert_eq!(
+            verify(&bytecode),
+            Err(Error::InvalidInstruction { location })
+        );
+    }
+}
+
+#[test]
+fn test_invalid_module() {
+    let modules = [Module {
+        path: PathBuf::from("lib.monkey"),
+        instructions: Rc::new(Instructions::new(&[
+            Instruction::Null,
+            Instruction::SetGlobal(1),
+        ])),
+        lines: Rc::new(LineTable::default()),
+        num_globals: 1,
+        global_names: Rc::new(vec![]),
+    }];
+
+    assert_eq!(
+        verify(&bytecode(vec![Instruction::Import(0)], &[], &modules)),
+        Err(Error::InvalidGlobal {
+            location: location("<module lib.monkey>", 1),
+            index: 1,
+        })
+    );
+}
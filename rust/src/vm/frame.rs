@@ -5,6 +5,8 @@ pub struct Frame {
     pub closure: object::Closure,
     pub ip: usize,
     pub base_pointer: usize,
+    /// Generator, which is resumed in this frame.
+    pub generator: Option<object::Generator>,
 }
 
 impl Frame {
@@ -13,6 +15,7 @@ impl Frame {
             closure,
             ip: 0,
             base_pointer,
+            generator: None,
         }
     }
 }
@@ -3,22 +3,26 @@
 #[cfg(test)]
 mod test;
 
-pub mod error;
-
 mod frame;
 
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::{convert::Infallible, rc::Rc};
+
+use indexmap::IndexMap;
 
-use crate::code::{Bytecode, Instruction};
-use crate::object::{self, DataType, HashKey, Object};
-pub use error::*;
+use crate::ast::{self, modify};
+use crate::code::{opcode, Bytecode, Instruction, Module};
+use crate::object::integer::Integer;
+use crate::object::{self, builtin, DataType, Generator, GeneratorState, HashKey, Object};
+use crate::runtime::{self, trace, StackFrame, StackTrace, DEFAULT_MAX_DEPTH};
+pub use crate::runtime::{Error, Result};
+use crate::verify;
 
 use self::frame::Frame;
 
+// Initial size of the stack, which grows when it's full.
 const STACK_SIZE: usize = 2048;
-const GLOBALS_SIZE: usize = u16::MAX as usize;
-const FRAME_STACK_SIZE: usize = 1024;
+/// Number of globals of the main program.
+pub const GLOBALS_SIZE: usize = u16::MAX as usize + 1;
 
 /// Virtual machine that can run the bytecode
 #[derive(Debug)]
@@ -28,10 +32,16 @@ pub struct VirtualMachine {
     // Top of the stack is stack[sp-1]
     sp: usize,
 
-    globals: Vec<Object>,
+    // Globals of the main program and of every imported module.
+    // See `object::Closure::module` for indexing. Globals are `None`
+    // until they are set, because their slots are never reused.
+    globals: Vec<Vec<Option<Object>>>,
+    // Cached exports of imported modules.
+    module_exports: Vec<Option<Object>>,
 
-    frames: Vec<Option<Frame>>,
-    frame_index: usize,
+    frames: Vec<Frame>,
+    // Maximum number of nested calls, see `set_max_depth`.
+    max_depth: usize,
 }
 
 impl VirtualMachine {
@@ -39,9 +49,10 @@ impl VirtualMachine {
         Self {
             stack: vec![],
             sp: 0,
-            globals: vec![Object::Null; GLOBALS_SIZE],
-            frames: vec![None; FRAME_STACK_SIZE],
-            frame_index: 0,
+            globals: vec![vec![None; GLOBALS_SIZE]],
+            module_exports: vec![],
+            frames: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
 }
@@ -65,102 +76,225 @@ impl VirtualMachine {
         &self.stack[self.sp]
     }
 
+    /// Sets the maximum number of nested function calls. Calls above
+    /// the limit fail with `RecursionLimit` instead of exhausting memory.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    #[inline(always)]
     fn push(&mut self, obj: Object) -> Result<()> {
-        if self.sp >= self.stack.len() {
-            return Err(Error::StackOverflow);
+        self.set_sp(self.sp + 1);
+        self.stack[self.sp - 1] = obj;
+        Ok(())
+    }
+
+    /// Moves the stack pointer and grows the stack if it's too small.
+    #[inline(always)]
+    fn set_sp(&mut self, sp: usize) {
+        if sp > self.stack.len() {
+            let len = sp.max(self.stack.len() * 2);
+            self.stack.resize(len, Object::Null);
         }
 
-        self.stack[self.sp] = obj;
-        self.sp += 1;
-        Ok(())
+        self.sp = sp;
     }
 
+    #[inline(always)]
     fn pop(&mut self) -> Object {
         self.sp -= 1;
         self.stack[self.sp].clone()
     }
 
+    #[inline(always)]
     fn current_frame_mut(&mut self) -> &mut Frame {
-        let frame = &mut self.frames[self.frame_index - 1];
-        frame.as_mut().expect("Invalid frame index")
+        self.frames.last_mut().expect("Invalid frame index")
     }
 
+    #[inline(always)]
     fn current_frame(&self) -> &Frame {
-        let frame = &self.frames[self.frame_index - 1];
-        frame.as_ref().expect("Invalid frame index")
+        self.frames.last().expect("Invalid frame index")
+    }
+
+    /// Returns the opcode at the instruction pointer of the current frame
+    /// and moves the pointer to its operands, which are read with
+    /// `read_u8` and `read_u16`. Returns `None` at the end of the code.
+    ///
+    /// The instruction pointer is moved before the instruction is executed,
+    /// so that calls and jumps only have to set it.
+    #[inline(always)]
+    fn fetch(&mut self) -> Option<u8> {
+        let frame = self.current_frame_mut();
+        let op = *frame
+            .closure
+            .function
+            .instructions
+            .as_bytes()
+            .get(frame.ip)?;
+        frame.ip += 1;
+        Some(op)
+    }
+
+    /// Reads a one byte operand of the current instruction.
+    #[inline(always)]
+    fn read_u8(&mut self) -> usize {
+        let frame = self.current_frame_mut();
+        let operand = frame.closure.function.instructions.as_bytes()[frame.ip];
+        frame.ip += 1;
+        operand as usize
+    }
+
+    /// Reads a two byte operand of the current instruction.
+    #[inline(always)]
+    fn read_u16(&mut self) -> usize {
+        let frame = self.current_frame_mut();
+        let bytes = frame.closure.function.instructions.as_bytes();
+        let operand = u16::from_le_bytes([bytes[frame.ip], bytes[frame.ip + 1]]);
+        frame.ip += 2;
+        operand as usize
     }
 
-    fn push_frame(&mut self, frame: Frame) {
-        self.frames[self.frame_index] = Some(frame);
-        self.frame_index += 1;
+    /// Pushes the frame of a called function. The frame of the main
+    /// program doesn't count towards the maximum depth.
+    fn push_frame(&mut self, frame: Frame) -> Result<()> {
+        if self.frames.len() > self.max_depth {
+            return Err(Error::RecursionLimit {
+                depth: self.max_depth,
+            });
+        }
+
+        self.frames.push(frame);
+        Ok(())
     }
 
     fn pop_frame(&mut self) -> Frame {
-        self.frame_index -= 1;
+        self.frames.pop().expect("Invalid frame index")
+    }
 
-        let mut frame = None;
-        std::mem::swap(&mut frame, &mut self.frames[self.frame_index]);
-        frame.expect("Invalid frame index")
+    /// Returns the calls that were active when `run` failed.
+    pub fn stack_trace(&self) -> StackTrace {
+        let frames = self.frames.iter().rev().map(|frame| {
+            // The instruction pointer is already past the executed instruction.
+            let function = &frame.closure.function;
+            StackFrame::new(
+                function.name.as_ref().map(|name| name.as_str()),
+                function.lines.line(frame.ip.saturating_sub(1)),
+            )
+        });
+
+        StackTrace(frames.collect())
     }
 
-    /// Runs the bytecode.
+    /// Verifies and runs the bytecode.
     ///
     /// The stack of the VM is cleaned, but the globals are left
     /// unchanged. If you don't want to keep the globals between runs,
     /// initialize a new VirtualMachine.
     pub fn run(&mut self, bytecode: &Bytecode) -> Result<()> {
+        self.frames = vec![];
+        verify::verify(bytecode)?;
+
         // Reinitialize the stack
         self.stack = vec![Object::Null; STACK_SIZE];
         self.sp = 0;
 
+        // Make space for the newly compiled modules
+        self.globals.resize(bytecode.modules.len() + 1, vec![]);
+        self.module_exports.resize(bytecode.modules.len(), None);
+
         // Reinitialize the frame stack
         let main_closure = object::Closure {
             function: object::CompiledFunction {
                 instructions: bytecode.instructions.clone(),
-                num_locals: 0,
-                num_arguments: 0,
+                name: Some(Rc::new(trace::MAIN_NAME.to_owned())),
+                lines: bytecode.lines.clone(),
+                ..Default::default()
             },
             free: Rc::new(vec![]),
+            module: 0,
         };
-        self.frames = vec![None; FRAME_STACK_SIZE];
-        self.frames[0] = Some(Frame::new(main_closure, 0));
-        self.frame_index = 1;
-
-        while self.current_frame().ip < self.current_frame().closure.function.instructions.len() {
-            let inst = &self.current_frame().closure.function.instructions[self.current_frame().ip];
-            match inst {
-                Instruction::Constant(idx) => {
-                    self.push(bytecode.constants[*idx as usize].clone())?
+        self.frames = vec![Frame::new(main_closure, 0)];
+
+        let res = self.execute(bytecode);
+        if res.is_err() {
+            // Generators, which were running when the error occurred,
+            // can't be resumed, because the next run discards their frames.
+            for frame in &self.frames {
+                if let Some(generator) = &frame.generator {
+                    generator.replace(GeneratorState::Done);
                 }
-                Instruction::Add | Instruction::Mul | Instruction::Sub | Instruction::Div => {
-                    self.execute_binary_operation(*inst)?;
-                }
-                Instruction::Equal | Instruction::NotEqual | Instruction::GreaterThan => {
-                    self.execute_comparison(*inst)?;
+            }
+        }
+
+        res
+    }
+
+    /// Executes the instructions until the main program returns.
+    fn execute(&mut self, bytecode: &Bytecode) -> Result<()> {
+        // The bytecode is verified, so the opcodes and operands are read without
+        // decoding them into an `Instruction`, which makes the dispatch faster.
+        while let Some(op) = self.fetch() {
+            match op {
+                opcode::CONSTANT => {
+                    let idx = self.read_u16();
+                    self.push(bytecode.constants[idx].clone())?
                 }
-                Instruction::True => self.push(Object::Boolean(true))?,
-                Instruction::False => self.push(Object::Boolean(false))?,
-                Instruction::Null => self.push(Object::Null)?,
-                Instruction::Pop => {
+                opcode::ADD => self.execute_binary_operation(Instruction::Add)?,
+                opcode::SUB => self.execute_binary_operation(Instruction::Sub)?,
+                opcode::MUL => self.execute_binary_operation(Instruction::Mul)?,
+                opcode::DIV => self.execute_binary_operation(Instruction::Div)?,
+                opcode::BIT_AND => self.execute_binary_operation(Instruction::BitAnd)?,
+                opcode::BIT_OR => self.execute_binary_operation(Instruction::BitOr)?,
+                opcode::BIT_XOR => self.execute_binary_operation(Instruction::BitXor)?,
+                opcode::SHIFT_LEFT => self.execute_binary_operation(Instruction::ShiftLeft)?,
+                opcode::SHIFT_RIGHT => self.execute_binary_operation(Instruction::ShiftRight)?,
+                opcode::EQUAL => self.execute_binary_operation(Instruction::Equal)?,
+                opcode::NOT_EQUAL => self.execute_binary_operation(Instruction::NotEqual)?,
+                opcode::GREATER_THAN => self.execute_binary_operation(Instruction::GreaterThan)?,
+                opcode::LESS_THAN => self.execute_binary_operation(Instruction::LessThan)?,
+                opcode::TRUE => self.push(Object::Boolean(true))?,
+                opcode::FALSE => self.push(Object::Boolean(false))?,
+                opcode::NULL => self.push(Object::Null)?,
+                opcode::POP => {
                     self.pop();
                 }
-                Instruction::Bang => self.execute_bang_operator()?,
-                Instruction::Minus => self.execute_minus_operator()?,
-                Instruction::JumpNotTruthy(pos) => {
-                    let pos = *pos;
+                opcode::BANG => self.execute_prefix_operation(Instruction::Bang)?,
+                opcode::MINUS => self.execute_prefix_operation(Instruction::Minus)?,
+                opcode::BIT_NOT => self.execute_prefix_operation(Instruction::BitNot)?,
+                opcode::JUMP_NOT_TRUTHY => {
+                    let target = self.read_u16();
                     let condition = self.pop();
                     if !condition.is_truthy() {
-                        self.current_frame_mut().ip = pos as usize - 1;
+                        self.current_frame_mut().ip = target;
                     }
                 }
-                Instruction::Jump(pos) => self.current_frame_mut().ip = *pos as usize - 1,
-                Instruction::GetGlobal(idx) => self.push(self.globals[*idx as usize].clone())?,
-                Instruction::SetGlobal(idx) => {
-                    let idx = *idx;
-                    self.globals[idx as usize] = self.pop();
+                opcode::JUMP => {
+                    let target = self.read_u16();
+                    self.current_frame_mut().ip = target;
+                }
+                opcode::GET_GLOBAL => {
+                    let idx = self.read_u16();
+                    let module = self.current_frame().closure.module;
+                    // The global is read in its own definition, like `let a = a + 1`.
+                    let Some(value) = self.globals[module][idx].clone() else {
+                        let names = match module {
+                            0 => &bytecode.global_names,
+                            module => &bytecode.modules[module - 1].global_names,
+                        };
+                        let name = names.get(idx).cloned();
+                        return Err(Error::UnknownIdentifier(
+                            name.unwrap_or_else(|| format!("global {}", idx)),
+                        ));
+                    };
+                    self.push(value)?
+                }
+                opcode::SET_GLOBAL => {
+                    let idx = self.read_u16();
+                    let module = self.current_frame().closure.module;
+                    self.globals[module][idx] = Some(self.pop());
                 }
-                Instruction::Array(len) => {
-                    let length = *len as usize;
+                opcode::ARRAY => {
+                    let length = self.read_u16();
                     let start = self.sp - length;
 
                     let arr = self.stack[start..self.sp].to_vec();
@@ -168,71 +302,122 @@ impl VirtualMachine {
                     self.sp -= length;
                     self.push(Object::Array(Rc::new(arr)))?;
                 }
-                Instruction::Hash(len) => {
-                    let length = *len as usize;
+                opcode::HASH => {
+                    let length = self.read_u16();
 
                     let hash_map = self.build_hash_map(length)?;
 
                     self.sp -= length;
                     self.push(hash_map)?;
                 }
-                Instruction::Index => self.execute_index_expression()?,
-                Instruction::Call(num_args) => {
-                    self.execute_call(*num_args as usize)?;
-
-                    // Continue so that we don't increment the instruction
-                    // pointer of the new frame.
-                    continue;
+                opcode::INDEX => self.execute_index_expression()?,
+                opcode::CALL => {
+                    let num_args = self.read_u8();
+                    self.execute_call(num_args)?
+                }
+                opcode::TAIL_CALL => {
+                    let num_args = self.read_u8();
+                    self.execute_tail_call(num_args)?
                 }
-                Instruction::ReturnValue => {
+                opcode::RETURN_VALUE => {
                     let return_value = self.pop();
 
+                    // Return from the main program stops the execution.
+                    // The value stays on the stack as the last popped value.
+                    if self.frames.len() == 1 {
+                        return Ok(());
+                    }
+
+                    self.return_from_frame(return_value)?;
+                }
+                opcode::RETURN_IF_ERROR => {
+                    if matches!(self.stack_top(), Some(Object::Error(_))) {
+                        let return_value = self.pop();
+
+                        // Error propagated out of the main program stops the execution.
+                        // The error stays on the stack as the last popped value.
+                        if self.frames.len() == 1 {
+                            return Ok(());
+                        }
+
+                        self.return_from_frame(return_value)?;
+                    }
+                }
+                opcode::YIELD => {
+                    let value = self.pop();
+                    self.execute_yield(value)?;
+                }
+                opcode::IMPORT => {
+                    let idx = self.read_u16();
+                    if let Some(exports) = &self.module_exports[idx] {
+                        self.push(exports.clone())?;
+                    } else {
+                        self.execute_module(&bytecode.modules[idx], idx)?;
+                    }
+                }
+                opcode::RETURN_MODULE => {
+                    let exports = self.pop();
+
                     let frame = self.pop_frame();
-                    self.sp = frame.base_pointer - 1; // Substract 1 to remove the function object from the stack
+                    self.module_exports[frame.closure.module - 1] = Some(exports.clone());
+                    self.sp = frame.base_pointer - 1;
 
-                    self.push(return_value)?;
+                    self.push(exports)?;
                 }
-                Instruction::SetLocal(idx) => {
-                    let frame = self.current_frame();
-                    let idx = frame.base_pointer + (*idx as usize);
+                opcode::SET_LOCAL => {
+                    let idx = self.current_frame().base_pointer + self.read_u8();
                     self.stack[idx] = self.pop();
                 }
-                Instruction::GetLocal(idx) => {
-                    let frame = self.current_frame();
-                    let idx = frame.base_pointer + (*idx as usize);
+                opcode::GET_LOCAL => {
+                    let idx = self.current_frame().base_pointer + self.read_u8();
                     self.push(self.stack[idx].clone())?;
                 }
-                Instruction::GetBuiltin(bltin) => self.push(Object::Builtin(*bltin))?,
-                Instruction::Closure {
-                    constant_index,
-                    free_variables,
-                } => {
-                    let idx = *constant_index as usize;
+                opcode::GET_BUILTIN => {
+                    let builtin = builtin::BuiltinFunction::ALL[self.read_u8()];
+                    self.push(Object::Builtin(builtin))?
+                }
+                opcode::CLOSURE => {
+                    let idx = self.read_u16();
+                    let free_variables = self.read_u8();
                     let Object::CompiledFunction(fun) = &bytecode.constants[idx] else {
                         return Err(Error::NotAFunction((&bytecode.constants[idx]).into()));
                     };
 
-                    let start = self.sp - *free_variables as usize;
+                    let start = self.sp - free_variables;
                     let free = self.stack[start..self.sp].to_owned();
                     self.sp = start;
 
                     let closure = Object::Closure(object::Closure {
                         function: fun.clone(),
                         free: Rc::new(free),
+                        module: self.current_frame().closure.module,
                     });
                     self.push(closure)?;
                 }
-                Instruction::GetFree(idx) => {
-                    let obj = self.current_frame().closure.free[*idx as usize].clone();
+                opcode::GET_FREE => {
+                    let idx = self.read_u8();
+                    let obj = self.current_frame().closure.free[idx].clone();
                     self.push(obj)?;
                 }
-                Instruction::CurrentClosure => {
+                opcode::CURRENT_CLOSURE => {
                     let current_closure = self.current_frame().closure.clone();
                     self.push(Object::Closure(current_closure))?;
                 }
+                opcode::UNQUOTE => {
+                    let value = runtime::unquote(self.pop())?;
+                    let quote = match self.pop() {
+                        Object::Quote(quote) => replace_unquote(&quote, value),
+                        obj => {
+                            return Err(Error::TypeMismatch(format!(
+                                "unquote in {}",
+                                DataType::from(&obj)
+                            )))
+                        }
+                    };
+                    self.push(Object::Quote(Rc::new(quote)))?;
+                }
+                _ => unreachable!("invalid opcode {} in verified bytecode", op),
             }
-
-            self.current_frame_mut().ip += 1
         }
 
         Ok(())
@@ -242,116 +427,42 @@ impl VirtualMachine {
         let right = self.pop();
         let left = self.pop();
 
+        // Operations on small integers, which don't overflow, are the most
+        // common ones and are computed without the general dispatch.
         if let (Object::Integer(left), Object::Integer(right)) = (&left, &right) {
-            return self.execute_binary_integer_operation(instruction, *left, *right);
-        };
-
-        if let (Object::String(left), Object::String(right)) = (&left, &right) {
-            return self.execute_binary_string_operation(instruction, left, right);
-        }
-
-        Err(Error::UnknownBinaryOperator(
-            instruction,
-            left.into(),
-            right.into(),
-        ))
-    }
-
-    fn execute_binary_integer_operation(
-        &mut self,
-        operation: Instruction,
-        left: i64,
-        right: i64,
-    ) -> Result<()> {
-        let res = match operation {
-            Instruction::Add => left + right,
-            Instruction::Sub => left - right,
-            Instruction::Mul => left * right,
-            Instruction::Div => left / right,
-            _ => unreachable!(),
-        };
-
-        self.push(Object::Integer(res))
-    }
-
-    fn execute_binary_string_operation(
-        &mut self,
-        operation: Instruction,
-        left: &str,
-        right: &str,
-    ) -> Result<()> {
-        if operation != Instruction::Add {
-            return Err(Error::UnknownBinaryOperator(
-                Instruction::Add,
-                DataType::String,
-                DataType::String,
-            ));
-        }
-
-        let res = String::from(left) + right;
-        self.push(Object::String(Rc::new(res)))
-    }
-
-    fn execute_comparison(&mut self, instruction: Instruction) -> Result<()> {
-        let right = self.pop();
-        let left = self.pop();
-
-        if let (Object::Integer(left), Object::Integer(right)) = (&left, &right) {
-            return self.execute_integer_comparison(instruction, *left, *right);
-        }
-
-        match instruction {
-            Instruction::Equal => self.push(Object::Boolean(left == right)),
-            Instruction::NotEqual => self.push(Object::Boolean(left != right)),
-            _ => Err(Error::UnknownBinaryOperator(
-                instruction,
-                left.into(),
-                right.into(),
-            )),
+            let result = match instruction {
+                Instruction::Add => left.checked_add(*right).map(Object::Integer),
+                Instruction::Sub => left.checked_sub(*right).map(Object::Integer),
+                Instruction::Equal => Some(Object::Boolean(left == right)),
+                Instruction::NotEqual => Some(Object::Boolean(left != right)),
+                Instruction::GreaterThan => Some(Object::Boolean(left > right)),
+                Instruction::LessThan => Some(Object::Boolean(left < right)),
+                _ => None,
+            };
+            if let Some(result) = result {
+                return self.push(result);
+            }
         }
-    }
 
-    fn execute_integer_comparison(
-        &mut self,
-        operation: Instruction,
-        left: i64,
-        right: i64,
-    ) -> Result<()> {
-        let res = match operation {
-            Instruction::Equal => left == right,
-            Instruction::NotEqual => left != right,
-            Instruction::GreaterThan => left > right,
-            _ => unreachable!(),
-        };
-
-        self.push(Object::Boolean(res))
+        self.push(binary_operation(instruction, &left, &right)?)
     }
 
-    fn execute_bang_operator(&mut self) -> Result<()> {
+    fn execute_prefix_operation(&mut self, instruction: Instruction) -> Result<()> {
         let operand = self.pop();
-        self.push(Object::Boolean(!operand.is_truthy()))
-    }
 
-    fn execute_minus_operator(&mut self) -> Result<()> {
-        let operand = self.pop();
-
-        let Object::Integer(value) = operand else {
-            return Err(Error::UnsupportedNegationType(operand.into()));
-        };
-
-        self.push(Object::Integer(-value))
+        self.push(prefix_operation(instruction, &operand)?)
     }
 
     fn build_hash_map(&self, length: usize) -> Result<Object> {
         let start = self.sp - length;
 
-        let hash_map: Result<HashMap<_, _>> = self.stack[start..self.sp]
+        let hash_map: Result<IndexMap<_, _>> = self.stack[start..self.sp]
             .chunks(2)
             .map(|chunk| -> Result<(HashKey, Object)> {
                 let key = &chunk[0];
                 let value = &chunk[1];
 
-                let key: HashKey = key.clone().try_into().map_err(Error::UnhashableKey)?;
+                let key: HashKey = key.clone().try_into().map_err(Error::NotHashable)?;
 
                 Ok((key, value.clone()))
             })
@@ -366,6 +477,7 @@ impl VirtualMachine {
 
         match left {
             Object::Array(arr) => self.execute_array_index(&arr, index),
+            Object::Bytes(bytes) => self.execute_bytes_index(&bytes, index),
             Object::HashMap(hash) => self.execute_hash_index(&hash, index),
             _ => Err(Error::IndexOperatorNotSupported(left.into(), index.into())),
         }
@@ -392,8 +504,24 @@ impl VirtualMachine {
         Ok(())
     }
 
-    fn execute_hash_index(&mut self, hash: &HashMap<HashKey, Object>, index: Object) -> Result<()> {
-        let key: HashKey = index.try_into().map_err(Error::UnhashableKey)?;
+    fn execute_bytes_index(&mut self, bytes: &[u8], index: Object) -> Result<()> {
+        let Object::Integer(idx) = index else {
+            return Err(Error::IndexOperatorNotSupported(
+                DataType::Bytes,
+                index.into(),
+            ));
+        };
+
+        let byte = usize::try_from(idx).ok().and_then(|idx| bytes.get(idx));
+        self.push(byte.map_or(Object::Null, |byte| Object::Integer(*byte as i64)))
+    }
+
+    fn execute_hash_index(
+        &mut self,
+        hash: &IndexMap<HashKey, Object>,
+        index: Object,
+    ) -> Result<()> {
+        let key: HashKey = index.try_into().map_err(Error::NotHashable)?;
 
         let obj = hash.get(&key).unwrap_or(&Object::Null);
         self.push(obj.clone())?;
@@ -401,23 +529,93 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// Calls the closure by replacing the current frame, so that tail
+    /// recursion runs with constant frame and stack usage. Builtins and calls
+    /// from the main program are executed as regular calls.
+    fn execute_tail_call(&mut self, num_args: usize) -> Result<()> {
+        let callee_idx = self.sp - num_args - 1;
+        let Object::Closure(closure) = &self.stack[callee_idx] else {
+            return self.execute_call(num_args);
+        };
+
+        // Generators keep their own frame, so calls from and to
+        // generator functions are regular calls.
+        if self.frames.len() == 1
+            || closure.function.generator
+            || self.current_frame().generator.is_some()
+        {
+            return self.execute_call(num_args);
+        }
+
+        if num_args != closure.function.num_arguments {
+            return Err(Error::WrongNumberOfArguments {
+                expected: closure.function.num_arguments,
+                got: num_args,
+            });
+        }
+
+        let closure = closure.clone();
+        let base_pointer = self.current_frame().base_pointer;
+
+        // Move the callee and the arguments in place of the current function.
+        for offset in 0..=num_args {
+            self.stack
+                .swap(base_pointer - 1 + offset, callee_idx + offset);
+        }
+
+        self.set_sp(base_pointer + closure.function.num_locals);
+        *self.current_frame_mut() = Frame::new(closure, base_pointer);
+
+        Ok(())
+    }
+
+    /// Pushes a frame that initializes the module globals
+    /// and returns the module exports.
+    fn execute_module(&mut self, module: &Module, index: usize) -> Result<()> {
+        let closure = object::Closure {
+            function: object::CompiledFunction {
+                instructions: module.instructions.clone(),
+                name: Some(Rc::new(trace::module_name(&module.path))),
+                lines: module.lines.clone(),
+                ..Default::default()
+            },
+            free: Rc::new(vec![]),
+            module: index + 1,
+        };
+        self.globals[index + 1] = vec![None; module.num_globals];
+
+        self.push(Object::Closure(closure.clone()))?;
+        self.push_frame(Frame::new(closure, self.sp))
+    }
+
     fn execute_call(&mut self, num_args: usize) -> Result<()> {
         match &self.stack[self.sp - num_args - 1] {
             Object::Closure(closure) => {
                 if num_args != closure.function.num_arguments {
-                    println!("{:?}", &self.stack[0..30]);
                     return Err(Error::WrongNumberOfArguments {
-                        want: closure.function.num_arguments,
+                        expected: closure.function.num_arguments,
                         got: num_args,
                     });
                 }
 
+                if closure.function.generator {
+                    return self.create_generator(closure.clone(), num_args);
+                }
+
                 let frame = Frame::new(closure.clone(), self.sp - num_args);
-                self.sp = frame.base_pointer + closure.function.num_locals;
-                self.push_frame(frame);
+                let sp = frame.base_pointer + closure.function.num_locals;
+                self.push_frame(frame)?;
+                self.set_sp(sp);
 
                 Ok(())
             }
+            Object::Builtin(builtin::BuiltinFunction::Next) => {
+                let args = &self.stack[(self.sp - num_args)..self.sp];
+                let generator = builtin::next_argument(args)?.clone();
+
+                self.sp = self.sp - num_args - 1;
+                self.resume_generator(generator)
+            }
             Object::Builtin(fun) => {
                 let args = &self.stack[(self.sp - num_args)..self.sp];
                 let result = fun.execute(args)?;
@@ -425,11 +623,232 @@ impl VirtualMachine {
                 self.sp = self.sp - num_args - 1;
 
                 self.push(result)?;
-                self.current_frame_mut().ip += 1;
 
                 Ok(())
             }
-            obj => Err(Error::NotCallable(obj.into())),
+            obj => Err(Error::NotAFunction(obj.into())),
+        }
+    }
+
+    /// Pops the current frame and pushes the return value for the caller.
+    fn return_from_frame(&mut self, return_value: Object) -> Result<()> {
+        let frame = self.pop_frame();
+        self.sp = frame.base_pointer - 1; // Substract 1 to remove the function object from the stack
+
+        if let Some(generator) = frame.generator {
+            return self.finish_generator(generator, return_value);
+        }
+
+        self.push(return_value)?;
+
+        Ok(())
+    }
+
+    /// Creates a generator with the arguments as the initial locals,
+    /// without executing the function body.
+    fn create_generator(&mut self, closure: object::Closure, num_args: usize) -> Result<()> {
+        let mut stack = self.stack[(self.sp - num_args)..self.sp].to_vec();
+        stack.resize(closure.function.num_locals, Object::Null);
+
+        let generator = Generator::new(GeneratorState::Compiled {
+            closure,
+            ip: 0,
+            stack,
+        });
+
+        self.sp = self.sp - num_args - 1;
+        self.push(Object::Generator(generator))?;
+
+        Ok(())
+    }
+
+    /// Pushes the suspended frame of the generator together with its
+    /// locals. The value returned from the frame is put on top of the stack.
+    fn resume_generator(&mut self, generator: Generator) -> Result<()> {
+        match generator.replace(GeneratorState::Running) {
+            GeneratorState::Compiled { closure, ip, stack } => {
+                let base_pointer = self.sp + 1;
+                let frame = Frame {
+                    closure,
+                    ip,
+                    base_pointer,
+                    generator: Some(generator.clone()),
+                };
+                if let Err(err) = self.push_frame(frame) {
+                    generator.replace(GeneratorState::Done);
+                    return Err(err);
+                }
+
+                self.set_sp(base_pointer + stack.len());
+                self.stack[base_pointer..self.sp].clone_from_slice(&stack);
+
+                Ok(())
+            }
+            GeneratorState::Done => {
+                generator.replace(GeneratorState::Done);
+
+                self.push(Object::Done)?;
+
+                Ok(())
+            }
+            GeneratorState::Running => Err(Error::GeneratorRunning),
+            GeneratorState::Evaluated { .. } => {
+                unreachable!("generator of the evaluator resumed by the VM")
+            }
+        }
+    }
+
+    /// Saves the current frame into its generator and returns
+    /// the yielded value from `next`.
+    fn execute_yield(&mut self, value: Object) -> Result<()> {
+        let frame = self.pop_frame();
+        let Some(generator) = frame.generator else {
+            return Err(Error::YieldOutsideGenerator);
+        };
+
+        generator.replace(GeneratorState::Compiled {
+            closure: frame.closure,
+            ip: frame.ip,
+            stack: self.stack[frame.base_pointer..self.sp].to_vec(),
+        });
+
+        self.sp = frame.base_pointer - 1;
+        self.push(value)?;
+
+        Ok(())
+    }
+
+    /// Handles the value returned from the generator function. A returned
+    /// generator is continued in place of the finished one, an error is
+    /// returned from `next` and anything else finishes the generator.
+    fn finish_generator(&mut self, generator: Generator, return_value: Object) -> Result<()> {
+        match return_value {
+            Object::Generator(next) if next != generator => {
+                let state = next.replace(GeneratorState::Done);
+                generator.replace(state);
+
+                self.resume_generator(generator)
+            }
+            Object::Error(_) => {
+                generator.replace(GeneratorState::Done);
+
+                self.push(return_value)?;
+
+                Ok(())
+            }
+            _ => {
+                generator.replace(GeneratorState::Done);
+
+                self.push(Object::Done)?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Applies the binary operator or comparison of the instruction to the operands.
+/// It's also used by the optimizer to fold constant operands.
+pub(crate) fn binary_operation(
+    instruction: Instruction,
+    left: &Object,
+    right: &Object,
+) -> Result<Object> {
+    if let (Some(left), Some(right)) = (Integer::from_object(left), Integer::from_object(right)) {
+        return integer_operation(instruction, left, right);
+    };
+
+    match (instruction, left, right) {
+        (Instruction::Add, Object::String(left), Object::String(right)) => {
+            Ok(Object::String(Rc::new(format!("{}{}", left, right))))
         }
+        (Instruction::Add, Object::Bytes(left), Object::Bytes(right)) => {
+            Ok(Object::Bytes(Rc::new([left.as_slice(), right].concat())))
+        }
+        (Instruction::Equal, left, right) => Ok(Object::Boolean(left == right)),
+        (Instruction::NotEqual, left, right) => Ok(Object::Boolean(left != right)),
+        (instruction, left, right) => Err(Error::unsupported_operands(
+            operator_symbol(instruction),
+            left.into(),
+            right.into(),
+        )),
     }
 }
+
+fn integer_operation(operation: Instruction, left: Integer, right: Integer) -> Result<Object> {
+    let res = match operation {
+        Instruction::Add => left.plus(right),
+        Instruction::Sub => left.minus(right),
+        Instruction::Mul => left.times(right),
+        Instruction::Div => left.divide(right).ok_or(Error::DivisionByZero)?,
+        Instruction::BitAnd => left.bit_and(right),
+        Instruction::BitOr => left.bit_or(right),
+        Instruction::BitXor => left.bit_xor(right),
+        Instruction::ShiftLeft => left
+            .shift_left(right)
+            .ok_or_else(|| Error::InvalidShiftAmount(right.to_string()))?,
+        Instruction::ShiftRight => left
+            .shift_right(right)
+            .ok_or_else(|| Error::InvalidShiftAmount(right.to_string()))?,
+        Instruction::Equal => Object::Boolean(left.compare(right).is_eq()),
+        Instruction::NotEqual => Object::Boolean(left.compare(right).is_ne()),
+        Instruction::GreaterThan => Object::Boolean(left.compare(right).is_gt()),
+        Instruction::LessThan => Object::Boolean(left.compare(right).is_lt()),
+        _ => unreachable!("{:?} is not a binary operator", operation),
+    };
+
+    Ok(res)
+}
+
+/// Applies the prefix operator of the instruction to the operand.
+pub(crate) fn prefix_operation(instruction: Instruction, operand: &Object) -> Result<Object> {
+    let symbol = match instruction {
+        Instruction::Bang => return Ok(Object::Boolean(!operand.is_truthy())),
+        Instruction::Minus => "-",
+        Instruction::BitNot => "~",
+        _ => unreachable!("{:?} is not a prefix operator", instruction),
+    };
+
+    let Some(value) = Integer::from_object(operand) else {
+        return Err(Error::unsupported_operand(symbol, operand.into()));
+    };
+
+    Ok(match instruction {
+        Instruction::Minus => value.negate(),
+        _ => value.bit_not(),
+    })
+}
+
+/// Returns the operator of the binary instruction, as it's written in the source.
+fn operator_symbol(instruction: Instruction) -> &'static str {
+    match instruction {
+        Instruction::Add => "+",
+        Instruction::Sub => "-",
+        Instruction::Mul => "*",
+        Instruction::Div => "/",
+        Instruction::BitAnd => "&",
+        Instruction::BitOr => "|",
+        Instruction::BitXor => "^",
+        Instruction::ShiftLeft => "<<",
+        Instruction::ShiftRight => ">>",
+        Instruction::Equal => "==",
+        Instruction::NotEqual => "!=",
+        Instruction::GreaterThan => ">",
+        Instruction::LessThan => "<",
+        _ => unreachable!("{:?} is not a binary operator", instruction),
+    }
+}
+
+/// Replaces the first `unquote` call in the order of the evaluation with the value.
+fn replace_unquote(quote: &ast::Expression, value: ast::Expression) -> ast::Expression {
+    let mut value = Some(value);
+    let Ok(res) = modify::modify_expression(quote.clone(), &mut |expr| {
+        let replacement = match expr.call_arguments("unquote") {
+            Some(_) => value.take(),
+            None => None,
+        };
+        Ok::<_, Infallible>(replacement.unwrap_or(expr))
+    });
+
+    res
+}
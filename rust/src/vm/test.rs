@@ -1,23 +1,36 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{path::Path, rc::Rc};
+
+use indexmap::IndexMap;
 
 use crate::{
+    code::{Bytecode, Instruction, Instructions, LineTable},
     compile::Compiler,
-    object::{builtin::ExecutionError, DataType, HashKey, Object},
+    evaluate::Evaluator,
+    object::{builtin::ExecutionError, DataType, ErrorObject, HashKey, Object},
     parse::parse,
+    verify,
 };
 
 use super::{Error, Result, VirtualMachine};
 
+// Every test case is run with and without the optimizer, which must not change
+// the results, and by the evaluator, which must give the same result.
 fn run_test_case(input: &str, expected: Object) -> Result<()> {
     let program = parse(input).unwrap();
 
-    let mut compiler = Compiler::new();
-    let bytecode = compiler.compile(&program).unwrap();
+    for optimize in [false, true] {
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(optimize);
+        let bytecode = compiler.compile(&program).unwrap();
 
-    let mut vm = VirtualMachine::new();
-    vm.run(&bytecode)?;
+        let mut vm = VirtualMachine::new();
+        vm.run(&bytecode)?;
 
-    assert_eq!(*vm.last_popped(), expected);
+        assert_eq!(*vm.last_popped(), expected, "optimize: {}", optimize);
+    }
+
+    let evaluated = Evaluator::new().evaluate(&program).map(|obj| obj.inspect());
+    assert_eq!(evaluated, Ok(expected.inspect()), "evaluator: {}", input);
 
     Ok(())
 }
@@ -25,13 +38,24 @@ fn run_test_case(input: &str, expected: Object) -> Result<()> {
 fn run_error_test_case(input: &str, expected: Error) {
     let program = parse(input).unwrap();
 
-    let mut compiler = Compiler::new();
-    let bytecode = compiler.compile(&program).unwrap();
+    for optimize in [false, true] {
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(optimize);
+        let bytecode = compiler.compile(&program).unwrap();
 
-    let mut vm = VirtualMachine::new();
-    let res = vm.run(&bytecode);
+        let mut vm = VirtualMachine::new();
+        let res = vm.run(&bytecode);
+
+        assert_eq!(res.as_ref(), Err(&expected), "optimize: {}", optimize);
+    }
 
-    assert_eq!(res, Err(expected));
+    let evaluated = Evaluator::new().evaluate(&program);
+    assert_eq!(
+        evaluated.as_ref().err(),
+        Some(&expected),
+        "evaluator: {}",
+        input
+    );
 }
 
 #[test]
@@ -62,6 +86,87 @@ fn test_integer_arithmetic() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_big_integers() -> Result<()> {
+    let big = |value: &str| Object::BigInteger(Rc::new(value.parse().unwrap()));
+
+    let tests = [
+        (
+            "let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; f(25)",
+            big("15511210043330985984000000"),
+        ),
+        ("9223372036854775807 + 1", big("9223372036854775808")),
+        ("9223372036854775808", big("9223372036854775808")),
+        ("-9223372036854775808", Object::Integer(i64::MIN)),
+        ("0x1_0000_0000_0000_0000 - 1", big("18446744073709551615")),
+        (
+            "18446744073709551616 / 4294967296",
+            Object::Integer(4294967296),
+        ),
+        ("9223372036854775807 + 1 - 1", Object::Integer(i64::MAX)),
+        ("-9223372036854775807 - 1", Object::Integer(i64::MIN)),
+        ("-(-9223372036854775807 - 1)", big("9223372036854775808")),
+        (
+            "(-9223372036854775807 - 1) / -1",
+            big("9223372036854775808"),
+        ),
+        (
+            "4611686018427387904 * 4 / 8",
+            Object::Integer(2305843009213693952),
+        ),
+        (
+            "9223372036854775807 * 2 > 9223372036854775807",
+            Object::Boolean(true),
+        ),
+        ("9223372036854775807 * 2 < 1", Object::Boolean(false)),
+        (
+            "9223372036854775807 * 2 == 9223372036854775807 + 9223372036854775807",
+            Object::Boolean(true),
+        ),
+        (
+            "{9223372036854775807 * 3: 1}[9223372036854775807 * 2 + 9223372036854775807]",
+            Object::Integer(1),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_test_case(input, expected)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bitwise_operators() -> Result<()> {
+    let tests = [
+        ("0b1100 & 0b1010", Object::Integer(0b1000)),
+        ("0b1100 | 0b1010", Object::Integer(0b1110)),
+        ("0b1100 ^ 0b1010", Object::Integer(0b0110)),
+        ("~0", Object::Integer(-1)),
+        ("~0xff & 0x1ff", Object::Integer(0x100)),
+        ("1 << 10", Object::Integer(1024)),
+        ("-16 >> 2", Object::Integer(-4)),
+        ("-1 >> 100", Object::Integer(-1)),
+        ("1 >> 100", Object::Integer(0)),
+        ("(1 | 2) == 3", Object::Boolean(true)),
+        ("0x10 + 0o10 + 0b10 + 1_0", Object::Integer(36)),
+        ("(1 << 64) >> 64", Object::Integer(1)),
+        (
+            "(1 << 64) - 1 == 0xffff_ffff * 0x1_0000_0001",
+            Object::Boolean(true),
+        ),
+        ("(1 << 64 | 1) & 3", Object::Integer(1)),
+        ("~(1 << 64) + (1 << 64)", Object::Integer(-1)),
+        ("(1 << 100) ^ (1 << 100)", Object::Integer(0)),
+    ];
+
+    for (input, expected) in tests {
+        run_test_case(input, expected)?;
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_boolean_expression() -> Result<()> {
     let tests = [
@@ -196,17 +301,24 @@ fn test_array_literals() -> Result<()> {
 #[test]
 fn test_hash_literals() -> Result<()> {
     let tests = [
-        ("{}", Object::HashMap(Rc::new(HashMap::from([])))),
+        ("{}", Object::HashMap(Rc::new(IndexMap::from([])))),
         (
             "{1: 2, 2: 3}",
-            Object::HashMap(Rc::new(HashMap::from([
+            Object::HashMap(Rc::new(IndexMap::from([
                 (HashKey::Integer(1), Object::Integer(2)),
                 (HashKey::Integer(2), Object::Integer(3)),
             ]))),
         ),
+        (
+            "{[1, 2]: [3]}",
+            Object::HashMap(Rc::new(IndexMap::from([(
+                HashKey::Array(Rc::new(vec![HashKey::Integer(1), HashKey::Integer(2)])),
+                Object::Array(Rc::new(vec![Object::Integer(3)])),
+            )]))),
+        ),
         (
             "{1 + 1: 2 * 2, 3 + 3: 4 * 4}",
-            Object::HashMap(Rc::new(HashMap::from([
+            Object::HashMap(Rc::new(IndexMap::from([
                 (HashKey::Integer(2), Object::Integer(4)),
                 (HashKey::Integer(6), Object::Integer(16)),
             ]))),
@@ -220,6 +332,27 @@ fn test_hash_literals() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_hash_order() {
+    let tests = [
+        (r#"{"b": 1, "a": 2, "c": 3}"#, "{b: 1, a: 2, c: 3}"),
+        ("{3: 1, 1: 2, 2: 3, 1: 4}", "{3: 1, 1: 4, 2: 3}"),
+        (r#"{[2, 1]: {"z": 1, "y": 2}}"#, "{[2, 1]: {z: 1, y: 2}}"),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::new();
+        let bytecode = compiler.compile(&program).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.run(&bytecode).unwrap();
+
+        assert_eq!(vm.last_popped().inspect(), expected);
+    }
+}
+
 #[test]
 fn test_index_expressions() -> Result<()> {
     let tests = [
@@ -233,6 +366,11 @@ fn test_index_expressions() -> Result<()> {
         ("{1: 1, 2: 2}[2]", Object::Integer(2)),
         ("{1: 1}[0]", Object::Null),
         ("{}[0]", Object::Null),
+        ("{[1, 2]: 3}[[1, 2]]", Object::Integer(3)),
+        ("{[1, 2]: 3}[[2, 1]]", Object::Null),
+        ("let n = [][0]; {n: 1}[n]", Object::Integer(1)),
+        ("{{1: 2, 3: 4}: 5}[{3: 4, 1: 2}]", Object::Integer(5)),
+        ("{[[1], {2: [3]}]: 4}[[[1], {2: [3]}]]", Object::Integer(4)),
     ];
 
     for (input, expected) in tests {
@@ -411,15 +549,24 @@ fn test_calling_functions_with_wrong_arguments() {
     let tests = [
         (
             "fn() { 1; }(1);",
-            Error::WrongNumberOfArguments { want: 0, got: 1 },
+            Error::WrongNumberOfArguments {
+                expected: 0,
+                got: 1,
+            },
         ),
         (
             "fn(a) { a; }()",
-            Error::WrongNumberOfArguments { want: 1, got: 0 },
+            Error::WrongNumberOfArguments {
+                expected: 1,
+                got: 0,
+            },
         ),
         (
             "fn(a, b) { a + b; }(1);",
-            Error::WrongNumberOfArguments { want: 2, got: 1 },
+            Error::WrongNumberOfArguments {
+                expected: 2,
+                got: 1,
+            },
         ),
     ];
 
@@ -428,6 +575,95 @@ fn test_calling_functions_with_wrong_arguments() {
     }
 }
 
+#[test]
+fn test_division_by_zero() {
+    let tests = [
+        ("1 / 0", Error::DivisionByZero),
+        ("1 << -1", Error::InvalidShiftAmount("-1".to_string())),
+        (
+            "1 >> (1 << 40)",
+            Error::InvalidShiftAmount("1099511627776".to_string()),
+        ),
+        ("~true", Error::UnknownOperator("~BOOLEAN".to_string())),
+        ("9223372036854775807 * 2 / 0", Error::DivisionByZero),
+        (
+            "1 / (9223372036854775807 * 2 - 9223372036854775807 * 2)",
+            Error::DivisionByZero,
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_error_test_case(input, expected);
+    }
+}
+
+#[test]
+fn test_bytes() {
+    let bytes = |bytes: &[u8]| Ok(Object::Bytes(Rc::new(bytes.to_vec())));
+    let string = |s: &str| Ok(Object::String(Rc::new(s.to_string())));
+    let invalid_argument = |message: &str| {
+        Err(Error::BuiltinFunction {
+            source: ExecutionError::InvalidArgument(message.to_string()),
+        })
+    };
+
+    let tests = [
+        (r#"b"\x00\xff""#, bytes(&[0x00, 0xff])),
+        (r#"b"\x00\xff"[1]"#, Ok(Object::Integer(255))),
+        (r#"b"\x00\xff"[2]"#, Ok(Object::Null)),
+        (r#"b"\x00\xff"[-1]"#, Ok(Object::Null)),
+        (r#"len(b"abc")"#, Ok(Object::Integer(3))),
+        (r#"b"ab" + b"\x00""#, bytes(b"ab\0")),
+        (r#"b"ab" == b"ab""#, Ok(Object::Boolean(true))),
+        (r#"b"ab" != b"ab\x00""#, Ok(Object::Boolean(true))),
+        (r#"{b"k": 1}[b"k"]"#, Ok(Object::Integer(1))),
+        (r#"bytes("é")"#, bytes(&[0xc3, 0xa9])),
+        (r#"bytes([104, 105])"#, bytes(b"hi")),
+        (r#"string(b"\xc3\xa9")"#, string("é")),
+        (
+            r#"array(b"\x01\x02")"#,
+            Ok(Object::Array(Rc::new(vec![
+                Object::Integer(1),
+                Object::Integer(2),
+            ]))),
+        ),
+        (r#"hex(b"\x00\xab")"#, string("00ab")),
+        (r#"from_hex("00AB")"#, bytes(&[0x00, 0xab])),
+        (r#"slice(b"hello", 1, 3)"#, bytes(b"el")),
+        (r#"slice(b"hello", -5, 50)"#, bytes(b"hello")),
+        (r#"slice(b"hello", 3, 1)"#, bytes(b"")),
+        (
+            "slice([1, 2, 3], 1, 5)",
+            Ok(Object::Array(Rc::new(vec![
+                Object::Integer(2),
+                Object::Integer(3),
+            ]))),
+        ),
+        ("bytes([256])", invalid_argument("256 is not a byte")),
+        (
+            r#"string(b"\xff")"#,
+            invalid_argument("invalid utf-8 sequence of 1 bytes from index 0"),
+        ),
+        (
+            r#"from_hex("abc")"#,
+            invalid_argument(r#"invalid hex string "abc""#),
+        ),
+        (
+            r#"hex("ab")"#,
+            Err(Error::BuiltinFunction {
+                source: ExecutionError::TypeMismatch(DataType::String.to_string()),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        match expected {
+            Ok(obj) => run_test_case(input, obj).unwrap(),
+            Err(err) => run_error_test_case(input, err),
+        }
+    }
+}
+
 #[test]
 fn test_builtin_functions() {
     let tests = [
@@ -449,23 +685,32 @@ fn test_builtin_functions() {
                 },
             }),
         ),
+        (
+            "error()",
+            Err(Error::BuiltinFunction {
+                source: ExecutionError::WrongNumberOfArgumentsRange {
+                    min: 1,
+                    max: 2,
+                    got: 0,
+                },
+            }),
+        ),
+        (
+            r#"error("a", 1, 2)"#,
+            Err(Error::BuiltinFunction {
+                source: ExecutionError::WrongNumberOfArgumentsRange {
+                    min: 1,
+                    max: 2,
+                    got: 3,
+                },
+            }),
+        ),
     ];
 
     for (input, expected) in tests {
-        let program = parse(input).unwrap();
-
-        let mut compiler = Compiler::new();
-        let bytecode = compiler.compile(&program).unwrap();
-
-        let mut vm = VirtualMachine::new();
-        let res = vm.run(&bytecode);
         match expected {
-            Ok(obj) => {
-                println!("{:?}", bytecode);
-                assert_eq!(res, Ok(()));
-                assert_eq!(*vm.last_popped(), obj);
-            }
-            Err(err) => assert_eq!(res, Err(err)),
+            Ok(obj) => run_test_case(input, obj).unwrap(),
+            Err(err) => run_error_test_case(input, err),
         }
     }
 }
@@ -601,3 +846,303 @@ fn recursive_functions() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_tail_calls() -> Result<()> {
+    let tests = [
+        (
+            r#"
+            let countDown = fn(x, acc) {
+                if (x == 0) { return acc; }
+                return countDown(x - 1, acc + 1);
+            };
+            countDown(100000, 0);"#,
+            Object::Integer(100000),
+        ),
+        (
+            r#"
+            let isEven = fn(x) { if (x < 2) { x == 0 } else { isEven(x - 2) } };
+            isEven(100001);"#,
+            Object::Boolean(false),
+        ),
+        (
+            r#"
+            let sum = fn(x) { if (x == 0) { 0 } else { x + sum(x - 1) } };
+            sum(100);"#,
+            Object::Integer(5050),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_test_case(input, expected)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_block_scopes() -> Result<()> {
+    let tests = [
+        ("{ let x = 1; x + 1 }", Object::Integer(2)),
+        ("let x = 1; { let x = 2; x } + x", Object::Integer(3)),
+        (
+            "let x = 1; if (true) { let x = x + 1; x } + x",
+            Object::Integer(3),
+        ),
+        (
+            "fn() { let x = 1; { let x = x * 10; x } + x }()",
+            Object::Integer(11),
+        ),
+        (
+            "fn() { let f = { let y = 5; fn() { y } }; let z = 6; f() + z }()",
+            Object::Integer(11),
+        ),
+        (
+            "let f = fn(x) { if (x > 0) { let y = x; y } else { let z = 0 - x; z } }; f(2) + f(-3)",
+            Object::Integer(5),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_test_case(input, expected)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_error_values() -> Result<()> {
+    let tests = [
+        (
+            r#"error("not found", 404)"#,
+            Object::Error(ErrorObject {
+                message: Rc::new("not found".to_string()),
+                data: Rc::new(Object::Integer(404)),
+            }),
+        ),
+        (r#"is_error(error("oops"))"#, Object::Boolean(true)),
+        ("is_error(1)", Object::Boolean(false)),
+        (
+            r#"
+            let check = fn(x) {
+                if (x > 10) { error("too big", x) } else { x }
+            };
+            let double = fn(x) { check(x)? * 2 };
+            double(3);"#,
+            Object::Integer(6),
+        ),
+        (
+            r#"
+            let check = fn(x) {
+                if (x > 10) { error("too big", x) } else { x }
+            };
+            let double = fn(x) {
+                let checked = check(x)?;
+                puts("unreachable");
+                checked * 2
+            };
+            is_error(double(20));"#,
+            Object::Boolean(true),
+        ),
+        (
+            r#"
+            let fail = fn() { error("failed") };
+            let outer = fn() {
+                let inner = fn() { [1, fail()?, 3] };
+                let res = inner();
+                if (is_error(res)) { 0 } else { 1 }
+            };
+            outer();"#,
+            Object::Integer(0),
+        ),
+        (
+            r#"let x = error("top level")?; x + 1"#,
+            Object::Error(ErrorObject {
+                message: Rc::new("top level".to_string()),
+                data: Rc::new(Object::Null),
+            }),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_test_case(input, expected)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_generators() -> Result<()> {
+    let tests = [
+        (
+            r#"
+            let gen = fn() { yield 1; yield 2; };
+            let g = gen();
+            [next(g), next(g), next(g), next(g)];"#,
+            Object::Array(Rc::new(vec![
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::Done,
+                Object::Done,
+            ])),
+        ),
+        (
+            r#"
+            let counter = fn(n) { let x = n * 2; yield x; yield x + 1; };
+            let g = counter(5);
+            next(g) + next(g);"#,
+            Object::Integer(21),
+        ),
+        (
+            r#"
+            let sign = fn(x) {
+                if (x > 0) { yield "positive"; } else { yield "negative"; }
+                yield "end";
+            };
+            let g = sign(1);
+            [next(g), next(g), is_done(next(g))];"#,
+            Object::Array(Rc::new(vec![
+                Object::String(Rc::new("positive".to_string())),
+                Object::String(Rc::new("end".to_string())),
+                Object::Boolean(true),
+            ])),
+        ),
+        (
+            r#"
+            let gen = fn() { yield 1; return 2; yield 3; };
+            let g = gen();
+            [next(g), next(g), next(g)];"#,
+            Object::Array(Rc::new(vec![
+                Object::Integer(1),
+                Object::Done,
+                Object::Done,
+            ])),
+        ),
+        (
+            r#"
+            let naturals = fn(n) { yield n; naturals(n + 1) };
+            let sum = fn(g, n, acc) {
+                if (n == 0) { acc } else { sum(g, n - 1, acc + next(g)) }
+            };
+            sum(naturals(1), 10000, 0);"#,
+            Object::Integer(50005000),
+        ),
+        (
+            r#"
+            let gen = fn() { yield 1; error("failed") };
+            let g = gen();
+            next(g);
+            [is_error(next(g)), is_done(next(g))];"#,
+            Object::Array(Rc::new(vec![Object::Boolean(true), Object::Boolean(true)])),
+        ),
+        (
+            r#"
+            let gen = fn(x) { let y = x?; yield y; };
+            let g = gen(error("failed"));
+            [is_error(next(g)), is_done(next(g))];"#,
+            Object::Array(Rc::new(vec![Object::Boolean(true), Object::Boolean(true)])),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_test_case(input, expected)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_generator_errors() {
+    let tests = [
+        (
+            "next(1)",
+            Error::BuiltinFunction {
+                source: ExecutionError::TypeMismatch(DataType::Integer.to_string()),
+            },
+        ),
+        (
+            "let gen = fn(f) { yield f(); }; let g = gen(fn() { next(g) }); next(g);",
+            Error::GeneratorRunning,
+        ),
+    ];
+
+    for (input, expected) in tests {
+        run_error_test_case(input, expected);
+    }
+}
+
+#[test]
+fn test_modules() -> Result<()> {
+    let main_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/modules/main.monkey");
+
+    let tests = [
+        (
+            r#"import "lib/strings.monkey" as strings; strings.twice("ab")"#,
+            Object::String(Rc::new("abab".to_string())),
+        ),
+        (
+            r#"
+            let double = 5;
+            import "lib/math.monkey" as math;
+            [double, math.double(3), math.answer(), math.secret]"#,
+            Object::Array(Rc::new(vec![
+                Object::Integer(5),
+                Object::Integer(6),
+                Object::Integer(42),
+                Object::Null,
+            ])),
+        ),
+        (
+            r#"
+            import "lib/math.monkey" as math;
+            import "lib/strings.monkey" as strings;
+            import "lib/math.monkey" as again;
+            again.double(len(strings.repeat("a", 3)))"#,
+            Object::Integer(6),
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input).unwrap();
+
+        let mut compiler = Compiler::with_path(&main_path);
+        let bytecode = compiler.compile(&program).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.run(&bytecode)?;
+
+        assert_eq!(*vm.last_popped(), expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_bytecode() {
+    let bytecode = Bytecode {
+        instructions: Rc::new(Instructions::new(&[
+            Instruction::Constant(0),
+            Instruction::Pop,
+        ])),
+        lines: Rc::new(LineTable::default()),
+        constants: &[],
+        modules: &[],
+        global_names: Rc::new(vec![]),
+    };
+
+    let mut vm = VirtualMachine::new();
+    let res = vm.run(&bytecode);
+
+    assert_eq!(
+        res,
+        Err(Error::InvalidBytecode {
+            source: verify::Error::InvalidConstant {
+                location: verify::Location {
+                    code: "<main>".to_string(),
+                    offset: 0,
+                },
+                index: 0,
+            },
+        })
+    );
+}